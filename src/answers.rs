@@ -0,0 +1,434 @@
+//! Pre-answered prompts, so interactive plugin flows also run in CI.
+//!
+//! A plugin declares its [`Question`]s once and asks them through
+//! [`Answers`] instead of prompting directly. Answers come from, in order:
+//!
+//! - `<PLUGIN>_ANSWER_<KEY>`, one variable per question (e.g.
+//!   `CARGO_FOO_ANSWER_PUBLISH=yes`);
+//! - `<PLUGIN>_ANSWERS`, a JSON object of answers by key, or the path of a file
+//!   with one (e.g. `CARGO_FOO_ANSWERS='{"publish": true}'`);
+//! - the terminal, when stdin is one and the plugin isn't on CI;
+//! - the question's default.
+//!
+//! Without a terminal, [`Answers::check`] fails upfront with every question
+//! left unanswered, instead of the run stopping at the first one:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::answers::{
+//!     Answers,
+//!     Question,
+//! };
+//!
+//! # fn example() -> anyhow::Result<()> {
+//! let publish = Question::confirm("publish", "Publish to crates.io?");
+//! let bump =
+//!     Question::choice("bump", "Version bump", ["patch", "minor", "major"]).default("patch");
+//!
+//! let answers = Answers::from_env("cargo-foo")?;
+//! answers.check(&[&publish, &bump])?;
+//! let mut logger = Logger::new();
+//! if answers.confirm(&mut logger, &publish)? {
+//!     let bump = answers.ask(&mut logger, &bump)?;
+//!     logger.status_permanent("Bumping", &bump);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use anyhow::{
+    Context,
+    Result,
+};
+
+use crate::logger::Logger;
+
+/// Number of times an invalid answer typed at the terminal is asked again.
+const ATTEMPTS: usize = 3;
+
+/// What kind of answer a question takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuestionKind {
+    /// Yes or no (`y`/`yes`/`true`, `n`/`no`/`false`)
+    Confirm,
+    /// Any line of text
+    Input,
+    /// One of the listed values
+    Choice(Vec<String>),
+}
+
+/// A prompt of a plugin, identified by a key for pre-answering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    /// Key of the answer, e.g. `publish`
+    pub key: String,
+    /// Text shown at the terminal
+    pub prompt: String,
+    /// Kind of answer
+    pub kind: QuestionKind,
+    /// Answer used without a preset answer or terminal
+    pub default: Option<String>,
+}
+
+impl Question {
+    /// A yes/no question.
+    pub fn confirm(key: &str, prompt: &str) -> Self {
+        Self::new(key, prompt, QuestionKind::Confirm)
+    }
+
+    /// A question answered with a line of text.
+    pub fn input(key: &str, prompt: &str) -> Self {
+        Self::new(key, prompt, QuestionKind::Input)
+    }
+
+    /// A question answered with one of `choices`.
+    pub fn choice<I, S>(key: &str, prompt: &str, choices: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let choices = choices.into_iter().map(Into::into).collect();
+        Self::new(key, prompt, QuestionKind::Choice(choices))
+    }
+
+    fn new(key: &str, prompt: &str, kind: QuestionKind) -> Self {
+        Self {
+            key: key.to_string(),
+            prompt: prompt.to_string(),
+            kind,
+            default: None,
+        }
+    }
+
+    /// Answer with `value` when there is no preset answer and no terminal,
+    /// or nothing is typed at the terminal.
+    ///
+    /// A valid default is stored in canonical form (`yes`/`no` for
+    /// confirmations); an invalid one is reported by [`Answers::check`] and
+    /// [`Answers::ask`].
+    pub fn default(mut self, value: impl Into<String>) -> Self {
+        let value = value.into();
+        self.default = Some(self.validate(&value).unwrap_or(value));
+        self
+    }
+
+    /// The validated default, if there is one.
+    fn validated_default(&self) -> Option<std::result::Result<String, String>> {
+        let default = self.default.as_ref()?;
+        Some(
+            self.validate(default)
+                .map_err(|err| format!("invalid default of `{}`: {}", self.key, err)),
+        )
+    }
+
+    /// `answer` in canonical form (`yes`/`no` for confirmations), or why it
+    /// doesn't answer this question.
+    fn validate(&self, answer: &str) -> std::result::Result<String, String> {
+        let answer = answer.trim();
+        match &self.kind {
+            QuestionKind::Confirm => match answer.to_ascii_lowercase().as_str() {
+                "y" | "yes" | "true" => Ok("yes".to_string()),
+                "n" | "no" | "false" => Ok("no".to_string()),
+                _ => Err(format!("`{}` is not yes or no", answer)),
+            },
+            QuestionKind::Input => Ok(answer.to_string()),
+            QuestionKind::Choice(choices) if choices.iter().any(|choice| choice == answer) => {
+                Ok(answer.to_string())
+            }
+            QuestionKind::Choice(choices) => {
+                Err(format!("`{}` is not one of {}", answer, choices.join(", ")))
+            }
+        }
+    }
+
+    /// The prompt with the accepted answers and default, as shown at the
+    /// terminal.
+    fn terminal_prompt(&self) -> String {
+        let mut prompt = self.prompt.clone();
+        match &self.kind {
+            QuestionKind::Confirm => prompt.push_str(" [y/n]"),
+            QuestionKind::Choice(choices) => prompt.push_str(&format!(" [{}]", choices.join("/"))),
+            QuestionKind::Input => {}
+        }
+        if let Some(default) = &self.default {
+            prompt.push_str(&format!(" (default: {})", default));
+        }
+        prompt
+    }
+}
+
+/// Preset answers to a plugin's questions, see the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct Answers {
+    /// Prefix of the environment variables, e.g. `CARGO_FOO`
+    prefix: Option<String>,
+    /// Answers from `<PLUGIN>_ANSWERS`
+    values: BTreeMap<String, String>,
+    interactive: bool,
+}
+
+impl Answers {
+    /// The answers for the plugin `name` (e.g. `cargo-foo`) from the
+    /// environment. Prompts are interactive when stdin is a terminal and the
+    /// plugin isn't on CI.
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    pub fn from_env(name: &str) -> Result<Self> {
+        let prefix = env_key(name);
+        let variable = format!("{}_ANSWERS", prefix);
+        let values = match std::env::var(&variable) {
+            Ok(value) if value.trim_start().starts_with('{') => {
+                parse_answers(&value).with_context(|| format!("Invalid {}", variable))?
+            }
+            Ok(value) if !value.is_empty() => {
+                read_answers(Path::new(&value)).with_context(|| format!("Invalid {}", variable))?
+            }
+            _ => BTreeMap::new(),
+        };
+        Ok(Self {
+            prefix: Some(prefix),
+            values,
+            interactive: std::io::stdin().is_terminal() && crate::ci::provider().is_none(),
+        })
+    }
+
+    /// The answers in a JSON file, without reading the environment.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            values: read_answers(path.as_ref())?,
+            ..Self::default()
+        })
+    }
+
+    /// Prompt at the terminal for questions without a preset answer, or
+    /// never.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Check the preset answers and defaults of `questions` and, without a
+    /// terminal, that every question is answered or has a default. The error
+    /// lists all the problems at once.
+    pub fn check(&self, questions: &[&Question]) -> Result<()> {
+        let mut problems = Vec::new();
+        for key in self.values.keys() {
+            if !questions.iter().any(|question| &question.key == key) {
+                problems.push(format!("unknown question `{}`", key));
+            }
+        }
+        for question in questions {
+            if let Some(Err(err)) = question.validated_default() {
+                problems.push(err);
+            }
+            match self.preset(question) {
+                Some(Err(err)) => problems.push(err),
+                Some(Ok(_)) => {}
+                None if self.interactive || question.default.is_some() => {}
+                None => problems.push(format!(
+                    "no answer to `{}` ({}), set {}",
+                    question.key,
+                    question.prompt,
+                    self.hint(question)
+                )),
+            }
+        }
+        anyhow::ensure!(
+            problems.is_empty(),
+            "Unanswered or invalid prompts:\n  {}",
+            problems.join("\n  ")
+        );
+        Ok(())
+    }
+
+    /// The answer to `question`: preset, typed at the terminal, or the
+    /// default. Confirmations are answered `yes` or `no`.
+    pub fn ask(&self, logger: &mut Logger, question: &Question) -> Result<String> {
+        if let Some(answer) = self.preset(question) {
+            return answer.map_err(anyhow::Error::msg);
+        }
+        if self.interactive {
+            return prompt(logger, question);
+        }
+        let default = question.validated_default().with_context(|| {
+            format!(
+                "No answer to `{}` ({}) without a terminal; set {}",
+                question.key,
+                question.prompt,
+                self.hint(question)
+            )
+        })?;
+        default.map_err(anyhow::Error::msg)
+    }
+
+    /// The answer to a yes/no `question`, see [`ask`](Self::ask).
+    pub fn confirm(&self, logger: &mut Logger, question: &Question) -> Result<bool> {
+        Ok(self.ask(logger, question)? == "yes")
+    }
+
+    /// The validated preset answer to `question`, if there is one.
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    fn preset(&self, question: &Question) -> Option<std::result::Result<String, String>> {
+        let from_env = self.prefix.as_ref().and_then(|prefix| {
+            let variable = format!("{}_ANSWER_{}", prefix, env_key(&question.key));
+            std::env::var(&variable).ok().map(|value| (variable, value))
+        });
+        let (source, value) = match from_env {
+            Some(found) => found,
+            None => (
+                format!("answer to `{}`", question.key),
+                self.values.get(&question.key)?.clone(),
+            ),
+        };
+        Some(
+            question
+                .validate(&value)
+                .map_err(|err| format!("invalid {}: {}", source, err)),
+        )
+    }
+
+    /// How to answer `question` without a terminal.
+    fn hint(&self, question: &Question) -> String {
+        match &self.prefix {
+            Some(prefix) => format!(
+                "{}_ANSWER_{} or `{}` in {}_ANSWERS",
+                prefix,
+                env_key(&question.key),
+                question.key,
+                prefix
+            ),
+            None => format!("`{}` in the answers file", question.key),
+        }
+    }
+}
+
+/// Ask `question` at the terminal until the answer is valid.
+fn prompt(logger: &mut Logger, question: &Question) -> Result<String> {
+    let text = question.terminal_prompt();
+    let mut last_error = String::new();
+    for _ in 0..ATTEMPTS {
+        let answer = logger.input(&format!("{}:", text));
+        let answer = match (&question.default, answer.trim()) {
+            (Some(default), "") => default.clone(),
+            (_, answer) => answer.to_string(),
+        };
+        match question.validate(&answer) {
+            Ok(answer) => return Ok(answer),
+            Err(err) => {
+                logger.warning("Invalid", &err);
+                last_error = err;
+            }
+        }
+    }
+    anyhow::bail!("No valid answer to `{}`: {}", question.key, last_error)
+}
+
+/// A plugin or question name as it appears in environment variables.
+fn env_key(name: &str) -> String {
+    name.to_ascii_uppercase().replace(['-', '.'], "_")
+}
+
+fn read_answers(path: &Path) -> Result<BTreeMap<String, String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_answers(&text).with_context(|| format!("Invalid answers in {}", path.display()))
+}
+
+/// A JSON object of answers; booleans and numbers are taken as text.
+fn parse_answers(json: &str) -> Result<BTreeMap<String, String>> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(json).context("Expected a JSON object")?;
+    object
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Bool(value) => value.to_string(),
+                serde_json::Value::Number(value) => value.to_string(),
+                other => anyhow::bail!("answer to `{}` is not a string: {}", key, other),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answers(json: &str) -> Answers {
+        Answers {
+            values: parse_answers(json).unwrap(),
+            ..Answers::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let confirm = Question::confirm("publish", "Publish?");
+        assert_eq!(confirm.validate("Y"), Ok("yes".to_string()));
+        assert_eq!(confirm.validate("false"), Ok("no".to_string()));
+        assert!(confirm.validate("maybe").is_err());
+        let choice = Question::choice("bump", "Bump", ["patch", "minor"]);
+        assert_eq!(choice.validate(" minor "), Ok("minor".to_string()));
+        assert_eq!(
+            choice.validate("major"),
+            Err("`major` is not one of patch, minor".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_lists_all_problems() {
+        let publish = Question::confirm("publish", "Publish?");
+        let bump = Question::choice("bump", "Bump", ["patch", "minor"]);
+        let tag = Question::input("tag", "Tag").default("v1");
+        let notes = Question::input("notes", "Release notes");
+        let questions = [&publish, &bump, &tag, &notes];
+
+        let preset = answers(r#"{"publish": true, "bump": "major", "typo": "x"}"#);
+        let err = preset.check(&questions).unwrap_err().to_string();
+        assert!(err.contains("unknown question `typo`"), "{}", err);
+        assert!(err.contains("invalid answer to `bump`"), "{}", err);
+        assert!(err.contains("no answer to `notes`"), "{}", err);
+        assert!(
+            !err.contains("`publish`") && !err.contains("`tag`"),
+            "{}",
+            err
+        );
+
+        let interactive = answers(r#"{"bump": "minor"}"#).interactive(true);
+        assert!(interactive.check(&questions).is_ok());
+    }
+
+    #[test]
+    fn test_ask_without_terminal() {
+        let mut logger = Logger::new();
+        let preset = answers(r#"{"publish": "yes"}"#);
+        let publish = Question::confirm("publish", "Publish?");
+        assert!(preset.confirm(&mut logger, &publish).unwrap());
+        let tag = Question::input("tag", "Tag").default("v1");
+        assert_eq!(preset.ask(&mut logger, &tag).unwrap(), "v1");
+        let notes = Question::input("notes", "Release notes");
+        assert!(preset.ask(&mut logger, &notes).is_err());
+        // Defaults are answered in canonical form
+        let upload = Question::confirm("upload", "Upload?").default("Y");
+        assert_eq!(upload.default.as_deref(), Some("yes"));
+        assert!(preset.confirm(&mut logger, &upload).unwrap());
+        let bump = Question::choice("bump", "Bump", ["patch", "minor"]).default("huge");
+        assert!(preset.ask(&mut logger, &bump).is_err());
+    }
+
+    #[test]
+    fn test_check_invalid_default() {
+        let bump = Question::choice("bump", "Bump", ["patch", "minor"]).default("huge");
+        let err = answers(r#"{"bump": "minor"}"#)
+            .check(&[&bump])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid default of `bump`"), "{}", err);
+    }
+}
@@ -0,0 +1,270 @@
+//! Baseline files for grandfathering existing [findings](crate::findings).
+//!
+//! A baseline is a checked-in JSON file listing the findings that were
+//! accepted when a check was introduced. Comparing a run against it splits
+//! the findings into known ones and new ones, so CI can fail on new findings
+//! only:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::baseline::Baseline;
+//! use cargo_plugin_utils::findings::Findings;
+//!
+//! # let findings = Findings::new();
+//! # let update_baseline = false;
+//! let path = ".lint-baseline.json";
+//! if update_baseline {
+//!     Baseline::from_findings(&findings).save(path)?;
+//! } else {
+//!     let comparison = Baseline::load(path)?.compare(&findings);
+//!     comparison
+//!         .new
+//!         .iter()
+//!         .for_each(|finding| eprintln!("{}", finding.render()));
+//!     if !comparison.new.is_empty() {
+//!         std::process::exit(1);
+//!     }
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Findings are matched by their [fingerprint](Finding::fingerprint), so
+//! entries survive edits that only move them to other lines. The file keeps
+//! the code, file and message next to the fingerprint to make it reviewable.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::findings::{
+    Finding,
+    Findings,
+};
+
+/// Version of the baseline file format.
+pub const BASELINE_VERSION: u32 = 1;
+
+/// One accepted finding.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    /// File of the finding, if it has a location
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Code of the finding
+    pub code: String,
+    /// Message of the finding
+    pub message: String,
+    /// [`Finding::fingerprint`] of the finding
+    pub fingerprint: String,
+}
+
+impl BaselineEntry {
+    fn new(finding: &Finding) -> Self {
+        Self {
+            file: finding
+                .location
+                .as_ref()
+                .map(|location| crate::patch::path_for_patch(&location.file)),
+            code: finding.code.clone(),
+            message: finding.message.clone(),
+            fingerprint: finding.fingerprint(),
+        }
+    }
+}
+
+/// A set of accepted findings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Baseline {
+    version: u32,
+    findings: Vec<BaselineEntry>,
+}
+
+impl Default for Baseline {
+    fn default() -> Self {
+        Self {
+            version: BASELINE_VERSION,
+            findings: Vec::new(),
+        }
+    }
+}
+
+/// Result of [`Baseline::compare`].
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    /// Findings not in the baseline
+    pub new: Findings,
+    /// Findings covered by the baseline
+    pub known: Findings,
+    /// Baseline entries without a matching finding (fixed since)
+    pub fixed: Vec<BaselineEntry>,
+}
+
+impl Baseline {
+    /// Accept all `findings`.
+    pub fn from_findings(findings: &Findings) -> Self {
+        let mut entries: Vec<_> = findings.iter().map(BaselineEntry::new).collect();
+        entries.sort();
+        Self {
+            version: BASELINE_VERSION,
+            findings: entries,
+        }
+    }
+
+    /// Read a baseline file; a missing file is an empty baseline.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        let baseline: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse baseline {}", path.display()))?;
+        if baseline.version != BASELINE_VERSION {
+            anyhow::bail!(
+                "Unsupported baseline version {} in {} (expected {})",
+                baseline.version,
+                path.display(),
+                BASELINE_VERSION
+            );
+        }
+        Ok(baseline)
+    }
+
+    /// Write the baseline as pretty-printed JSON, ending with a newline.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The accepted findings.
+    pub fn entries(&self) -> &[BaselineEntry] {
+        &self.findings
+    }
+
+    /// Number of accepted findings.
+    pub fn len(&self) -> usize {
+        self.findings.len()
+    }
+
+    /// Whether no findings are accepted.
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Split `findings` into new and known ones.
+    ///
+    /// Each entry covers one finding, so a finding that occurs more often
+    /// than it was accepted counts as new.
+    pub fn compare(&self, findings: &Findings) -> Comparison {
+        let mut remaining: HashMap<&str, Vec<&BaselineEntry>> = HashMap::new();
+        for entry in &self.findings {
+            remaining
+                .entry(entry.fingerprint.as_str())
+                .or_default()
+                .push(entry);
+        }
+
+        let mut comparison = Comparison::default();
+        for finding in findings {
+            let fingerprint = finding.fingerprint();
+            let matched = remaining
+                .get_mut(fingerprint.as_str())
+                .and_then(|entries| entries.pop());
+            match matched {
+                Some(_) => comparison.known.push(finding.clone()),
+                None => comparison.new.push(finding.clone()),
+            }
+        }
+        comparison.fixed = remaining.into_values().flatten().cloned().collect();
+        comparison.fixed.sort();
+        comparison
+    }
+
+    /// Drop entries that no longer match a finding, without accepting new
+    /// findings. Returns the number of entries removed.
+    pub fn update(&mut self, findings: &Findings) -> usize {
+        let fixed = self.compare(findings).fixed;
+        for entry in &fixed {
+            if let Some(index) = self.findings.iter().position(|other| other == entry) {
+                self.findings.remove(index);
+            }
+        }
+        fixed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::findings::Severity;
+
+    fn finding(code: &str, line: usize) -> Finding {
+        Finding::new(Severity::Warning, code, format!("{} found", code)).at("src/lib.rs", line, 1)
+    }
+
+    fn findings(items: &[Finding]) -> Findings {
+        items.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = Baseline::from_findings(&findings(&[
+            finding("old", 1),
+            finding("dup", 2),
+            finding("fixed", 3),
+        ]));
+
+        // Lines moved, one more `dup` and a new finding
+        let current = findings(&[
+            finding("old", 10),
+            finding("dup", 11),
+            finding("dup", 12),
+            finding("new", 13),
+        ]);
+        let comparison = baseline.compare(&current);
+        let codes = |findings: &Findings| {
+            findings
+                .iter()
+                .map(|finding| finding.code.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(codes(&comparison.known), ["old", "dup"]);
+        assert_eq!(codes(&comparison.new), ["dup", "new"]);
+        assert_eq!(comparison.fixed.len(), 1);
+        assert_eq!(comparison.fixed[0].code, "fixed");
+    }
+
+    #[test]
+    fn test_update_drops_fixed_entries() {
+        let mut baseline = Baseline::from_findings(&findings(&[finding("a", 1), finding("b", 2)]));
+        let removed = baseline.update(&findings(&[finding("a", 1), finding("new", 2)]));
+        assert_eq!(removed, 1);
+        assert_eq!(baseline.len(), 1);
+        assert_eq!(baseline.entries()[0].code, "a");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("baseline.json");
+        assert!(Baseline::load(&path).unwrap().is_empty());
+
+        let baseline = Baseline::from_findings(&findings(&[finding("a", 1)]));
+        baseline.save(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\"file\": \"src/lib.rs\""));
+        assert_eq!(Baseline::load(&path).unwrap(), baseline);
+
+        std::fs::write(&path, r#"{"version": 99, "findings": []}"#).unwrap();
+        assert!(Baseline::load(&path).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! Bounded capture of subprocess output.
+//!
+//! By default the `run_subprocess*` functions keep everything a child writes.
+//! A [`CaptureLimit`] (see [`RunOptions::capture`](crate::RunOptions::capture))
+//! caps the memory per stream, and the returned output records what was left
+//! out in a [`Truncation`].
+//!
+//! Independently of that,
+//! [`RunOptions::transcript`](crate::RunOptions::transcript) writes the
+//! complete output to a file as it arrives, each line prefixed with the seconds
+//! since the start of the run:
+//!
+//! ```text
+//! $ cargo build
+//! [    0.084] \x1b[1m\x1b[32m   Compiling\x1b[0m serde v1.0.219
+//! [   12.310] \x1b[1m\x1b[32m    Finished\x1b[0m `dev` profile
+//! # exit code 0 after 12.3s
+//! ```
+//!
+//! Escape sequences are kept as the child wrote them, so `less -R` shows the
+//! colors. In pipe mode each line is tagged `out` or `err`.
+//!
+//! In pipe mode, [`RunOptions::interleave`](crate::RunOptions::interleave)
+//! also keeps the lines of both streams in the order they arrived, each
+//! tagged with its stream, in
+//! [`SubprocessOutput::interleaved`](crate::SubprocessOutput::interleaved):
+//!
+//! ```text
+//! [out] {"reason":"compiler-artifact", ...}
+//! [err] warning: unused variable `x`
+//! [out] {"reason":"build-finished","success":true}
+//! ```
+
+use std::io::Write;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::Instant;
+
+use anyhow::Context;
+
+/// How much of each output stream to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureLimit {
+    /// Keep the first `head` and the last `tail` bytes, dropping the middle
+    HeadTail {
+        /// Bytes kept from the start of the stream
+        head: usize,
+        /// Bytes kept from the end of the stream
+        tail: usize,
+    },
+    /// Keep the first `memory` bytes in memory; once the stream gets longer,
+    /// write all of it to a file in the temp directory
+    Spill {
+        /// Bytes kept in memory
+        memory: usize,
+    },
+}
+
+/// What a [`CaptureLimit`] left out of a captured stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truncation {
+    /// Total length of the stream
+    pub total_bytes: u64,
+    /// Bytes missing from the in-memory capture
+    pub dropped_bytes: u64,
+    /// File holding the complete stream, for [`CaptureLimit::Spill`]; the
+    /// caller owns and should remove it
+    pub spill_file: Option<PathBuf>,
+}
+
+impl std::fmt::Display for Truncation {
+    /// Formats as e.g. `output truncated: 1.2 GB of 1.3 GB dropped`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "output truncated: {} of {} dropped",
+            indicatif::DecimalBytes(self.dropped_bytes),
+            indicatif::DecimalBytes(self.total_bytes)
+        )?;
+        if let Some(path) = &self.spill_file {
+            write!(f, ", full output in {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// One stream being captured under an optional limit.
+#[derive(Debug, Default)]
+pub(crate) struct Capture {
+    limit: Option<CaptureLimit>,
+    head: Vec<u8>,
+    /// End of the stream; may grow to twice the tail limit so old bytes are
+    /// dropped in batches
+    tail: Vec<u8>,
+    total: u64,
+    spill: Option<Spill>,
+}
+
+#[derive(Debug)]
+struct Spill {
+    path: PathBuf,
+    /// `None` after a write error; the in-memory head is still returned
+    file: Option<std::fs::File>,
+}
+
+impl Capture {
+    pub(crate) fn new(limit: Option<CaptureLimit>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        self.total += chunk.len() as u64;
+        match self.limit {
+            None => self.head.extend_from_slice(chunk),
+            Some(CaptureLimit::HeadTail { head, tail }) => {
+                let room = head.saturating_sub(self.head.len()).min(chunk.len());
+                self.head.extend_from_slice(&chunk[..room]);
+                self.tail.extend_from_slice(&chunk[room..]);
+                if self.tail.len() > tail.saturating_mul(2) {
+                    trim_front(&mut self.tail, tail);
+                }
+            }
+            Some(CaptureLimit::Spill { memory }) => {
+                if let Some(spill) = &mut self.spill {
+                    spill.write(chunk);
+                    return;
+                }
+                let room = memory.saturating_sub(self.head.len()).min(chunk.len());
+                self.head.extend_from_slice(&chunk[..room]);
+                if room < chunk.len() {
+                    let mut spill = Spill::create();
+                    spill.write(&self.head);
+                    spill.write(&chunk[room..]);
+                    self.spill = Some(spill);
+                }
+            }
+        }
+    }
+
+    /// The captured bytes, and what was left out if anything.
+    pub(crate) fn finish(mut self) -> (Vec<u8>, Option<Truncation>) {
+        if let Some(CaptureLimit::HeadTail { tail, .. }) = self.limit {
+            trim_front(&mut self.tail, tail);
+        }
+        let mut captured = self.head;
+        captured.append(&mut self.tail);
+        let dropped_bytes = self.total - captured.len() as u64;
+        let truncation = (dropped_bytes > 0).then(|| Truncation {
+            total_bytes: self.total,
+            dropped_bytes,
+            spill_file: self
+                .spill
+                .filter(|spill| spill.file.is_some())
+                .map(|spill| spill.path),
+        });
+        (captured, truncation)
+    }
+}
+
+impl Spill {
+    /// Create a new file only the current user can read, never reusing an
+    /// existing path (which could be a planted symlink).
+    fn create() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let mut path = PathBuf::new();
+        for _ in 0..16 {
+            path = std::env::temp_dir().join(format!(
+                "cargo-plugin-output-{}-{}-{}.log",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |since| since.subsec_nanos())
+            ));
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(file) => {
+                    return Self {
+                        path,
+                        file: Some(file),
+                    };
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(_) => break,
+            }
+        }
+        Self { path, file: None }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(file) = &mut self.file
+            && file.write_all(bytes).is_err()
+        {
+            self.file = None;
+        }
+    }
+}
+
+/// File receiving the complete output of one run.
+#[derive(Debug, Clone)]
+pub(crate) struct Transcript {
+    /// `None` after a write error
+    file: Arc<Mutex<Option<std::fs::File>>>,
+    started: Instant,
+}
+
+impl Transcript {
+    /// Create (or truncate) `path` and write the command line to it.
+    pub(crate) fn create(path: &Path, command: &str) -> anyhow::Result<Self> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create transcript {}", path.display()))?;
+        let transcript = Self {
+            file: Arc::new(Mutex::new(Some(file))),
+            started: Instant::now(),
+        };
+        transcript.write(format!("$ {}\n", command).as_bytes());
+        Ok(transcript)
+    }
+
+    /// Writer for one output stream; `tag` marks its lines in pipe mode.
+    pub(crate) fn stream(&self, tag: Option<&'static str>) -> TranscriptStream {
+        TranscriptStream {
+            transcript: self.clone(),
+            tag,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Record how the run ended, e.g. `exit code 0`.
+    pub(crate) fn finish(&self, status: &str) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        self.write(format!("# {} after {:.1}s\n", status, elapsed).as_bytes());
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(handle) = file.as_mut()
+            && handle.write_all(bytes).is_err()
+        {
+            *file = None;
+        }
+    }
+}
+
+/// One stream of a [`Transcript`], split into timestamped lines.
+#[derive(Debug)]
+pub(crate) struct TranscriptStream {
+    transcript: Transcript,
+    tag: Option<&'static str>,
+    partial: Vec<u8>,
+}
+
+impl TranscriptStream {
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        for &byte in chunk {
+            self.partial.push(byte);
+            if byte == b'\n' {
+                self.flush_line();
+            }
+        }
+    }
+
+    /// Write a trailing line without line ending.
+    pub(crate) fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            self.partial.push(b'\n');
+            self.flush_line();
+        }
+    }
+
+    fn flush_line(&mut self) {
+        let elapsed = self.transcript.started.elapsed().as_secs_f64();
+        let mut line = match self.tag {
+            Some(tag) => format!("[{:>9.3} {}] ", elapsed, tag),
+            None => format!("[{:>9.3}] ", elapsed),
+        }
+        .into_bytes();
+        line.append(&mut self.partial);
+        self.transcript.write(&line);
+    }
+}
+
+/// The stream a [`TaggedLine`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    /// The child's stdout
+    Stdout,
+    /// The child's stderr
+    Stderr,
+}
+
+impl Stream {
+    /// The tag of the stream's lines: `out` or `err`.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::Stdout => "out",
+            Self::Stderr => "err",
+        }
+    }
+}
+
+/// A line of the interleaved output of a piped run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedLine {
+    /// Where the line came from
+    pub stream: Stream,
+    /// The line, without its line ending
+    pub text: Vec<u8>,
+}
+
+impl std::fmt::Display for TaggedLine {
+    /// Formats as e.g. `[err] warning: unused variable`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}",
+            self.stream.tag(),
+            String::from_utf8_lossy(&self.text)
+        )
+    }
+}
+
+/// The lines of both streams of a run in order of arrival.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interleaved {
+    lines: Arc<Mutex<Vec<TaggedLine>>>,
+}
+
+impl Interleaved {
+    /// Splitter adding the lines of `stream`.
+    pub(crate) fn stream(&self, stream: Stream) -> InterleavedStream {
+        InterleavedStream {
+            lines: self.lines.clone(),
+            stream,
+            partial: Vec::new(),
+        }
+    }
+
+    /// The lines so far.
+    pub(crate) fn take(&self) -> Vec<TaggedLine> {
+        std::mem::take(&mut *self.lines.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+/// One stream of an [`Interleaved`] timeline.
+#[derive(Debug)]
+pub(crate) struct InterleavedStream {
+    lines: Arc<Mutex<Vec<TaggedLine>>>,
+    stream: Stream,
+    partial: Vec<u8>,
+}
+
+impl InterleavedStream {
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        for &byte in chunk {
+            if byte == b'\n' {
+                self.flush_line();
+            } else {
+                self.partial.push(byte);
+            }
+        }
+    }
+
+    /// Add a trailing line without line ending.
+    pub(crate) fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            self.flush_line();
+        }
+    }
+
+    fn flush_line(&mut self) {
+        let mut text = std::mem::take(&mut self.partial);
+        if text.last() == Some(&b'\r') {
+            text.pop();
+        }
+        self.lines
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(TaggedLine {
+                stream: self.stream,
+                text,
+            });
+    }
+}
+
+/// Drop all but the last `keep` bytes of `bytes`.
+fn trim_front(bytes: &mut Vec<u8>, keep: usize) {
+    if bytes.len() > keep {
+        bytes.drain(..bytes.len() - keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(limit: Option<CaptureLimit>, chunks: &[&[u8]]) -> (Vec<u8>, Option<Truncation>) {
+        let mut capture = Capture::new(limit);
+        for chunk in chunks {
+            capture.push(chunk);
+        }
+        capture.finish()
+    }
+
+    #[test]
+    fn test_capture_head_tail() {
+        let chunks: &[&[u8]] = &[b"abc", b"defg", b"hi"];
+        let tail_only = Some(CaptureLimit::HeadTail { head: 0, tail: 4 });
+        let (captured, truncation) = capture(tail_only, chunks);
+        assert_eq!(captured, b"fghi");
+        let truncation = truncation.unwrap();
+        assert_eq!((truncation.total_bytes, truncation.dropped_bytes), (9, 5));
+        assert_eq!(truncation.spill_file, None);
+
+        let both = Some(CaptureLimit::HeadTail { head: 2, tail: 3 });
+        assert_eq!(capture(both, chunks).0, b"abghi");
+
+        let (captured, truncation) = capture(None, chunks);
+        assert_eq!(captured, b"abcdefghi");
+        assert_eq!(truncation, None);
+        let roomy = Some(CaptureLimit::HeadTail { head: 5, tail: 5 });
+        assert_eq!(capture(roomy, chunks), (b"abcdefghi".to_vec(), None));
+    }
+
+    #[test]
+    fn test_capture_spill() {
+        let chunks: &[&[u8]] = &[b"abc", b"defg", b"hi"];
+        let (captured, truncation) = capture(Some(CaptureLimit::Spill { memory: 4 }), chunks);
+        assert_eq!(captured, b"abcd");
+        let truncation = truncation.unwrap();
+        assert_eq!(truncation.dropped_bytes, 5);
+        let path = truncation.spill_file.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcdefghi");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(path).unwrap();
+
+        let short = capture(Some(CaptureLimit::Spill { memory: 9 }), chunks);
+        assert_eq!(short, (b"abcdefghi".to_vec(), None));
+    }
+
+    #[test]
+    fn test_transcript() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("build.log");
+        let transcript = Transcript::create(&path, "cargo build").unwrap();
+        let mut out = transcript.stream(Some("out"));
+        let mut err = transcript.stream(Some("err"));
+        out.push(b"one\ntw");
+        err.push(b"\x1b[31mwarning\x1b[0m\n");
+        out.push(b"o\nthree");
+        out.finish();
+        err.finish();
+        transcript.finish("exit code 0");
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "$ cargo build");
+        assert!(lines[1].starts_with("[    0.") && lines[1].ends_with(" out] one"));
+        assert!(lines[2].ends_with(" err] \x1b[31mwarning\x1b[0m"));
+        assert!(lines[3].ends_with(" out] two"));
+        assert!(lines[4].ends_with(" out] three"));
+        assert!(lines[5].starts_with("# exit code 0 after 0."));
+    }
+}
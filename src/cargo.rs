@@ -0,0 +1,764 @@
+//! Running cargo from a plugin.
+//!
+//! [`run_cargo`] runs a cargo subcommand with the cargo that invoked the
+//! plugin and the plugin's `--manifest-path`, `--locked`, `--offline` and
+//! `--color` choices:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::cargo::{
+//!     CargoOptions,
+//!     run_cargo,
+//! };
+//!
+//! # async fn example(manifest_path: Option<std::path::PathBuf>) -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let mut options = CargoOptions::new().locked(true);
+//! if let Some(path) = manifest_path {
+//!     options = options.manifest_path(path);
+//! }
+//! let output = run_cargo(
+//!     &mut logger,
+//!     "fetch",
+//!     ["--target", "wasm32-unknown-unknown"],
+//!     &options,
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With [`CargoOptions::progress`], a build shows a single progress bar on
+//! the Logger's status line instead of cargo's own and the output window.
+//!
+//! [`run_cargo_json`] runs cargo with `--message-format=json`, parses the
+//! JSON messages on stdout into [`cargo_metadata::Message`]s for the plugin
+//! and shows the rendered diagnostics they carry in the output window, next
+//! to cargo's status lines, as a plain cargo run would. (With
+//! `json-render-diagnostics` cargo would print the diagnostics itself but
+//! leave them out of the JSON.)
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::cargo::run_cargo_json;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let build = run_cargo_json(&mut logger, ["build", "--release"]).await?;
+//! for artifact in build.artifacts() {
+//!     println!("{}: {:?}", artifact.target.name, artifact.executable);
+//! }
+//! println!("{} warnings", build.diagnostics().count());
+//! if let Some(kind) = build.failure() {
+//!     logger.error("Failed", &kind.to_string());
+//!     if let Some(advice) = kind.advice() {
+//!         logger.info("Help", advice);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use cargo_metadata::diagnostic::{
+    Diagnostic,
+    DiagnosticLevel,
+};
+use cargo_metadata::{
+    Artifact,
+    Message,
+};
+use clap::ColorChoice;
+use portable_pty::CommandBuilder;
+
+use crate::cli::CommonArgs;
+use crate::logger::{
+    Logger,
+    RunOptions,
+    SubprocessOutput,
+    format_status,
+    run_subprocess_with_options,
+};
+use crate::registry::Registry;
+
+/// The cargo to run: `$CARGO`, which cargo sets for the subcommands it
+/// runs, or `cargo` from `PATH`.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+pub fn cargo_program() -> OsString {
+    std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into())
+}
+
+/// Standard cargo flags and run options for [`run_cargo`].
+#[derive(Debug, Clone, Default)]
+pub struct CargoOptions {
+    manifest_path: Option<PathBuf>,
+    locked: bool,
+    offline: bool,
+    registry: Option<String>,
+    color: Option<ColorChoice>,
+    progress: bool,
+    run: RunOptions,
+}
+
+impl CargoOptions {
+    /// No extra flags; colors follow the plugin's stderr.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `--manifest-path` and `--color` choices of the plugin's command
+    /// line; `--quiet` makes the runs [quiet](RunOptions::quiet).
+    pub fn from_common(args: &CommonArgs) -> Self {
+        Self {
+            manifest_path: args.manifest_path.clone(),
+            color: args.color,
+            run: if args.quiet {
+                RunOptions::new().quiet(true)
+            } else {
+                RunOptions::new()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Pass `--manifest-path path`.
+    pub fn manifest_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest_path = Some(path.into());
+        self
+    }
+
+    /// Pass `--locked`.
+    pub fn locked(mut self, locked: bool) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    /// Pass `--offline`.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Pass `--registry` for `registry` (nothing for crates.io). Only for
+    /// subcommands that take it: `publish`, `search`, `install`, `add`,
+    /// `owner`, `yank`, `package` and `login`.
+    pub fn registry(mut self, registry: &Registry) -> Self {
+        self.registry = registry.name().map(str::to_string);
+        self
+    }
+
+    /// Pass `--color choice` instead of following the plugin's stderr.
+    pub fn color(mut self, choice: ColorChoice) -> Self {
+        self.color = Some(choice);
+        self
+    }
+
+    /// Show the build's progress on the Logger's status line, as
+    /// `Building [=====>    ] 45/120: serde`, instead of cargo's own
+    /// progress bar and the output window; see [`BuildProgress`].
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Run with `options` (directory, environment, timeout, pipes, ...).
+    pub fn run_options(mut self, options: RunOptions) -> Self {
+        self.run = options;
+        self
+    }
+
+    /// `cargo subcommand` with the standard flags, then `args`.
+    pub fn command<I, S>(&self, subcommand: &str, args: I) -> CommandBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let mut cmd = CommandBuilder::new(cargo_program());
+        cmd.arg(subcommand);
+        if let Some(path) = &self.manifest_path {
+            cmd.arg("--manifest-path");
+            cmd.arg(path);
+        }
+        if self.locked {
+            cmd.arg("--locked");
+        }
+        if self.offline {
+            cmd.arg("--offline");
+        }
+        if let Some(registry) = &self.registry {
+            cmd.args(["--registry", registry]);
+        }
+        let color = match self.color {
+            Some(ColorChoice::Always) => "always",
+            Some(ColorChoice::Never) => "never",
+            Some(ColorChoice::Auto) => "auto",
+            None if console::colors_enabled_stderr() => "always",
+            None => "never",
+        };
+        cmd.args(["--color", color]);
+        cmd.args(args);
+        cmd
+    }
+}
+
+/// Run `cargo subcommand args` with the flags of `options`, see
+/// [`CargoOptions::command`].
+///
+/// Fails only if cargo can't be run; check [`SubprocessOutput::success`]
+/// for the result, and [`failure_kind`] for why it failed. The flags come
+/// before `args`, so `args` may end with `-- ...`; subcommands that don't
+/// know a flag (e.g. `cargo fmt` and `--locked`) reject it.
+pub async fn run_cargo<I, S>(
+    logger: &mut Logger,
+    subcommand: &str,
+    args: I,
+    options: &CargoOptions,
+) -> anyhow::Result<SubprocessOutput>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let command = options.command(subcommand, args);
+    if !options.progress || options.run.is_quiet() {
+        return run_subprocess_with_options(logger, || command, &options.run).await;
+    }
+    logger.status(BuildProgress::ACTION, "");
+    let status_bar = logger.status_bar();
+    let mut progress = BuildProgress::default();
+    let run = options
+        .run
+        .clone()
+        .window_height(0)
+        // Cargo draws its bar only for a terminal, and needs a width without
+        // one
+        .env("CARGO_TERM_PROGRESS_WHEN", "always")
+        .env("CARGO_TERM_PROGRESS_WIDTH", "100")
+        .also_on_line(move |line| {
+            if progress.update(line)
+                && let Some(status_bar) = &status_bar
+            {
+                status_bar.set_message(format_status(BuildProgress::ACTION, &progress.render()));
+            }
+        });
+    let result = run_subprocess_with_options(logger, || command, &run).await;
+    logger.clear_status();
+    result
+}
+
+/// Progress of a cargo build, read from the lines it prints.
+///
+/// Recognizes cargo's progress bar (`Building [===>  ] 12/58: serde, syn`)
+/// and its `Compiling`, `Checking` and `Documenting` lines, optionally
+/// ending in a `(12/58)` count. Feed it lines with [`update`](Self::update),
+/// e.g. from [`RunOptions::on_line`], and show [`render`](Self::render)
+/// where it fits; [`CargoOptions::progress`] does this on the status line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildProgress {
+    /// Units (crates, build scripts) built so far
+    pub finished: usize,
+    /// Units to build, once cargo reported it
+    pub total: Option<usize>,
+    /// What cargo is working on, e.g. `serde, syn`
+    pub current: Option<String>,
+}
+
+impl BuildProgress {
+    /// Action word of the status line.
+    const ACTION: &str = "Building";
+
+    /// Width of the rendered bar between the brackets.
+    const BAR_WIDTH: usize = 25;
+
+    /// Read a line of cargo output; `true` if it changed the progress.
+    ///
+    /// A line may hold several `\r`-separated redraws of cargo's bar; the
+    /// last one counts.
+    pub fn update(&mut self, line: &[u8]) -> bool {
+        let line = String::from_utf8_lossy(line);
+        let line = console::strip_ansi_codes(&line);
+        let before = self.clone();
+        for segment in line.split('\r') {
+            self.update_segment(segment.trim());
+        }
+        *self != before
+    }
+
+    fn update_segment(&mut self, segment: &str) {
+        if let Some(drawn) = segment.strip_prefix("Building [") {
+            let Some((_, rest)) = drawn.split_once(']') else {
+                return;
+            };
+            let (counts, current) = rest.split_once(':').unwrap_or((rest, ""));
+            if let Some((finished, total)) = parse_count(counts.trim()) {
+                self.finished = finished;
+                self.total = Some(total);
+            }
+            let current = current.trim();
+            if !current.is_empty() {
+                self.current = Some(current.to_string());
+            }
+            return;
+        }
+        let Some((action, target)) = segment.split_once(' ') else {
+            return;
+        };
+        if !matches!(action, "Compiling" | "Checking" | "Documenting") {
+            return;
+        }
+        if let Some(name) = target.split_whitespace().next() {
+            self.current = Some(name.to_string());
+        }
+        let count = target
+            .strip_suffix(')')
+            .and_then(|rest| rest.rsplit_once('('))
+            .and_then(|(_, count)| parse_count(count));
+        if let Some((finished, total)) = count {
+            self.finished = finished;
+            self.total = Some(total);
+        }
+    }
+
+    /// The progress as text, e.g. `[=====>    ] 45/120: serde`, or just
+    /// what is being built while the total is unknown.
+    pub fn render(&self) -> String {
+        let current = self.current.as_deref().unwrap_or_default();
+        let Some(total) = self.total.filter(|&total| total > 0) else {
+            return current.to_string();
+        };
+        let done = Self::BAR_WIDTH * self.finished.min(total) / total;
+        let filled = if done == Self::BAR_WIDTH {
+            "=".repeat(done)
+        } else {
+            format!(
+                "{}>{}",
+                "=".repeat(done),
+                " ".repeat(Self::BAR_WIDTH - done - 1)
+            )
+        };
+        let mut text = format!("[{}] {}/{}", filled, self.finished, total);
+        if !current.is_empty() {
+            text.push_str(": ");
+            text.push_str(current);
+        }
+        text
+    }
+}
+
+/// Parse `12/58`.
+fn parse_count(text: &str) -> Option<(usize, usize)> {
+    let (finished, total) = text.split_once('/')?;
+    Some((finished.trim().parse().ok()?, total.trim().parse().ok()?))
+}
+
+/// Why a cargo command failed, for targeted advice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CargoFailureKind {
+    /// A crate didn't compile
+    Compile,
+    /// Tests ran and some failed
+    Test,
+    /// A download or registry update failed
+    Network,
+    /// Cargo was stopped while waiting for a lock held by another cargo
+    Lock,
+    /// Anything else
+    Other,
+}
+
+impl CargoFailureKind {
+    /// A hint for the user, if there is something to suggest.
+    pub fn advice(self) -> Option<&'static str> {
+        match self {
+            Self::Network => Some(
+                "check your network connection, or run with `--offline` to use the \
+                 packages already downloaded",
+            ),
+            Self::Lock => Some(
+                "another cargo process holds the lock on the package cache or build \
+                 directory; wait for it to finish or stop it",
+            ),
+            Self::Compile | Self::Test | Self::Other => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CargoFailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Compile => "compilation failed",
+            Self::Test => "tests failed",
+            Self::Network => "network error",
+            Self::Lock => "waiting for a lock held by another cargo",
+            Self::Other => "cargo failed",
+        })
+    }
+}
+
+/// Classify a failed cargo run by its stderr; `None` if it succeeded.
+///
+/// Also works on the output of a run that was stopped, e.g. by a
+/// [timeout](crate::RunOptions::timeout) while cargo waited for a lock.
+pub fn failure_kind(output: &SubprocessOutput) -> Option<CargoFailureKind> {
+    if output.success() {
+        return None;
+    }
+    Some(classify(&String::from_utf8_lossy(&output.stderr), false))
+}
+
+/// Classify stderr text; `compile_errors` if the JSON messages had errors.
+fn classify(stderr: &str, compile_errors: bool) -> CargoFailureKind {
+    let stderr = console::strip_ansi_codes(stderr).to_lowercase();
+    let last_line = stderr.lines().rev().find(|line| !line.trim().is_empty());
+    let contains = |patterns: &[&str]| patterns.iter().any(|pattern| stderr.contains(pattern));
+    if last_line.is_some_and(|line| line.contains("blocking waiting for file lock"))
+        || contains(&["failed to acquire package cache lock"])
+    {
+        CargoFailureKind::Lock
+    } else if contains(&[
+        "failed to download",
+        "spurious network error",
+        "could not resolve host",
+        "couldn't resolve host",
+        "failed to update registry",
+        "unable to update registry",
+        "failed to query replaced source registry",
+        "timeout was reached",
+        "network failure",
+    ]) {
+        CargoFailureKind::Network
+    } else if compile_errors || contains(&["error: could not compile"]) {
+        CargoFailureKind::Compile
+    } else if contains(&[
+        "error: test failed",
+        "error: doctest failed",
+        "test result: failed",
+        "error: test run failed",
+    ]) {
+        CargoFailureKind::Test
+    } else {
+        CargoFailureKind::Other
+    }
+}
+
+/// The messages and output of a [`run_cargo_json`] run.
+#[derive(Debug, Clone)]
+pub struct CargoJsonOutput {
+    /// Cargo's JSON messages in the order they were printed; stray stdout
+    /// lines (e.g. from build scripts) are [`Message::TextLine`]s
+    pub messages: Vec<Message>,
+    /// The run's output; `stderr` holds the rendered diagnostics and
+    /// cargo's status lines
+    pub output: SubprocessOutput,
+}
+
+impl CargoJsonOutput {
+    /// Whether cargo succeeded.
+    pub fn success(&self) -> bool {
+        self.output.success()
+    }
+
+    /// The artifacts cargo built or found fresh.
+    pub fn artifacts(&self) -> impl Iterator<Item = &Artifact> {
+        self.messages.iter().filter_map(|message| match message {
+            Message::CompilerArtifact(artifact) => Some(artifact),
+            _ => None,
+        })
+    }
+
+    /// The compiler's diagnostics (errors, warnings, notes).
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.messages.iter().filter_map(|message| match message {
+            Message::CompilerMessage(message) => Some(&message.message),
+            _ => None,
+        })
+    }
+
+    /// Why cargo failed; `None` if it succeeded.
+    pub fn failure(&self) -> Option<CargoFailureKind> {
+        if self.success() {
+            return None;
+        }
+        let compile_errors = self.diagnostics().any(|diagnostic| {
+            matches!(
+                diagnostic.level,
+                DiagnosticLevel::Error | DiagnosticLevel::Ice
+            )
+        });
+        Some(classify(
+            &String::from_utf8_lossy(&self.output.stderr),
+            compile_errors,
+        ))
+    }
+
+    /// The result of the `build-finished` message, if cargo got that far.
+    pub fn build_success(&self) -> Option<bool> {
+        self.messages.iter().find_map(|message| match message {
+            Message::BuildFinished(finished) => Some(finished.success),
+            _ => None,
+        })
+    }
+}
+
+/// Run cargo with `args` (starting with the subcommand, e.g. `["build",
+/// "--release"]`, possibly after a `+toolchain` and global options) and
+/// parse its JSON messages.
+///
+/// The rendered diagnostics scroll by in the output window, in color if
+/// stderr shows colors. Fails only if cargo can't be run; check
+/// [`CargoJsonOutput::success`] for the result.
+pub async fn run_cargo_json<I, S>(logger: &mut Logger, args: I) -> anyhow::Result<CargoJsonOutput>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    run_cargo_json_with_options(logger, args, &RunOptions::new()).await
+}
+
+/// [`run_cargo_json`] with `options` (directory, environment, timeout,
+/// ...). Stdout is always piped to read cargo's messages.
+pub async fn run_cargo_json_with_options<I, S>(
+    logger: &mut Logger,
+    args: I,
+    options: &RunOptions,
+) -> anyhow::Result<CargoJsonOutput>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let colors = console::colors_enabled_stderr();
+    let command = json_command(args.into_iter().map(Into::into).collect(), colors);
+    let mut options = options.clone().piped(true);
+    if colors {
+        // Cargo can't see the terminal through the pipe
+        options = options.env("CARGO_TERM_COLOR", "always");
+    }
+    options.render_stdout = Some(rendered_diagnostic);
+    let output = run_subprocess_with_options(logger, || command, &options).await?;
+    let messages = Message::parse_stream(output.stdout.as_slice()).collect::<Result<_, _>>()?;
+    Ok(CargoJsonOutput { messages, output })
+}
+
+/// The cargo command for `args`, with the message format right after the
+/// subcommand so it stays in front of a `--`.
+fn json_command(mut args: Vec<String>, colors: bool) -> CommandBuilder {
+    let mut cmd = CommandBuilder::new(cargo_program());
+    let format = if colors {
+        "--message-format=json-diagnostic-rendered-ansi"
+    } else {
+        "--message-format=json"
+    };
+    let position = subcommand_index(&args).map_or(args.len(), |index| index + 1);
+    args.insert(position, format.to_string());
+    cmd.args(args);
+    cmd
+}
+
+/// Index of the subcommand in cargo's `args`, after a `+toolchain` and
+/// global options such as `-Zflag` or `--config key=value`.
+fn subcommand_index(args: &[String]) -> Option<usize> {
+    let mut index = usize::from(args.first().is_some_and(|arg| arg.starts_with('+')));
+    while let Some(arg) = args.get(index) {
+        if !arg.starts_with('-') {
+            return Some(index);
+        }
+        // Global options whose value is the next argument
+        let takes_value = matches!(arg.as_str(), "-C" | "-Z" | "--config" | "--color");
+        index += if takes_value { 2 } else { 1 };
+    }
+    None
+}
+
+/// The human-readable text of a `compiler-message` line.
+fn rendered_diagnostic(line: &[u8]) -> Option<Vec<u8>> {
+    match serde_json::from_slice(line).ok()? {
+        Message::CompilerMessage(message) => message.message.rendered.map(String::into_bytes),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_command() {
+        let cmd = json_command(vec!["test".into(), "--".into(), "filter".into()], false);
+        let argv: Vec<_> = cmd.get_argv()[1..]
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(argv, ["test", "--message-format=json", "--", "filter"]);
+
+        let args = [
+            "+nightly",
+            "-Z",
+            "unstable-options",
+            "--locked",
+            "build",
+            "--",
+        ];
+        let cmd = json_command(args.map(String::from).to_vec(), false);
+        let argv: Vec<_> = cmd.get_argv()[1..]
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            argv,
+            [
+                "+nightly",
+                "-Z",
+                "unstable-options",
+                "--locked",
+                "build",
+                "--message-format=json",
+                "--"
+            ]
+        );
+        assert_eq!(subcommand_index(&["+stable".to_string()]), None);
+    }
+
+    #[test]
+    fn test_cargo_options_command() {
+        let options = CargoOptions::new()
+            .manifest_path("crates/app/Cargo.toml")
+            .locked(true)
+            .offline(true)
+            .color(ColorChoice::Never);
+        let cmd = options.command("test", ["--workspace", "--", "--nocapture"]);
+        let argv: Vec<_> = cmd.get_argv()[1..]
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            argv,
+            [
+                "test",
+                "--manifest-path",
+                "crates/app/Cargo.toml",
+                "--locked",
+                "--offline",
+                "--color",
+                "never",
+                "--workspace",
+                "--",
+                "--nocapture"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_cargo() {
+        let mut logger = Logger::new();
+        let options = CargoOptions::new()
+            .manifest_path(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+            .offline(true)
+            .run_options(RunOptions::new().piped(true));
+        let output = run_cargo(
+            &mut logger,
+            "metadata",
+            ["--format-version", "1", "--no-deps"],
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(output.success());
+        let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(metadata["packages"][0]["name"], "cargo-plugin-utils");
+    }
+
+    #[test]
+    fn test_build_progress() {
+        let mut progress = BuildProgress::default();
+        assert!(progress.update(b"\x1b[1m\x1b[32m   Compiling\x1b[0m serde v1.0.219"));
+        assert_eq!(progress.render(), "serde");
+        assert!(progress.update(
+            b"\r    Building [===>    ] 10/58: serde\r    Building [====>   ] 12/58: serde, syn\r"
+        ));
+        assert_eq!(progress.finished, 12);
+        assert_eq!(progress.total, Some(58));
+        assert_eq!(
+            progress.render(),
+            "[=====>                   ] 12/58: serde, syn"
+        );
+        assert!(progress.update(b"    Checking app v0.1.0 (/src/app) (57/58)"));
+        assert_eq!(progress.current.as_deref(), Some("app"));
+        assert_eq!(progress.finished, 57);
+        assert!(!progress.update(b"warning: unused variable: `x`"));
+        progress.finished = 58;
+        assert_eq!(
+            progress.render(),
+            format!("[{}] 58/58: app", "=".repeat(25))
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let compile = "   Compiling warns v0.1.0\nerror[E0425]: cannot find value `x`\n\
+                       error: could not compile `warns` (bin \"warns\") due to 1 previous error\n";
+        assert_eq!(classify(compile, false), CargoFailureKind::Compile);
+        let test = "test tests::it_works ... FAILED\n\ntest result: FAILED. 0 passed; 1 \
+                    failed\n\nerror: test failed, to rerun pass `--lib`\n";
+        assert_eq!(classify(test, false), CargoFailureKind::Test);
+        let network = "    Updating crates.io index\nerror: failed to download from \
+                       `https://static.crates.io/crates/serde/1.0.0/download`\n\nCaused by:\n  \
+                       [6] Could not resolve host: static.crates.io\n";
+        assert_eq!(classify(network, false), CargoFailureKind::Network);
+        let lock = "    Blocking waiting for file lock on build directory\n";
+        assert_eq!(classify(lock, false), CargoFailureKind::Lock);
+        assert!(
+            CargoFailureKind::Lock
+                .advice()
+                .unwrap()
+                .contains("another cargo")
+        );
+        // Waiting for the lock before failing to compile is not the cause
+        let waited = format!("{}   Compiling warns v0.1.0\n{}", lock, compile);
+        assert_eq!(classify(&waited, false), CargoFailureKind::Compile);
+        assert_eq!(classify("", true), CargoFailureKind::Compile);
+        assert_eq!(
+            classify("error: no such command: `frob`", false),
+            CargoFailureKind::Other
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_cargo_json() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("Cargo.toml"),
+            "[package]\nname = \"warns\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(
+            dir.path().join("src/main.rs"),
+            "fn main() {\n    let unused = 1;\n}\n",
+        )
+        .unwrap();
+
+        let mut logger = Logger::new();
+        // Outside of this repository, whose cargo config denies warnings
+        let options = RunOptions::new()
+            .cwd(dir.path())
+            .env_remove("RUSTFLAGS")
+            .env_remove("CARGO_ENCODED_RUSTFLAGS");
+        let build = run_cargo_json_with_options(&mut logger, ["check"], &options)
+            .await
+            .unwrap();
+        assert!(build.success());
+        assert_eq!(build.failure(), None);
+        assert_eq!(build.build_success(), Some(true));
+        assert!(
+            build
+                .artifacts()
+                .any(|artifact| artifact.target.name == "warns")
+        );
+        let warning = build.diagnostics().next().unwrap();
+        assert!(warning.message.contains("unused variable"));
+        // The rendered text is what the window showed
+        let rendered = warning.rendered.as_deref().unwrap();
+        assert!(rendered.contains("src/main.rs:2:9"));
+    }
+}
@@ -0,0 +1,301 @@
+//! Long-running children the plugin talks to while they run.
+//!
+//! The `run_subprocess*` functions run a command to completion. For a server
+//! the plugin starts, uses and stops later (a local docs preview, a fixture
+//! daemon for tests), [`spawn_subprocess`] returns a [`ChildHandle`]
+//! instead:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::RunOptions;
+//! use cargo_plugin_utils::child::spawn_subprocess;
+//! use portable_pty::CommandBuilder;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut server = spawn_subprocess(
+//!     || {
+//!         let mut cmd = CommandBuilder::new("python3");
+//!         cmd.args(["-m", "http.server", "8000"]);
+//!         cmd
+//!     },
+//!     &RunOptions::new().cwd("target/doc"),
+//! )?;
+//! while let Some(line) = server.next_line().await {
+//!     if String::from_utf8_lossy(&line.text).contains("Serving HTTP") {
+//!         break;
+//!     }
+//! }
+//! // ... use the server ...
+//! server.kill().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The child runs with pipes, in a process group of its own, so Ctrl-C in
+//! the terminal doesn't reach it; a handle that is dropped while the child
+//! runs kills it with everything it started.
+
+use std::process::Stdio;
+
+use anyhow::Context;
+use tokio::io::{
+    AsyncBufReadExt,
+    AsyncRead,
+    AsyncWriteExt,
+    BufReader,
+};
+use tokio::sync::mpsc;
+
+use crate::capture::{
+    Stream,
+    TaggedLine,
+};
+use crate::command::{
+    IntoCommand,
+    spawn_error,
+};
+use crate::logger::{
+    ExitStatus,
+    ProcessTree,
+    RunOptions,
+    Termination,
+    kill_tree,
+    std_command,
+};
+
+/// Start a command in the background and return a handle to it.
+///
+/// The directory and environment options and the
+/// [`kill_grace`](RunOptions::kill_grace) of `options` apply; the output
+/// options don't, as nothing is shown. Must be called from within a tokio
+/// runtime.
+pub fn spawn_subprocess<F>(cmd_builder: F, options: &RunOptions) -> anyhow::Result<ChildHandle>
+where
+    F: IntoCommand,
+{
+    let mut cmd = cmd_builder.into_command();
+    options.apply(&mut cmd);
+    let mut command = tokio::process::Command::from(std_command(&cmd)?);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            let err =
+                anyhow::Error::new(err).context(format!("Failed to spawn {:?}", cmd.get_argv()[0]));
+            return Err(spawn_error(&cmd, options.not_found_hint_text(), err));
+        }
+    };
+    let (sender, lines) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_lines(stdout, Stream::Stdout, sender.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_lines(stderr, Stream::Stderr, sender));
+    }
+    Ok(ChildHandle {
+        pid: child.id(),
+        stdin: child.stdin.take(),
+        child,
+        lines,
+        grace: options.kill_grace_or_default(),
+        status: None,
+    })
+}
+
+/// Send the lines of `reader` to `sender` until it ends or nobody listens.
+async fn forward_lines(
+    reader: impl AsyncRead + Unpin,
+    stream: Stream,
+    sender: mpsc::UnboundedSender<TaggedLine>,
+) {
+    let mut reader = BufReader::new(reader);
+    let mut text = Vec::new();
+    loop {
+        text.clear();
+        match reader.read_until(b'\n', &mut text).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        if text.last() == Some(&b'\n') {
+            text.pop();
+        }
+        if text.last() == Some(&b'\r') {
+            text.pop();
+        }
+        let line = TaggedLine {
+            stream,
+            text: crate::redact::redact_bytes(&text).into_owned(),
+        };
+        if sender.send(line).is_err() {
+            return;
+        }
+    }
+}
+
+/// A child started with [`spawn_subprocess`].
+///
+/// Output lines are kept until read with [`next_line`](Self::next_line),
+/// so a chatty child that is never read from grows the plugin's memory.
+#[derive(Debug)]
+pub struct ChildHandle {
+    child: tokio::process::Child,
+    pid: Option<u32>,
+    stdin: Option<tokio::process::ChildStdin>,
+    lines: mpsc::UnboundedReceiver<TaggedLine>,
+    grace: std::time::Duration,
+    /// Set once the child was reaped
+    status: Option<ExitStatus>,
+}
+
+impl ChildHandle {
+    /// The child's process ID, while it runs.
+    pub fn id(&self) -> Option<u32> {
+        self.status.is_none().then_some(self.pid).flatten()
+    }
+
+    /// The next line the child printed on stdout or stderr, waiting for
+    /// one; `None` once both streams ended.
+    pub async fn next_line(&mut self) -> Option<TaggedLine> {
+        self.lines.recv().await
+    }
+
+    /// The next line if one is waiting, without waiting.
+    pub fn try_next_line(&mut self) -> Option<TaggedLine> {
+        self.lines.try_recv().ok()
+    }
+
+    /// Write `data` to the child's stdin.
+    pub async fn write_stdin(&mut self, data: impl AsRef<[u8]>) -> anyhow::Result<()> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .context("The child's stdin was closed")?;
+        stdin
+            .write_all(data.as_ref())
+            .await
+            .context("Failed to write to the child's stdin")?;
+        stdin
+            .flush()
+            .await
+            .context("Failed to write to the child's stdin")
+    }
+
+    /// Close the child's stdin, signalling the end of its input.
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+
+    /// Wait for the child to exit on its own.
+    pub async fn wait(&mut self) -> anyhow::Result<ExitStatus> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+        let status = ExitStatus::from(
+            self.child
+                .wait()
+                .await
+                .context("Failed to wait for subprocess")?,
+        );
+        self.status = Some(status);
+        Ok(status)
+    }
+
+    /// Stop the child: ask it to exit with `SIGTERM`, then kill it with
+    /// everything it started if it's still running after the
+    /// [`kill_grace`](RunOptions::kill_grace). On Windows it is killed right
+    /// away.
+    ///
+    /// Returns how it exited and whether it took the kill; a child that
+    /// had already exited is reported as [`Termination::Graceful`].
+    pub async fn kill(&mut self) -> anyhow::Result<(ExitStatus, Termination)> {
+        if let Some(status) = self.status {
+            return Ok((status, Termination::Graceful));
+        }
+        let tree = ProcessTree::find(self.pid).await;
+        if tree.terminate()
+            && let Ok(status) = tokio::time::timeout(self.grace, self.wait()).await
+        {
+            tree.kill();
+            return Ok((status?, Termination::Graceful));
+        }
+        if let Some(pid) = self.pid {
+            let _ = tokio::task::spawn_blocking(move || kill_tree(pid)).await;
+        }
+        tree.kill();
+        let _ = self.child.start_kill();
+        Ok((self.wait().await?, Termination::Killed))
+    }
+}
+
+impl Drop for ChildHandle {
+    fn drop(&mut self) {
+        if self.status.is_some() || !matches!(self.child.try_wait(), Ok(None)) {
+            return;
+        }
+        if let Some(pid) = self.pid {
+            let _ = kill_tree(pid);
+        }
+        let _ = self.child.start_kill();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use portable_pty::CommandBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spawn_subprocess() {
+        let mut child = spawn_subprocess(
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args([
+                    "-c",
+                    "echo ready; while read line; do echo \"got $line\" >&2; done",
+                ]);
+                cmd
+            },
+            &RunOptions::new(),
+        )
+        .unwrap();
+        assert!(child.id().is_some());
+        let ready = child.next_line().await.unwrap();
+        assert_eq!(ready.to_string(), "[out] ready");
+
+        child.write_stdin("ping\n").await.unwrap();
+        let reply = child.next_line().await.unwrap();
+        assert_eq!(
+            (reply.stream, reply.text),
+            (Stream::Stderr, b"got ping".to_vec())
+        );
+
+        child.close_stdin();
+        assert_eq!(child.wait().await.unwrap(), ExitStatus::Code(0));
+        assert!(child.next_line().await.is_none());
+        assert!(child.id().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_kill_subprocess() {
+        let options = RunOptions::new().kill_grace(std::time::Duration::from_millis(200));
+        let mut child =
+            spawn_subprocess(crate::CommandSpec::new("sleep").arg("30"), &options).unwrap();
+        let (status, termination) = child.kill().await.unwrap();
+        assert_eq!(status, ExitStatus::Signal(libc::SIGTERM));
+        assert_eq!(termination, Termination::Graceful);
+
+        let mut stubborn = spawn_subprocess(
+            crate::CommandSpec::new("sh").args(["-c", "trap '' TERM; sleep 30"]),
+            &options,
+        )
+        .unwrap();
+        let (status, termination) = stubborn.kill().await.unwrap();
+        assert_eq!(status, ExitStatus::Signal(libc::SIGKILL));
+        assert_eq!(termination, Termination::Killed);
+    }
+}
@@ -0,0 +1,186 @@
+//! Plain output for CI logs and other non-terminal stderr.
+//!
+//! When stderr isn't a terminal that understands VT sequences, nothing can
+//! be redrawn in place: the live output window and the status spinner would
+//! be lost. The CI renderer takes over instead, selected automatically:
+//!
+//! - subprocess output is streamed line by line, inside a collapsible section
+//!   named after the command when the CI service supports sections (GitHub
+//!   Actions, GitLab CI, Azure Pipelines);
+//! - ephemeral [`status`](crate::Logger::status) lines are printed as plain
+//!   lines, at most one per [`STATUS_INTERVAL`].
+//!
+//! Plugins can group their own output with [`group`]:
+//!
+//! ```no_run
+//! let _section = cargo_plugin_utils::ci::group("Checking links");
+//! // everything printed here is folded into the section
+//! ```
+
+use std::sync::OnceLock;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+/// Minimum time between two plain status lines.
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A CI service with its own log markup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiProvider {
+    /// GitHub Actions (`::group::`)
+    GitHubActions,
+    /// GitLab CI (`section_start`)
+    GitLab,
+    /// Azure Pipelines (`##[group]`)
+    AzurePipelines,
+    /// Any other CI service (`CI` is set); no sections
+    Other,
+}
+
+/// The CI service the plugin runs on, if any. Detected once.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+pub fn provider() -> Option<CiProvider> {
+    static PROVIDER: OnceLock<Option<CiProvider>> = OnceLock::new();
+    *PROVIDER.get_or_init(|| {
+        let set = |key: &str| std::env::var_os(key).is_some_and(|value| !value.is_empty());
+        if set("GITHUB_ACTIONS") {
+            Some(CiProvider::GitHubActions)
+        } else if set("GITLAB_CI") {
+            Some(CiProvider::GitLab)
+        } else if set("TF_BUILD") {
+            Some(CiProvider::AzurePipelines)
+        } else if set("CI") {
+            Some(CiProvider::Other)
+        } else {
+            None
+        }
+    })
+}
+
+/// Whether output is rendered for a log rather than a terminal.
+pub fn is_active() -> bool {
+    !crate::tty::supports_vt()
+}
+
+/// A collapsible section of the log, closed when dropped.
+#[derive(Debug)]
+pub struct Group {
+    provider: Option<CiProvider>,
+    id: String,
+}
+
+/// Open a section titled `title`. Without a CI service that supports
+/// sections, a plain `==> title` line is printed instead.
+pub fn group(title: &str) -> Group {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let group = Group {
+        provider: provider(),
+        id: format!("cargo_plugin_{}", COUNTER.fetch_add(1, Ordering::Relaxed)),
+    };
+    eprintln!("{}", group.start_marker(title));
+    group
+}
+
+impl Group {
+    fn start_marker(&self, title: &str) -> String {
+        match self.provider {
+            Some(CiProvider::GitHubActions) => format!("::group::{}", title),
+            Some(CiProvider::GitLab) => format!(
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+                unix_time(),
+                self.id,
+                title
+            ),
+            Some(CiProvider::AzurePipelines) => format!("##[group]{}", title),
+            Some(CiProvider::Other) | None => format!("==> {}", title),
+        }
+    }
+
+    fn end_marker(&self) -> Option<String> {
+        match self.provider {
+            Some(CiProvider::GitHubActions) => Some("::endgroup::".to_string()),
+            Some(CiProvider::GitLab) => Some(format!(
+                "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+                unix_time(),
+                self.id
+            )),
+            Some(CiProvider::AzurePipelines) => Some("##[endgroup]".to_string()),
+            Some(CiProvider::Other) | None => None,
+        }
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        if let Some(marker) = self.end_marker() {
+            eprintln!("{}", marker);
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Streams subprocess output into a section of the log.
+pub(crate) struct CiRenderer {
+    _group: Group,
+    colors: bool,
+}
+
+impl CiRenderer {
+    /// Open a section for `command`.
+    pub(crate) fn new(command: &str) -> Self {
+        Self {
+            _group: group(&format!("Running `{}`", command)),
+            colors: console::colors_enabled_stderr(),
+        }
+    }
+
+    /// Print one output line, with its line ending.
+    pub(crate) fn line(&self, line: &[u8]) {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        if self.colors {
+            eprintln!("{}", text);
+        } else {
+            eprintln!("{}", console::strip_ansi_codes(text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_markers() {
+        let group = |provider| Group {
+            provider,
+            id: "cargo_plugin_7".to_string(),
+        };
+        let github = group(Some(CiProvider::GitHubActions));
+        assert_eq!(github.start_marker("Build"), "::group::Build");
+        assert_eq!(github.end_marker().unwrap(), "::endgroup::");
+        let gitlab = group(Some(CiProvider::GitLab));
+        assert!(
+            gitlab
+                .start_marker("Build")
+                .ends_with(":cargo_plugin_7[collapsed=true]\r\x1b[0KBuild")
+        );
+        assert!(gitlab.end_marker().unwrap().contains("section_end:"));
+        let plain = group(None);
+        assert_eq!(plain.start_marker("Build"), "==> Build");
+        assert_eq!(plain.end_marker(), None);
+    }
+}
@@ -0,0 +1,394 @@
+//! Command-line argument helpers shared by cargo plugins.
+
+use std::path::PathBuf;
+
+use clap::ColorChoice;
+use clap::builder::Styles;
+use clap::builder::styling::{
+    AnsiColor,
+    Effects,
+    Style,
+};
+
+use crate::human::Locale;
+use crate::message::MessageFormat;
+
+/// Arguments most cargo plugins accept, meant to be flattened into the
+/// plugin's own clap parser:
+///
+/// ```no_run
+/// use cargo_plugin_utils::cli::CommonArgs;
+/// use clap::Parser;
+///
+/// #[derive(Parser)]
+/// struct Args {
+///     #[command(flatten)]
+///     common: CommonArgs,
+///     /// Plugin-specific flag
+///     #[arg(long)]
+///     dry_run: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, clap::Args)]
+pub struct CommonArgs {
+    /// Path to Cargo.toml
+    #[arg(long, value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Repository owner (detected from the git remote if omitted)
+    #[arg(long, requires = "repo")]
+    pub owner: Option<String>,
+
+    /// Repository name (detected from the git remote if omitted)
+    #[arg(long, requires = "owner")]
+    pub repo: Option<String>,
+
+    /// Do not print cargo log messages
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Use verbose output (-vv very verbose)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Coloring: auto, always, never
+    #[arg(long, value_name = "WHEN")]
+    pub color: Option<ColorChoice>,
+
+    /// Output format for messages
+    #[arg(long, value_enum, value_name = "FMT", default_value_t)]
+    pub message_format: MessageFormat,
+
+    /// Locale for numbers and dates in reports, e.g. en-US or de
+    #[arg(long, value_name = "LOCALE", default_value = "C")]
+    pub locale: Locale,
+
+    /// Keep temporary directories instead of removing them
+    #[arg(long)]
+    pub keep_temp: bool,
+
+    /// Continue with the remaining steps after a step fails
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Reproduce a run from a snapshot file written by an earlier run
+    #[arg(long, value_name = "PATH")]
+    pub from_snapshot: Option<PathBuf>,
+}
+
+impl CommonArgs {
+    /// Apply the `--color` choice to all terminal output of this process.
+    ///
+    /// Without `--color`, the terminal auto-detection of the `console` crate
+    /// is kept.
+    pub fn apply_color(&self) {
+        match self.color {
+            Some(ColorChoice::Always) => {
+                console::set_colors_enabled(true);
+                console::set_colors_enabled_stderr(true);
+            }
+            Some(ColorChoice::Never) => {
+                console::set_colors_enabled(false);
+                console::set_colors_enabled_stderr(false);
+            }
+            Some(ColorChoice::Auto) | None => {}
+        }
+    }
+}
+
+/// Section headers (`Usage:`, `Options:`), bright green and bold.
+const HEADER: Style = AnsiColor::BrightGreen.on_default().effects(Effects::BOLD);
+/// Literal text such as flags and subcommand names, bright cyan and bold.
+const LITERAL: Style = AnsiColor::BrightCyan.on_default().effects(Effects::BOLD);
+/// Value placeholders such as `<PATH>`, cyan.
+const PLACEHOLDER: Style = AnsiColor::Cyan.on_default();
+/// The `error:` prefix, bright red and bold.
+const ERROR: Style = AnsiColor::BrightRed.on_default().effects(Effects::BOLD);
+/// Values clap suggests, bright cyan and bold.
+const VALID: Style = AnsiColor::BrightCyan.on_default().effects(Effects::BOLD);
+/// Values clap rejects, yellow and bold.
+const INVALID: Style = AnsiColor::Yellow.on_default().effects(Effects::BOLD);
+
+/// Clap styles matching cargo's own `--help` and error output.
+///
+/// ```no_run
+/// use cargo_plugin_utils::cli::cargo_help_style;
+/// use clap::Parser;
+///
+/// #[derive(Parser)]
+/// #[command(styles = cargo_help_style())]
+/// struct Args {}
+/// ```
+pub fn cargo_help_style() -> Styles {
+    Styles::styled()
+        .header(HEADER)
+        .usage(HEADER)
+        .literal(LITERAL)
+        .placeholder(PLACEHOLDER)
+        .error(ERROR)
+        .valid(VALID)
+        .invalid(INVALID)
+}
+
+/// Wrap the plugin's `command` the way cargo invokes it: as subcommand
+/// `name` of a `cargo` command, so usage reads `cargo <name> [OPTIONS]` and
+/// `cargo-<name> <name> ...` (what `cargo <name>` runs) parses. Help uses
+/// [`cargo_help_style`].
+///
+/// ```no_run
+/// use cargo_plugin_utils::cli::plugin_command;
+/// use clap::{
+///     Args,
+///     FromArgMatches,
+/// };
+///
+/// #[derive(Args)]
+/// struct Release {
+///     #[arg(long)]
+///     dry_run: bool,
+/// }
+///
+/// let command = plugin_command(
+///     "release",
+///     Release::augment_args(clap::Command::new("release")),
+/// );
+/// let matches = command.get_matches();
+/// let args = Release::from_arg_matches(matches.subcommand_matches("release").unwrap())?;
+/// # Ok::<(), clap::Error>(())
+/// ```
+pub fn plugin_command(name: &'static str, command: clap::Command) -> clap::Command {
+    clap::Command::new("cargo")
+        .bin_name("cargo")
+        .styles(cargo_help_style())
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(command.name(name))
+}
+
+/// The visible subcommands of `command` as a list like `cargo --list`
+/// prints, headed by `heading`:
+///
+/// ```text
+/// Commands:
+///     build                Build the thing
+///     check                Check the thing
+/// ```
+pub fn subcommand_list(command: &clap::Command, heading: &str) -> String {
+    let mut list = format!("{}{}:{}", HEADER.render(), heading, HEADER.render_reset());
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let about = subcommand
+            .get_about()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let name = subcommand.get_name();
+        list.push_str(&format!(
+            "\n    {}{}{}{} {}",
+            LITERAL.render(),
+            name,
+            LITERAL.render_reset(),
+            " ".repeat(20usize.saturating_sub(name.len())),
+            about
+        ));
+    }
+    list.trim_end().to_string()
+}
+
+/// Cargo's error for a subcommand `name` the plugin doesn't have, suggesting
+/// the closest of `known` and listing them all:
+///
+/// ```text
+/// no such command: `biuld`
+///
+///         Did you mean `build`?
+///
+/// Available commands:
+///     build
+///     check
+/// ```
+pub fn unknown_subcommand(name: &str, known: &[&str]) -> anyhow::Error {
+    let mut message = format!("no such command: `{}`", name);
+    if let Some(suggestion) = did_you_mean(name, known.iter().copied()) {
+        message.push_str(&format!("\n\n\tDid you mean `{}`?", suggestion));
+    }
+    if !known.is_empty() {
+        message.push_str("\n\nAvailable commands:");
+        for command in known {
+            message.push_str(&format!("\n    {}", command));
+        }
+    }
+    anyhow::anyhow!(message)
+}
+
+/// The candidate closest to `input` if it is close enough to be a likely
+/// typo: at most one edit per three characters, as cargo allows.
+pub fn did_you_mean<'a>(
+    input: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let limit = input.chars().count().max(3) / 3;
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(input, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance between `left` and `right` in characters, counting a swap
+/// of two adjacent characters as one edit (like rustc's and cargo's
+/// suggestions).
+fn edit_distance(left: &str, right: &str) -> usize {
+    let left: Vec<char> = left.chars().collect();
+    let right: Vec<char> = right.chars().collect();
+    // distances[row][column]: distance between the first `row` characters of
+    // `left` and the first `column` characters of `right`
+    let mut distances = vec![vec![0; right.len() + 1]; left.len() + 1];
+    for (row, distance) in distances.iter_mut().enumerate() {
+        distance[0] = row;
+    }
+    for (column, distance) in distances[0].iter_mut().enumerate() {
+        *distance = column;
+    }
+    for row in 1..=left.len() {
+        for column in 1..=right.len() {
+            let cost = usize::from(left[row - 1] != right[column - 1]);
+            let mut distance = (distances[row - 1][column] + 1)
+                .min(distances[row][column - 1] + 1)
+                .min(distances[row - 1][column - 1] + cost);
+            let swapped = row > 1
+                && column > 1
+                && left[row - 1] == right[column - 2]
+                && left[row - 2] == right[column - 1];
+            if swapped {
+                distance = distance.min(distances[row - 2][column - 2] + 1);
+            }
+            distances[row][column] = distance;
+        }
+    }
+    distances[left.len()][right.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        common: CommonArgs,
+    }
+
+    #[test]
+    fn test_common_args_defaults() {
+        let cli = TestCli::try_parse_from(["cargo-test"]).unwrap();
+        assert_eq!(cli.common, CommonArgs::default());
+    }
+
+    #[test]
+    fn test_common_args_parse() {
+        let cli = TestCli::try_parse_from([
+            "cargo-test",
+            "--manifest-path",
+            "crates/core/Cargo.toml",
+            "-vv",
+            "--color",
+            "never",
+            "--owner",
+            "acme",
+            "--repo",
+            "widgets",
+            "--keep-temp",
+            "--keep-going",
+            "--message-format",
+            "json",
+            "--locale",
+            "de_DE.UTF-8",
+            "--from-snapshot",
+            "run.json",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.common.manifest_path,
+            Some(PathBuf::from("crates/core/Cargo.toml"))
+        );
+        assert_eq!(cli.common.verbose, 2);
+        assert_eq!(cli.common.color, Some(ColorChoice::Never));
+        assert_eq!(cli.common.owner.as_deref(), Some("acme"));
+        assert_eq!(cli.common.repo.as_deref(), Some("widgets"));
+        assert!(cli.common.keep_temp);
+        assert!(cli.common.keep_going);
+        assert_eq!(cli.common.message_format, MessageFormat::Json);
+        assert_eq!(cli.common.locale, Locale::DE);
+        assert_eq!(cli.common.from_snapshot, Some(PathBuf::from("run.json")));
+    }
+
+    #[test]
+    fn test_common_args_owner_requires_repo() {
+        assert!(TestCli::try_parse_from(["cargo-test", "--owner", "acme"]).is_err());
+        assert!(TestCli::try_parse_from(["cargo-test", "-q", "-v"]).is_err());
+    }
+
+    fn example_command() -> clap::Command {
+        plugin_command(
+            "release",
+            clap::Command::new("release")
+                .about("Publish a release")
+                .subcommand(clap::Command::new("prepare").about("Bump versions"))
+                .subcommand(clap::Command::new("internal").hide(true))
+                .arg(clap::Arg::new("path").long("path").value_name("PATH")),
+        )
+    }
+
+    #[test]
+    fn test_plugin_command_usage() {
+        let mut command = example_command();
+        command.build();
+        let help = command
+            .find_subcommand_mut("release")
+            .unwrap()
+            .render_help()
+            .ansi()
+            .to_string();
+        assert!(help.contains("\x1b[1m\x1b[92mUsage:\x1b[0m \x1b[1m\x1b[96mcargo release"));
+        assert!(help.contains("\x1b[36m<PATH>"));
+
+        let matches = example_command()
+            .try_get_matches_from(["cargo-release", "release", "--path", "x"])
+            .unwrap();
+        let release = matches.subcommand_matches("release").unwrap();
+        assert_eq!(release.get_one::<String>("path").unwrap(), "x");
+    }
+
+    #[test]
+    fn test_subcommand_list() {
+        let command = example_command();
+        let list = subcommand_list(command.find_subcommand("release").unwrap(), "Commands");
+        let list = console::strip_ansi_codes(&list).into_owned();
+        assert_eq!(list, "Commands:\n    prepare              Bump versions");
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let known = ["build", "check", "publish"];
+        assert_eq!(edit_distance("biuld", "build"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(did_you_mean("biuld", known), Some("build"));
+        assert_eq!(did_you_mean("chek", known), Some("check"));
+        assert_eq!(did_you_mean("xyz", known), None);
+        assert_eq!(did_you_mean("", known), None);
+    }
+
+    #[test]
+    fn test_unknown_subcommand() {
+        let err = unknown_subcommand("publsh", &["build", "publish"]);
+        assert_eq!(
+            err.to_string(),
+            "no such command: `publsh`\n\n\tDid you mean `publish`?\n\nAvailable \
+             commands:\n    build\n    publish"
+        );
+        let err = unknown_subcommand("zzz", &[]);
+        assert_eq!(err.to_string(), "no such command: `zzz`");
+    }
+}
@@ -0,0 +1,529 @@
+//! Commands as plain data.
+//!
+//! The `run_subprocess*` functions take anything that is [`IntoCommand`]: a
+//! closure building a [`CommandBuilder`], or a [`CommandSpec`]. A spec can be
+//! built declaratively, printed, stored in a configuration file and run again
+//! (for a retry or after a dry run) without a builder closure:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::logger::run_subprocess;
+//! use cargo_plugin_utils::{
+//!     CommandSpec,
+//!     Logger,
+//! };
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let spec = CommandSpec::new("cargo")
+//!     .args(["build", "--release"])
+//!     .env("CARGO_TERM_COLOR", "always");
+//! println!("Would run `{}`", spec);
+//! let mut logger = Logger::new();
+//! let output = run_subprocess(&mut logger, &spec, None).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use portable_pty::CommandBuilder;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// A program with its arguments, working directory and environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandSpec {
+    /// Program to run, looked up on `PATH` unless it is a path
+    pub program: String,
+    /// Arguments, without the program
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Working directory; the current one if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Environment variables set on top of the inherited environment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CommandSpec {
+    /// Run `program` without arguments.
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run in `dir`.
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// Set an environment variable.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// The command to spawn.
+    pub fn to_command(&self) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new(&self.program);
+        cmd.args(&self.args);
+        if let Some(cwd) = &self.cwd {
+            cmd.cwd(cwd);
+        }
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        cmd
+    }
+}
+
+impl std::fmt::Display for CommandSpec {
+    /// Formats as a [shell-quoted](crate::logger::shell_quote) command line,
+    /// without the directory and environment.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let argv = std::iter::once(&self.program).chain(&self.args);
+        let quoted: Vec<_> = argv.map(|arg| crate::logger::shell_quote(arg)).collect();
+        write!(f, "{}", quoted.join(" "))
+    }
+}
+
+/// A shell to run a command line with, see
+/// [`run_shell`](crate::logger::run_shell).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    /// `sh -c`
+    Sh,
+    /// `cmd /S /C`, for scripts without double quotes (see
+    /// [`command`](Self::command))
+    Cmd,
+    /// `powershell -NoLogo -NoProfile -NonInteractive -Command`
+    PowerShell,
+}
+
+impl Default for Shell {
+    /// `sh` on Unix, PowerShell on Windows, which receives the script
+    /// intact whatever quotes it contains.
+    fn default() -> Self {
+        if cfg!(windows) {
+            Self::PowerShell
+        } else {
+            Self::Sh
+        }
+    }
+}
+
+impl Shell {
+    /// The command running `script` in this shell.
+    ///
+    /// The script is passed as a single argument, so it needs no quoting for
+    /// `sh` and PowerShell. `cmd` doesn't understand the escaped double
+    /// quotes Windows programs receive their arguments with; use PowerShell
+    /// for scripts that contain double quotes.
+    pub fn command(self, script: &str) -> CommandBuilder {
+        let argv: &[&str] = match self {
+            Self::Sh => &["sh", "-c"],
+            Self::Cmd => &["cmd", "/S", "/C"],
+            Self::PowerShell => &[
+                "powershell",
+                "-NoLogo",
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+            ],
+        };
+        let mut cmd = CommandBuilder::from_argv(argv.iter().map(Into::into).collect());
+        cmd.arg(script);
+        cmd
+    }
+}
+
+/// The environment a child starts from, see
+/// [`RunOptions::env_preset`](crate::RunOptions::env_preset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvPreset {
+    /// The plugin's whole environment
+    #[default]
+    InheritAll,
+    /// Only `PATH`, `HOME` and `TERM` (and on Windows `SystemRoot`,
+    /// `USERPROFILE`, `TEMP` and `TMP`, without which many programs fail)
+    Minimal,
+    /// The plugin's environment without the `CARGO_*`, `__CARGO_*` and
+    /// `RUSTUP_*` variables cargo and rustup set for the plugin, which make
+    /// a nested cargo build the plugin's package or use its toolchain;
+    /// `CARGO_HOME` and `RUSTUP_HOME` are kept
+    StripCargo,
+}
+
+impl EnvPreset {
+    /// Variables [`Minimal`](Self::Minimal) keeps.
+    const MINIMAL: &[&str] = if cfg!(windows) {
+        &[
+            "PATH",
+            "HOME",
+            "TERM",
+            "SystemRoot",
+            "USERPROFILE",
+            "TEMP",
+            "TMP",
+        ]
+    } else {
+        &["PATH", "HOME", "TERM"]
+    };
+
+    /// Reduce the inherited environment of `cmd` to the preset. Variables
+    /// set on `cmd` explicitly (with [`CommandBuilder::env`]) are kept.
+    pub fn apply(self, cmd: &mut CommandBuilder) {
+        let explicit = explicit_env(cmd);
+        match self {
+            Self::InheritAll => {}
+            Self::Minimal => {
+                let kept: Vec<_> = Self::MINIMAL
+                    .iter()
+                    .filter_map(|key| Some((*key, cmd.get_env(key)?.to_owned())))
+                    .collect();
+                cmd.env_clear();
+                for (key, value) in kept {
+                    cmd.env(key, value);
+                }
+            }
+            Self::StripCargo => {
+                let stripped: Vec<_> = cmd
+                    .iter_full_env_as_str()
+                    .map(|(key, _)| key.to_string())
+                    .filter(|key| is_cargo_override(key))
+                    .collect();
+                for key in stripped {
+                    cmd.env_remove(key);
+                }
+            }
+        }
+        for (key, value) in explicit {
+            cmd.env(key, value);
+        }
+    }
+}
+
+/// The variables set on `cmd` explicitly, as opposed to inherited.
+pub(crate) fn explicit_env(cmd: &CommandBuilder) -> Vec<(String, String)> {
+    cmd.iter_extra_env_as_str()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Whether [`EnvPreset::StripCargo`] removes `key`.
+fn is_cargo_override(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    let prefixed = ["CARGO_", "__CARGO_", "RUSTUP_"]
+        .iter()
+        .any(|prefix| key.starts_with(prefix));
+    prefixed && key != "CARGO_HOME" && key != "RUSTUP_HOME"
+}
+
+/// Something the `run_subprocess*` functions can turn into a command.
+pub trait IntoCommand {
+    /// Build the command.
+    fn into_command(self) -> CommandBuilder;
+}
+
+impl<F> IntoCommand for F
+where
+    F: FnOnce() -> CommandBuilder,
+{
+    fn into_command(self) -> CommandBuilder {
+        self()
+    }
+}
+
+impl IntoCommand for CommandSpec {
+    fn into_command(self) -> CommandBuilder {
+        self.to_command()
+    }
+}
+
+impl IntoCommand for &CommandSpec {
+    fn into_command(self) -> CommandBuilder {
+        self.to_command()
+    }
+}
+
+/// A program that couldn't be started because it doesn't exist.
+///
+/// Returned (inside the [`anyhow::Error`]) by the `run_subprocess*`
+/// functions in place of the bare spawn error, so plugins can
+/// [downcast](anyhow::Error::downcast_ref) it and react, e.g. by skipping an
+/// optional step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandNotFound {
+    /// The program as given to the command
+    pub program: String,
+    /// Whether the program was looked up on `PATH`, rather than given as a
+    /// path
+    pub searched_path: bool,
+    /// Suggested fix, from [`RunOptions::not_found_hint`] or a built-in one
+    /// for rustup components and cargo subcommands
+    ///
+    /// [`RunOptions::not_found_hint`]: crate::RunOptions::not_found_hint
+    pub hint: Option<String>,
+}
+
+impl std::fmt::Display for CommandNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.searched_path {
+            write!(f, "`{}` not found on PATH", self.program)?;
+        } else {
+            write!(f, "`{}` does not exist", self.program)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n\nhelp: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandNotFound {}
+
+/// Turn the error of spawning `cmd` into a [`CommandNotFound`] if its
+/// program doesn't exist, otherwise return it unchanged.
+pub(crate) fn spawn_error(
+    cmd: &CommandBuilder,
+    hint: Option<&str>,
+    err: anyhow::Error,
+) -> anyhow::Error {
+    let Some(program) = cmd.get_argv().first() else {
+        return err;
+    };
+    let program = Path::new(program);
+    let searched_path = program.components().count() == 1;
+    let found = if searched_path {
+        find_on_path(cmd, program).is_some()
+    } else {
+        match cmd.get_cwd() {
+            Some(cwd) => Path::new(cwd).join(program).exists(),
+            None => program.exists(),
+        }
+    };
+    if found {
+        return err;
+    }
+    let name = program.to_string_lossy().into_owned();
+    anyhow::Error::new(CommandNotFound {
+        hint: hint
+            .map(str::to_string)
+            .or_else(|| default_hint(&name).filter(|_| searched_path)),
+        program: name,
+        searched_path,
+    })
+}
+
+/// Look `program` up on the `PATH` of `cmd`, or the plugin's own `PATH`.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn find_on_path(cmd: &CommandBuilder, program: &Path) -> Option<PathBuf> {
+    let path = match cmd.get_env("PATH") {
+        Some(path) => path.to_os_string(),
+        None => std::env::var_os("PATH")?,
+    };
+    let extensions: Vec<String> = if cfg!(windows) && program.extension().is_none() {
+        std::env::var("PATHEXT")
+            .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string())
+            .split(';')
+            .map(str::to_string)
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    std::env::split_paths(&path).find_map(|dir| {
+        extensions.iter().find_map(|extension| {
+            let mut candidate = dir.join(program).into_os_string();
+            candidate.push(extension);
+            let candidate = PathBuf::from(candidate);
+            candidate.is_file().then_some(candidate)
+        })
+    })
+}
+
+/// How to install well-known programs.
+fn default_hint(program: &str) -> Option<String> {
+    let stem = program.strip_suffix(".exe").unwrap_or(program);
+    let component = match stem {
+        "rustfmt" | "cargo-fmt" => Some("rustfmt"),
+        "cargo-clippy" | "clippy-driver" => Some("clippy"),
+        "rust-analyzer" => Some("rust-analyzer"),
+        _ if stem.starts_with("llvm-") => Some("llvm-tools"),
+        _ => None,
+    };
+    if let Some(component) = component {
+        return Some(format!(
+            "install it with `rustup component add {}`",
+            component
+        ));
+    }
+    stem.starts_with("cargo-")
+        .then(|| format!("install it with `cargo install {}`", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_round_trip() {
+        let spec = CommandSpec::new("cargo")
+            .args(["build", "--features", "a b"])
+            .cwd("/work")
+            .env("RUSTFLAGS", "-Dwarnings");
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["env"]["RUSTFLAGS"], "-Dwarnings");
+        assert_eq!(serde_json::from_value::<CommandSpec>(json).unwrap(), spec);
+
+        // Only the program is required
+        let minimal: CommandSpec = serde_json::from_str(r#"{"program": "ls"}"#).unwrap();
+        assert_eq!(minimal, CommandSpec::new("ls"));
+
+        let cmd = spec.clone().into_command();
+        assert_eq!(cmd.get_argv().len(), 4);
+        assert_eq!(cmd.get_cwd(), Some(&"/work".into()));
+        assert_eq!(cmd.get_env("RUSTFLAGS"), Some("-Dwarnings".as_ref()));
+        #[cfg(unix)]
+        assert_eq!(spec.to_string(), "cargo build --features 'a b'");
+    }
+
+    #[test]
+    fn test_shell_command() {
+        let expected = if cfg!(windows) {
+            Shell::PowerShell
+        } else {
+            Shell::Sh
+        };
+        assert_eq!(Shell::default(), expected);
+        let script = r#"echo "a b" | tr a-z A-Z"#;
+        for shell in [Shell::Sh, Shell::Cmd, Shell::PowerShell] {
+            let cmd = shell.command(script);
+            assert_eq!(cmd.get_argv().last().unwrap(), script);
+        }
+    }
+
+    #[test]
+    fn test_env_preset() {
+        assert!(is_cargo_override("CARGO_PKG_NAME"));
+        assert!(is_cargo_override("__CARGO_FIX_PLZ"));
+        assert!(is_cargo_override("RUSTUP_TOOLCHAIN"));
+        assert!(!is_cargo_override("CARGO_HOME"));
+        assert!(!is_cargo_override("RUSTUP_HOME"));
+        assert!(!is_cargo_override("LANG"));
+
+        // `cargo test` runs tests with `CARGO_*` variables inherited, while
+        // the spec's own variables are explicit
+        let spec = CommandSpec::new("cargo")
+            .env("CARGO_TERM_COLOR", "always")
+            .env("LANG", "C");
+        let keys = |preset: EnvPreset| {
+            let mut cmd = spec.to_command();
+            preset.apply(&mut cmd);
+            let mut keys: Vec<_> = cmd
+                .iter_full_env_as_str()
+                .map(|(key, _)| key.to_string())
+                .collect();
+            keys.sort();
+            keys
+        };
+        assert!(keys(EnvPreset::InheritAll).contains(&"CARGO_PKG_NAME".to_string()));
+        let minimal = keys(EnvPreset::Minimal);
+        assert!(minimal.contains(&"PATH".to_string()));
+        assert!(minimal.contains(&"CARGO_TERM_COLOR".to_string()));
+        assert!(
+            minimal
+                .iter()
+                .all(|key| EnvPreset::MINIMAL.contains(&key.as_str())
+                    || key == "CARGO_TERM_COLOR"
+                    || key == "LANG"),
+            "{:?}",
+            minimal
+        );
+        let stripped = keys(EnvPreset::StripCargo);
+        assert!(stripped.contains(&"CARGO_TERM_COLOR".to_string()));
+        assert!(!stripped.contains(&"CARGO_PKG_NAME".to_string()));
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_spec() {
+        let spec = CommandSpec::new("sh")
+            .args(["-c", "echo \"$GREETING\""])
+            .env("GREETING", "hello");
+        let mut logger = crate::Logger::new();
+        let output = crate::logger::run_subprocess_piped(&mut logger, &spec, None)
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+        // The spec is still there for a second run
+        let output = crate::logger::run_subprocess_piped(&mut logger, spec, None)
+            .await
+            .unwrap();
+        assert!(output.success());
+    }
+
+    #[tokio::test]
+    async fn test_command_not_found() {
+        let mut logger = crate::Logger::new();
+        let options = crate::RunOptions::new()
+            .piped(true)
+            .not_found_hint("install `gh` or pass --no-github");
+        let err = crate::logger::run_subprocess_with_options(
+            &mut logger,
+            CommandSpec::new("gh-does-not-exist"),
+            &options,
+        )
+        .await
+        .unwrap_err();
+        let not_found = err.downcast_ref::<CommandNotFound>().unwrap();
+        assert!(not_found.searched_path);
+        assert_eq!(
+            err.to_string(),
+            "`gh-does-not-exist` not found on PATH\n\nhelp: install `gh` or pass --no-github"
+        );
+
+        let err = crate::logger::run_subprocess(
+            &mut logger,
+            CommandSpec::new("./tools/cargo-missing"),
+            None,
+        )
+        .await
+        .unwrap_err();
+        let not_found = err.downcast_ref::<CommandNotFound>().unwrap();
+        assert!(!not_found.searched_path);
+        assert_eq!(not_found.hint, None);
+        assert_eq!(
+            default_hint("cargo-missing"),
+            Some("install it with `cargo install cargo-missing`".to_string())
+        );
+        assert_eq!(
+            default_hint("llvm-cov"),
+            Some("install it with `rustup component add llvm-tools`".to_string())
+        );
+    }
+}
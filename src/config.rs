@@ -0,0 +1,526 @@
+//! Plugin settings merged from every place a user may put them.
+//!
+//! A [`ConfigLoader`] reads a plugin's settings from, highest precedence
+//! first:
+//!
+//! 1. Overrides from the command line, [`set`](ConfigLoader::set) by the plugin
+//! 2. Environment variables with the plugin's prefix, `MY_PLUGIN_LEVEL=3`
+//! 3. `[workspace.metadata.my-plugin]` in the workspace root manifest
+//! 4. `[package.metadata.my-plugin]` in the current package's manifest
+//! 5. A `my-plugin.toml` file in the workspace root, if there is one
+//!
+//! Tables are merged key by key, so a workspace can set one key and a
+//! package another. The result is deserialized into the plugin's type, and
+//! [`Config::source`] tells where each key came from:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::config::ConfigLoader;
+//!
+//! #[derive(serde::Deserialize)]
+//! struct Settings {
+//!     #[serde(default)]
+//!     level: u32,
+//! }
+//!
+//! # fn example(level: Option<u32>) -> anyhow::Result<()> {
+//! let metadata = cargo_plugin_utils::get_metadata(None)?;
+//! let package = cargo_plugin_utils::find_package_in(&metadata)?;
+//! let mut loader = ConfigLoader::new("my-plugin");
+//! if let Some(level) = level {
+//!     loader = loader.set("level", level);
+//! }
+//! let config = loader.load::<Settings>(&metadata, Some(&package))?;
+//! if let Some(source) = config.source("level") {
+//!     eprintln!("level {} from {}", config.value.level, source);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Environment variable names map to keys by dropping the prefix and
+//! lowercasing, with `__` separating nested tables: `MY_PLUGIN_MAX_JOBS` sets
+//! `max_jobs`, `MY_PLUGIN_LINT__LEVEL` sets `level` in the `lint` table.
+//! Values are read as TOML values where they parse as one (`3`, `true`,
+//! `["a", "b"]`) and as strings otherwise, or when the plugin's type wants a
+//! string there. `MY_PLUGIN_ANSWERS` and `MY_PLUGIN_ANSWER_*` are left to
+//! [`answers`](crate::answers).
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde_json::{
+    Map,
+    Value,
+};
+
+/// Where a setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Set by the plugin from its command line
+    Cli,
+    /// The environment variable of this name
+    Env(String),
+    /// `[workspace.metadata.<plugin>]` in this manifest
+    Workspace(PathBuf),
+    /// `[package.metadata.<plugin>]` in this manifest
+    Package(PathBuf),
+    /// This configuration file
+    File(PathBuf),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cli => write!(f, "the command line"),
+            Self::Env(name) => write!(f, "environment variable {}", name),
+            Self::Workspace(path) => write!(f, "[workspace.metadata] in {}", path.display()),
+            Self::Package(path) => write!(f, "[package.metadata] in {}", path.display()),
+            Self::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Settings loaded by [`ConfigLoader::load`], with where each came from.
+#[derive(Debug, Clone)]
+pub struct Config<T> {
+    /// The merged settings
+    pub value: T,
+    /// Source of each set key, by dotted path
+    sources: BTreeMap<String, Source>,
+}
+
+impl<T> Config<T> {
+    /// Where the key at the dotted `path` (`level`, `lint.level`) was set;
+    /// `None` if no source set it and it has its default.
+    pub fn source(&self, path: &str) -> Option<&Source> {
+        self.sources.get(path)
+    }
+
+    /// Every set key with its source, sorted by path.
+    pub fn sources(&self) -> impl Iterator<Item = (&str, &Source)> {
+        self.sources
+            .iter()
+            .map(|(path, source)| (path.as_str(), source))
+    }
+}
+
+/// Loads a plugin's settings from all sources, see the [module
+/// documentation](self).
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    plugin: String,
+    env_prefix: String,
+    file: Option<PathBuf>,
+    overrides: Vec<(String, Value)>,
+}
+
+impl ConfigLoader {
+    /// A loader for the plugin `plugin`, the key of its metadata tables.
+    ///
+    /// The environment prefix defaults to the plugin name in upper case with
+    /// `_` for `-`, plus `_`: `MY_PLUGIN_` for `my-plugin`.
+    pub fn new(plugin: impl Into<String>) -> Self {
+        let plugin = plugin.into();
+        let env_prefix = format!("{}_", plugin.to_uppercase().replace('-', "_"));
+        Self {
+            plugin,
+            env_prefix,
+            file: None,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Read environment variables starting with `prefix` instead.
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = prefix.into();
+        self
+    }
+
+    /// Read the configuration file at `path` instead of `<plugin>.toml` in
+    /// the workspace root. A relative path is relative to the workspace
+    /// root; a missing file is skipped either way.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Override the key at the dotted `path` with `value`, taking
+    /// precedence over every other source.
+    pub fn set(mut self, path: &str, value: impl Into<Value>) -> Self {
+        self.overrides.push((path.to_string(), value.into()));
+        self
+    }
+
+    /// Merge the sources for the workspace of `metadata` and, if given, the
+    /// manifest of `package`, and deserialize the result.
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    pub fn load<T: serde::de::DeserializeOwned>(
+        &self,
+        metadata: &cargo_metadata::Metadata,
+        package: Option<&cargo_metadata::Package>,
+    ) -> Result<Config<T>> {
+        self.load_with_env(metadata, package, std::env::vars())
+    }
+
+    fn load_with_env<T: serde::de::DeserializeOwned>(
+        &self,
+        metadata: &cargo_metadata::Metadata,
+        package: Option<&cargo_metadata::Package>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config<T>> {
+        let root = metadata.workspace_root.as_std_path();
+        let mut layers = Vec::new();
+        let file = root.join(
+            self.file
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("{}.toml", self.plugin))),
+        );
+        if let Some(table) = read_file(&file)? {
+            layers.push((table, Source::File(file)));
+        }
+        if let Some(package) = package
+            && let Some(table) = package.metadata.get(&self.plugin)
+        {
+            let manifest = package.manifest_path.clone().into_std_path_buf();
+            layers.push((table.clone(), Source::Package(manifest)));
+        }
+        if let Some(table) = metadata.workspace_metadata.get(&self.plugin) {
+            layers.push((table.clone(), Source::Workspace(root.join("Cargo.toml"))));
+        }
+
+        let mut vars: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.len() > self.env_prefix.len())
+            .filter(|(name, _)| name.starts_with(&self.env_prefix))
+            .filter(|(name, _)| !is_reserved(&name[self.env_prefix.len()..]))
+            .collect();
+        vars.sort();
+        let env: Vec<(String, String, String)> = vars
+            .into_iter()
+            .map(|(name, value)| {
+                let path = name[self.env_prefix.len()..]
+                    .to_lowercase()
+                    .replace("__", ".");
+                (path, name, value)
+            })
+            .collect();
+
+        // Environment values are typed where they parse as TOML; one the
+        // plugin's type rejects is taken as the string it was written as
+        let mut as_string = BTreeSet::new();
+        loop {
+            let mut merged = Merged::default();
+            for (table, source) in &layers {
+                merged.merge(table.clone(), source);
+            }
+            for (path, name, value) in &env {
+                let value = if as_string.contains(name) {
+                    Value::String(value.clone())
+                } else {
+                    env_value(value)
+                };
+                merged.merge(nested(path, value), &Source::Env(name.clone()));
+            }
+            for (path, value) in &self.overrides {
+                merged.merge(nested(path, value.clone()), &Source::Cli);
+            }
+
+            match serde_path_to_error::deserialize(&merged.value) {
+                Ok(value) => {
+                    return Ok(Config {
+                        value,
+                        sources: merged.sources,
+                    });
+                }
+                Err(err) => {
+                    let retry = match merged.sources.get(&err.path().to_string()) {
+                        Some(Source::Env(name)) => as_string.insert(name.clone()),
+                        _ => false,
+                    };
+                    if !retry {
+                        return Err(err.into_inner())
+                            .with_context(|| format!("Invalid {} configuration", self.plugin));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether an environment variable (without the plugin's prefix) belongs
+/// to [`Answers`](crate::answers::Answers) rather than the configuration.
+fn is_reserved(name: &str) -> bool {
+    name == "ANSWERS" || name.starts_with("ANSWER_")
+}
+
+/// The settings merged so far.
+#[derive(Debug)]
+struct Merged {
+    value: Value,
+    sources: BTreeMap<String, Source>,
+}
+
+impl Default for Merged {
+    fn default() -> Self {
+        Self {
+            value: Value::Object(Map::new()),
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+impl Merged {
+    /// Merge `layer` over the settings so far.
+    fn merge(&mut self, layer: Value, source: &Source) {
+        merge_into(&mut self.value, layer, source, "", &mut self.sources);
+    }
+}
+
+fn merge_into(
+    target: &mut Value,
+    layer: Value,
+    source: &Source,
+    prefix: &str,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    let (Value::Object(target), Value::Object(layer)) = (&mut *target, &layer) else {
+        // A value replaces whatever was below its path
+        sources.retain(|path, _| !is_below(path, prefix));
+        if !prefix.is_empty() {
+            sources.insert(prefix.to_string(), source.clone());
+        }
+        *target = layer;
+        return;
+    };
+    for (key, value) in layer {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        let slot = target.entry(key.clone()).or_insert(Value::Null);
+        if !slot.is_object() && value.is_object() {
+            *slot = Value::Object(Map::new());
+            sources.remove(&path);
+        }
+        merge_into(slot, value.clone(), source, &path, sources);
+    }
+}
+
+/// Whether the dotted `path` is `prefix` or below it.
+fn is_below(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// `value` at the dotted `path` of otherwise empty tables.
+fn nested(path: &str, value: Value) -> Value {
+    path.rsplit('.').fold(value, |value, key| {
+        let mut table = Map::new();
+        table.insert(key.to_string(), value);
+        Value::Object(table)
+    })
+}
+
+/// An environment variable's value: a TOML value if it parses as one, the
+/// string otherwise.
+fn env_value(value: &str) -> Value {
+    match value.parse::<toml_edit::Value>() {
+        Ok(parsed) => toml_value(&parsed),
+        Err(_) => Value::String(value.to_string()),
+    }
+}
+
+/// The settings of a TOML file, `None` if it doesn't exist.
+fn read_file(path: &Path) -> Result<Option<Value>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let document: toml_edit::DocumentMut = text
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(toml_item(document.as_item())))
+}
+
+fn toml_item(item: &toml_edit::Item) -> Value {
+    match item {
+        toml_edit::Item::None => Value::Null,
+        toml_edit::Item::Value(value) => toml_value(value),
+        toml_edit::Item::Table(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, item)| (key.to_string(), toml_item(item)))
+                .collect(),
+        ),
+        toml_edit::Item::ArrayOfTables(tables) => Value::Array(
+            tables
+                .iter()
+                .map(|table| toml_item(&toml_edit::Item::Table(table.clone())))
+                .collect(),
+        ),
+    }
+}
+
+fn toml_value(value: &toml_edit::Value) -> Value {
+    match value {
+        toml_edit::Value::String(text) => Value::String(text.value().clone()),
+        toml_edit::Value::Integer(number) => Value::from(*number.value()),
+        toml_edit::Value::Float(number) => {
+            serde_json::Number::from_f64(*number.value()).map_or(Value::Null, Value::Number)
+        }
+        toml_edit::Value::Boolean(flag) => Value::Bool(*flag.value()),
+        toml_edit::Value::Datetime(datetime) => Value::String(datetime.value().to_string()),
+        toml_edit::Value::Array(array) => Value::Array(array.iter().map(toml_value).collect()),
+        toml_edit::Value::InlineTable(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_string(), toml_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Settings {
+        level: u32,
+        name: String,
+        tags: Vec<String>,
+        lint: Lint,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Lint {
+        strict: bool,
+        deny: Vec<String>,
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+             [package.metadata.my-plugin]\nname = \"package\"\nlint = { deny = [\"warnings\"] }\n\n\
+             [workspace]\n\n\
+             [workspace.metadata.my-plugin]\nname = \"workspace\"\nlevel = 1\n",
+        )
+        .unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/lib.rs"), "").unwrap();
+        std::fs::write(
+            root.join("my-plugin.toml"),
+            "level = 0\nname = \"file\"\ntags = [\"a\"]\n\n[lint]\nstrict = false\n",
+        )
+        .unwrap();
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(root.join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .unwrap();
+        let package = &metadata.packages[0];
+        let vars = [
+            ("MY_PLUGIN_LEVEL", "2"),
+            ("MY_PLUGIN_LINT__STRICT", "true"),
+            ("MY_PLUGIN_ANSWERS", "{}"),
+            ("MY_PLUGIN_ANSWER_PUBLISH", "yes"),
+            ("OTHER_LEVEL", "9"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        let config: Config<Settings> = ConfigLoader::new("my-plugin")
+            .set("level", 3)
+            .load_with_env(&metadata, Some(package), vars.clone())
+            .unwrap();
+        assert_eq!(
+            config.value,
+            Settings {
+                level: 3,
+                name: "workspace".to_string(),
+                tags: vec!["a".to_string()],
+                lint: Lint {
+                    strict: true,
+                    deny: vec!["warnings".to_string()],
+                },
+            }
+        );
+        let manifest = metadata
+            .workspace_root
+            .join("Cargo.toml")
+            .into_std_path_buf();
+        assert_eq!(config.source("level"), Some(&Source::Cli));
+        assert_eq!(
+            config.source("name"),
+            Some(&Source::Workspace(manifest.clone()))
+        );
+        assert_eq!(config.source("lint.deny"), Some(&Source::Package(manifest)));
+        assert_eq!(
+            config.source("lint.strict"),
+            Some(&Source::Env("MY_PLUGIN_LINT__STRICT".to_string()))
+        );
+        assert_eq!(
+            config.source("tags"),
+            Some(&Source::File(
+                metadata.workspace_root.join("my-plugin.toml").into()
+            ))
+        );
+        assert_eq!(config.sources().count(), 5);
+
+        let err = ConfigLoader::new("my-plugin")
+            .set("level", "high")
+            .load_with_env::<Settings>(&metadata, Some(package), vars)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Invalid my-plugin configuration")
+        );
+
+        // A value that parses as TOML but should be a string
+        let vars = [("MY_PLUGIN_NAME", "1.0"), ("MY_PLUGIN_LEVEL", "4")]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        let config: Config<Settings> = ConfigLoader::new("my-plugin")
+            .load_with_env(&metadata, Some(package), vars)
+            .unwrap();
+        assert_eq!(config.value.name, "1.0");
+        assert_eq!(config.value.level, 4);
+    }
+
+    #[test]
+    fn test_merge_replaces_tables() {
+        let mut merged = Merged::default();
+        merged.merge(
+            serde_json::json!({"lint": {"level": 1, "deny": []}}),
+            &Source::Cli,
+        );
+        merged.merge(
+            nested("lint", Value::String("off".to_string())),
+            &Source::Env("P_LINT".to_string()),
+        );
+        assert_eq!(merged.value, serde_json::json!({"lint": "off"}));
+        let paths: Vec<&str> = merged.sources.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["lint"]);
+        assert_eq!(env_value("[1, 2]"), serde_json::json!([1, 2]));
+        assert_eq!(env_value("not toml"), Value::String("not toml".to_string()));
+    }
+}
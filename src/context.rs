@@ -0,0 +1,608 @@
+//! A single context object for plugin commands.
+//!
+//! [`PluginContext`] bundles the parsed [`CommonArgs`] with everything that is
+//! derived from them (workspace metadata and graph, the current package, the
+//! repository identity, the plugin's configuration, the logger and a
+//! cancellation token). Derived values are computed on first use and
+//! cached, so commands can take one `&mut PluginContext` instead of threading
+//! several handles around, without paying for lookups they don't need.
+//!
+//! A [`Snapshot`] of a run records its inputs (arguments, workspace, selected
+//! packages, tool versions, environment and the plugin's resolved
+//! configuration) in a JSON file. Passing it back with `--from-snapshot`
+//! reproduces the run with the same inputs:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::cli::CommonArgs;
+//! use cargo_plugin_utils::context::PluginContext;
+//!
+//! # fn example(args: CommonArgs, config: serde_json::Value) -> anyhow::Result<()> {
+//! let ctx = PluginContext::new(args);
+//! let config = match ctx.replay()? {
+//!     // Use the configuration of the recorded run
+//!     Some(snapshot) => snapshot.config.clone(),
+//!     None => config,
+//! };
+//! ctx.snapshot()?
+//!     .config(&config)?
+//!     .write("target/plugin-snapshot.json")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::cli::CommonArgs;
+use crate::common::{
+    WorkspaceInfo,
+    degraded_workspace_info,
+    find_package_at,
+    get_metadata,
+    get_owner_repo,
+};
+use crate::config::{
+    Config,
+    ConfigLoader,
+};
+use crate::graph::WorkspaceGraph;
+use crate::logger::{
+    Logger,
+    RunOptions,
+};
+use crate::pipeline::Pipeline;
+use crate::tempdirs::{
+    ScopedTempDir,
+    TempDirBuilder,
+};
+use crate::work_cache::WorkCache;
+
+/// Lazily populated context shared by the commands of a plugin.
+pub struct PluginContext {
+    args: CommonArgs,
+    logger: Logger,
+    metadata: OnceCell<cargo_metadata::Metadata>,
+    graph: OnceCell<WorkspaceGraph>,
+    package: OnceCell<cargo_metadata::Package>,
+    repo: OnceCell<(String, String)>,
+    replay: OnceCell<Snapshot>,
+    cancel: CancellationToken,
+}
+
+impl PluginContext {
+    /// Create a context from the parsed common arguments.
+    ///
+    /// Applies the `--color` and `--message-format` choices immediately;
+    /// everything else is resolved on first access.
+    pub fn new(args: CommonArgs) -> Self {
+        args.apply_color();
+        let mut logger = Logger::new();
+        logger.set_message_format(args.message_format);
+        Self {
+            args,
+            logger,
+            metadata: OnceCell::new(),
+            graph: OnceCell::new(),
+            package: OnceCell::new(),
+            repo: OnceCell::new(),
+            replay: OnceCell::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// The common arguments this context was created from.
+    pub fn args(&self) -> &CommonArgs {
+        &self.args
+    }
+
+    /// The logger for cargo-style status output.
+    pub fn logger(&mut self) -> &mut Logger {
+        &mut self.logger
+    }
+
+    /// Workspace metadata, respecting `--manifest-path`.
+    ///
+    /// Runs `cargo metadata` on first call only. Failures are not cached, so a
+    /// later call retries.
+    pub fn metadata(&self) -> Result<&cargo_metadata::Metadata> {
+        if let Some(metadata) = self.metadata.get() {
+            return Ok(metadata);
+        }
+        let metadata = get_metadata(self.manifest_path()?)?;
+        Ok(self.metadata.get_or_init(|| metadata))
+    }
+
+    /// The workspace members, read from the manifests when `cargo metadata`
+    /// fails (see [`get_workspace_info`](crate::common::get_workspace_info)).
+    pub fn workspace_info(&self) -> Result<WorkspaceInfo> {
+        match self.metadata() {
+            Ok(metadata) => Ok(WorkspaceInfo::from_metadata(metadata)),
+            Err(err) => degraded_workspace_info(self.manifest_path()?, &err),
+        }
+    }
+
+    /// The graph of the workspace members, built from
+    /// [`metadata`](Self::metadata) on first call.
+    pub fn graph(&self) -> Result<&WorkspaceGraph> {
+        if let Some(graph) = self.graph.get() {
+            return Ok(graph);
+        }
+        let graph = WorkspaceGraph::from_metadata(self.metadata()?);
+        Ok(self.graph.get_or_init(|| graph))
+    }
+
+    /// The plugin's settings, merged by `loader` for this workspace and the
+    /// current [`package`](Self::package), if there is one.
+    ///
+    /// Not cached, since the settings type is the caller's; load them once
+    /// and keep the result.
+    pub fn config<T: serde::de::DeserializeOwned>(
+        &self,
+        loader: &ConfigLoader,
+    ) -> Result<Config<T>> {
+        loader.load(self.metadata()?, self.package().ok())
+    }
+
+    /// The token that cancels the work of this run, e.g. when the user
+    /// presses a key or a sibling task failed.
+    ///
+    /// Pass clones (or [child tokens](CancellationToken::child_token)) to
+    /// tasks; [`run_options`](Self::run_options) hands it to subprocesses.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Options for subprocesses of this run, killed when the
+    /// [cancellation token](Self::cancellation_token) is cancelled.
+    pub fn run_options(&self) -> RunOptions {
+        RunOptions::new().cancel_on(self.cancel.clone())
+    }
+
+    /// `--manifest-path`, or the recorded one when replaying a snapshot.
+    fn manifest_path(&self) -> Result<Option<&Path>> {
+        Ok(match (&self.args.manifest_path, self.replay()?) {
+            (Some(path), _) => Some(path.as_path()),
+            (None, Some(snapshot)) => snapshot.manifest_path.as_deref(),
+            (None, None) => None,
+        })
+    }
+
+    /// The package for the current context: the one `--manifest-path`
+    /// points at, or else selected from the working directory (see
+    /// [`find_package`](crate::common::find_package) for the rules).
+    ///
+    /// When replaying a snapshot, the first recorded package, or the one
+    /// selected from the recorded working directory.
+    pub fn package(&self) -> Result<&cargo_metadata::Package> {
+        if let Some(package) = self.package.get() {
+            return Ok(package);
+        }
+        let metadata = self.metadata()?;
+        let replay = self.replay()?;
+        let manifest = self
+            .manifest_path()?
+            .and_then(|path| path.canonicalize().ok());
+        let package = if let Some(name) = replay.and_then(|snapshot| snapshot.packages.first()) {
+            metadata
+                .workspace_packages()
+                .into_iter()
+                .find(|package| package.name.as_str() == name)
+                .cloned()
+                .with_context(|| format!("Recorded package `{}` is not in the workspace", name))?
+        } else if let Some(package) = manifest.and_then(|manifest| {
+            metadata.packages.iter().find(|package| {
+                package.manifest_path.as_std_path().canonicalize().ok() == Some(manifest.clone())
+            })
+        }) {
+            package.clone()
+        } else {
+            let cwd = match replay {
+                Some(snapshot) => snapshot.cwd.clone(),
+                None => std::env::current_dir().context("Failed to get current directory")?,
+            };
+            find_package_at(metadata, &cwd)?
+        };
+        Ok(self.package.get_or_init(|| package))
+    }
+
+    /// Repository owner and name, from `--owner`/`--repo` or detected from
+    /// the environment and git remote.
+    pub fn repo(&self) -> Result<(&str, &str)> {
+        if self.repo.get().is_none() {
+            let recorded = self.replay()?.and_then(|snapshot| snapshot.repo.clone());
+            let repo = match (&self.args.owner, &self.args.repo, recorded) {
+                (None, None, Some(repo)) => repo,
+                (owner, repo, _) => get_owner_repo(owner.clone(), repo.clone())?,
+            };
+            let _ = self.repo.set(repo);
+        }
+        let (owner, repo) = self.repo.get().expect("repo was just initialized");
+        Ok((owner, repo))
+    }
+
+    /// The snapshot given with `--from-snapshot`, read on first call.
+    ///
+    /// Its manifest path and repository are used unless given on the
+    /// command line again.
+    pub fn replay(&self) -> Result<Option<&Snapshot>> {
+        let Some(path) = &self.args.from_snapshot else {
+            return Ok(None);
+        };
+        if let Some(snapshot) = self.replay.get() {
+            return Ok(Some(snapshot));
+        }
+        let snapshot = Snapshot::read(path)?;
+        Ok(Some(self.replay.get_or_init(|| snapshot)))
+    }
+
+    /// Record the inputs of this run: the command line (with credentials
+    /// redacted), manifest, current package, repository, tool versions and
+    /// environment.
+    ///
+    /// The manifest is the one given with `--manifest-path` (or recorded
+    /// in the replayed snapshot), else the workspace root's.
+    ///
+    /// Add the plugin's resolved configuration with [`Snapshot::config`]
+    /// and other selected packages with [`Snapshot::packages`].
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let metadata = self.metadata()?;
+        let mut tools = BTreeMap::new();
+        if let Ok(cargo) = crate::toolchain::cargo_version() {
+            tools.insert(
+                "cargo".to_string(),
+                format!("{} ({:?})", cargo.version, cargo.channel),
+            );
+        }
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        if let Ok(output) = std::process::Command::new(rustc).arg("--version").output()
+            && output.status.success()
+        {
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            tools.insert("rustc".to_string(), version);
+        }
+        let env = std::env::vars()
+            .filter(|(key, _)| {
+                (key.starts_with("CARGO") || key.starts_with("RUST"))
+                    && !crate::crash::is_secret_name(key)
+            })
+            .collect();
+        let cwd = std::env::current_dir().context("Failed to get the current directory")?;
+        let manifest_path = match self.manifest_path()? {
+            Some(path) => cwd.join(path),
+            None => metadata.workspace_root.join("Cargo.toml").into(),
+        };
+        Ok(Snapshot {
+            args: crate::crash::redact_args(std::env::args()),
+            cwd,
+            manifest_path: Some(manifest_path),
+            packages: self
+                .package()
+                .map(|package| vec![package.name.to_string()])
+                .unwrap_or_default(),
+            repo: self
+                .repo()
+                .ok()
+                .map(|(owner, repo)| (owner.to_string(), repo.to_string())),
+            tools,
+            env,
+            config: serde_json::Value::Null,
+        })
+    }
+
+    /// Register a cleanup task to run when the plugin exits, in reverse
+    /// registration order.
+    ///
+    /// Hooks also run on [`finish`](Self::finish). See [`crate::exit`].
+    pub fn on_exit<F>(&self, hook: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        crate::exit::on_exit(hook);
+    }
+
+    /// Finish the logger and run the exit hooks, at the end of the plugin's
+    /// command.
+    pub fn finish(&mut self) {
+        self.logger.finish();
+        crate::exit::run_exit_hooks();
+    }
+
+    /// A scoped temporary directory under `<target-dir>/tmp` of this
+    /// workspace, kept on drop when `--keep-temp` was given.
+    pub fn temp_dir(&self, prefix: &str) -> Result<ScopedTempDir> {
+        let root = self.metadata()?.target_directory.join("tmp");
+        TempDirBuilder::new(prefix)
+            .root(root)
+            .keep(self.args.keep_temp)
+            .create()
+    }
+
+    /// An empty [`Pipeline`] honoring `--keep-going`.
+    pub fn pipeline(&self) -> Pipeline {
+        Pipeline::new().keep_going(self.args.keep_going)
+    }
+
+    /// The [work cache](WorkCache) named `name` under
+    /// `<target-dir>/plugin-cache` of this workspace.
+    pub fn work_cache(&self, name: &str) -> Result<WorkCache> {
+        let target_dir = &self.metadata()?.target_directory;
+        Ok(WorkCache::in_dir(
+            target_dir.join("plugin-cache").join(name),
+        ))
+    }
+}
+
+/// The inputs of a plugin run, see [`PluginContext::snapshot`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Command line of the run, including the program, with credentials
+    /// redacted
+    pub args: Vec<String>,
+    /// Working directory
+    pub cwd: PathBuf,
+    /// Manifest given with `--manifest-path`, or of the workspace
+    pub manifest_path: Option<PathBuf>,
+    /// Names of the selected packages
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Repository owner and name
+    #[serde(default)]
+    pub repo: Option<(String, String)>,
+    /// Versions of the tools used, e.g. `cargo` and `rustc`
+    #[serde(default)]
+    pub tools: BTreeMap<String, String>,
+    /// `CARGO*` and `RUST*` environment variables, without credentials
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// The plugin's resolved configuration
+    #[serde(default)]
+    pub config: serde_json::Value,
+}
+
+impl Snapshot {
+    /// Record the plugin's resolved configuration.
+    pub fn config(mut self, config: &impl Serialize) -> Result<Self> {
+        self.config = serde_json::to_value(config).context("Failed to serialize configuration")?;
+        Ok(self)
+    }
+
+    /// Record the selected packages, replacing the current package.
+    pub fn packages<I, S>(mut self, packages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.packages = packages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The recorded configuration as the plugin's type.
+    pub fn config_as<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.config.clone())
+            .context("Snapshot has a different configuration")
+    }
+
+    /// Options running subprocesses in the recorded directory and
+    /// environment.
+    pub fn run_options(&self) -> RunOptions {
+        self.env
+            .iter()
+            .fold(RunOptions::new().cwd(&self.cwd), |options, (key, value)| {
+                options.env(key, value)
+            })
+    }
+
+    /// Write the snapshot as pretty-printed JSON, creating parent
+    /// directories.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("Failed to write snapshot {}", path.display()))
+    }
+
+    /// Read a snapshot written by [`write`](Self::write).
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse snapshot {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::write_file;
+
+    #[test]
+    fn test_context_repo_from_args() {
+        let ctx = PluginContext::new(CommonArgs {
+            owner: Some("acme".to_string()),
+            repo: Some("widgets".to_string()),
+            ..CommonArgs::default()
+        });
+        assert_eq!(ctx.repo().unwrap(), ("acme", "widgets"));
+        // Second call is served from the cache
+        assert_eq!(ctx.repo().unwrap(), ("acme", "widgets"));
+    }
+
+    #[test]
+    fn test_context_metadata_is_cached() {
+        let ctx = PluginContext::new(CommonArgs::default());
+        let first = ctx.metadata().unwrap() as *const _;
+        let second = ctx.metadata().unwrap() as *const _;
+        assert_eq!(first, second);
+        assert_eq!(ctx.package().unwrap().name.as_str(), "cargo-plugin-utils");
+    }
+
+    #[test]
+    fn test_context_metadata_error_not_cached() {
+        let ctx = PluginContext::new(CommonArgs {
+            manifest_path: Some("/nonexistent/path/Cargo.toml".into()),
+            ..CommonArgs::default()
+        });
+        assert!(ctx.metadata().is_err());
+        assert!(ctx.metadata().is_err());
+        assert!(ctx.package().is_err());
+    }
+
+    #[test]
+    fn test_context_temp_dir() {
+        let ctx = PluginContext::new(CommonArgs::default());
+        let dir = ctx.temp_dir("cargo-test").unwrap();
+        let path = dir.path().to_path_buf();
+        let target_dir = &ctx.metadata().unwrap().target_directory;
+        assert!(path.starts_with(target_dir.as_std_path()));
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let ctx = PluginContext::new(CommonArgs {
+            owner: Some("acme".to_string()),
+            repo: Some("widgets".to_string()),
+            ..CommonArgs::default()
+        });
+        let snapshot = ctx
+            .snapshot()
+            .unwrap()
+            .config(&serde_json::json!({"level": 3}))
+            .unwrap();
+        assert_eq!(snapshot.packages, vec!["cargo-plugin-utils".to_string()]);
+        assert!(snapshot.tools.contains_key("cargo"));
+        let path = dir.path().join("snapshots/run.json");
+        snapshot.write(&path).unwrap();
+
+        // The replay uses the recorded inputs unless given again
+        let replay = PluginContext::new(CommonArgs {
+            from_snapshot: Some(path),
+            ..CommonArgs::default()
+        });
+        let recorded = replay.replay().unwrap().unwrap();
+        assert_eq!(recorded, &snapshot);
+        assert_eq!(
+            recorded.config_as::<serde_json::Value>().unwrap()["level"],
+            3
+        );
+        assert_eq!(replay.repo().unwrap(), ("acme", "widgets"));
+        assert_eq!(
+            replay.metadata().unwrap().workspace_root,
+            ctx.metadata().unwrap().workspace_root
+        );
+
+        let missing = PluginContext::new(CommonArgs {
+            from_snapshot: Some(dir.path().join("missing.json")),
+            ..CommonArgs::default()
+        });
+        assert!(missing.replay().is_err());
+        assert!(missing.metadata().is_err());
+    }
+
+    #[test]
+    fn test_snapshot_member_package() {
+        let dir = tempfile::TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"core\"]\nresolver = \"2\"\n",
+        );
+        for member in ["app", "core"] {
+            write_file(
+                dir.path(),
+                &format!("{}/Cargo.toml", member),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                    member
+                ),
+            );
+            write_file(dir.path(), &format!("{}/src/lib.rs", member), "");
+        }
+        let manifest = dir.path().join("core/Cargo.toml");
+        let ctx = PluginContext::new(CommonArgs {
+            manifest_path: Some(manifest.clone()),
+            ..CommonArgs::default()
+        });
+        assert_eq!(ctx.package().unwrap().name.as_str(), "core");
+        let snapshot = ctx.snapshot().unwrap();
+        assert_eq!(snapshot.manifest_path, Some(manifest));
+        assert_eq!(snapshot.packages, vec!["core".to_string()]);
+
+        // The replay selects the recorded package, not one from the cwd
+        let path = dir.path().join("snapshot.json");
+        snapshot.packages(["app"]).write(&path).unwrap();
+        let replay = PluginContext::new(CommonArgs {
+            from_snapshot: Some(path),
+            ..CommonArgs::default()
+        });
+        assert_eq!(replay.package().unwrap().name.as_str(), "app");
+    }
+
+    #[test]
+    fn test_context_graph_and_config() {
+        #[derive(Debug, Default, serde::Deserialize)]
+        #[serde(default)]
+        struct Settings {
+            level: u32,
+        }
+
+        let ctx = PluginContext::new(CommonArgs::default());
+        let graph = ctx.graph().unwrap();
+        assert!(graph.index_of("cargo-plugin-utils").is_some());
+        assert!(std::ptr::eq(graph, ctx.graph().unwrap()));
+
+        let loader = ConfigLoader::new("cargo-plugin-utils-test").set("level", 3);
+        let config = ctx.config::<Settings>(&loader).unwrap();
+        assert_eq!(config.value.level, 3);
+    }
+
+    #[test]
+    fn test_context_cancellation_token() {
+        let ctx = PluginContext::new(CommonArgs::default());
+        let task = ctx.cancellation_token().child_token();
+        assert!(!task.is_cancelled());
+        ctx.cancellation_token().cancel();
+        assert!(task.is_cancelled());
+    }
+
+    #[test]
+    fn test_context_logger() {
+        let mut ctx = PluginContext::new(CommonArgs {
+            message_format: crate::message::MessageFormat::Json,
+            ..CommonArgs::default()
+        });
+        assert_eq!(
+            ctx.logger().message_format(),
+            crate::message::MessageFormat::Json
+        );
+        let first: *const Logger = ctx.logger();
+        let second: *const Logger = ctx.logger();
+        assert_eq!(first, second);
+        // Errors reported through one call are seen by the next
+        assert!(!ctx.logger().has_errors());
+        ctx.logger().error("Failed", "test");
+        assert!(ctx.logger().has_errors());
+    }
+}
@@ -0,0 +1,426 @@
+//! Plumbing for LLVM source-based code coverage.
+//!
+//! A coverage run has three parts: build and run the code instrumented
+//! (`-C instrument-coverage`, with every process writing a raw profile),
+//! merge the raw profiles with `llvm-profdata`, and turn the merged profile
+//! into a report with `llvm-cov`. [`Coverage`] handles the environment and
+//! the file layout, and runs the LLVM tools through the subprocess runner:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::coverage::Coverage;
+//! use cargo_plugin_utils::logger::{
+//!     RunOptions,
+//!     run_subprocess_with_options,
+//! };
+//! use portable_pty::CommandBuilder;
+//!
+//! # async fn example(test_binaries: Vec<std::path::PathBuf>) -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let coverage = Coverage::new("target/coverage");
+//! coverage.clean()?;
+//! let options = coverage.apply(RunOptions::new());
+//! run_subprocess_with_options(
+//!     &mut logger,
+//!     || {
+//!         let mut cmd = CommandBuilder::new("cargo");
+//!         cmd.arg("test");
+//!         cmd
+//!     },
+//!     &options,
+//! )
+//! .await?;
+//! let profdata = coverage.merge(&mut logger).await?;
+//! coverage
+//!     .export_lcov(&mut logger, &profdata, &test_binaries, "target/lcov.info")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The LLVM tools come from the `llvm-tools` rustup component, matching the
+//! LLVM version of the compiler.
+
+use std::ffi::OsString;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::Context;
+use portable_pty::CommandBuilder;
+
+use crate::logger::{
+    Logger,
+    RunOptions,
+    run_subprocess_with_options,
+};
+
+/// Pattern of the raw profile file names: one file per process (`%p`) and
+/// binary (`%m`).
+const PROFILE_FILE_PATTERN: &str = "cargo-%p-%m.profraw";
+
+/// Name of the merged profile in the profile directory.
+const MERGED_PROFILE: &str = "coverage.profdata";
+
+/// A coverage run writing its profiles to one directory.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    profile_dir: PathBuf,
+}
+
+impl Coverage {
+    /// Coverage run with raw and merged profiles in `profile_dir`.
+    pub fn new(profile_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            profile_dir: profile_dir.into(),
+        }
+    }
+
+    /// The directory holding the profiles.
+    pub fn profile_dir(&self) -> &Path {
+        &self.profile_dir
+    }
+
+    /// Environment for building and running instrumented code.
+    ///
+    /// Adds `-C instrument-coverage` to the flags cargo would otherwise use
+    /// (`CARGO_ENCODED_RUSTFLAGS`, `RUSTFLAGS` or the configured
+    /// `rustflags`), points `LLVM_PROFILE_FILE` into the profile directory
+    /// and turns off incremental compilation, which doesn't mix with
+    /// instrumentation.
+    ///
+    /// The profile path is made absolute: tests run in their package's
+    /// directory, not the one the plugin started in.
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    pub fn env(&self) -> Vec<(String, OsString)> {
+        let config = crate::registry::CargoConfig::load();
+        let host = crate::toolchain::host_target().ok();
+        let mut rustflags = inherited_rustflags(
+            std::env::var("CARGO_ENCODED_RUSTFLAGS").ok(),
+            std::env::var("RUSTFLAGS").ok(),
+            &config,
+            host.as_deref(),
+        );
+        rustflags.extend(["-C".to_string(), "instrument-coverage".to_string()]);
+        let profile_dir =
+            std::path::absolute(&self.profile_dir).unwrap_or_else(|_| self.profile_dir.clone());
+        vec![
+            (
+                "CARGO_ENCODED_RUSTFLAGS".to_string(),
+                rustflags.join("\x1f").into(),
+            ),
+            (
+                "LLVM_PROFILE_FILE".to_string(),
+                profile_dir.join(PROFILE_FILE_PATTERN).into_os_string(),
+            ),
+            ("CARGO_INCREMENTAL".to_string(), "0".into()),
+        ]
+    }
+
+    /// Add the [coverage environment](Self::env) to `options`.
+    pub fn apply(&self, options: RunOptions) -> RunOptions {
+        self.env()
+            .into_iter()
+            .fold(options, |options, (key, value)| options.env(key, value))
+    }
+
+    /// Remove the profiles of earlier runs, creating the directory if needed.
+    pub fn clean(&self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.profile_dir)
+            .with_context(|| format!("Failed to create {}", self.profile_dir.display()))?;
+        let merged = self.profile_dir.join(MERGED_PROFILE);
+        for path in self.raw_profiles()?.into_iter().chain([merged]) {
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err)
+                        .with_context(|| format!("Failed to remove {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The raw profiles written so far, sorted.
+    pub fn raw_profiles(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let entries = match std::fs::read_dir(&self.profile_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to list {}", self.profile_dir.display()));
+            }
+        };
+        let mut profiles = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "profraw")
+            {
+                profiles.push(path);
+            }
+        }
+        profiles.sort();
+        Ok(profiles)
+    }
+
+    /// Merge the raw profiles with `llvm-profdata` into
+    /// `<profile-dir>/coverage.profdata` and return its path.
+    pub async fn merge(&self, logger: &mut Logger) -> anyhow::Result<PathBuf> {
+        let profiles = self.raw_profiles()?;
+        anyhow::ensure!(
+            !profiles.is_empty(),
+            "No raw coverage profiles in {}; did the instrumented code run?",
+            self.profile_dir.display()
+        );
+        let output = self.profile_dir.join(MERGED_PROFILE);
+        logger.status_permanent("Merging", &format!("{} raw profiles", profiles.len()));
+        let mut cmd = CommandBuilder::new(llvm_tool("llvm-profdata")?);
+        cmd.args(["merge", "-sparse"]);
+        cmd.args(&profiles);
+        cmd.arg("-o");
+        cmd.arg(&output);
+        run_tool(logger, cmd, &RunOptions::new()).await?;
+        Ok(output)
+    }
+
+    /// Write an LCOV report for `objects` (the instrumented binaries that
+    /// ran) from a merged profile, leaving out dependencies from the
+    /// registry and the standard library.
+    pub async fn export_lcov(
+        &self,
+        logger: &mut Logger,
+        profdata: &Path,
+        objects: &[PathBuf],
+        output: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let output = output.as_ref();
+        let Some((first, rest)) = objects.split_first() else {
+            anyhow::bail!("No binaries to export coverage for");
+        };
+        logger.status_permanent("Exporting", &output.display().to_string());
+        let mut cmd = CommandBuilder::new(llvm_tool("llvm-cov")?);
+        cmd.args(["export", "-format=lcov"]);
+        cmd.arg(format!("-instr-profile={}", profdata.display()));
+        cmd.args([r"-ignore-filename-regex=(\.cargo/registry|/rustc/)"]);
+        cmd.arg(first);
+        for object in rest {
+            cmd.arg("-object");
+            cmd.arg(object);
+        }
+        let lcov = run_tool(logger, cmd, &RunOptions::new().piped(true)).await?;
+        std::fs::write(output, lcov)
+            .with_context(|| format!("Failed to write {}", output.display()))
+    }
+}
+
+/// The flags cargo passes to rustc without our changes, from the first
+/// source that is set: `CARGO_ENCODED_RUSTFLAGS`, `RUSTFLAGS`,
+/// `target.<host>.rustflags`, `build.rustflags`.
+///
+/// Setting `RUSTFLAGS` or `CARGO_ENCODED_RUSTFLAGS` replaces the
+/// configuration, so the coverage flag is added to these instead of
+/// discarding them. `target.<cfg>` tables are not evaluated.
+fn inherited_rustflags(
+    encoded: Option<String>,
+    plain: Option<String>,
+    config: &crate::registry::CargoConfig,
+    host: Option<&str>,
+) -> Vec<String> {
+    if let Some(encoded) = encoded {
+        return encoded
+            .split('\x1f')
+            .filter(|flag| !flag.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    if let Some(plain) = plain {
+        return plain.split_whitespace().map(str::to_string).collect();
+    }
+    if let Some(host) = host {
+        let flags = config.string_list(&["target", host, "rustflags"]);
+        if !flags.is_empty() {
+            return flags;
+        }
+    }
+    config.string_list(&["build", "rustflags"])
+}
+
+/// Run an LLVM tool, failing with its output if it fails. Returns stdout.
+async fn run_tool(
+    logger: &mut Logger,
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let tool = cmd.get_argv()[0].to_string_lossy().into_owned();
+    let output = run_subprocess_with_options(logger, || cmd, options).await?;
+    if !output.success() {
+        anyhow::bail!(
+            "{} failed with {}:\n{}",
+            tool,
+            output.status(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Path of an LLVM tool from the `llvm-tools` component of the active
+/// toolchain, or the bare name to look up on `PATH` if the component isn't
+/// installed but the tool is.
+pub fn llvm_tool(name: &str) -> anyhow::Result<PathBuf> {
+    let file_name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    if let Ok(dir) = llvm_tools_dir() {
+        let path = dir.join(&file_name);
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    let on_path = std::process::Command::new(&file_name)
+        .arg("--version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    anyhow::ensure!(
+        on_path,
+        "{} not found; install it with `rustup component add llvm-tools`",
+        name
+    );
+    Ok(PathBuf::from(file_name))
+}
+
+/// `<sysroot>/lib/rustlib/<host>/bin` of the active rustc.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn llvm_tools_dir() -> anyhow::Result<PathBuf> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = std::process::Command::new(&rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .context("Failed to run `rustc --print sysroot`")?;
+    let sysroot = String::from_utf8(output.stdout)?.trim().to_string();
+    Ok(Path::new(&sysroot)
+        .join("lib")
+        .join("rustlib")
+        .join(crate::toolchain::host_target()?)
+        .join("bin"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env() {
+        let coverage = Coverage::new("/tmp/profiles");
+        let env = coverage.env();
+        let value = |key: &str| {
+            env.iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.to_string_lossy().into_owned())
+                .unwrap()
+        };
+        assert!(value("CARGO_ENCODED_RUSTFLAGS").ends_with("-C\x1finstrument-coverage"));
+        assert_eq!(
+            PathBuf::from(value("LLVM_PROFILE_FILE")),
+            Path::new("/tmp/profiles").join("cargo-%p-%m.profraw")
+        );
+        assert_eq!(value("CARGO_INCREMENTAL"), "0");
+    }
+
+    #[test]
+    fn test_env_absolute_profile_dir() {
+        let env = Coverage::new("target/coverage").env();
+        let (_, profile_file) = env
+            .iter()
+            .find(|(name, _)| name == "LLVM_PROFILE_FILE")
+            .unwrap();
+        assert!(Path::new(profile_file).is_absolute());
+    }
+
+    #[test]
+    fn test_inherited_rustflags() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = dir.path().join("project");
+        let home = dir.path().join("home");
+        std::fs::create_dir_all(project.join(".cargo")).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(
+            project.join(".cargo/config.toml"),
+            "[build]\nrustflags = [\"--cfg\", \"tokio_unstable\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            home.join("config.toml"),
+            "[build]\nrustflags = \"-D warnings\"\n",
+        )
+        .unwrap();
+        let config = crate::registry::CargoConfig::load_from(&project, Some(&home));
+
+        assert_eq!(
+            inherited_rustflags(None, None, &config, Some("x86_64-unknown-linux-gnu")),
+            ["-D", "warnings", "--cfg", "tokio_unstable"]
+        );
+        assert_eq!(
+            inherited_rustflags(None, Some("-C opt-level=1".to_string()), &config, None),
+            ["-C", "opt-level=1"]
+        );
+        assert_eq!(
+            inherited_rustflags(
+                Some("--cfg\x1ffoo bar".to_string()),
+                Some("-C opt-level=1".to_string()),
+                &config,
+                None
+            ),
+            ["--cfg", "foo bar"]
+        );
+
+        std::fs::write(
+            project.join(".cargo/config.toml"),
+            "[target.x86_64-unknown-linux-gnu]\nrustflags = [\"-C\", \"target-cpu=native\"]\n",
+        )
+        .unwrap();
+        let config = crate::registry::CargoConfig::load_from(&project, Some(&home));
+        assert_eq!(
+            inherited_rustflags(None, None, &config, Some("x86_64-unknown-linux-gnu")),
+            ["-C", "target-cpu=native"]
+        );
+    }
+
+    #[test]
+    fn test_raw_profiles_and_clean() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let coverage = Coverage::new(dir.path().join("profiles"));
+        assert!(coverage.raw_profiles().unwrap().is_empty());
+
+        coverage.clean().unwrap();
+        for name in ["b.profraw", "a.profraw", "coverage.profdata", "notes.txt"] {
+            std::fs::write(coverage.profile_dir().join(name), "").unwrap();
+        }
+        let names: Vec<_> = coverage
+            .raw_profiles()
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["a.profraw", "b.profraw"]);
+
+        coverage.clean().unwrap();
+        assert!(coverage.raw_profiles().unwrap().is_empty());
+        assert!(!coverage.profile_dir().join("coverage.profdata").exists());
+        assert!(coverage.profile_dir().join("notes.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_merge_without_profiles() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut logger = Logger::new();
+        let err = Coverage::new(dir.path())
+            .merge(&mut logger)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No raw coverage profiles"));
+    }
+}
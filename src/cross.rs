@@ -0,0 +1,277 @@
+//! Run a command once per target triple.
+//!
+//! A [`TargetMatrix`] lists the targets to build for, either explicitly or
+//! every target with an installed standard library, plus per-target
+//! environment such as the linker. [`TargetMatrix::run`] appends `--target`
+//! to a command template for every target, runs them through
+//! [`run_subprocesses_parallel`] and prints a summary table:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::cross::TargetMatrix;
+//! use portable_pty::CommandBuilder;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut build = CommandBuilder::new("cargo");
+//! build.args(["build", "--release"]);
+//! let mut logger = Logger::new();
+//! let report = TargetMatrix::installed()?
+//!     .linker("aarch64-unknown-linux-gnu", "aarch64-linux-gnu-gcc")
+//!     .run(&mut logger, &build)
+//!     .await;
+//! anyhow::ensure!(report.success(), "cross builds failed");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use portable_pty::CommandBuilder;
+
+use crate::logger::{
+    Logger,
+    RunOptions,
+    SubprocessOutput,
+};
+use crate::parallel::{
+    Job,
+    run_subprocesses_parallel,
+};
+use crate::pipeline::StepStatus;
+use crate::table::Table;
+
+/// Target triples to run a command for, with per-target environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetMatrix {
+    targets: Vec<String>,
+    env: BTreeMap<String, Vec<(String, String)>>,
+    jobs: usize,
+}
+
+impl TargetMatrix {
+    /// An empty matrix; add targets with [`target`](Self::target).
+    pub fn new() -> Self {
+        Self {
+            jobs: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Every target whose standard library is installed, see
+    /// [`installed_targets`](crate::toolchain::installed_targets).
+    pub fn installed() -> anyhow::Result<Self> {
+        Ok(Self::new().targets(crate::toolchain::installed_targets()?))
+    }
+
+    /// Add a target triple.
+    pub fn target(mut self, triple: impl Into<String>) -> Self {
+        let triple = triple.into();
+        if !self.targets.contains(&triple) {
+            self.targets.push(triple);
+        }
+        self
+    }
+
+    /// Add target triples.
+    pub fn targets<I, S>(self, triples: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        triples.into_iter().fold(self, Self::target)
+    }
+
+    /// Link `triple` with `linker`, through cargo's
+    /// `CARGO_TARGET_<TRIPLE>_LINKER` variable.
+    pub fn linker(self, triple: &str, linker: impl Into<String>) -> Self {
+        let key = target_env_var(triple, "LINKER");
+        self.env(triple, key, linker)
+    }
+
+    /// Set an environment variable for the run of `triple` only.
+    pub fn env(mut self, triple: &str, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env
+            .entry(triple.to_string())
+            .or_default()
+            .push((key.into(), value.into()));
+        self
+    }
+
+    /// Run up to `jobs` targets at once (default 1).
+    ///
+    /// Cargo commands sharing a target directory wait for each other's lock;
+    /// give each target its own `CARGO_TARGET_DIR` with
+    /// [`env`](Self::env) to really run them in parallel.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// The target triples, in the order they run.
+    pub fn triples(&self) -> &[String] {
+        &self.targets
+    }
+
+    /// Run `template` with `--target <triple>` for every target and print a
+    /// summary table.
+    pub async fn run(&self, logger: &mut Logger, template: &CommandBuilder) -> TargetReport {
+        let jobs = self.targets.iter().map(|triple| {
+            let mut cmd = template.clone();
+            cmd.args(["--target", triple]);
+            let options = self
+                .env
+                .get(triple)
+                .into_iter()
+                .flatten()
+                .fold(RunOptions::new(), |options, (key, value)| {
+                    options.env(key, value)
+                });
+            Job::new(triple.clone(), cmd).options(options)
+        });
+        let outputs = run_subprocesses_parallel(logger, jobs, self.jobs).await;
+
+        let results = self
+            .targets
+            .iter()
+            .zip(outputs)
+            .map(|(target, output)| {
+                let (status, output) = match output {
+                    Ok(output) if output.success() => (StepStatus::Passed, Some(output)),
+                    Ok(output) => (
+                        StepStatus::Failed {
+                            exit_code: output.exit_code(),
+                        },
+                        Some(output),
+                    ),
+                    Err(err) => (StepStatus::Error(format!("{:#}", err)), None),
+                };
+                TargetResult {
+                    target: target.clone(),
+                    status,
+                    output,
+                }
+            })
+            .collect();
+        let report = TargetReport { results };
+        report.print(logger);
+        report
+    }
+}
+
+/// Cargo's per-target configuration variable, e.g.
+/// `CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_LINKER` for `LINKER`.
+pub fn target_env_var(triple: &str, key: &str) -> String {
+    let triple: String = triple
+        .chars()
+        .map(|character| match character {
+            '-' | '.' => '_',
+            other => other.to_ascii_uppercase(),
+        })
+        .collect();
+    format!("CARGO_TARGET_{}_{}", triple, key)
+}
+
+/// Result for one target.
+#[derive(Debug, Clone)]
+pub struct TargetResult {
+    /// The target triple
+    pub target: String,
+    /// How the command ended
+    pub status: StepStatus,
+    /// Captured output, if the command ran
+    pub output: Option<SubprocessOutput>,
+}
+
+/// Results of a [`TargetMatrix::run`], in target order.
+#[derive(Debug, Clone)]
+pub struct TargetReport {
+    /// One result per target
+    pub results: Vec<TargetResult>,
+}
+
+impl TargetReport {
+    /// Whether the command succeeded for every target.
+    pub fn success(&self) -> bool {
+        !self.results.iter().any(|result| result.status.is_failure())
+    }
+
+    /// The targets the command failed for.
+    pub fn failures(&self) -> impl Iterator<Item = &TargetResult> {
+        self.results
+            .iter()
+            .filter(|result| result.status.is_failure())
+    }
+
+    /// Target and status of every target.
+    pub fn table(&self) -> Table {
+        let mut table = Table::new(["Target", "Status"]);
+        for result in &self.results {
+            table.add_row([result.target.clone(), result.status.styled()]);
+        }
+        table
+    }
+
+    fn print(&self, logger: &Logger) {
+        if self.results.is_empty() {
+            return;
+        }
+        let width = usize::from(crate::resize::current().cols);
+        logger.print_message(&self.table().render(width));
+        let failed = self.failures().count();
+        if failed == 0 {
+            logger.status_permanent("Finished", &format!("{} targets", self.results.len()));
+        } else {
+            logger.error(
+                "Failed",
+                &format!("{} of {} targets", failed, self.results.len()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_env_var() {
+        assert_eq!(
+            target_env_var("aarch64-unknown-linux-gnu", "LINKER"),
+            "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER"
+        );
+        assert_eq!(
+            target_env_var("thumbv7em-none-eabihf", "RUNNER"),
+            "CARGO_TARGET_THUMBV7EM_NONE_EABIHF_RUNNER"
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_per_target() {
+        // `sh -c <script> sh --target <triple>`: the script sees the triple as
+        // $2 and fails for the second target
+        let mut template = CommandBuilder::new("sh");
+        template.args([
+            "-c",
+            r#"test "$2" = x86_64-unknown-linux-gnu && test "$CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_LINKER" = cc"#,
+            "sh",
+        ]);
+        let matrix = TargetMatrix::new()
+            .targets(["x86_64-unknown-linux-gnu", "wasm32-unknown-unknown"])
+            .target("x86_64-unknown-linux-gnu")
+            .linker("x86_64-unknown-linux-gnu", "cc");
+        assert_eq!(matrix.triples().len(), 2);
+
+        let mut logger = Logger::new();
+        let report = matrix.run(&mut logger, &template).await;
+        assert!(!report.success());
+        let statuses: Vec<_> = report.results.iter().map(|result| &result.status).collect();
+        assert_eq!(
+            statuses,
+            [&StepStatus::Passed, &StepStatus::Failed { exit_code: 1 }]
+        );
+        let table = console::strip_ansi_codes(&report.table().render(80)).into_owned();
+        let last = table.lines().last().unwrap();
+        assert!(last.starts_with("wasm32-unknown-unknown") && last.contains("FAILED"));
+    }
+}
@@ -0,0 +1,137 @@
+//! Tracing of the crate's own internals, for diagnosing terminal bugs.
+//!
+//! Set `CARGO_PLUGIN_UTILS_DEBUG=1` (or `all`) to trace everything, or a
+//! comma-separated list of areas such as `pty,scrolling` to trace only those.
+//! Traces go to a side file so they can't corrupt the terminal they are
+//! about: `CARGO_PLUGIN_UTILS_DEBUG_FILE` if set, otherwise
+//! `cargo-plugin-utils-debug-<pid>.log` in the temp directory. Each line has
+//! the milliseconds since the first trace and the area:
+//!
+//! ```text
+//!      12 [pty] spawned pid 4242 in a PTY, window of 5 lines
+//!      13 [render] 112 bytes, 0 chunks queued
+//!      13 [render] redrew 3 lines over 2 in 210.5µs
+//!     518 [scrolling] "\x1b[1;40r"
+//! ```
+//!
+//! The areas are `pty` (PTY and pipe lifecycle), `render` (output window
+//! redraws and channel backlog) and `scrolling` (scrolling region changes).
+
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{
+    Mutex,
+    OnceLock,
+};
+use std::time::Instant;
+
+/// Where traces go, once tracing is enabled.
+struct Tracer {
+    areas: Option<Vec<String>>,
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+    started: Instant,
+}
+
+fn tracer() -> Option<&'static Tracer> {
+    static TRACER: OnceLock<Option<Tracer>> = OnceLock::new();
+    TRACER.get_or_init(init).as_ref()
+}
+
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn init() -> Option<Tracer> {
+    let setting = std::env::var("CARGO_PLUGIN_UTILS_DEBUG").ok()?;
+    let areas = parse_areas(&setting)?;
+    let path = std::env::var_os("CARGO_PLUGIN_UTILS_DEBUG_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            std::env::temp_dir().join(format!(
+                "cargo-plugin-utils-debug-{}.log",
+                std::process::id()
+            ))
+        });
+    let file = File::options().create(true).append(true).open(&path).ok();
+    Some(Tracer {
+        areas,
+        path,
+        file: Mutex::new(file),
+        started: Instant::now(),
+    })
+}
+
+/// The areas selected by a `CARGO_PLUGIN_UTILS_DEBUG` value: `None` inside
+/// for all of them, or `None` overall when tracing is off.
+fn parse_areas(setting: &str) -> Option<Option<Vec<String>>> {
+    match setting.trim() {
+        "" | "0" | "false" | "off" => None,
+        "1" | "true" | "all" => Some(None),
+        list => Some(Some(
+            list.split(',')
+                .map(|area| area.trim().to_ascii_lowercase())
+                .filter(|area| !area.is_empty())
+                .collect(),
+        )),
+    }
+}
+
+/// Whether `area` is traced.
+pub fn enabled(area: &str) -> bool {
+    tracer().is_some_and(|tracer| {
+        tracer
+            .areas
+            .as_ref()
+            .is_none_or(|areas| areas.iter().any(|selected| selected == area))
+    })
+}
+
+/// The trace file, if tracing is on.
+pub fn log_path() -> Option<PathBuf> {
+    tracer().map(|tracer| tracer.path.clone())
+}
+
+/// Append a line for `area` to the trace file. Use the `trace!` macro,
+/// which skips formatting when the area isn't traced.
+pub(crate) fn write(area: &str, message: std::fmt::Arguments<'_>) {
+    let Some(tracer) = tracer() else {
+        return;
+    };
+    let mut file = tracer.file.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(file) = file.as_mut() {
+        let _ = writeln!(
+            file,
+            "{:>8} [{}] {}",
+            tracer.started.elapsed().as_millis(),
+            area,
+            message
+        );
+    }
+}
+
+/// Trace a message for an area: `trace!("pty", "spawned pid {}", pid)`.
+macro_rules! trace {
+    ($area:literal, $($arg:tt)+) => {
+        if $crate::debug::enabled($area) {
+            $crate::debug::write($area, format_args!($($arg)+));
+        }
+    };
+}
+
+pub(crate) use trace;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_areas() {
+        assert_eq!(parse_areas(""), None);
+        assert_eq!(parse_areas("0"), None);
+        assert_eq!(parse_areas("1"), Some(None));
+        assert_eq!(parse_areas("all"), Some(None));
+        assert_eq!(
+            parse_areas("PTY, scrolling,"),
+            Some(Some(vec!["pty".to_string(), "scrolling".to_string()]))
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Run `cargo doc` and report where the documentation ended up.
+//!
+//! [`DocRunner`] builds the docs with cargo's JSON messages turned on, so the
+//! `index.html` of every documented crate is known exactly, even with
+//! `--target` or a custom target directory. The finished [`DocReport`] is
+//! printed as clickable links and can be served locally for a preview:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::docs::DocRunner;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let report = DocRunner::new()
+//!     .args(["--workspace", "--no-deps"])
+//!     .run(&mut logger)
+//!     .await?;
+//! report.serve(&logger, 8000)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use portable_pty::CommandBuilder;
+
+use crate::logger::{
+    Logger,
+    RunOptions,
+    run_subprocess_with_options,
+    spawn_detached,
+};
+use crate::tty::{
+    file_link,
+    hyperlink,
+};
+
+/// Builder for a `cargo doc` run.
+#[derive(Debug, Clone, Default)]
+pub struct DocRunner {
+    args: Vec<String>,
+    options: RunOptions,
+}
+
+impl DocRunner {
+    /// `cargo doc` with no extra arguments.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an argument for `cargo doc`, e.g. `--no-deps` or `-p`.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add arguments for `cargo doc`.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run with `options` (directory, environment, timeout, ...). Stdout is
+    /// always piped to read cargo's messages.
+    pub fn options(mut self, options: RunOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The `cargo doc` command.
+    pub fn command(&self) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new("cargo");
+        cmd.args(["doc", "--message-format", "json-render-diagnostics"]);
+        cmd.args(&self.args);
+        cmd
+    }
+
+    /// Build the docs, showing cargo's progress in the output window, and
+    /// print a link to each local crate's docs.
+    ///
+    /// Fails if cargo fails; the error includes cargo's last output lines.
+    pub async fn run(self, logger: &mut Logger) -> anyhow::Result<DocReport> {
+        let pages = Arc::new(Mutex::new(Vec::new()));
+        let hook_pages = pages.clone();
+        let options = self.options.clone().piped(true).on_line(move |line| {
+            if let Some(page) = parse_artifact(line) {
+                let mut pages = hook_pages.lock().unwrap_or_else(|err| err.into_inner());
+                pages.push(page);
+            }
+        });
+        let command = self.command();
+        let output = run_subprocess_with_options(logger, || command, &options).await?;
+        if !output.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let tail: Vec<_> = stderr.lines().rev().take(10).collect();
+            anyhow::bail!(
+                "cargo doc failed with {}:\n{}",
+                output.status(),
+                tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+            );
+        }
+
+        let mut pages = std::mem::take(&mut *pages.lock().unwrap_or_else(|err| err.into_inner()));
+        pages.sort_by(|left, right| left.crate_name.cmp(&right.crate_name));
+        pages.dedup_by(|left, right| left.index == right.index);
+        let report = DocReport { pages };
+        report.print(logger);
+        Ok(report)
+    }
+}
+
+/// Documentation of one crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocPage {
+    /// Crate name as used in paths, e.g. `cargo_plugin_utils`
+    pub crate_name: String,
+    /// Path of the crate's `index.html`
+    pub index: PathBuf,
+}
+
+/// Result of a [`DocRunner`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocReport {
+    /// The documented local crates (dependencies from registries and git are
+    /// left out), sorted by name
+    pub pages: Vec<DocPage>,
+}
+
+impl DocReport {
+    /// The `doc` directory all pages are in, e.g. `target/doc`.
+    pub fn doc_dir(&self) -> Option<&Path> {
+        let page = self.pages.first()?;
+        page.index.parent()?.parent()
+    }
+
+    /// Start a local HTTP server for the docs in the background and print
+    /// links to it. Returns the URL of the first crate's docs.
+    ///
+    /// Uses Python's `http.server`, bound to `127.0.0.1`; the server keeps
+    /// running after the plugin exits.
+    pub fn serve(&self, logger: &Logger, port: u16) -> anyhow::Result<String> {
+        let doc_dir = self
+            .doc_dir()
+            .ok_or_else(|| anyhow::anyhow!("No documentation to serve"))?;
+        let mut cmd = CommandBuilder::new(if cfg!(windows) { "python" } else { "python3" });
+        cmd.args(["-m", "http.server", "--bind", "127.0.0.1", "--directory"]);
+        cmd.arg(doc_dir);
+        cmd.arg(port.to_string());
+        let pid = spawn_detached(cmd)?;
+
+        let root = format!("http://127.0.0.1:{}", port);
+        logger.status_permanent(
+            "Serving",
+            &format!("{} (pid {})", hyperlink(&root, &root), pid),
+        );
+        let urls: Vec<_> = self
+            .pages
+            .iter()
+            .map(|page| format!("{}/{}/index.html", root, page.crate_name))
+            .collect();
+        for url in &urls {
+            logger.info("Preview", &hyperlink(url, url));
+        }
+        Ok(urls.into_iter().next().unwrap_or(root))
+    }
+
+    fn print(&self, logger: &Logger) {
+        if self.pages.is_empty() {
+            logger.warning("Warning", "cargo doc didn't document any local crate");
+        }
+        for page in &self.pages {
+            logger.status_permanent("Generated", &file_link(&page.index));
+        }
+    }
+}
+
+/// The docs of a local crate from one of cargo's JSON messages.
+fn parse_artifact(line: &[u8]) -> Option<DocPage> {
+    let Ok(cargo_metadata::Message::CompilerArtifact(artifact)) = serde_json::from_slice(line)
+    else {
+        return None;
+    };
+    if !artifact.package_id.repr.contains("path+file://") {
+        return None;
+    }
+    let index = artifact
+        .filenames
+        .into_iter()
+        .find(|path| path.file_name() == Some("index.html"))?;
+    let crate_name = index.parent()?.file_name()?.to_string();
+    Some(DocPage {
+        crate_name,
+        index: index.into_std_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifact(package_id: &str, filename: &str) -> String {
+        serde_json::json!({
+            "reason": "compiler-artifact",
+            "package_id": package_id,
+            "manifest_path": "/work/my-crate/Cargo.toml",
+            "target": {
+                "kind": ["lib"],
+                "crate_types": ["lib"],
+                "name": "my_crate",
+                "src_path": "/work/my-crate/src/lib.rs",
+                "edition": "2024",
+                "doc": true,
+                "doctest": true,
+                "test": true
+            },
+            "profile": {
+                "opt_level": "0",
+                "debuginfo": 2,
+                "debug_assertions": true,
+                "overflow_checks": true,
+                "test": false
+            },
+            "features": [],
+            "filenames": [filename],
+            "executable": null,
+            "fresh": false
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_parse_artifact() {
+        let local = artifact(
+            "path+file:///work/my-crate#0.1.0",
+            "/work/target/doc/my_crate/index.html",
+        );
+        let page = parse_artifact(local.as_bytes()).unwrap();
+        assert_eq!(page.crate_name, "my_crate");
+        assert_eq!(
+            page.index,
+            PathBuf::from("/work/target/doc/my_crate/index.html")
+        );
+        let report = DocReport { pages: vec![page] };
+        assert_eq!(report.doc_dir(), Some(Path::new("/work/target/doc")));
+
+        // Dependencies and other messages are skipped
+        let dependency = artifact(
+            "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0",
+            "/work/target/doc/serde/index.html",
+        );
+        assert_eq!(parse_artifact(dependency.as_bytes()), None);
+        let rmeta = artifact(
+            "path+file:///work/my-crate#0.1.0",
+            "/work/target/debug/deps/libmy_crate.rmeta",
+        );
+        assert_eq!(parse_artifact(rmeta.as_bytes()), None);
+        assert_eq!(parse_artifact(b"   Documenting my-crate v0.1.0"), None);
+    }
+
+    #[test]
+    fn test_command() {
+        let cmd = DocRunner::new().args(["--no-deps", "-p", "core"]).command();
+        let argv: Vec<_> = cmd
+            .get_argv()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            argv,
+            [
+                "cargo",
+                "doc",
+                "--message-format",
+                "json-render-diagnostics",
+                "--no-deps",
+                "-p",
+                "core"
+            ]
+        );
+    }
+}
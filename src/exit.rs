@@ -0,0 +1,349 @@
+//! Cleanup hooks that run when the plugin exits.
+//!
+//! Hooks registered with [`on_exit`] run once, in reverse registration order,
+//! when the process ends:
+//!
+//! - on normal exit: when `main` returns, also after a panic unwound out of it,
+//!   or `std::process::exit` is called (via `atexit` on Unix);
+//! - on a panic in a binary built with `panic = "abort"`, which skips `atexit`;
+//! - when [`run_exit_hooks`] or [`PluginContext::finish`] is called;
+//! - on `SIGINT`, `SIGTERM` and `SIGHUP` (Ctrl-C / Ctrl-Break on Windows),
+//!   after which the process terminates as the signal would have, but only once
+//!   the plugin opted in with [`handle_signals`].
+//!
+//! Signal handling is opt-in because it takes over these signals for the
+//! whole process. While a run with
+//! [`RunOptions::interrupt_on_ctrl_c`](crate::RunOptions::interrupt_on_ctrl_c)
+//! is active, Ctrl-C is left to that run, which stops its child first.
+//!
+//! Use them for cleanup that must not be skipped, such as deleting a
+//! temporary tag, resetting the terminal scroll region or releasing a lock:
+//!
+//! ```no_run
+//! cargo_plugin_utils::exit::on_exit(|| {
+//!     let _ = cargo_plugin_utils::scrolling::reset_scrolling_region();
+//! });
+//! ```
+//!
+//! Wrapper-style plugins forward a failed child to their caller with
+//! [`propagate`], which exits with the child's code once the terminal is
+//! back in order:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::logger::run_subprocess;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let output = run_subprocess(
+//!     &mut logger,
+//!     || portable_pty::CommandBuilder::new("cargo"),
+//!     None,
+//! )
+//! .await?;
+//! logger.finish();
+//! cargo_plugin_utils::exit::propagate(&output);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`PluginContext::finish`]: crate::context::PluginContext::finish
+
+use std::io::Write;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::{
+    Mutex,
+    Once,
+};
+
+use crate::logger::{
+    ExitStatus,
+    SubprocessOutput,
+};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// An ordered list of cleanup hooks.
+struct ExitHooks {
+    hooks: Mutex<Vec<Hook>>,
+}
+
+impl ExitHooks {
+    const fn new() -> Self {
+        Self {
+            hooks: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn register(&self, hook: Hook) {
+        self.hooks
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(hook);
+    }
+
+    /// Run and remove all hooks, most recently registered first.
+    ///
+    /// The lock is not held while hooks run, so hooks may register further
+    /// hooks (which run in the same pass). A panicking hook doesn't prevent
+    /// the others from running.
+    fn run(&self) {
+        loop {
+            let hook = self
+                .hooks
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .pop();
+            let Some(hook) = hook else {
+                break;
+            };
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(hook));
+        }
+    }
+}
+
+static HOOKS: ExitHooks = ExitHooks::new();
+
+/// Register a cleanup task to run when the process exits, or is interrupted
+/// by a signal after [`handle_signals`].
+///
+/// Hooks run at most once, in reverse registration order.
+pub fn on_exit<F>(hook: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    install_handlers();
+    HOOKS.register(Box::new(hook));
+}
+
+/// Run the exit hooks when the plugin receives `SIGINT`, `SIGTERM` or
+/// `SIGHUP` (Ctrl-C / Ctrl-Break on Windows), then terminate the way the
+/// signal would have.
+///
+/// This replaces the default handling of these signals for the rest of the
+/// process, so call it once from `main`, not from library code. Calling it
+/// again has no effect.
+pub fn handle_signals() {
+    static WATCHING: Once = Once::new();
+    WATCHING.call_once(|| {
+        let _ = std::thread::Builder::new()
+            .name("exit-signals".to_string())
+            .spawn(watch_signals);
+    });
+}
+
+/// Run all pending exit hooks now.
+///
+/// Called automatically in the situations listed in the [module
+/// docs](self); call it explicitly before exiting in ways that bypass them
+/// (e.g. `std::process::abort`).
+pub fn run_exit_hooks() {
+    HOOKS.run();
+}
+
+/// Exit the plugin the way a failed child exited, or return if `output`
+/// [succeeded](SubprocessOutput::success).
+///
+/// Before exiting, the exit hooks run, the scrolling region is reset, the
+/// cursor is shown again and stdout and stderr are flushed. Finish the
+/// [`Logger`](crate::Logger) first, so its status line is cleared.
+pub fn propagate(output: &SubprocessOutput) {
+    if output.success() {
+        return;
+    }
+    let code = exit_code(&output.status());
+    run_exit_hooks();
+    if crate::tty::supports_vt() {
+        let _ = crate::scrolling::reset_scrolling_region();
+        let _ = console::Term::stderr().show_cursor();
+    }
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    std::process::exit(code);
+}
+
+/// The exit code that reports `status` to the plugin's caller: the child's
+/// own code, or 128 + the signal number for a child killed by a signal, as
+/// shells do.
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    match status {
+        ExitStatus::Code(code) => *code as i32,
+        ExitStatus::Signal(signal) => 128 + signal,
+    }
+}
+
+/// Number of active runs that handle Ctrl-C themselves.
+static INTERRUPT_CLAIMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Leaves Ctrl-C to the holder while alive: the [signal
+/// watcher](handle_signals) ignores it instead of exiting.
+pub(crate) struct InterruptClaim(());
+
+impl Drop for InterruptClaim {
+    fn drop(&mut self) {
+        INTERRUPT_CLAIMS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Claim Ctrl-C for a run that stops its child on interrupt, see
+/// [`RunOptions::interrupt_on_ctrl_c`](crate::RunOptions::interrupt_on_ctrl_c).
+pub(crate) fn claim_interrupts() -> InterruptClaim {
+    INTERRUPT_CLAIMS.fetch_add(1, Ordering::SeqCst);
+    InterruptClaim(())
+}
+
+/// Whether a run currently handles Ctrl-C itself.
+fn interrupts_claimed() -> bool {
+    INTERRUPT_CLAIMS.load(Ordering::SeqCst) > 0
+}
+
+/// Install the atexit and panic handlers (once).
+fn install_handlers() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        #[cfg(unix)]
+        {
+            extern "C" fn run_at_exit() {
+                let _ = std::panic::catch_unwind(run_exit_hooks);
+            }
+            // SAFETY: registering a function pointer has no preconditions
+            unsafe {
+                libc::atexit(run_at_exit);
+            }
+        }
+
+        // An unwinding panic ends in a normal exit (or is caught), which
+        // runs the hooks; an aborting one never gets there
+        if cfg!(panic = "abort") {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                previous(info);
+                run_exit_hooks();
+            }));
+        }
+    });
+}
+
+/// Wait for a fatal signal, run the hooks and terminate.
+fn watch_signals() {
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return;
+    };
+    runtime.block_on(async {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{
+                SignalKind,
+                signal,
+            };
+
+            let (Ok(mut interrupt), Ok(mut terminate), Ok(mut hangup)) = (
+                signal(SignalKind::interrupt()),
+                signal(SignalKind::terminate()),
+                signal(SignalKind::hangup()),
+            ) else {
+                return;
+            };
+            let signum = loop {
+                tokio::select! {
+                    _ = interrupt.recv() => if !interrupts_claimed() {
+                        break libc::SIGINT;
+                    },
+                    _ = terminate.recv() => break libc::SIGTERM,
+                    _ = hangup.recv() => break libc::SIGHUP,
+                }
+            };
+            run_exit_hooks();
+            // Terminate the way the signal would have without our handler
+            // SAFETY: resetting to the default disposition and raising the
+            // signal has no memory safety preconditions
+            unsafe {
+                libc::signal(signum, libc::SIG_DFL);
+                libc::raise(signum);
+            }
+        }
+        #[cfg(windows)]
+        {
+            let Ok(mut ctrl_c) = tokio::signal::windows::ctrl_c() else {
+                return;
+            };
+            while ctrl_c.recv().await.is_some() {
+                if !interrupts_claimed() {
+                    run_exit_hooks();
+                    // STATUS_CONTROL_C_EXIT
+                    std::process::exit(0xC000013Au32 as i32);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&ExitStatus::Code(101)), 101);
+        assert_eq!(exit_code(&ExitStatus::Signal(9)), 137);
+        // Windows NTSTATUS codes keep their bits
+        assert_eq!(
+            exit_code(&ExitStatus::Code(0xC000013A)),
+            0xC000013Au32 as i32
+        );
+    }
+
+    #[test]
+    fn test_exit_hooks_run_in_reverse_order_once() {
+        let hooks = ExitHooks::new();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for index in 1..=3 {
+            let order = order.clone();
+            hooks.register(Box::new(move || order.lock().unwrap().push(index)));
+        }
+        hooks.run();
+        hooks.run();
+        assert_eq!(*order.lock().unwrap(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_exit_hooks_survive_panicking_hook() {
+        let hooks = ExitHooks::new();
+        let ran = Arc::new(Mutex::new(false));
+        let ran_hook = ran.clone();
+        hooks.register(Box::new(move || *ran_hook.lock().unwrap() = true));
+        hooks.register(Box::new(|| panic!("cleanup failed")));
+        hooks.run();
+        assert!(*ran.lock().unwrap());
+    }
+
+    #[test]
+    fn test_interrupt_claims() {
+        let outer = claim_interrupts();
+        let inner = claim_interrupts();
+        drop(outer);
+        assert!(interrupts_claimed());
+        drop(inner);
+        assert!(!interrupts_claimed());
+    }
+
+    #[test]
+    fn test_exit_hooks_registered_while_running() {
+        let hooks = Arc::new(ExitHooks::new());
+        let ran = Arc::new(Mutex::new(false));
+        let (inner_hooks, inner_ran) = (hooks.clone(), ran.clone());
+        hooks.register(Box::new(move || {
+            inner_hooks.register(Box::new(move || *inner_ran.lock().unwrap() = true));
+        }));
+        hooks.run();
+        assert!(*ran.lock().unwrap());
+    }
+}
@@ -0,0 +1,262 @@
+//! Features of a package and what enabling them pulls in.
+//!
+//! [`feature_closure`] follows the `[features]` table the way Cargo does:
+//! features enabling other features, `dep:` entries enabling optional
+//! dependencies, `dep/feature` entries enabling a feature of a dependency
+//! (and the dependency, if optional), and weak `dep?/feature` entries that
+//! only apply when the dependency is enabled anyway:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::common;
+//! use cargo_plugin_utils::features::feature_closure;
+//!
+//! let package = common::find_package(None)?;
+//! let closure = feature_closure(&package, &["tls"])?;
+//! println!("tls enables {:?}", closure.optional_dependencies);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The features come from `cargo metadata`, which lists the implicit
+//! feature of an optional dependency not named with `dep:` as
+//! `name = ["dep:name"]`.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+use cargo_metadata::Package;
+
+/// A feature declared by a package, as listed by [`declared_features`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    /// Feature name
+    pub name: String,
+    /// The entries of the feature as written in `[features]`, e.g. `std`,
+    /// `dep:serde` or `serde?/std`
+    pub enables: Vec<String>,
+    /// Whether the `default` feature enables it, directly or indirectly
+    pub default: bool,
+}
+
+/// Everything enabling a set of features ends up enabling, as computed by
+/// [`feature_closure`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureClosure {
+    /// The features themselves and every feature they enable
+    pub features: BTreeSet<String>,
+    /// Optional dependencies that get enabled, by the name the package uses
+    /// for them (the `rename` if there is one)
+    pub optional_dependencies: BTreeSet<String>,
+    /// Features enabled on dependencies, by dependency name
+    pub dependency_features: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The features `package` declares, sorted by name, without `default`
+/// itself.
+pub fn declared_features(package: &Package) -> Vec<Feature> {
+    let defaults = default_features(package);
+    package
+        .features
+        .iter()
+        .filter(|(name, _)| *name != "default")
+        .map(|(name, enables)| Feature {
+            name: name.clone(),
+            enables: enables.clone(),
+            default: defaults.contains(name),
+        })
+        .collect()
+}
+
+/// The features enabled when `package` is used with its default features,
+/// without `default` itself; empty if it has no `default` feature.
+pub fn default_features(package: &Package) -> BTreeSet<String> {
+    if !package.features.contains_key("default") {
+        return BTreeSet::new();
+    }
+    let mut features = closure(package, ["default"]).features;
+    features.remove("default");
+    features
+}
+
+/// What enabling `features` of `package` (without the default features)
+/// enables in the end.
+///
+/// Fails if `package` has no feature of one of the given names.
+pub fn feature_closure(package: &Package, features: &[&str]) -> anyhow::Result<FeatureClosure> {
+    if let Some(unknown) = features
+        .iter()
+        .find(|feature| !package.features.contains_key(**feature))
+    {
+        anyhow::bail!(
+            "Package `{}` has no feature `{}` (in {})",
+            package.name,
+            unknown,
+            package.manifest_path
+        );
+    }
+    Ok(closure(package, features.iter().copied()))
+}
+
+fn closure<'a>(
+    package: &'a Package,
+    features: impl IntoIterator<Item = &'a str>,
+) -> FeatureClosure {
+    let optional: BTreeSet<&str> = package
+        .dependencies
+        .iter()
+        .filter(|dependency| dependency.optional)
+        .map(|dependency| dependency.rename.as_deref().unwrap_or(&dependency.name))
+        .collect();
+
+    let mut result = FeatureClosure::default();
+    let mut weak = Vec::new();
+    let mut pending: Vec<&str> = features.into_iter().collect();
+    while let Some(feature) = pending.pop() {
+        if !result.features.insert(feature.to_string()) {
+            continue;
+        }
+        let entries = package.features.get(feature).map_or(&[][..], Vec::as_slice);
+        for entry in entries {
+            if let Some(dependency) = entry.strip_prefix("dep:") {
+                result.optional_dependencies.insert(dependency.to_string());
+                continue;
+            }
+            let Some((dependency, dependency_feature)) = entry.split_once('/') else {
+                pending.push(entry);
+                continue;
+            };
+            if let Some(dependency) = dependency.strip_suffix('?') {
+                weak.push((dependency, dependency_feature));
+                continue;
+            }
+            // `dep/feature` enables an optional `dep`, with its implicit
+            // feature if it has one
+            if optional.contains(dependency) {
+                result.optional_dependencies.insert(dependency.to_string());
+                if package.features.contains_key(dependency) {
+                    pending.push(dependency);
+                }
+            }
+            add_dependency_feature(&mut result, dependency, dependency_feature);
+        }
+    }
+
+    for (dependency, feature) in weak {
+        if !optional.contains(dependency) || result.optional_dependencies.contains(dependency) {
+            add_dependency_feature(&mut result, dependency, feature);
+        }
+    }
+    result
+}
+
+fn add_dependency_feature(result: &mut FeatureClosure, dependency: &str, feature: &str) {
+    result
+        .dependency_features
+        .entry(dependency.to_string())
+        .or_default()
+        .insert(feature.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::write_file;
+
+    #[test]
+    fn test_feature_closure() {
+        let dir = tempfile::TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\n\
+             fmt = { path = \"fmt\", optional = true }\n\
+             net = { path = \"net\", optional = true }\n\
+             codec = { path = \"codec\", optional = true, package = \"codec-impl\" }\n\
+             base = { path = \"base\" }\n\n\
+             [features]\n\
+             default = [\"std\"]\n\
+             std = [\"base/std\", \"fmt?/std\", \"net?/std\"]\n\
+             tls = [\"dep:net\", \"net/tls\"]\n\
+             pretty = [\"fmt/color\"]\n\
+             full = [\"tls\", \"pretty\", \"std\"]\n",
+        );
+        write_file(dir.path(), "src/lib.rs", "");
+        for (name, features) in [
+            ("fmt", "std = []\ncolor = []\n"),
+            ("net", "std = []\ntls = []\n"),
+            ("codec-impl", ""),
+            ("base", "std = []\n"),
+        ] {
+            let path = if name == "codec-impl" { "codec" } else { name };
+            write_file(
+                dir.path(),
+                &format!("{}/Cargo.toml", path),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+                     [features]\n{}",
+                    name, features
+                ),
+            );
+            write_file(dir.path(), &format!("{}/src/lib.rs", path), "");
+        }
+        let metadata = crate::common::get_metadata(Some(&dir.path().join("Cargo.toml"))).unwrap();
+        let package = metadata.root_package().unwrap();
+
+        let declared = declared_features(package);
+        let names: Vec<(&str, bool)> = declared
+            .iter()
+            .map(|feature| (feature.name.as_str(), feature.default))
+            .collect();
+        // `fmt` and `codec` get implicit features, `net` doesn't, it is
+        // named with `dep:`
+        assert_eq!(
+            names,
+            [
+                ("codec", false),
+                ("fmt", false),
+                ("full", false),
+                ("pretty", false),
+                ("std", true),
+                ("tls", false)
+            ]
+        );
+        assert_eq!(
+            default_features(package),
+            BTreeSet::from(["std".to_string()])
+        );
+
+        let closure = feature_closure(package, &["tls"]).unwrap();
+        assert_eq!(closure.features, BTreeSet::from(["tls".to_string()]));
+        assert_eq!(
+            closure.optional_dependencies,
+            BTreeSet::from(["net".to_string()])
+        );
+
+        let closure = feature_closure(package, &["std", "tls", "pretty"]).unwrap();
+        let strings = |items: &[&str]| {
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<BTreeSet<_>>()
+        };
+        assert_eq!(closure.features, strings(&["fmt", "pretty", "std", "tls"]));
+        assert_eq!(closure.optional_dependencies, strings(&["fmt", "net"]));
+        assert_eq!(closure.dependency_features["base"], strings(&["std"]));
+        assert_eq!(
+            closure.dependency_features["fmt"],
+            strings(&["color", "std"])
+        );
+        assert_eq!(closure.dependency_features["net"], strings(&["std", "tls"]));
+
+        // Weak features alone don't enable their dependencies
+        let closure = feature_closure(package, &["std"]).unwrap();
+        assert!(closure.optional_dependencies.is_empty());
+        assert!(!closure.dependency_features.contains_key("net"));
+
+        let closure = feature_closure(package, &["codec"]).unwrap();
+        assert_eq!(closure.optional_dependencies, strings(&["codec"]));
+        assert!(feature_closure(package, &["missing"]).is_err());
+    }
+}
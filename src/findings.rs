@@ -0,0 +1,653 @@
+//! Lint-style findings for checker plugins.
+//!
+//! Plugins collect [`Finding`]s (severity, code, message, location and an
+//! optional fix) in a [`Findings`] list and then [`report`](Findings::report)
+//! them: as rustc-style diagnostics, or as JSON messages with
+//! `--message-format json`. Machine-applicable fixes can be turned into file
+//! edits with [`Findings::fix`]:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::findings::{
+//!     Finding,
+//!     Findings,
+//!     Fix,
+//!     Severity,
+//! };
+//! use cargo_plugin_utils::patch::PatchSet;
+//!
+//! let mut findings = Findings::new();
+//! findings.push(
+//!     Finding::new(
+//!         Severity::Warning,
+//!         "wildcard-version",
+//!         "`serde = \"*\"` is unpinned",
+//!     )
+//!     .at("Cargo.toml", 12, 9)
+//!     .with_fix(Fix::machine_applicable("pin the version").replace(
+//!         "Cargo.toml",
+//!         240..243,
+//!         "\"1\"",
+//!     )),
+//! );
+//!
+//! let logger = Logger::new();
+//! findings.report(&logger);
+//!
+//! let mut patch = PatchSet::new(".");
+//! findings.fix(&mut patch)?;
+//! patch.apply()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::ops::Range;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::logger::Logger;
+use crate::message::{
+    Message,
+    MessageFormat,
+};
+use crate::patch::PatchSet;
+
+// 64-bit FNV-1a, stable across platforms and Rust versions
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// How serious a finding is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    /// Informational
+    Note,
+    /// Should be fixed, but doesn't fail the check by default
+    Warning,
+    /// Fails the check
+    Error,
+}
+
+impl Severity {
+    /// The lowercase name used in diagnostics, e.g. `warning`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Note => "note",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+
+    fn style(self) -> console::Style {
+        match self {
+            Self::Note => console::Style::new().cyan().bold(),
+            Self::Warning => console::Style::new().yellow().bold(),
+            Self::Error => console::Style::new().red().bold(),
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where a finding was found. Lines and columns are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Location {
+    /// File, relative to the workspace root
+    pub file: PathBuf,
+    /// Line, if the finding isn't about the file as a whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// Column within `line`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How confident a fix is, following rustc's terminology.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Applicability {
+    /// Safe to apply automatically
+    MachineApplicable,
+    /// Probably right, but should be reviewed; never applied by
+    /// [`Findings::fix`]
+    MaybeIncorrect,
+}
+
+/// Replace a byte range of a file with new text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Replacement {
+    /// File, relative to the workspace root
+    pub file: PathBuf,
+    /// Byte range to replace (empty to insert)
+    pub range: Range<usize>,
+    /// The new text
+    pub text: String,
+}
+
+/// A suggested fix for a finding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fix {
+    /// What the fix does, e.g. `remove the dependency`
+    pub message: String,
+    /// Whether the fix can be applied automatically
+    pub applicability: Applicability,
+    /// The edits making up the fix
+    pub replacements: Vec<Replacement>,
+}
+
+impl Fix {
+    /// A fix that is safe to apply automatically.
+    pub fn machine_applicable(message: impl Into<String>) -> Self {
+        Self::new(message, Applicability::MachineApplicable)
+    }
+
+    /// A fix that should be reviewed before applying.
+    pub fn maybe_incorrect(message: impl Into<String>) -> Self {
+        Self::new(message, Applicability::MaybeIncorrect)
+    }
+
+    fn new(message: impl Into<String>, applicability: Applicability) -> Self {
+        Self {
+            message: message.into(),
+            applicability,
+            replacements: Vec::new(),
+        }
+    }
+
+    /// Add an edit replacing the byte `range` of `file` with `text`.
+    pub fn replace(
+        mut self,
+        file: impl Into<PathBuf>,
+        range: Range<usize>,
+        text: impl Into<String>,
+    ) -> Self {
+        self.replacements.push(Replacement {
+            file: file.into(),
+            range,
+            text: text.into(),
+        });
+        self
+    }
+}
+
+/// A single problem reported by a checker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    /// How serious the finding is
+    pub severity: Severity,
+    /// Stable identifier of the check, e.g. `wildcard-version`
+    pub code: String,
+    /// Human-readable description
+    pub message: String,
+    /// Where the problem is, if it has a location
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    /// Suggested fix, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+}
+
+impl Finding {
+    /// A finding without location or fix.
+    pub fn new(severity: Severity, code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: code.into(),
+            message: message.into(),
+            location: None,
+            fix: None,
+        }
+    }
+
+    /// Locate the finding at `line` and `column` of `file`.
+    pub fn at(mut self, file: impl Into<PathBuf>, line: usize, column: usize) -> Self {
+        self.location = Some(Location {
+            file: file.into(),
+            line: Some(line),
+            column: Some(column),
+        });
+        self
+    }
+
+    /// Locate the finding in `file` as a whole.
+    pub fn in_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.location = Some(Location {
+            file: file.into(),
+            line: None,
+            column: None,
+        });
+        self
+    }
+
+    /// Attach a suggested fix.
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    /// A stable identifier of the finding, for deduplication across runs.
+    ///
+    /// It covers the code, file and message but not the line and column, so
+    /// it survives unrelated edits that move the finding around. Identical
+    /// findings in one file share it; [`Findings::fingerprints`] tells them
+    /// apart.
+    pub fn fingerprint(&self) -> String {
+        self.fingerprint_of(0)
+    }
+
+    /// The fingerprint of the `occurrence`-th identical finding in its file,
+    /// counting from 0 (which is [`fingerprint`](Self::fingerprint)).
+    fn fingerprint_of(&self, occurrence: usize) -> String {
+        let file = self
+            .location
+            .as_ref()
+            .map(|location| crate::patch::path_for_patch(&location.file))
+            .unwrap_or_default();
+        let number = occurrence.to_string();
+        let mut parts = vec![self.code.as_str(), &file, &self.message];
+        if occurrence > 0 {
+            parts.push(&number);
+        }
+        let mut hash = FNV_OFFSET_BASIS;
+        for part in parts {
+            for &byte in part.as_bytes().iter().chain(&[0]) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+        format!("{:016x}", hash)
+    }
+
+    /// Line and column, for ordering identical findings within a file.
+    fn position(&self) -> (Option<usize>, Option<usize>) {
+        self.location
+            .as_ref()
+            .map_or((None, None), |location| (location.line, location.column))
+    }
+
+    /// Render the finding as a rustc-style diagnostic:
+    ///
+    /// ```text
+    /// warning[wildcard-version]: `serde = "*"` is unpinned
+    ///   --> Cargo.toml:12:9
+    ///   = help: pin the version
+    /// ```
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "{}: {}",
+            self.severity
+                .style()
+                .apply_to(format!("{}[{}]", self.severity, self.code)),
+            console::style(&self.message).bold()
+        );
+        if let Some(location) = &self.location {
+            text.push_str(&format!(
+                "\n  {} {}",
+                console::style("-->").blue().bold(),
+                location
+            ));
+        }
+        if let Some(fix) = &self.fix {
+            text.push_str(&format!(
+                "\n  {} {}",
+                console::style("= help:").bold(),
+                fix.message
+            ));
+        }
+        text
+    }
+}
+
+/// An ordered collection of findings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Findings {
+    findings: Vec<Finding>,
+}
+
+impl Findings {
+    /// An empty collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a finding.
+    pub fn push(&mut self, finding: Finding) {
+        self.findings.push(finding);
+    }
+
+    /// The findings, in the order they were added.
+    pub fn iter(&self) -> std::slice::Iter<'_, Finding> {
+        self.findings.iter()
+    }
+
+    /// The [fingerprint](Finding::fingerprint) of each finding, in order.
+    ///
+    /// Identical findings in one file are numbered from the top of the file,
+    /// and all but the first get the number mixed into their fingerprint, so
+    /// each fingerprint is unique within the set.
+    pub fn fingerprints(&self) -> Vec<String> {
+        let mut order: Vec<usize> = (0..self.findings.len()).collect();
+        order.sort_by_key(|&index| self.findings[index].position());
+        let mut occurrences = std::collections::HashMap::new();
+        let mut fingerprints = vec![String::new(); self.findings.len()];
+        for index in order {
+            let finding = &self.findings[index];
+            let occurrence = occurrences.entry(finding.fingerprint()).or_insert(0);
+            fingerprints[index] = finding.fingerprint_of(*occurrence);
+            *occurrence += 1;
+        }
+        fingerprints
+    }
+
+    /// Number of findings.
+    pub fn len(&self) -> usize {
+        self.findings.len()
+    }
+
+    /// Whether there are no findings.
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Number of findings of `severity`.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    /// Whether any finding is an error.
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// Report all findings, followed by a summary line.
+    ///
+    /// In human mode each finding is printed as a diagnostic (see
+    /// [`Finding::render`]); in JSON mode each is emitted as a
+    /// [`Message::Data`] of kind `finding`. Errors are counted by the logger
+    /// (see [`Logger::has_errors`]).
+    pub fn report(&self, logger: &Logger) {
+        for finding in self.iter() {
+            match logger.message_format() {
+                MessageFormat::Human => logger.print_message(&format!("{}\n", finding.render())),
+                MessageFormat::Json => {
+                    let data = serde_json::to_value(finding).unwrap_or_default();
+                    let _ = logger.emit(Message::Data {
+                        kind: "finding".to_string(),
+                        data,
+                    });
+                }
+            }
+        }
+
+        let errors = self.count(Severity::Error);
+        let warnings = self.count(Severity::Warning);
+        let summary = format!(
+            "{} error{}, {} warning{}",
+            errors,
+            if errors == 1 { "" } else { "s" },
+            warnings,
+            if warnings == 1 { "" } else { "s" }
+        );
+        if errors > 0 {
+            logger.error("Found", &summary);
+        } else if warnings > 0 {
+            logger.warning("Found", &summary);
+        } else {
+            logger.status_permanent("Checked", "no findings");
+        }
+    }
+
+    /// Add the machine-applicable fixes to `patch` and return how many were
+    /// applied.
+    ///
+    /// Fixes whose replacements overlap an already applied fix are skipped.
+    /// A replacement pointing outside its file is an error, and leaves
+    /// `patch` as it was. Paths are relative to the patch root.
+    pub fn fix(&self, patch: &mut PatchSet) -> anyhow::Result<usize> {
+        let fixes: Vec<&Fix> = self
+            .iter()
+            .filter_map(|finding| finding.fix.as_ref())
+            .filter(|fix| fix.applicability == Applicability::MachineApplicable)
+            .collect();
+
+        // Accept fixes in order as long as none of their ranges overlap
+        let mut accepted: Vec<&Replacement> = Vec::new();
+        let mut applied = 0;
+        for fix in fixes {
+            let overlaps = fix.replacements.iter().any(|replacement| {
+                accepted.iter().any(|other| {
+                    other.file == replacement.file
+                        && ranges_overlap(&other.range, &replacement.range)
+                })
+            });
+            if !overlaps {
+                accepted.extend(&fix.replacements);
+                applied += 1;
+            }
+        }
+
+        // Apply back to front so earlier offsets stay valid, to a copy that
+        // only replaces `patch` once every edit succeeded
+        accepted.sort_by(|a, b| {
+            (&a.file, b.range.start, b.range.end).cmp(&(&b.file, a.range.start, a.range.end))
+        });
+        let mut scratch = patch.clone();
+        for replacement in accepted {
+            let file: &Path = &replacement.file;
+            let mut error = None;
+            scratch.edit(file, |contents| {
+                let mut contents = contents.to_string();
+                if contents.is_char_boundary(replacement.range.start)
+                    && contents.is_char_boundary(replacement.range.end)
+                    && replacement.range.start <= replacement.range.end
+                {
+                    contents.replace_range(replacement.range.clone(), &replacement.text);
+                } else {
+                    error = Some(replacement.range.clone());
+                }
+                contents
+            })?;
+            if let Some(range) = error {
+                anyhow::bail!(
+                    "Fix range {:?} is outside of {} or splits a character",
+                    range,
+                    file.display()
+                );
+            }
+        }
+        *patch = scratch;
+        Ok(applied)
+    }
+}
+
+impl<'a> IntoIterator for &'a Findings {
+    type Item = &'a Finding;
+    type IntoIter = std::slice::Iter<'a, Finding>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Extend<Finding> for Findings {
+    fn extend<T: IntoIterator<Item = Finding>>(&mut self, iter: T) {
+        self.findings.extend(iter);
+    }
+}
+
+impl FromIterator<Finding> for Findings {
+    fn from_iter<T: IntoIterator<Item = Finding>>(iter: T) -> Self {
+        Self {
+            findings: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Whether two ranges overlap; empty ranges (insertions) only overlap ranges
+/// that strictly contain their position, or insertions at the same position.
+fn ranges_overlap(a: &Range<usize>, b: &Range<usize>) -> bool {
+    if a.is_empty() && b.is_empty() {
+        return a.start == b.start;
+    }
+    a.start < b.end && b.start < a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wildcard(line: usize, range: Range<usize>) -> Finding {
+        Finding::new(Severity::Warning, "wildcard-version", "unpinned dependency")
+            .at("Cargo.toml", line, 9)
+            .with_fix(Fix::machine_applicable("pin the version").replace(
+                "Cargo.toml",
+                range,
+                "\"1\"",
+            ))
+    }
+
+    #[test]
+    fn test_finding_render() {
+        let finding = wildcard(2, 0..0);
+        assert_eq!(
+            console::strip_ansi_codes(&finding.render()),
+            "warning[wildcard-version]: unpinned dependency\n  --> Cargo.toml:2:9\n  = help: pin the version"
+        );
+        let finding =
+            Finding::new(Severity::Error, "license", "missing license").in_file("Cargo.toml");
+        assert_eq!(
+            console::strip_ansi_codes(&finding.render()),
+            "error[license]: missing license\n  --> Cargo.toml"
+        );
+    }
+
+    #[test]
+    fn test_findings_counts() {
+        let findings: Findings = [
+            wildcard(1, 0..0),
+            Finding::new(Severity::Error, "license", "missing license"),
+            Finding::new(Severity::Note, "info", "fyi"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings.count(Severity::Warning), 1);
+        assert!(findings.has_errors());
+    }
+
+    #[test]
+    fn test_finding_fingerprint() {
+        let fingerprint = wildcard(2, 0..0).fingerprint();
+        assert_eq!(fingerprint.len(), 16);
+        // Moving the finding keeps the fingerprint, changing it doesn't
+        assert_eq!(wildcard(7, 3..4).fingerprint(), fingerprint);
+        let other = Finding::new(Severity::Warning, "wildcard-version", "other message");
+        assert_ne!(other.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_findings_fingerprints_repeated() {
+        let findings: Findings = [wildcard(9, 0..0), wildcard(2, 0..0), wildcard(5, 0..0)]
+            .into_iter()
+            .collect();
+        let fingerprints = findings.fingerprints();
+        // Numbered from the top of the file, the first keeps the plain one
+        assert_eq!(fingerprints[1], wildcard(2, 0..0).fingerprint());
+        assert_ne!(fingerprints[0], fingerprints[2]);
+        assert_ne!(fingerprints[0], fingerprints[1]);
+        assert_ne!(fingerprints[1], fingerprints[2]);
+        // Stable when the findings are reported in another order
+        let reversed: Findings = findings.iter().rev().cloned().collect();
+        let mut again = reversed.fingerprints();
+        again.reverse();
+        assert_eq!(again, fingerprints);
+    }
+
+    #[test]
+    fn test_finding_json() {
+        let finding =
+            Finding::new(Severity::Error, "license", "missing license").in_file("Cargo.toml");
+        let json = serde_json::to_string(&finding).unwrap();
+        assert_eq!(
+            json,
+            r#"{"severity":"error","code":"license","message":"missing license","location":{"file":"Cargo.toml"}}"#
+        );
+        assert_eq!(serde_json::from_str::<Finding>(&json).unwrap(), finding);
+    }
+
+    #[test]
+    fn test_findings_fix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manifest = "[dependencies]\nserde = \"*\"\ntoml = \"*\"\n";
+        std::fs::write(dir.path().join("Cargo.toml"), manifest).unwrap();
+
+        let serde = manifest.find("\"*\"").unwrap();
+        let toml = manifest.rfind("\"*\"").unwrap();
+        let findings: Findings = [
+            wildcard(2, serde..serde + 3),
+            wildcard(3, toml..toml + 3),
+            // Overlaps the first fix
+            wildcard(2, serde..serde + 1),
+            Finding::new(Severity::Warning, "review", "needs review")
+                .with_fix(Fix::maybe_incorrect("rewrite").replace("Cargo.toml", 0..1, "#")),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut patch = PatchSet::new(dir.path());
+        assert_eq!(findings.fix(&mut patch).unwrap(), 2);
+        assert_eq!(
+            patch.read("Cargo.toml").unwrap().unwrap(),
+            "[dependencies]\nserde = \"1\"\ntoml = \"1\"\n"
+        );
+    }
+
+    #[test]
+    fn test_findings_fix_out_of_range() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "short").unwrap();
+        std::fs::write(dir.path().join("A.toml"), "a = \"*\"\n").unwrap();
+        let valid = Finding::new(Severity::Warning, "wildcard-version", "unpinned")
+            .with_fix(Fix::machine_applicable("pin").replace("A.toml", 4..7, "\"1\""));
+        let findings: Findings = [valid, wildcard(1, 10..20)].into_iter().collect();
+        let mut patch = PatchSet::new(dir.path());
+        assert!(findings.fix(&mut patch).is_err());
+        // The valid fix isn't left half-applied
+        assert!(patch.is_empty());
+    }
+
+    #[test]
+    fn test_ranges_overlap() {
+        assert!(ranges_overlap(&(0..5), &(4..6)));
+        assert!(!ranges_overlap(&(0..5), &(5..6)));
+        assert!(ranges_overlap(&(3..3), &(3..3)));
+        assert!(ranges_overlap(&(3..3), &(0..5)));
+        assert!(!ranges_overlap(&(5..5), &(0..5)));
+    }
+}
@@ -0,0 +1,604 @@
+//! Workspace dependency graph and its export formats.
+//!
+//! [`WorkspaceGraph`] holds the workspace members and the dependencies
+//! between them. [`export`] writes it as Graphviz DOT, Mermaid or JSON, with
+//! the version, publishability and (if known) changed state of each package,
+//! so visualization plugins and docs generators share one exporter:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::graph::{
+//!     Format,
+//!     WorkspaceGraph,
+//!     export,
+//! };
+//!
+//! let metadata = cargo_plugin_utils::get_metadata(None)?;
+//! let graph = WorkspaceGraph::from_metadata(&metadata);
+//! export(&graph, Format::Mermaid, None)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The graph answers the usual questions about the workspace too, such as
+//! which members [depend on](WorkspaceGraph::dependents_of) a given one.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::Context;
+use cargo_metadata::semver::Version;
+use serde::Serialize;
+
+/// Kind of a dependency edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyKind {
+    /// `[dependencies]`
+    Normal,
+    /// `[dev-dependencies]`
+    Dev,
+    /// `[build-dependencies]`
+    Build,
+}
+
+/// A workspace member.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    /// Package name
+    pub name: String,
+    /// Package version
+    pub version: Version,
+    /// Path to the package's Cargo.toml
+    #[serde(skip)]
+    pub manifest_path: PathBuf,
+    /// Whether the package may be published (`publish` is not `false`)
+    pub publishable: bool,
+    /// Whether the package changed since the compared ref; `None` if unknown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed: Option<bool>,
+}
+
+impl Node {
+    /// Directory containing the package's Cargo.toml.
+    pub fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(Path::new(""))
+    }
+}
+
+/// A dependency of one workspace member on another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Edge {
+    /// Index of the dependent package in [`WorkspaceGraph::nodes`]
+    pub from: usize,
+    /// Index of the dependency in [`WorkspaceGraph::nodes`]
+    pub to: usize,
+    /// Dependency kind
+    pub kind: DependencyKind,
+}
+
+/// Workspace members and the dependencies between them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkspaceGraph {
+    /// Workspace members, sorted by name
+    pub nodes: Vec<Node>,
+    /// Intra-workspace dependencies, sorted and without duplicates
+    pub edges: Vec<Edge>,
+}
+
+impl WorkspaceGraph {
+    /// Build the graph of the workspace members in `metadata`.
+    ///
+    /// Dependencies on packages outside the workspace are not included.
+    /// Target-specific duplicates of the same dependency collapse into one
+    /// edge.
+    pub fn from_metadata(metadata: &cargo_metadata::Metadata) -> Self {
+        let mut members: Vec<&cargo_metadata::Package> = metadata.workspace_packages();
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let nodes: Vec<Node> = members
+            .iter()
+            .map(|package| Node {
+                name: package.name.to_string(),
+                version: package.version.clone(),
+                manifest_path: package.manifest_path.clone().into_std_path_buf(),
+                publishable: package
+                    .publish
+                    .as_ref()
+                    .is_none_or(|registries| !registries.is_empty()),
+                changed: None,
+            })
+            .collect();
+
+        let mut edges = BTreeSet::new();
+        for (from, package) in members.iter().enumerate() {
+            for dependency in &package.dependencies {
+                if dependency.path.is_none() {
+                    continue;
+                }
+                let Some(to) = nodes.iter().position(|node| node.name == dependency.name) else {
+                    continue;
+                };
+                let kind = match dependency.kind {
+                    cargo_metadata::DependencyKind::Development => DependencyKind::Dev,
+                    cargo_metadata::DependencyKind::Build => DependencyKind::Build,
+                    _ => DependencyKind::Normal,
+                };
+                edges.insert(Edge { from, to, kind });
+            }
+        }
+
+        Self {
+            nodes,
+            edges: edges.into_iter().collect(),
+        }
+    }
+
+    /// Index of the node named `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// Members the member `name` depends on directly, with the kind of each
+    /// dependency; a member depended on in several ways is listed once per
+    /// kind. Empty if there is no member `name`.
+    pub fn dependencies_of(&self, name: &str) -> Vec<(&Node, DependencyKind)> {
+        let Some(index) = self.index_of(name) else {
+            return Vec::new();
+        };
+        self.edges
+            .iter()
+            .filter(|edge| edge.from == index)
+            .map(|edge| (&self.nodes[edge.to], edge.kind))
+            .collect()
+    }
+
+    /// Members that depend on the member `name` directly, with the kind of
+    /// each dependency, like [`dependencies_of`](Self::dependencies_of).
+    pub fn dependents_of(&self, name: &str) -> Vec<(&Node, DependencyKind)> {
+        let Some(index) = self.index_of(name) else {
+            return Vec::new();
+        };
+        self.edges
+            .iter()
+            .filter(|edge| edge.to == index)
+            .map(|edge| (&self.nodes[edge.from], edge.kind))
+            .collect()
+    }
+
+    /// Members that depend on the member `name` directly or through other
+    /// members, sorted by name. A dev-dependency only counts as the last
+    /// link: it affects the tests of the dev-dependent, not its dependents.
+    /// The member itself is only included if it is part of a cycle (through
+    /// dev-dependencies).
+    pub fn transitive_dependents_of(&self, name: &str) -> Vec<&Node> {
+        let Some(start) = self.index_of(name) else {
+            return Vec::new();
+        };
+        let mut found = BTreeSet::new();
+        let mut followed = BTreeSet::from([start]);
+        let mut pending = vec![start];
+        while let Some(index) = pending.pop() {
+            for edge in self.edges.iter().filter(|edge| edge.to == index) {
+                found.insert(edge.from);
+                if edge.kind != DependencyKind::Dev && followed.insert(edge.from) {
+                    pending.push(edge.from);
+                }
+            }
+        }
+        // Nodes are sorted by name, so their indices are too
+        found.into_iter().map(|index| &self.nodes[index]).collect()
+    }
+
+    /// Set the `changed` attribute from a list of changed files.
+    ///
+    /// A package counts as changed when a file below its directory changed;
+    /// files of nested packages are attributed to the innermost one. Relative
+    /// paths are resolved against `root` (usually the repository root).
+    pub fn mark_changed_files<P: AsRef<Path>>(&mut self, root: &Path, files: &[P]) {
+        for node in &mut self.nodes {
+            node.changed = Some(false);
+        }
+        for file in files {
+            let file = root.join(file);
+            let owner = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| file.starts_with(node.dir()))
+                .max_by_key(|(_, node)| node.dir().components().count())
+                .map(|(index, _)| index);
+            if let Some(index) = owner {
+                self.nodes[index].changed = Some(true);
+            }
+        }
+    }
+}
+
+/// Output format for [`export`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+    /// JSON with `nodes` and `edges` arrays
+    Json,
+}
+
+/// Write the graph in `format` to `path`, or to stdout if `path` is `None`.
+pub fn export(graph: &WorkspaceGraph, format: Format, path: Option<&Path>) -> anyhow::Result<()> {
+    let rendered = render(graph, format);
+    match path {
+        Some(path) => std::fs::write(path, rendered)
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout
+                .write_all(rendered.as_bytes())
+                .and_then(|()| stdout.flush())
+                .context("Failed to write graph to stdout")
+        }
+    }
+}
+
+/// Render the graph in `format`.
+pub fn render(graph: &WorkspaceGraph, format: Format) -> String {
+    match format {
+        Format::Dot => render_dot(graph),
+        Format::Mermaid => render_mermaid(graph),
+        Format::Json => render_json(graph),
+    }
+}
+
+fn render_dot(graph: &WorkspaceGraph) -> String {
+    let mut out = String::from("digraph workspace {\n    node [shape=box];\n");
+    for node in &graph.nodes {
+        let (name, version) = (
+            dot_escape(&node.name),
+            dot_escape(&node.version.to_string()),
+        );
+        let _ = write!(
+            out,
+            "    \"{}\" [label=\"{}\\n{}\", version=\"{}\", publishable={}",
+            name, name, version, version, node.publishable
+        );
+        if let Some(changed) = node.changed {
+            let _ = write!(out, ", changed={}", changed);
+        }
+        let mut styles = Vec::new();
+        if !node.publishable {
+            styles.push("dashed");
+        }
+        if node.changed == Some(true) {
+            styles.push("bold");
+        }
+        if !styles.is_empty() {
+            let _ = write!(out, ", style=\"{}\"", styles.join(","));
+        }
+        out.push_str("];\n");
+    }
+    for edge in &graph.edges {
+        let _ = write!(
+            out,
+            "    \"{}\" -> \"{}\"",
+            dot_escape(&graph.nodes[edge.from].name),
+            dot_escape(&graph.nodes[edge.to].name)
+        );
+        match edge.kind {
+            DependencyKind::Normal => out.push_str(";\n"),
+            DependencyKind::Dev => out.push_str(" [kind=\"dev\", style=dashed];\n"),
+            DependencyKind::Build => out.push_str(" [kind=\"build\", style=dotted];\n"),
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_mermaid(graph: &WorkspaceGraph) -> String {
+    let mut out = String::from("graph TD\n");
+    for (index, node) in graph.nodes.iter().enumerate() {
+        let label = format!("{} {}", node.name, node.version);
+        let _ = writeln!(out, "    n{}[\"{}\"]", index, mermaid_escape(&label));
+    }
+    for edge in &graph.edges {
+        let arrow = match edge.kind {
+            DependencyKind::Normal => "-->",
+            DependencyKind::Dev => "-.->|dev|",
+            DependencyKind::Build => "-.->|build|",
+        };
+        let _ = writeln!(out, "    n{} {} n{}", edge.from, arrow, edge.to);
+    }
+    let class_members = |predicate: &dyn Fn(&Node) -> bool| {
+        graph
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| predicate(node))
+            .map(|(index, _)| format!("n{}", index))
+            .collect::<Vec<_>>()
+    };
+    let unpublished = class_members(&|node| !node.publishable);
+    if !unpublished.is_empty() {
+        out.push_str("    classDef unpublished stroke-dasharray: 5 5\n");
+        let _ = writeln!(out, "    class {} unpublished", unpublished.join(","));
+    }
+    let changed = class_members(&|node| node.changed == Some(true));
+    if !changed.is_empty() {
+        out.push_str("    classDef changed stroke-width: 3px\n");
+        let _ = writeln!(out, "    class {} changed", changed.join(","));
+    }
+    out
+}
+
+/// Escape `text` for a quoted DOT string.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Escape `text` for a quoted Mermaid label, with entity codes.
+fn mermaid_escape(text: &str) -> String {
+    text.replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+fn render_json(graph: &WorkspaceGraph) -> String {
+    #[derive(Serialize)]
+    struct JsonEdge<'a> {
+        from: &'a str,
+        to: &'a str,
+        kind: DependencyKind,
+    }
+
+    #[derive(Serialize)]
+    struct JsonGraph<'a> {
+        nodes: &'a [Node],
+        edges: Vec<JsonEdge<'a>>,
+    }
+
+    let json = JsonGraph {
+        nodes: &graph.nodes,
+        edges: graph
+            .edges
+            .iter()
+            .map(|edge| JsonEdge {
+                from: &graph.nodes[edge.from].name,
+                to: &graph.nodes[edge.to].name,
+                kind: edge.kind,
+            })
+            .collect(),
+    };
+    let mut out = serde_json::to_string_pretty(&json).unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testing::write_file;
+
+    fn node(name: &str, publishable: bool, changed: Option<bool>) -> Node {
+        Node {
+            name: name.to_string(),
+            version: Version::new(0, 1, 0),
+            manifest_path: PathBuf::from(format!("/ws/crates/{}/Cargo.toml", name)),
+            publishable,
+            changed,
+        }
+    }
+
+    fn sample() -> WorkspaceGraph {
+        WorkspaceGraph {
+            nodes: vec![
+                node("app", false, Some(true)),
+                node("core", true, Some(false)),
+            ],
+            edges: vec![
+                Edge {
+                    from: 0,
+                    to: 1,
+                    kind: DependencyKind::Normal,
+                },
+                Edge {
+                    from: 0,
+                    to: 1,
+                    kind: DependencyKind::Dev,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_render_dot() {
+        let dot = render(&sample(), Format::Dot);
+        assert!(dot.starts_with("digraph workspace {"));
+        assert!(dot.contains(
+            "\"app\" [label=\"app\\n0.1.0\", version=\"0.1.0\", publishable=false, changed=true, style=\"dashed,bold\"];"
+        ));
+        assert!(dot.contains("\"app\" -> \"core\";"));
+        assert!(dot.contains("\"app\" -> \"core\" [kind=\"dev\", style=dashed];"));
+    }
+
+    #[test]
+    fn test_render_mermaid() {
+        let mermaid = render(&sample(), Format::Mermaid);
+        assert_eq!(
+            mermaid,
+            "graph TD\n    n0[\"app 0.1.0\"]\n    n1[\"core 0.1.0\"]\n    n0 --> n1\n    n0 -.->|dev| n1\n    classDef unpublished stroke-dasharray: 5 5\n    class n0 unpublished\n    classDef changed stroke-width: 3px\n    class n0 changed\n"
+        );
+    }
+
+    #[test]
+    fn test_render_escapes_labels() {
+        let graph = WorkspaceGraph {
+            nodes: vec![node("a\"b\\c", true, None), node("x[#y]<z>", true, None)],
+            edges: vec![Edge {
+                from: 0,
+                to: 1,
+                kind: DependencyKind::Normal,
+            }],
+        };
+        let dot = render(&graph, Format::Dot);
+        assert!(dot.contains("\"a\\\"b\\\\c\" [label=\"a\\\"b\\\\c\\n0.1.0\""));
+        assert!(dot.contains("\"a\\\"b\\\\c\" -> \"x[#y]<z>\";"));
+        let mermaid = render(&graph, Format::Mermaid);
+        assert!(mermaid.contains("n0[\"a#quot;b\\c 0.1.0\"]"));
+        assert!(mermaid.contains("n1[\"x[#35;y]#lt;z#gt; 0.1.0\"]"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&sample(), Format::Json)).unwrap();
+        assert_eq!(json["nodes"][0]["name"], "app");
+        assert_eq!(json["nodes"][0]["version"], "0.1.0");
+        assert_eq!(json["nodes"][0]["publishable"], false);
+        assert_eq!(json["nodes"][1]["changed"], false);
+        assert_eq!(json["edges"][1]["from"], "app");
+        assert_eq!(json["edges"][1]["to"], "core");
+        assert_eq!(json["edges"][1]["kind"], "dev");
+    }
+
+    #[test]
+    fn test_export_to_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("graph.dot");
+        export(&sample(), Format::Dot, Some(&path)).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, render(&sample(), Format::Dot));
+    }
+
+    #[test]
+    fn test_dependency_queries() {
+        let mut graph = sample();
+        graph.nodes.push(node("tool", true, None));
+        graph.edges.push(Edge {
+            from: 2,
+            to: 0,
+            kind: DependencyKind::Build,
+        });
+
+        let names = |nodes: Vec<(&Node, DependencyKind)>| {
+            nodes
+                .into_iter()
+                .map(|(node, kind)| (node.name.clone(), kind))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(graph.dependencies_of("app")),
+            [
+                ("core".to_string(), DependencyKind::Normal),
+                ("core".to_string(), DependencyKind::Dev)
+            ]
+        );
+        assert_eq!(
+            names(graph.dependents_of("app")),
+            [("tool".to_string(), DependencyKind::Build)]
+        );
+        assert!(graph.dependencies_of("core").is_empty());
+        assert!(graph.dependents_of("missing").is_empty());
+
+        let transitive: Vec<&str> = graph
+            .transitive_dependents_of("core")
+            .into_iter()
+            .map(|node| node.name.as_str())
+            .collect();
+        assert_eq!(transitive, ["app", "tool"]);
+        assert!(graph.transitive_dependents_of("tool").is_empty());
+    }
+
+    #[test]
+    fn test_transitive_dependents_stop_at_dev_dependencies() {
+        let edge = |from, to, kind| Edge { from, to, kind };
+        // a <-dev- b <-normal- c, and a <-normal- d <-dev- e
+        let graph = WorkspaceGraph {
+            nodes: ["a", "b", "c", "d", "e"]
+                .into_iter()
+                .map(|name| node(name, true, None))
+                .collect(),
+            edges: vec![
+                edge(1, 0, DependencyKind::Dev),
+                edge(2, 1, DependencyKind::Normal),
+                edge(3, 0, DependencyKind::Normal),
+                edge(4, 3, DependencyKind::Dev),
+            ],
+        };
+        let transitive: Vec<&str> = graph
+            .transitive_dependents_of("a")
+            .into_iter()
+            .map(|node| node.name.as_str())
+            .collect();
+        assert_eq!(transitive, ["b", "d", "e"]);
+    }
+
+    #[test]
+    fn test_mark_changed_files() {
+        let mut graph = sample();
+        graph.nodes.push(Node {
+            manifest_path: PathBuf::from("/ws/crates/app/nested/Cargo.toml"),
+            ..node("nested", true, None)
+        });
+        graph.mark_changed_files(
+            Path::new("/ws"),
+            &["crates/app/nested/src/lib.rs", "README.md"],
+        );
+        assert_eq!(graph.nodes[0].changed, Some(false));
+        assert_eq!(graph.nodes[1].changed, Some(false));
+        assert_eq!(graph.nodes[2].changed, Some(true));
+    }
+
+    #[test]
+    fn test_from_metadata_workspace() {
+        let dir = TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"core\"]\nresolver = \"2\"\n",
+        );
+        write_file(
+            dir.path(),
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.2.0\"\nedition = \"2021\"\npublish = false\n\n[dependencies]\ncore = { path = \"../core\" }\n\n[dev-dependencies]\ncore = { path = \"../core\" }\n",
+        );
+        write_file(dir.path(), "app/src/main.rs", "fn main() {}\n");
+        write_file(
+            dir.path(),
+            "core/Cargo.toml",
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        );
+        write_file(dir.path(), "core/src/lib.rs", "");
+
+        let metadata = crate::common::get_metadata(Some(&dir.path().join("Cargo.toml"))).unwrap();
+        let graph = WorkspaceGraph::from_metadata(&metadata);
+        let names: Vec<&str> = graph.nodes.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, vec!["app", "core"]);
+        assert!(!graph.nodes[0].publishable);
+        assert!(graph.nodes[1].publishable);
+        assert_eq!(graph.index_of("core"), Some(1));
+        assert_eq!(
+            graph.edges,
+            vec![
+                Edge {
+                    from: 0,
+                    to: 1,
+                    kind: DependencyKind::Normal,
+                },
+                Edge {
+                    from: 0,
+                    to: 1,
+                    kind: DependencyKind::Dev,
+                },
+            ]
+        );
+    }
+}
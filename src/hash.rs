@@ -0,0 +1,312 @@
+//! SHA-256 digests of files and directory trees.
+//!
+//! Files are hashed on all CPU cores. Directories are walked like git sees
+//! them: `.gitignore`, `.ignore` and `.git/info/exclude` rules apply, so build
+//! output and editor droppings don't change the digest of a source tree.
+//!
+//! ```no_run
+//! use cargo_plugin_utils::hash;
+//!
+//! let sources = hash::digest_dir("crates/core")?;
+//! for (path, digest) in hash::digest_files(["Cargo.toml", "Cargo.lock"])? {
+//!     println!("{}  {}", digest, path.display());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::io::Read;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+
+use anyhow::Context;
+use sha2::{
+    Digest as _,
+    Sha256,
+};
+
+use crate::logger::Logger;
+
+/// A SHA-256 digest, displayed and serialized as lowercase hex.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// Digest of `bytes`.
+    pub fn of(bytes: impl AsRef<[u8]>) -> Self {
+        Self(Sha256::digest(bytes).into())
+    }
+
+    /// The raw digest bytes.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Digest {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl std::fmt::Debug for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+impl std::str::FromStr for Digest {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            hex.len() == 64 && hex.bytes().all(|byte| byte.is_ascii_hexdigit()),
+            "Invalid SHA-256 digest `{}`",
+            hex
+        );
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair)?;
+            *byte = u8::from_str_radix(pair, 16)
+                .with_context(|| format!("Invalid SHA-256 digest `{}`", hex))?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl serde::Serialize for Digest {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Digest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Digest of the contents of one file.
+pub fn digest_file(path: impl AsRef<Path>) -> anyhow::Result<Digest> {
+    let path = path.as_ref();
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(Digest(hasher.finalize().into()))
+}
+
+/// Digests of the contents of `paths`, hashed in parallel and returned in
+/// the given order.
+pub fn digest_files<I, P>(paths: I) -> anyhow::Result<Vec<(PathBuf, Digest)>>
+where
+    I: IntoIterator<Item = P>,
+    P: Into<PathBuf>,
+{
+    let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+    let digests = digest_parallel(&paths, || {})?;
+    Ok(paths.into_iter().zip(digests).collect())
+}
+
+/// Like [`digest_files`], showing a `Hashing` progress line on `logger`.
+pub fn digest_files_with_progress<I, P>(
+    logger: &mut Logger,
+    paths: I,
+) -> anyhow::Result<Vec<(PathBuf, Digest)>>
+where
+    I: IntoIterator<Item = P>,
+    P: Into<PathBuf>,
+{
+    let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+    let total = paths.len();
+    logger.progress(&format!("Hashing 0/{} files", total));
+    let done = AtomicUsize::new(0);
+    let shared: &Logger = logger;
+    let digests = digest_parallel(&paths, || {
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        shared.set_progress_message(&format!("Hashing {}/{} files", done, total));
+    });
+    logger.clear_status();
+    Ok(paths.into_iter().zip(digests?).collect())
+}
+
+/// Files below `dir` that git would consider, sorted.
+///
+/// `.gitignore` files apply even outside of a git checkout; hidden files are
+/// included, the `.git` directory is not.
+pub fn list_files(dir: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let walker = ignore::WalkBuilder::new(dir)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = entry.with_context(|| format!("Failed to list {}", dir.display()))?;
+        if entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+        {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Digest of a directory tree: the relative paths and contents of its
+/// [files](list_files).
+///
+/// Renaming, adding or removing a file changes the digest; moving the whole
+/// directory doesn't.
+pub fn digest_dir(dir: impl AsRef<Path>) -> anyhow::Result<Digest> {
+    let dir = dir.as_ref();
+    let files = list_files(dir)?;
+    let digests = digest_parallel(&files, || {})?;
+    let mut hasher = Sha256::new();
+    for (file, digest) in files.iter().zip(digests) {
+        let relative = file.strip_prefix(dir).unwrap_or(file);
+        let relative = crate::patch::path_for_patch(relative);
+        hasher.update((relative.len() as u64).to_le_bytes());
+        hasher.update(relative.as_bytes());
+        hasher.update(digest.0);
+    }
+    Ok(Digest(hasher.finalize().into()))
+}
+
+/// Hash `paths` on up to one thread per CPU, calling `on_done` after each
+/// file.
+fn digest_parallel(paths: &[PathBuf], on_done: impl Fn() + Sync) -> anyhow::Result<Vec<Digest>> {
+    let threads = std::thread::available_parallelism().map_or(1, std::num::NonZero::get);
+    let chunk_size = paths.len().div_ceil(threads).max(1);
+    let hash_chunk = |chunk: &[PathBuf]| -> anyhow::Result<Vec<Digest>> {
+        chunk
+            .iter()
+            .map(|path| {
+                let digest = digest_file(path);
+                on_done();
+                digest
+            })
+            .collect()
+    };
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || hash_chunk(chunk)))
+            .collect();
+        let mut digests = Vec::with_capacity(paths.len());
+        for worker in workers {
+            let chunk = worker
+                .join()
+                .map_err(|_| anyhow::anyhow!("Hashing thread panicked"))??;
+            digests.extend(chunk);
+        }
+        Ok(digests)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of "hello\n".
+    const HELLO: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    #[test]
+    fn test_digest_display_and_parse() {
+        let digest = Digest::of("hello\n");
+        assert_eq!(digest.to_string(), HELLO);
+        assert_eq!(HELLO.parse::<Digest>().unwrap(), digest);
+        assert!("abc".parse::<Digest>().is_err());
+        assert!("zz".repeat(32).parse::<Digest>().is_err());
+        // from_str_radix would take a sign
+        assert!(format!("+{}", &HELLO[1..]).parse::<Digest>().is_err());
+        assert_eq!(
+            serde_json::to_string(&digest).unwrap(),
+            format!("\"{}\"", HELLO)
+        );
+    }
+
+    #[test]
+    fn test_digest_files_keeps_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths: Vec<_> = (0..20)
+            .map(|index| {
+                let path = dir.path().join(format!("{}.txt", index));
+                std::fs::write(&path, format!("{}\n", index)).unwrap();
+                path
+            })
+            .collect();
+        let digests = digest_files(&paths).unwrap();
+        assert_eq!(digests.len(), 20);
+        for (index, (path, digest)) in digests.iter().enumerate() {
+            assert_eq!(path, &paths[index]);
+            assert_eq!(*digest, Digest::of(format!("{}\n", index)));
+        }
+        assert!(digest_files([dir.path().join("missing")]).is_err());
+    }
+
+    #[test]
+    fn test_digest_files_with_progress() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths: Vec<_> = (0..5)
+            .map(|index| {
+                let path = dir.path().join(format!("{}.txt", index));
+                std::fs::write(&path, format!("{}\n", index)).unwrap();
+                path
+            })
+            .collect();
+        let mut logger = Logger::new();
+        let digests = digest_files_with_progress(&mut logger, &paths).unwrap();
+        assert_eq!(digests, digest_files(&paths).unwrap());
+        assert!(digest_files_with_progress(&mut logger, [dir.path().join("missing")]).is_err());
+        logger.finish();
+    }
+
+    #[test]
+    fn test_digest_dir_respects_gitignore() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join(".gitignore"), "/target\n*.log\n").unwrap();
+        std::fs::write(root.join("src/lib.rs"), "fn main() {}\n").unwrap();
+        let before = digest_dir(root).unwrap();
+
+        // Ignored files don't count
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join("target/out"), "binary").unwrap();
+        std::fs::write(root.join("debug.log"), "log").unwrap();
+        assert_eq!(digest_dir(root).unwrap(), before);
+        let files = list_files(root).unwrap();
+        assert_eq!(files, [root.join(".gitignore"), root.join("src/lib.rs")]);
+
+        // Contents and names do
+        std::fs::write(root.join("src/lib.rs"), "fn main() { }\n").unwrap();
+        let edited = digest_dir(root).unwrap();
+        assert_ne!(edited, before);
+        std::fs::rename(root.join("src/lib.rs"), root.join("src/main.rs")).unwrap();
+        assert_ne!(digest_dir(root).unwrap(), edited);
+    }
+}
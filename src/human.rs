@@ -0,0 +1,349 @@
+//! Human-readable formatting of numbers, sizes, durations and dates.
+//!
+//! Formatting follows an explicit [`Locale`] rather than the system locale,
+//! so reports are reproducible across machines and in CI. The default,
+//! [`Locale::POSIX`], uses no digit grouping, a `.` decimal separator and ISO
+//! 8601 dates; other locales are opt-in:
+//!
+//! ```
+//! use cargo_plugin_utils::human::{
+//!     Date,
+//!     Locale,
+//! };
+//!
+//! let date = Date::new(2026, 3, 14);
+//! assert_eq!(Locale::POSIX.integer(1234567), "1234567");
+//! assert_eq!(Locale::POSIX.date(date), "2026-03-14");
+//! assert_eq!(Locale::EN_US.integer(1234567), "1,234,567");
+//! assert_eq!(Locale::DE.decimal(1234.5, 2), "1.234,50");
+//! assert_eq!(Locale::DE.date(date), "14.03.2026");
+//! ```
+
+use std::str::FromStr;
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+/// How calendar dates are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+    /// `2026-03-14`
+    Iso,
+    /// Day, month, year with the given separator, e.g. `14.03.2026`
+    DayMonthYear(char),
+    /// Month, day, year with the given separator, e.g. `03/14/2026`
+    MonthDayYear(char),
+}
+
+/// Number and date conventions used for formatting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    /// Separator between groups of three digits, if any
+    pub thousands_separator: Option<char>,
+    /// Separator between the integer and fractional part
+    pub decimal_separator: char,
+    /// How dates are written
+    pub date_format: DateFormat,
+}
+
+impl Locale {
+    /// No grouping, `.` decimals, ISO dates (the default)
+    pub const POSIX: Self = Self {
+        thousands_separator: None,
+        decimal_separator: '.',
+        date_format: DateFormat::Iso,
+    };
+
+    /// `1,234.5`, `03/14/2026`
+    pub const EN_US: Self = Self {
+        thousands_separator: Some(','),
+        decimal_separator: '.',
+        date_format: DateFormat::MonthDayYear('/'),
+    };
+
+    /// `1,234.5`, `14/03/2026`
+    pub const EN_GB: Self = Self {
+        thousands_separator: Some(','),
+        decimal_separator: '.',
+        date_format: DateFormat::DayMonthYear('/'),
+    };
+
+    /// `1.234,5`, `14.03.2026`
+    pub const DE: Self = Self {
+        thousands_separator: Some('.'),
+        decimal_separator: ',',
+        date_format: DateFormat::DayMonthYear('.'),
+    };
+
+    /// `1 234,5` (narrow no-break space), `14/03/2026`
+    pub const FR: Self = Self {
+        thousands_separator: Some('\u{202f}'),
+        decimal_separator: ',',
+        date_format: DateFormat::DayMonthYear('/'),
+    };
+
+    /// `1.234,5`, `14-03-2026`
+    pub const NL: Self = Self {
+        thousands_separator: Some('.'),
+        decimal_separator: ',',
+        date_format: DateFormat::DayMonthYear('-'),
+    };
+
+    /// Format an integer, grouping digits if the locale does.
+    pub fn integer(&self, value: i64) -> String {
+        let digits = self.group(&value.unsigned_abs().to_string());
+        if value < 0 {
+            format!("-{}", digits)
+        } else {
+            digits
+        }
+    }
+
+    /// Format a number with `precision` fractional digits.
+    pub fn decimal(&self, value: f64, precision: usize) -> String {
+        let formatted = format!("{:.*}", precision, value.abs());
+        let (integer, fraction) = match formatted.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+        let mut out = String::new();
+        if value.is_sign_negative() && formatted.bytes().any(|byte| matches!(byte, b'1'..=b'9')) {
+            out.push('-');
+        }
+        out.push_str(&self.group(integer));
+        if let Some(fraction) = fraction {
+            out.push(self.decimal_separator);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    /// Format a byte count with decimal units, e.g. `3.20 GB`.
+    pub fn bytes(&self, bytes: u64) -> String {
+        const UNITS: [&str; 6] = ["kB", "MB", "GB", "TB", "PB", "EB"];
+
+        if bytes < 1000 {
+            return format!("{} B", bytes);
+        }
+        let mut value = bytes as f64;
+        let mut unit = 0;
+        value /= 1000.0;
+        while value >= 1000.0 && unit < UNITS.len() - 1 {
+            value /= 1000.0;
+            unit += 1;
+        }
+        format!("{} {}", self.decimal(value, 2), UNITS[unit])
+    }
+
+    /// Format a duration compactly, e.g. `850ms`, `42.3s`, `2m 05s`,
+    /// `1h 02m`.
+    pub fn duration(&self, duration: Duration) -> String {
+        let secs = duration.as_secs();
+        if secs >= 3600 {
+            format!(
+                "{}h {:02}m",
+                self.integer((secs / 3600) as i64),
+                secs % 3600 / 60
+            )
+        } else if secs >= 60 {
+            format!("{}m {:02}s", secs / 60, secs % 60)
+        } else if duration.as_millis() >= 1000 {
+            format!("{}s", self.decimal(duration.as_secs_f64(), 1))
+        } else {
+            format!("{}ms", duration.as_millis())
+        }
+    }
+
+    /// Format a calendar date.
+    pub fn date(&self, date: Date) -> String {
+        let Date { year, month, day } = date;
+        match self.date_format {
+            DateFormat::Iso => format!("{:04}-{:02}-{:02}", year, month, day),
+            DateFormat::DayMonthYear(sep) => {
+                format!("{:02}{}{:02}{}{:04}", day, sep, month, sep, year)
+            }
+            DateFormat::MonthDayYear(sep) => {
+                format!("{:02}{}{:02}{}{:04}", month, sep, day, sep, year)
+            }
+        }
+    }
+
+    /// Insert the thousands separator into a string of ASCII digits.
+    fn group(&self, digits: &str) -> String {
+        let Some(separator) = self.thousands_separator else {
+            return digits.to_string();
+        };
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3 * 3);
+        for (index, digit) in digits.chars().enumerate() {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                out.push(separator);
+            }
+            out.push(digit);
+        }
+        out
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::POSIX
+    }
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    /// Parse a locale name such as `de`, `en-US` or `fr_FR.UTF-8`.
+    ///
+    /// `C`, `POSIX` and the empty string select [`Locale::POSIX`].
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        let name = name.split('.').next().unwrap_or_default();
+        let normalized = name.replace('_', "-").to_ascii_lowercase();
+        let locale = match normalized.as_str() {
+            "" | "c" | "posix" => Self::POSIX,
+            "en" | "en-us" => Self::EN_US,
+            "en-gb" | "en-ie" | "en-au" | "en-nz" => Self::EN_GB,
+            lang if lang == "de" || lang.starts_with("de-") => Self::DE,
+            lang if lang == "fr" || lang.starts_with("fr-") => Self::FR,
+            lang if lang == "nl" || lang.starts_with("nl-") => Self::NL,
+            _ => anyhow::bail!("Unsupported locale: {}", name),
+        };
+        Ok(locale)
+    }
+}
+
+/// A calendar date (proleptic Gregorian, UTC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    /// Year, e.g. 2026
+    pub year: i32,
+    /// Month, 1-12
+    pub month: u8,
+    /// Day of the month, 1-31
+    pub day: u8,
+}
+
+impl Date {
+    /// Create a date from its parts.
+    pub fn new(year: i32, month: u8, day: u8) -> Self {
+        Self { year, month, day }
+    }
+
+    /// The UTC date of a point in time.
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(err) => {
+                let before = err.duration();
+                -(before.as_secs() as i64) - i64::from(before.subsec_nanos() > 0)
+            }
+        };
+        Self::from_days(secs.div_euclid(86_400))
+    }
+
+    /// Today's date (UTC).
+    pub fn today() -> Self {
+        Self::from_system_time(SystemTime::now())
+    }
+
+    /// Date from days since 1970-01-01 (Howard Hinnant's `civil_from_days`).
+    fn from_days(days: i64) -> Self {
+        let shifted = days + 719_468;
+        let era = shifted.div_euclid(146_097);
+        let day_of_era = shifted.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Self {
+            year: year as i32,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_grouping() {
+        assert_eq!(Locale::POSIX.integer(1234567), "1234567");
+        assert_eq!(Locale::EN_US.integer(0), "0");
+        assert_eq!(Locale::EN_US.integer(999), "999");
+        assert_eq!(Locale::EN_US.integer(1000), "1,000");
+        assert_eq!(Locale::EN_US.integer(-1234567), "-1,234,567");
+        assert_eq!(Locale::DE.integer(1234567), "1.234.567");
+        assert_eq!(Locale::FR.integer(12345), "12\u{202f}345");
+        assert_eq!(
+            Locale::EN_US.integer(i64::MIN),
+            "-9,223,372,036,854,775,808"
+        );
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(Locale::POSIX.decimal(1234.5, 2), "1234.50");
+        assert_eq!(Locale::EN_US.decimal(1234.5, 1), "1,234.5");
+        assert_eq!(Locale::DE.decimal(-1234.5, 0), "-1.234");
+        assert_eq!(Locale::DE.decimal(-0.001, 1), "0,0");
+    }
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(Locale::POSIX.bytes(999), "999 B");
+        assert_eq!(Locale::POSIX.bytes(3_200_000_000), "3.20 GB");
+        assert_eq!(Locale::DE.bytes(1_500), "1,50 kB");
+    }
+
+    #[test]
+    fn test_duration() {
+        let locale = Locale::POSIX;
+        assert_eq!(locale.duration(Duration::from_millis(850)), "850ms");
+        assert_eq!(locale.duration(Duration::from_millis(42_300)), "42.3s");
+        assert_eq!(locale.duration(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(locale.duration(Duration::from_secs(3720)), "1h 02m");
+        assert_eq!(Locale::DE.duration(Duration::from_millis(1500)), "1,5s");
+    }
+
+    #[test]
+    fn test_date_formats() {
+        let date = Date::new(2026, 3, 14);
+        assert_eq!(Locale::POSIX.date(date), "2026-03-14");
+        assert_eq!(Locale::EN_US.date(date), "03/14/2026");
+        assert_eq!(Locale::EN_GB.date(date), "14/03/2026");
+        assert_eq!(Locale::DE.date(date), "14.03.2026");
+        assert_eq!(Locale::NL.date(date), "14-03-2026");
+    }
+
+    #[test]
+    fn test_date_from_system_time() {
+        let epoch = SystemTime::UNIX_EPOCH;
+        assert_eq!(Date::from_system_time(epoch), Date::new(1970, 1, 1));
+        let leap_day = epoch + Duration::from_secs(1_709_164_800); // 2024-02-29
+        assert_eq!(Date::from_system_time(leap_day), Date::new(2024, 2, 29));
+        let before = epoch - Duration::from_secs(1);
+        assert_eq!(Date::from_system_time(before), Date::new(1969, 12, 31));
+        let day_before = epoch - Duration::from_secs(86_400);
+        assert_eq!(Date::from_system_time(day_before), Date::new(1969, 12, 31));
+    }
+
+    #[test]
+    fn test_locale_from_str() {
+        assert_eq!("C".parse::<Locale>().unwrap(), Locale::POSIX);
+        assert_eq!("".parse::<Locale>().unwrap(), Locale::POSIX);
+        assert_eq!("en_US.UTF-8".parse::<Locale>().unwrap(), Locale::EN_US);
+        assert_eq!("de-AT".parse::<Locale>().unwrap(), Locale::DE);
+        assert_eq!("fr".parse::<Locale>().unwrap(), Locale::FR);
+        assert!("xx".parse::<Locale>().is_err());
+    }
+}
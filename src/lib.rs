@@ -1,21 +1,73 @@
 //! Shared utilities for cargo plugins.
 
+pub mod answers;
+pub mod baseline;
+pub mod capture;
+pub mod cargo;
+pub mod child;
+pub mod ci;
+pub mod cli;
+pub mod command;
 pub mod common;
+pub mod config;
+pub mod context;
+pub mod coverage;
+pub mod crash;
+pub mod cross;
+pub mod debug;
+pub mod docs;
+pub mod exit;
+pub mod features;
+pub mod findings;
+pub mod graph;
+pub mod hash;
+pub mod human;
 pub mod logger;
+pub mod matrix;
+pub mod message;
+pub mod parallel;
+pub mod patch;
+pub mod pipeline;
+pub mod priority;
 pub mod progress_logger;
+pub mod redact;
+pub mod registry;
+pub mod reports;
+pub mod resize;
+pub mod resources;
+pub mod sarif;
 pub mod scrolling;
+pub mod self_update;
+pub mod table;
+pub mod telemetry;
+pub mod tempdirs;
+pub mod test_runner;
+pub mod testing;
+pub mod toolchain;
 pub mod tty;
+pub mod versioning;
+pub mod work_cache;
 
+pub use command::CommandSpec;
 pub use common::{
     detect_repo,
+    detect_repo_info,
     find_package,
+    find_package_in,
     get_metadata,
     get_owner_repo,
     get_package_version_from_manifest,
     get_workspace_packages,
 };
+pub use context::PluginContext;
 pub use logger::{
+    EventLevel,
+    ExitStatus,
+    LogEvent,
     Logger,
+    RunOptions,
+    ScopeGuard,
+    SubprocessError,
     SubprocessOutput,
 };
 pub use progress_logger::ProgressLogger;
@@ -1,6 +1,17 @@
 //! Logger for handling output with cargo-style progress and status messages.
 
+use std::ffi::OsString;
 use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::Duration;
 
 use anyhow::Context;
 use carlog::Status;
@@ -15,6 +26,42 @@ use portable_pty::{
     PtySize,
     native_pty_system,
 };
+use tokio_util::sync::CancellationToken;
+
+use crate::capture::{
+    Capture,
+    CaptureLimit,
+    Interleaved,
+    InterleavedStream,
+    Stream,
+    TaggedLine,
+    Transcript,
+    TranscriptStream,
+    Truncation,
+};
+use crate::ci::CiRenderer;
+use crate::command::{
+    EnvPreset,
+    IntoCommand,
+    spawn_error,
+};
+use crate::debug::trace;
+use crate::message::{
+    Envelope,
+    Level,
+    Message,
+    MessageFormat,
+};
+use crate::parallel::PanelSlot;
+use crate::priority::Priority;
+use crate::redact::RedactStream;
+use crate::resources::{
+    Exited,
+    JobKiller,
+    ResourceUsage,
+    UsageTracker,
+};
+use crate::table::Table;
 
 /// Logger for handling output with cargo-style progress and status messages.
 ///
@@ -24,14 +71,86 @@ use portable_pty::{
 pub struct Logger {
     progress_bar: Option<ProgressBar>,
     line_count: usize,
+    /// Whether `progress_bar` currently shows a `status()` line
+    status_active: bool,
+    emoji: bool,
+    format: MessageFormat,
+    /// Step counter of the current phase, shown before status lines
+    step: Option<(usize, usize)>,
+    /// Table shown by the current `status_board()` bar
+    board: Option<Arc<Mutex<Table>>>,
+    /// Upgrade warnings to errors
+    deny_warnings: bool,
+    /// Number of errors reported so far
+    errors: AtomicUsize,
+    /// Middleware applied to every line before it is printed
+    filters: Vec<Filter>,
+    /// When the last plain status line was printed for a CI log
+    ci_status_at: Option<std::time::Instant>,
+}
+
+/// Middleware registered with [`Logger::add_filter`].
+type Filter = Box<dyn Fn(LogEvent) -> Option<LogEvent> + Send + Sync>;
+
+/// What kind of line a [`LogEvent`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EventLevel {
+    /// An ephemeral [`status`](Logger::status) line
+    Status,
+    /// A completed operation
+    Success,
+    /// Informational
+    Info,
+    /// A warning
+    Warning,
+    /// An error
+    Error,
+    /// A [`print_message`](Logger::print_message) line, whose text is the
+    /// target (the action is empty)
+    Message,
+}
+
+/// A line about to be printed by the [`Logger`], as seen by filters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEvent {
+    /// Kind of line
+    pub level: EventLevel,
+    /// The action word, e.g. `Compiling`
+    pub action: String,
+    /// The text after the action word
+    pub target: String,
+}
+
+impl LogEvent {
+    /// Create an event.
+    pub fn new(level: EventLevel, action: &str, target: &str) -> Self {
+        Self {
+            level,
+            action: action.to_string(),
+            target: target.to_string(),
+        }
+    }
 }
 
+/// Maximum number of status line redraws per second.
+const STATUS_REDRAW_HZ: u8 = 20;
+
 impl Logger {
     /// Create a new logger.
     pub fn new() -> Self {
         Self {
             progress_bar: None,
             line_count: 0,
+            status_active: false,
+            emoji: false,
+            format: MessageFormat::Human,
+            step: None,
+            board: None,
+            deny_warnings: false,
+            errors: AtomicUsize::new(0),
+            filters: Vec::new(),
+            ci_status_at: None,
         }
     }
 
@@ -41,17 +160,16 @@ impl Logger {
     /// Always uses stderr (matching cargo's behavior).
     #[allow(dead_code)] // Will be used for long-running operations
     pub fn progress(&mut self, message: &str) {
-        let pb = ProgressBar::new_spinner();
-        pb.set_draw_target(ProgressDrawTarget::stderr());
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} {msg}")
-                .unwrap(),
-        );
-        pb.set_message(message.to_string());
-        pb.enable_steady_tick(std::time::Duration::from_millis(100));
+        if self.format == MessageFormat::Json {
+            return;
+        }
+        let pb = new_spinner_bar(message.to_string());
 
-        self.progress_bar = Some(pb);
+        if let Some(previous) = self.progress_bar.replace(pb) {
+            previous.finish_and_clear();
+        }
+        self.status_active = false;
+        self.board = None;
     }
 
     /// Update the progress bar message.
@@ -67,24 +185,114 @@ impl Logger {
     /// Uses cyan color for the action word (ephemeral operations).
     /// This creates an ephemeral message that will be cleared on finish().
     /// Always goes to stderr (matching cargo's behavior).
+    ///
+    /// Rapid calls are coalesced: the status line is redrawn at most
+    /// `STATUS_REDRAW_HZ` times per second, always showing the most recent
+    /// message, so per-file updates don't make the terminal flicker.
+    ///
+    /// When stderr isn't a terminal, the status is printed as a plain line
+    /// instead, at most once per
+    /// [`ci::STATUS_INTERVAL`](crate::ci::STATUS_INTERVAL).
     pub fn status(&mut self, action: &str, target: &str) {
-        // Clear previous status (replaces it with new one)
+        if self.format == MessageFormat::Json {
+            return;
+        }
+        let Some(LogEvent { action, target, .. }) =
+            self.filter(LogEvent::new(EventLevel::Status, action, target))
+        else {
+            return;
+        };
+        let mut formatted_message = format_status(&action, &target);
+        if let Some((current, total)) = self.step {
+            formatted_message = format!(
+                "{} {}",
+                console::style(format!("[{}/{}]", current, total)).dim(),
+                formatted_message
+            );
+        }
+        if crate::ci::is_active() {
+            self.print_ci_status(&formatted_message);
+        }
+
+        // Reuse the current status line; the rate-limited draw target and the
+        // steady tick take care of coalescing redraws
+        if self.status_active
+            && let Some(pb) = &self.progress_bar
+        {
+            pb.set_message(formatted_message);
+            return;
+        }
+
+        // Clear previous progress output (replaces it with the status line)
         if let Some(pb) = self.progress_bar.take() {
             pb.finish_and_clear();
         }
 
-        // Format status message with cyan color (like cargo's "Building")
-        use console::style;
-        let formatted_message = format!("{:>12} {}", style(action).cyan().bold(), target);
+        self.progress_bar = Some(new_status_bar(formatted_message));
+        self.status_active = true;
+        self.board = None;
+        self.line_count = 1;
+    }
+
+    /// Print a status line for a log, unless one was printed recently.
+    fn print_ci_status(&mut self, message: &str) {
+        let now = std::time::Instant::now();
+        if self
+            .ci_status_at
+            .is_some_and(|at| now.duration_since(at) < crate::ci::STATUS_INTERVAL)
+        {
+            return;
+        }
+        self.ci_status_at = Some(now);
+        eprintln!("{}", message);
+    }
+
+    /// The bar showing the current [`status`](Self::status) line, so the
+    /// line can be updated from another thread (e.g. a subprocess output
+    /// hook) with [`format_status`].
+    pub(crate) fn status_bar(&self) -> Option<ProgressBar> {
+        self.progress_bar.clone().filter(|_| self.status_active)
+    }
+
+    /// Show a table as a multi-line, ephemeral status board.
+    ///
+    /// Calling this again with updated contents replaces the board in place.
+    /// Column widths are recomputed from the current terminal width on every
+    /// redraw, so the board re-lays out when the terminal is resized. A later
+    /// [`status`](Self::status) or [`progress`](Self::progress) replaces the
+    /// board; [`clear_status`](Self::clear_status) removes it.
+    pub fn status_board(&mut self, table: &Table) {
+        if self.format == MessageFormat::Json {
+            return;
+        }
+        if let Some(board) = &self.board
+            && self.progress_bar.is_some()
+        {
+            *board.lock().unwrap_or_else(|err| err.into_inner()) = table.clone();
+            return;
+        }
 
-        // Create a progress bar that shows the message ephemerally
-        let pb = ProgressBar::new_spinner();
-        pb.set_draw_target(ProgressDrawTarget::stderr());
-        pb.set_style(ProgressStyle::default_spinner().template("{msg}").unwrap());
-        pb.set_message(formatted_message);
+        // The bar clears its own lines, and the board's are drawn by the
+        // bar too, so none are left for the logger to erase
+        if let Some(pb) = self.progress_bar.take() {
+            pb.finish_and_clear();
+        }
+        self.line_count = 0;
+        let board = Arc::new(Mutex::new(table.clone()));
+        self.progress_bar = Some(new_board_bar(board.clone()));
+        self.board = Some(board);
+        self.status_active = false;
+    }
 
-        self.progress_bar = Some(pb);
-        self.line_count = 1;
+    /// Start step `current` of `total` and show it as a status line:
+    /// "[3/7]     Building foo".
+    ///
+    /// The counter stays in front of subsequent [`status`](Self::status)
+    /// lines until the next `step()` call or until the phase ends with
+    /// [`clear_status`](Self::clear_status) or [`finish`](Self::finish).
+    pub fn step(&mut self, current: usize, total: usize, action: &str, target: &str) {
+        self.step = Some((current.min(total), total));
+        self.status(action, target);
     }
 
     /// Print a permanent status message in cargo's style: "   Compiling
@@ -95,22 +303,7 @@ impl Logger {
     /// subprocesses. Always goes to stderr (matching cargo's behavior).
     #[allow(dead_code)] // Will be used for subprocess-heavy operations
     pub fn status_permanent(&self, action: &str, target: &str) {
-        let status = Status::new()
-            .bold()
-            .justify()
-            .color(carlog::CargoColor::Green)
-            .status(action);
-
-        let formatted_target = format!(" {}", target);
-
-        // Print permanent message to stderr (suspend if progress bar active)
-        if let Some(pb) = &self.progress_bar {
-            pb.suspend(|| {
-                let _ = status.print_stderr(&formatted_target);
-            });
-        } else {
-            let _ = status.print_stderr(&formatted_target);
-        }
+        self.print_status_line(LineKind::Success, action, target);
     }
 
     /// Print a permanent message (will be kept in output).
@@ -118,6 +311,10 @@ impl Logger {
     /// Always goes to stderr (matching cargo's behavior).
     #[allow(dead_code)] // May be used by other commands
     pub fn print_message(&self, msg: &str) {
+        let Some(event) = self.filter(LogEvent::new(EventLevel::Message, "", msg)) else {
+            return;
+        };
+        let msg = event.target;
         if let Some(pb) = &self.progress_bar {
             pb.suspend(|| {
                 eprintln!("{}", msg);
@@ -127,74 +324,253 @@ impl Logger {
         }
     }
 
+    /// Write payload data (badges, JSON, changelogs, ...) to stdout.
+    ///
+    /// The stderr status line is hidden while writing and stdout is flushed
+    /// before it is redrawn, so the data isn't torn by the spinner when both
+    /// streams go to the same terminal. Use this instead of `print!` while a
+    /// status is active.
+    pub fn write_stdout(&self, bytes: &[u8]) -> std::io::Result<()> {
+        self.write_below_status(&mut std::io::stdout().lock(), bytes)
+    }
+
+    /// Write `bytes` to `out` with the status line hidden, see
+    /// [`write_stdout`](Self::write_stdout).
+    fn write_below_status(&self, out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+        let mut write = || {
+            out.write_all(bytes)?;
+            out.flush()
+        };
+        if let Some(pb) = &self.progress_bar {
+            pb.suspend(write)
+        } else {
+            write()
+        }
+    }
+
     /// Print an info message (cyan colored).
     ///
     /// Info messages are permanent (not cleared).
     /// Always goes to stderr (matching cargo's behavior).
     #[allow(dead_code)] // May be used by other commands
     pub fn info(&self, action: &str, target: &str) {
-        let status = Status::new()
-            .bold()
-            .justify()
-            .color(carlog::CargoColor::Cyan)
-            .status(action);
-
-        let formatted_target = format!(" {}", target);
-
-        // Suspend progress bar to print permanent message to stderr
-        if let Some(pb) = &self.progress_bar {
-            pb.suspend(|| {
-                let _ = status.print_stderr(&formatted_target);
-            });
-        } else {
-            let _ = status.print_stderr(&formatted_target);
-        }
+        self.print_status_line(LineKind::Info, action, target);
     }
 
     /// Print a warning message (yellow colored).
     ///
     /// Warning messages are permanent (not cleared).
     /// Always goes to stderr (matching cargo's behavior).
+    /// With [`deny_warnings`](Self::deny_warnings) enabled, the warning is
+    /// printed and counted as an error instead.
     pub fn warning(&self, action: &str, target: &str) {
-        let status = Status::new()
-            .bold()
-            .justify()
-            .color(carlog::CargoColor::Yellow)
-            .status(action);
-
-        let formatted_target = format!(" {}", target);
-
-        // Suspend progress bar to print permanent message to stderr
-        if let Some(pb) = &self.progress_bar {
-            pb.suspend(|| {
-                let _ = status.print_stderr(&formatted_target);
-            });
-        } else {
-            let _ = status.print_stderr(&formatted_target);
-        }
+        let kind = self.warning_kind();
+        self.print_status_line(kind, action, target);
     }
 
     /// Print an error message (red colored).
     ///
-    /// Error messages are permanent (not cleared).
+    /// Error messages are permanent (not cleared) and counted, see
+    /// [`has_errors`](Self::has_errors).
     /// Always goes to stderr (matching cargo's behavior).
     #[allow(dead_code)] // May be used by other commands
     pub fn error(&self, action: &str, target: &str) {
+        self.print_status_line(LineKind::Error, action, target);
+    }
+
+    /// Treat warnings as errors, like `RUSTFLAGS=-Dwarnings`.
+    ///
+    /// When enabled, [`warning`](Self::warning) and
+    /// [`deprecated`](Self::deprecated) print red error lines and count
+    /// towards [`has_errors`](Self::has_errors).
+    pub fn deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    /// Whether any errors (including denied warnings) were reported.
+    ///
+    /// Plugins typically check this at the end of a run and exit nonzero.
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    /// Number of errors (including denied warnings) reported so far.
+    pub fn error_count(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Register middleware that sees every line before it is printed.
+    ///
+    /// The filter receives the [`LogEvent`] of each `status()`,
+    /// `status_permanent()`, `info()`, `warning()`, `error()`,
+    /// `deprecated()` and `print_message()` call (also in JSON mode) and
+    /// returns it, possibly modified, or `None`
+    /// to drop it. Use it to redact secrets, prefix targets per package, or
+    /// route messages elsewhere. Filters run in registration order; errors
+    /// are counted before filtering.
+    pub fn add_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(LogEvent) -> Option<LogEvent> + Send + Sync + 'static,
+    {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Run an event through the registered filters, then mask
+    /// [registered secrets](crate::redact).
+    fn filter(&self, event: LogEvent) -> Option<LogEvent> {
+        let mut event = self
+            .filters
+            .iter()
+            .try_fold(event, |event, filter| filter(event))?;
+        if crate::redact::is_active() {
+            event.action = crate::redact::redact(&event.action).into_owned();
+            event.target = crate::redact::redact(&event.target).into_owned();
+        }
+        Some(event)
+    }
+
+    /// Line kind for warnings, honoring `deny_warnings`.
+    fn warning_kind(&self) -> LineKind {
+        if self.deny_warnings {
+            LineKind::Error
+        } else {
+            LineKind::Warning
+        }
+    }
+
+    /// Report use of a deprecated feature of the plugin.
+    ///
+    /// Prints a yellow notice like
+    /// `Deprecated --old-flag since 0.3.0, use --new-flag instead`, or a
+    /// [`Message::Deprecated`] in JSON mode. Each feature is reported at most
+    /// once per run, no matter how many loggers report it. Setting
+    /// `CARGO_PLUGIN_NO_DEPRECATION_WARNINGS=1` suppresses the notices.
+    pub fn deprecated(&self, feature: &str, since: &str, alternative: Option<&str>) {
+        static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        if deprecation_warnings_suppressed() {
+            return;
+        }
+        {
+            let mut reported = REPORTED.lock().unwrap_or_else(|err| err.into_inner());
+            if reported.iter().any(|seen| seen == feature) {
+                return;
+            }
+            reported.push(feature.to_string());
+        }
+
+        let target = match alternative {
+            Some(alternative) => {
+                format!("{} since {}, use {} instead", feature, since, alternative)
+            }
+            None => format!("{} since {}", feature, since),
+        };
+        let kind = self.warning_kind();
+        if self.format != MessageFormat::Json {
+            self.print_status_line(kind, "Deprecated", &target);
+            return;
+        }
+        if kind == LineKind::Error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        // Filters see the notice as a line and may drop it; the message
+        // keeps its fields, redacted like lines are
+        if self
+            .filter(LogEvent::new(kind.event_level(), "Deprecated", &target))
+            .is_none()
+        {
+            return;
+        }
+        let redact = |text: &str| crate::redact::redact(text).into_owned();
+        let _ = self.emit(Message::Deprecated {
+            feature: redact(feature),
+            since: redact(since),
+            alternative: alternative.map(redact),
+        });
+    }
+
+    /// Enable or disable emoji prefixes on permanent lines.
+    ///
+    /// When enabled, success, warning and error lines are prefixed with
+    /// ✅/⚠️/❌ (info lines are indented to stay aligned). Terminals without
+    /// Unicode support, and non-terminal output, get ASCII fallbacks instead.
+    /// Disabled by default.
+    pub fn set_emoji(&mut self, enabled: bool) {
+        self.emoji = enabled;
+    }
+
+    /// Select human-readable or JSON output.
+    ///
+    /// In [`MessageFormat::Json`] mode no status lines or progress bars are
+    /// drawn; permanent lines (`status_permanent`, `info`, `warning`,
+    /// `error`) are written to stdout as [`Message::Status`] JSON lines
+    /// instead, and [`emit`](Self::emit) becomes active.
+    pub fn set_message_format(&mut self, format: MessageFormat) {
+        if format == MessageFormat::Json {
+            self.clear_status();
+        }
+        self.format = format;
+    }
+
+    /// The selected output format.
+    pub fn message_format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Write a machine-readable message to stdout in JSON mode.
+    ///
+    /// Does nothing in human mode, so plugins can emit their payload
+    /// unconditionally and print the human rendering separately.
+    pub fn emit(&self, message: Message) -> std::io::Result<()> {
+        if self.format != MessageFormat::Json {
+            return Ok(());
+        }
+        let mut line = serde_json::to_vec(&Envelope::new(message))?;
+        line.push(b'\n');
+        self.write_stdout(&line)
+    }
+
+    /// Print a permanent, justified status line to stderr, suspending the
+    /// progress bar if one is active.
+    fn print_status_line(&self, kind: LineKind, action: &str, target: &str) {
+        // Errors count as reported, even if a filter drops or rewrites them
+        if kind == LineKind::Error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let Some(event) = self.filter(LogEvent::new(kind.event_level(), action, target)) else {
+            return;
+        };
+        let (kind, target) = (LineKind::from_event_level(event.level), event.target);
+        if self.format == MessageFormat::Json {
+            let _ = self.emit(Message::Status {
+                level: kind.level(),
+                action: event.action,
+                target,
+            });
+            return;
+        }
         let status = Status::new()
             .bold()
             .justify()
-            .color(carlog::CargoColor::Red)
-            .status(action);
+            .color(kind.color())
+            .status(event.action);
 
-        let formatted_target = format!(" {}", target);
+        let mut line = Vec::new();
+        if self.emoji {
+            let unicode = console::Term::stderr().features().wants_emoji();
+            line.extend_from_slice(kind.prefix(unicode).as_bytes());
+        }
+        let _ = status.print(&mut line, format!(" {}", target));
 
-        // Suspend progress bar to print permanent message to stderr
+        let write_line = || {
+            let mut stderr = std::io::stderr();
+            let _ = stderr.write_all(&line);
+            let _ = stderr.flush();
+        };
         if let Some(pb) = &self.progress_bar {
-            pb.suspend(|| {
-                let _ = status.print_stderr(&formatted_target);
-            });
+            pb.suspend(write_line);
         } else {
-            let _ = status.print_stderr(&formatted_target);
+            write_line();
         }
     }
 
@@ -202,6 +578,8 @@ impl Logger {
     ///
     /// Useful before subprocess operations that might write to stderr.
     pub fn clear_status(&mut self) {
+        self.step = None;
+        self.board = None;
         if let Some(pb) = self.progress_bar.take() {
             pb.finish_and_clear();
             self.line_count = 0;
@@ -223,28 +601,347 @@ impl Logger {
         }
     }
 
+    /// Temporarily hide the status line while awaiting a future.
+    ///
+    /// The async counterpart of [`suspend`](Self::suspend): the progress bar
+    /// is cleared before the future is polled and redrawn with the same
+    /// message once it completes, so subprocess output produced in the
+    /// meantime isn't mixed with the status line.
+    pub async fn suspend_async<Fut>(&mut self, fut: Fut) -> Fut::Output
+    where
+        Fut: std::future::Future,
+    {
+        let Some(pb) = self.progress_bar.take() else {
+            return fut.await;
+        };
+        let message = pb.message();
+        pb.finish_and_clear();
+
+        let output = fut.await;
+
+        self.progress_bar = Some(if let Some(board) = &self.board {
+            new_board_bar(board.clone())
+        } else if self.status_active {
+            new_status_bar(message)
+        } else {
+            new_spinner_bar(message)
+        });
+        output
+    }
+
+    /// Show a status line for the duration of a scope.
+    ///
+    /// The status is shown immediately and cleared when the returned guard is
+    /// dropped, so early returns and `?` don't leave a stale status line on
+    /// screen. If the guard is dropped during a panic, the status is turned
+    /// into a permanent error line instead. The guard dereferences to the
+    /// logger, so it can be used for further output inside the scope.
+    pub fn scope(&mut self, action: &str, target: &str) -> ScopeGuard<'_> {
+        self.status(action, target);
+        ScopeGuard {
+            logger: self,
+            action: action.to_string(),
+            target: target.to_string(),
+        }
+    }
+
+    /// Ask a yes/no question and wait for the answer.
+    ///
+    /// The active status line is hidden while the question is on screen and
+    /// restored afterwards, so the prompt doesn't garble the progress output.
+    /// Anything other than `y`/`yes` (case-insensitive) counts as "no",
+    /// including an empty answer or a read error.
+    pub fn confirm(&mut self, question: &str) -> bool {
+        let prompt = format!("{} [y/N] ", question);
+        self.read_answer(&prompt)
+            .map(|answer| is_affirmative(&answer))
+            .unwrap_or(false)
+    }
+
+    /// Prompt for a line of input and return it without the trailing newline.
+    ///
+    /// The active status line is hidden while the prompt is on screen and
+    /// restored afterwards. Returns an empty string if reading fails.
+    pub fn input(&mut self, prompt: &str) -> String {
+        let prompt = format!("{} ", prompt);
+        self.read_answer(&prompt).unwrap_or_default()
+    }
+
+    /// Print `prompt` to stderr and read one line from the terminal, with the
+    /// progress bar suspended.
+    ///
+    /// The answer is read from the terminal rather than stdin, so a plugin
+    /// reading piped input doesn't hand it to the prompt. Without a terminal
+    /// this fails, and the caller takes its default.
+    fn read_answer(&mut self, prompt: &str) -> std::io::Result<String> {
+        let terminal = open_terminal()?;
+        self.suspend(|| {
+            let mut stderr = std::io::stderr();
+            write!(stderr, "{}", prompt)?;
+            stderr.flush()?;
+
+            let mut answer = String::new();
+            std::io::BufRead::read_line(&mut std::io::BufReader::new(terminal), &mut answer)?;
+            Ok(answer.trim_end_matches(['\r', '\n']).to_string())
+        })
+    }
+
     /// Finish logging and clear ephemeral status messages.
     pub fn finish(&mut self) {
+        self.step = None;
+        self.board = None;
         if let Some(pb) = self.progress_bar.take() {
             // finish_and_clear() will clear the progress bar's line
             pb.finish_and_clear();
             self.line_count = 0;
         }
     }
+
+    /// Remove the progress bar and any status lines from the terminal before
+    /// a subprocess output window is drawn below the cursor.
+    pub(crate) fn clear_for_window(&mut self, term: &console::Term) {
+        // Clear progress bar if present
+        if let Some(pb) = self.progress_bar.take() {
+            pb.finish_and_clear();
+        }
+        // Clear any status lines the Logger has printed
+        if self.line_count > 0 {
+            let _ = term.clear_last_lines(self.line_count);
+            self.line_count = 0;
+        }
+    }
+}
+
+/// Format a status line with a cyan action word, like cargo's "Building".
+pub(crate) fn format_status(action: &str, target: &str) -> String {
+    format!("{:>12} {}", console::style(action).cyan().bold(), target)
+}
+
+/// Create the spinner used by [`Logger::progress`].
+fn new_spinner_bar(message: String) -> ProgressBar {
+    let pb = ProgressBar::new_spinner();
+    pb.set_draw_target(ProgressDrawTarget::stderr());
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} {msg}")
+            .unwrap(),
+    );
+    pb.set_message(message);
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+    pb
+}
+
+/// Create the multi-line bar used by [`Logger::status_board`].
+///
+/// The table is rendered by a template key, so it is laid out for the
+/// current terminal width on every draw.
+fn new_board_bar(board: Arc<Mutex<Table>>) -> ProgressBar {
+    let render = move |_: &indicatif::ProgressState, out: &mut dyn std::fmt::Write| {
+        let table = board.lock().unwrap_or_else(|err| err.into_inner());
+        let width = usize::from(crate::resize::current().cols);
+        let _ = out.write_str(&table.render(width));
+    };
+    let pb = ProgressBar::new_spinner();
+    pb.set_draw_target(ProgressDrawTarget::stderr_with_hz(STATUS_REDRAW_HZ));
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .with_key("board", render)
+            .template("{board}")
+            .unwrap(),
+    );
+    pb.enable_steady_tick(std::time::Duration::from_millis(
+        1000 / u64::from(STATUS_REDRAW_HZ),
+    ));
+    pb
+}
+
+/// Create the rate-limited status line used by [`Logger::status`].
+fn new_status_bar(formatted_message: String) -> ProgressBar {
+    // Create a progress bar that shows the message ephemerally
+    let pb = ProgressBar::new_spinner();
+    pb.set_draw_target(ProgressDrawTarget::stderr_with_hz(STATUS_REDRAW_HZ));
+    pb.set_style(ProgressStyle::default_spinner().template("{msg}").unwrap());
+    pb.set_message(formatted_message);
+    // Ticking guarantees the latest message is drawn even when it arrived
+    // inside the rate limit window
+    pb.enable_steady_tick(std::time::Duration::from_millis(
+        1000 / u64::from(STATUS_REDRAW_HZ),
+    ));
+    pb
+}
+
+/// Guard returned by [`Logger::scope`] that clears the status line on drop.
+pub struct ScopeGuard<'a> {
+    logger: &'a mut Logger,
+    action: String,
+    target: String,
+}
+
+impl ScopeGuard<'_> {
+    /// End the scope with a permanent (green) status line instead of just
+    /// clearing it.
+    pub fn finish_with(self, action: &str, target: &str) {
+        self.logger.clear_status();
+        self.logger.status_permanent(action, target);
+    }
+}
+
+impl std::ops::Deref for ScopeGuard<'_> {
+    type Target = Logger;
+
+    fn deref(&self) -> &Logger {
+        self.logger
+    }
+}
+
+impl std::ops::DerefMut for ScopeGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Logger {
+        self.logger
+    }
+}
+
+impl Drop for ScopeGuard<'_> {
+    fn drop(&mut self) {
+        self.logger.clear_status();
+        if std::thread::panicking() {
+            // "Publishing" -> "Failed publishing foo"
+            let mut chars = self.action.chars();
+            let action: String = chars
+                .next()
+                .map(|first| first.to_lowercase().chain(chars).collect())
+                .unwrap_or_default();
+            self.logger
+                .error("Failed", &format!("{} {}", action, self.target));
+        }
+    }
+}
+
+/// Kind of permanent status line, determining its color and emoji prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+
+impl LineKind {
+    fn color(self) -> carlog::CargoColor {
+        match self {
+            Self::Success => carlog::CargoColor::Green,
+            Self::Info => carlog::CargoColor::Cyan,
+            Self::Warning => carlog::CargoColor::Yellow,
+            Self::Error => carlog::CargoColor::Red,
+        }
+    }
+
+    fn level(self) -> Level {
+        match self {
+            Self::Success => Level::Success,
+            Self::Info => Level::Info,
+            Self::Warning => Level::Warning,
+            Self::Error => Level::Error,
+        }
+    }
+
+    fn event_level(self) -> EventLevel {
+        match self {
+            Self::Success => EventLevel::Success,
+            Self::Info => EventLevel::Info,
+            Self::Warning => EventLevel::Warning,
+            Self::Error => EventLevel::Error,
+        }
+    }
+
+    /// Line kind for a (possibly filter-modified) level.
+    fn from_event_level(level: EventLevel) -> Self {
+        match level {
+            EventLevel::Success => Self::Success,
+            EventLevel::Warning => Self::Warning,
+            EventLevel::Error => Self::Error,
+            EventLevel::Info | EventLevel::Status | EventLevel::Message => Self::Info,
+        }
+    }
+
+    /// Prefix for emoji mode; `unicode` selects emoji over ASCII fallbacks.
+    /// Info lines only get padding so they stay aligned with the others.
+    fn prefix(self, unicode: bool) -> &'static str {
+        match (self, unicode) {
+            (Self::Success, true) => "✅ ",
+            (Self::Warning, true) => "⚠️ ",
+            (Self::Error, true) => "❌ ",
+            (Self::Info, true) => "   ",
+            (Self::Success, false) => "[+] ",
+            (Self::Warning, false) => "[!] ",
+            (Self::Error, false) => "[x] ",
+            (Self::Info, false) => "    ",
+        }
+    }
+}
+
+/// Whether `CARGO_PLUGIN_NO_DEPRECATION_WARNINGS` asks to hide deprecation
+/// notices.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn deprecation_warnings_suppressed() -> bool {
+    std::env::var("CARGO_PLUGIN_NO_DEPRECATION_WARNINGS")
+        .is_ok_and(|value| !value.is_empty() && value != "0" && value != "false")
+}
+
+/// The controlling terminal, to read prompt answers from.
+fn open_terminal() -> std::io::Result<std::fs::File> {
+    let path = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+}
+
+/// Whether a prompt answer means "yes".
+fn is_affirmative(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
 }
 
 /// Result of running a subprocess with windowed stderr rendering.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SubprocessOutput {
     /// Captured stdout
     pub stdout: Vec<u8>,
     /// Captured stderr
     pub stderr: Vec<u8>,
-    /// Exit code
+    /// Exit code; 1 if the child was killed by a signal, see
+    /// [`status`](Self::status)
     pub exit_code: u32,
+    /// How the child ended: with an exit code or killed by a signal
+    pub status: ExitStatus,
+    /// Wall-clock time from spawning the child until it was reaped
+    pub duration: Duration,
+    /// Peak memory and CPU time of the child, where the platform reports it
+    pub resources: Option<ResourceUsage>,
+    /// What the [capture limit](RunOptions::capture) left out of stdout
+    pub stdout_truncation: Option<Truncation>,
+    /// What the [capture limit](RunOptions::capture) left out of stderr (or
+    /// of the combined output in PTY mode)
+    pub stderr_truncation: Option<Truncation>,
+    /// Exit codes besides 0 that count as [success](Self::success), from
+    /// [`RunOptions::ok_exit_codes`]
+    pub ok_exit_codes: Vec<u32>,
+    /// For a child stopped by a timeout, cancellation, stall or Ctrl-C (see
+    /// [`SubprocessError`]): whether it exited when asked or was killed
+    pub termination: Option<Termination>,
+    /// Lines of stdout and stderr in order of arrival, if asked for with
+    /// [`RunOptions::interleave`]
+    pub interleaved: Vec<TaggedLine>,
 }
 
 impl SubprocessOutput {
+    /// Move stdout in front of stderr, the way a PTY merges them.
+    fn merged_into_stderr(mut self) -> Self {
+        let mut merged = std::mem::take(&mut self.stdout);
+        merged.append(&mut self.stderr);
+        self.stderr = merged;
+        self
+    }
+
     /// Get stdout as a string, with UTF-8 error handling.
     pub fn stdout_str(&self) -> anyhow::Result<String> {
         String::from_utf8(self.stdout.clone()).context("Failed to parse stdout as UTF-8")
@@ -255,381 +952,3727 @@ impl SubprocessOutput {
         String::from_utf8(self.stderr.clone()).context("Failed to parse stderr as UTF-8")
     }
 
-    /// Check if the process exited successfully.
+    /// Check if the process exited successfully: with code 0 or one of the
+    /// [`ok_exit_codes`](Self::ok_exit_codes), not killed by a signal.
     pub fn success(&self) -> bool {
-        self.exit_code == 0
+        match self.status.code() {
+            Some(code) => {
+                code == self.exit_code && (code == 0 || self.ok_exit_codes.contains(&code))
+            }
+            None => false,
+        }
     }
 
     /// Get the exit code.
     pub fn exit_code(&self) -> u32 {
         self.exit_code
     }
+
+    /// Get how the process ended, telling exit codes and signals apart.
+    pub fn status(&self) -> ExitStatus {
+        self.status
+    }
+
+    /// Get the peak memory and CPU time of the child (and the descendants it
+    /// waited for), if the platform reports it.
+    pub fn resources(&self) -> Option<ResourceUsage> {
+        self.resources
+    }
+
+    /// Get the wall-clock time the child ran.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// One-line timing summary for per-step reports, e.g.
+    /// `12.3s, 3.2 GB RAM, 142.0s CPU`; just the duration where the platform
+    /// reports no resource usage.
+    pub fn timing_summary(&self) -> String {
+        let wall = format!("{:.1}s", self.duration.as_secs_f64());
+        match self.resources {
+            Some(resources) => format!("{}, {}", wall, resources),
+            None => wall,
+        }
+    }
+
+    /// The [interleaved](Self::interleaved) lines, each prefixed with
+    /// `[out]` or `[err]`.
+    pub fn tagged(&self) -> String {
+        self.interleaved
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
+    /// Whether the capture limit dropped any output.
+    pub fn truncated(&self) -> bool {
+        self.stdout_truncation.is_some() || self.stderr_truncation.is_some()
+    }
 }
 
-/// Run a subprocess with piped stdout/stderr, capturing stdout fully while
-/// rendering stderr lines live in a ring buffer.
-///
-/// # Arguments
-///
-/// * `logger` - Logger instance to manage progress bar suspension/clearing
-/// * `cmd_builder` - Closure that builds a `portable_pty::CommandBuilder`
-/// * `stderr_lines` - Number of stderr lines to show in the scrolling region
-///   (default: 5)
-///
-/// # Behavior
-///
-/// - Uses PTY mode so subprocesses see a TTY (preserves ANSI colors)
-/// - Sets up a scrolling region at the bottom of the terminal
-/// - Suspends/clears any active progress bar before running
-/// - Captures stdout fully
-/// - Renders stderr lines live in the scrolling region
-/// - On success: clears the scrolling region cleanly
-/// - On failure: leaves/replays the final window
-///
-/// # Returns
-///
-/// Returns `SubprocessOutput` with captured stdout, stderr, and exit status.
-pub async fn run_subprocess<F>(
-    logger: &mut Logger,
-    cmd_builder: F,
-    stderr_lines: Option<usize>,
-) -> anyhow::Result<SubprocessOutput>
-where
-    F: FnOnce() -> CommandBuilder,
-{
-    let stderr_lines = stderr_lines.unwrap_or(5);
+/// How a subprocess ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited on its own with this code
+    Code(u32),
+    /// The process was killed by this signal (Unix only)
+    Signal(i32),
+}
 
-    let term = console::Term::stderr();
-    let is_term = term.is_term();
+impl Default for ExitStatus {
+    fn default() -> Self {
+        Self::Code(0)
+    }
+}
 
-    // Clear any existing Logger output before subprocess to avoid cursor
-    // position conflicts. The scrolling region will change cursor position,
-    // so Logger's Drop wouldn't be able to clear its lines correctly.
-    if is_term {
-        // Clear progress bar if present
-        if let Some(pb) = logger.progress_bar.take() {
-            pb.finish_and_clear();
+impl ExitStatus {
+    /// Whether the process exited with code 0.
+    pub fn success(&self) -> bool {
+        *self == Self::Code(0)
+    }
+
+    /// The exit code, unless the process was killed by a signal.
+    pub fn code(&self) -> Option<u32> {
+        match *self {
+            Self::Code(code) => Some(code),
+            Self::Signal(_) => None,
         }
-        // Clear any status lines the Logger has printed
-        if logger.line_count > 0 {
-            let _ = term.clear_last_lines(logger.line_count);
-            logger.line_count = 0;
+    }
+
+    /// The signal that killed the process.
+    pub fn signal(&self) -> Option<i32> {
+        match *self {
+            Self::Code(_) => None,
+            Self::Signal(signal) => Some(signal),
         }
     }
 
-    // Track how many lines we've drawn for cleanup
-    let stderr_lines_u16 = stderr_lines as u16;
-    let lines_drawn = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
-    let lines_drawn_render = lines_drawn.clone();
+    /// Name of the signal that killed the process, e.g. `SIGKILL`, if it is a
+    /// well-known one.
+    pub fn signal_name(&self) -> Option<&'static str> {
+        let signal = self.signal()?;
+        signals()
+            .into_iter()
+            .find(|(number, ..)| *number == signal)
+            .map(|(_, name, _)| name)
+    }
 
-    // Build command using portable-pty
-    let cmd = cmd_builder();
+    /// A short description for error messages, e.g. `exit code 101` or
+    /// `killed by SIGSEGV (signal 11)`.
+    pub fn describe(&self) -> String {
+        match (*self, self.signal_name()) {
+            (Self::Code(code), _) => format!("exit code {}", code),
+            (Self::Signal(signal), Some(name)) => format!("killed by {} (signal {})", name, signal),
+            (Self::Signal(signal), None) => format!("killed by signal {}", signal),
+        }
+    }
+}
 
-    // Create PTY
-    let pty_system = native_pty_system();
-    let pty_size = PtySize {
-        rows: stderr_lines_u16,
-        cols: 80,
-        pixel_width: 0,
-        pixel_height: 0,
-    };
-    let pty = pty_system
-        .openpty(pty_size)
-        .context("Failed to create PTY")?;
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.describe())
+    }
+}
 
-    // Spawn command in PTY
-    let mut child = pty
-        .slave
-        .spawn_command(cmd)
-        .context("Failed to spawn command in PTY")?;
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return Self::Signal(signal);
+        }
+        // Windows exit codes are unsigned (e.g. 0xC0000005)
+        Self::Code(status.code().unwrap_or(1) as u32)
+    }
+}
 
-    // Get handles for stdout and stderr from PTY
-    // We need to keep a reference to the master to close it later
-    let mut reader = pty
-        .master
-        .try_clone_reader()
-        .context("Failed to clone PTY reader")?;
+impl From<&portable_pty::ExitStatus> for ExitStatus {
+    fn from(status: &portable_pty::ExitStatus) -> Self {
+        match status.signal() {
+            None => Self::Code(status.exit_code()),
+            // The resources module reaps children as `Signal <number>`;
+            // portable-pty itself reports the `strsignal` description
+            Some(signal) => signal
+                .strip_prefix("Signal ")
+                .and_then(|number| number.parse().ok())
+                .or_else(|| signal_number(signal))
+                .map_or(Self::Code(status.exit_code()), Self::Signal),
+        }
+    }
+}
 
-    // Keep the master alive until we're done reading
-    let master = pty.master;
+/// Number, name and `strsignal` description of the signals a child commonly
+/// dies from.
+#[cfg(unix)]
+fn signals() -> [(i32, &'static str, &'static str); 13] {
+    [
+        (libc::SIGHUP, "SIGHUP", "hangup"),
+        (libc::SIGINT, "SIGINT", "interrupt"),
+        (libc::SIGQUIT, "SIGQUIT", "quit"),
+        (libc::SIGILL, "SIGILL", "illegal instruction"),
+        (libc::SIGTRAP, "SIGTRAP", "trace/breakpoint trap"),
+        (libc::SIGABRT, "SIGABRT", "aborted"),
+        (libc::SIGBUS, "SIGBUS", "bus error"),
+        (libc::SIGFPE, "SIGFPE", "floating point exception"),
+        (libc::SIGKILL, "SIGKILL", "killed"),
+        (libc::SIGSEGV, "SIGSEGV", "segmentation fault"),
+        (libc::SIGPIPE, "SIGPIPE", "broken pipe"),
+        (libc::SIGALRM, "SIGALRM", "alarm clock"),
+        (libc::SIGTERM, "SIGTERM", "terminated"),
+    ]
+}
 
-    // Channel to coordinate rendering (send raw bytes to preserve ANSI codes)
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
-    // Keep a clone of tx to close the channel if we timeout
-    let tx_clone = tx.clone();
+#[cfg(not(unix))]
+fn signals() -> [(i32, &'static str, &'static str); 0] {
+    []
+}
 
-    // Collect output as it arrives (for timeout fallback)
-    let collected_output = std::sync::Arc::new(std::sync::Mutex::new(Vec::<u8>::new()));
-    let collected_output_clone = collected_output.clone();
+/// Number of a signal from its `strsignal` description.
+fn signal_number(description: &str) -> Option<i32> {
+    signals()
+        .into_iter()
+        .find(|(_, _, meaning)| meaning.eq_ignore_ascii_case(description))
+        .map(|(number, ..)| number)
+}
 
-    // Task to read from PTY (combines stdout and stderr)
-    // PTY reader is blocking, so we use spawn_blocking
-    // Allow excessive nesting: inherent to async spawn + spawn_blocking + loop +
-    // match pattern
-    #[allow(clippy::excessive_nesting)]
-    let pty_task = tokio::spawn(async move {
-        tokio::task::spawn_blocking(move || {
-            let mut full_output = Vec::new();
-            let mut buffer = vec![0u8; 4096];
+/// A subprocess that was stopped before it finished on its own.
+///
+/// Returned (wrapped in [`anyhow::Error`]) by the `run_subprocess*` functions;
+/// use `err.downcast_ref::<SubprocessError>()` to tell it apart from failures
+/// to start the process and to get at the output captured so far.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SubprocessError {
+    /// The [`RunOptions::timeout`] elapsed and the child was killed
+    TimedOut {
+        /// The timeout that was exceeded
+        timeout: Duration,
+        /// Output captured until the child was killed
+        output: SubprocessOutput,
+    },
+    /// The [`RunOptions::cancel_on`] token was cancelled and the child was
+    /// killed
+    Cancelled {
+        /// Output captured until the child was killed
+        output: SubprocessOutput,
+    },
+    /// Ctrl-C was pressed during a run with
+    /// [`RunOptions::interrupt_on_ctrl_c`] and the child was stopped
+    Interrupted {
+        /// Output captured until the child was stopped
+        output: SubprocessOutput,
+    },
+    /// The child printed nothing for the [`RunOptions::stall_timeout`] and
+    /// was killed
+    Stalled {
+        /// How long the child had been silent
+        silence: Duration,
+        /// Output captured until the child was killed
+        output: SubprocessOutput,
+    },
+}
 
-            loop {
-                match reader.read(&mut buffer) {
-                    Ok(0) => break, // EOF
-                    Ok(bytes_read) => {
-                        let chunk = &buffer[..bytes_read];
-                        full_output.extend_from_slice(chunk);
-                        // Also collect in shared buffer for timeout fallback
-                        if let Ok(mut collected) = collected_output_clone.lock() {
-                            collected.extend_from_slice(chunk);
-                        }
-                        let _ = tx.send(chunk.to_vec());
-                    }
-                    Err(err) => {
-                        // On error, still capture what we have
-                        let error_msg = format!("<pty read error: {}>", err);
-                        let error_bytes = error_msg.as_bytes();
-                        full_output.extend_from_slice(error_bytes);
-                        if let Ok(mut collected) = collected_output_clone.lock() {
-                            collected.extend_from_slice(error_bytes);
-                        }
-                        let _ = tx.send(error_bytes.to_vec());
-                        break;
-                    }
-                }
-            }
+impl SubprocessError {
+    /// Output captured before the subprocess was stopped.
+    pub fn output(&self) -> &SubprocessOutput {
+        match self {
+            Self::TimedOut { output, .. }
+            | Self::Cancelled { output }
+            | Self::Interrupted { output }
+            | Self::Stalled { output, .. } => output,
+        }
+    }
+}
 
-            // Close the channel to signal completion
-            drop(tx);
+impl std::fmt::Display for SubprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimedOut { timeout, .. } => write!(
+                f,
+                "Subprocess timed out after {}",
+                crate::human::Locale::POSIX.duration(*timeout)
+            ),
+            Self::Cancelled { .. } => write!(f, "Subprocess was cancelled"),
+            Self::Interrupted { .. } => write!(f, "Subprocess was interrupted"),
+            Self::Stalled { silence, .. } => write!(
+                f,
+                "Subprocess stalled: no output for {}",
+                crate::human::Locale::POSIX.duration(*silence)
+            ),
+        }
+    }
+}
 
-            Ok::<Vec<u8>, anyhow::Error>(full_output)
-        })
-        .await
-        .context("Failed to join blocking PTY read task")?
-    });
+impl std::error::Error for SubprocessError {}
 
-    // Render output inline (below current cursor position)
-    let mut output_buffer = Vec::new();
-    let mut output_ring: Vec<Vec<u8>> = Vec::with_capacity(stderr_lines);
+/// How a child that was stopped before it finished came to exit, see
+/// [`RunOptions::kill_grace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// It exited within the grace period after being asked to (`SIGTERM`,
+    /// or `SIGINT` for Ctrl-C)
+    Graceful,
+    /// It was still running after the grace period, or couldn't be asked
+    /// (on Windows), and was killed with everything it started
+    Killed,
+}
 
-    // Process output bytes as they arrive
-    // Allow excessive nesting: inherent to async spawn with nested loops and
-    // conditionals
-    #[allow(clippy::excessive_nesting)]
-    let render_task = tokio::spawn(async move {
-        let mut current_lines_displayed: usize = 0;
-
-        while let Some(chunk) = rx.recv().await {
-            output_buffer.extend_from_slice(&chunk);
-
-            // Split buffer into complete lines (preserving ANSI codes)
-            let mut lines: Vec<Vec<u8>> = Vec::new();
-            let mut current_line = Vec::new();
-            for byte in output_buffer.iter().copied() {
-                current_line.push(byte);
-                if byte == b'\n' {
-                    lines.push(current_line);
-                    current_line = Vec::new();
-                }
-            }
-            output_buffer = current_line;
+/// Why [`wait_child`] stopped the child.
+#[derive(Debug, Clone, Copy)]
+enum StopReason {
+    TimedOut(Duration),
+    Cancelled,
+    Interrupted,
+    Stalled(Duration),
+}
 
-            // Update ring buffer with new complete lines
-            for line in lines {
-                output_ring.push(line);
-                if output_ring.len() > stderr_lines {
-                    output_ring.remove(0);
-                }
-            }
+impl StopReason {
+    /// Turn the collected output into the result of a run.
+    fn into_result(
+        stopped: Option<(Self, Termination)>,
+        mut output: SubprocessOutput,
+    ) -> anyhow::Result<SubprocessOutput> {
+        let Some((reason, termination)) = stopped else {
+            return Ok(output);
+        };
+        output.termination = Some(termination);
+        let err = match reason {
+            Self::TimedOut(timeout) => SubprocessError::TimedOut { timeout, output },
+            Self::Cancelled => SubprocessError::Cancelled { output },
+            Self::Interrupted => SubprocessError::Interrupted { output },
+            Self::Stalled(silence) => SubprocessError::Stalled { silence, output },
+        };
+        Err(err.into())
+    }
+}
 
-            // Render ring buffer inline (below current position)
-            if is_term && !output_ring.is_empty() {
-                let mut stderr_handle = std::io::stderr();
+/// Output left on screen after a successful run.
+#[derive(Debug, Clone, Copy)]
+enum KeepOutput {
+    /// The window as last drawn
+    Window,
+    /// The last lines of the output
+    Lines(usize),
+}
 
-                // Move cursor up to clear previous output (if any)
-                if current_lines_displayed > 0 {
-                    // Move up and clear each line
-                    write!(stderr_handle, "\x1b[{}A", current_lines_displayed).ok();
-                    for _ in 0..current_lines_displayed {
-                        write!(stderr_handle, "\x1b[2K\x1b[1B").ok(); // Clear line, move down
-                    }
-                    // Move back up to start position
-                    write!(stderr_handle, "\x1b[{}A", current_lines_displayed).ok();
-                }
+/// Default number of output lines shown in the live window.
+const DEFAULT_WINDOW_HEIGHT: usize = 5;
 
-                // Write all lines in the ring buffer (preserving ANSI codes)
-                for line_bytes in &output_ring {
-                    let _ = stderr_handle.write_all(line_bytes);
-                }
-                let _ = stderr_handle.flush();
+/// Options for [`run_subprocess_with_options`].
+///
+/// ```no_run
+/// use cargo_plugin_utils::logger::{
+///     Logger,
+///     RunOptions,
+///     run_subprocess_with_options,
+/// };
+/// use portable_pty::CommandBuilder;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let mut logger = Logger::new();
+/// let options = RunOptions::new()
+///     .cwd("crates/core")
+///     .env("CARGO_TERM_COLOR", "always")
+///     .window_height(10)
+///     .echo_command(true);
+/// let output = run_subprocess_with_options(
+///     &mut logger,
+///     || {
+///         let mut cmd = CommandBuilder::new("cargo");
+///         cmd.arg("build");
+///         cmd
+///     },
+///     &options,
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    cwd: Option<PathBuf>,
+    /// Variables to set (`Some`) or remove (`None`), in order
+    env: Vec<(OsString, Option<OsString>)>,
+    env_clear: bool,
+    env_preset: EnvPreset,
+    pub(crate) window_size: Option<WindowSize>,
+    capture_limit: Option<CaptureLimit>,
+    transcript: Option<PathBuf>,
+    ok_exit_codes: Vec<u32>,
+    echo_command: bool,
+    /// `None` to decide from the progress settings
+    quiet: Option<bool>,
+    keep_on_success: Option<KeepOutput>,
+    priority: Priority,
+    piped: bool,
+    passthrough_stdout: bool,
+    interleave: bool,
+    /// Let the child write to the terminal directly, see
+    /// [`RenderMode::Inherit`]
+    inherit: bool,
+    timeout: Option<Duration>,
+    cancel: Option<CancellationToken>,
+    interrupt_on_ctrl_c: bool,
+    /// `None` for [`INTERRUPT_GRACE`]
+    kill_grace: Option<Duration>,
+    stall_warning: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    on_line: Option<LineHook>,
+    stdin: Option<StdinSource>,
+    not_found_hint: Option<String>,
+    /// In pipe mode, text to show in the window for a stdout line
+    pub(crate) render_stdout: Option<RenderLine>,
+    /// Job window of a parallel run to render into
+    pub(crate) slot: Option<PanelSlot>,
+}
+
+impl RunOptions {
+    /// Default options: PTY mode, inherited environment, 5-line window.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Suggestion shown when the program doesn't exist, e.g. ``install `gh`
+    /// or pass --no-github``. See
+    /// [`CommandNotFound`](crate::command::CommandNotFound).
+    pub fn not_found_hint(mut self, hint: impl Into<String>) -> Self {
+        self.not_found_hint = Some(hint.into());
+        self
+    }
+
+    /// Run the command in `dir` instead of the current directory.
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// Set an environment variable for the command.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), Some(value.into())));
+        self
+    }
+
+    /// Remove an environment variable for the command.
+    pub fn env_remove(mut self, key: impl Into<OsString>) -> Self {
+        self.env.push((key.into(), None));
+        self
+    }
+
+    /// Start from an empty environment instead of inheriting the plugin's.
+    ///
+    /// Like [`std::process::Command::env_clear`], this also discards
+    /// variables set earlier; set variables after calling it.
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    /// Start from `preset` instead of the plugin's whole environment, e.g.
+    /// [`EnvPreset::StripCargo`] for a nested cargo that should build its
+    /// own package. Variables set with [`env`](Self::env) apply on top.
+    pub fn env_preset(mut self, preset: EnvPreset) -> Self {
+        self.env_preset = preset;
+        self
+    }
+
+    /// Number of output lines shown in the live window (default: 5).
+    pub fn window_height(mut self, lines: usize) -> Self {
+        self.window_size = Some(WindowSize::Lines(lines));
+        self
+    }
+
+    /// Size the live window to the terminal, see [`WindowSize`].
+    pub fn window_size(mut self, size: WindowSize) -> Self {
+        self.window_size = Some(size);
+        self
+    }
 
-                current_lines_displayed = output_ring.len();
-                lines_drawn_render
-                    .store(current_lines_displayed, std::sync::atomic::Ordering::SeqCst);
+    /// Show the output as `mode` says: in a window of a given height, not at
+    /// all, or written by the child straight to the terminal.
+    pub fn render(mut self, mode: RenderMode) -> Self {
+        self.inherit = mode == RenderMode::Inherit;
+        match mode {
+            RenderMode::Window { lines } => {
+                self.window_size = Some(WindowSize::Lines(lines));
+                self.quiet = Some(false);
             }
+            RenderMode::Inherit => {}
+            RenderMode::Silent => self.quiet = Some(true),
         }
+        self
+    }
 
-        // Handle any remaining partial line
-        if !output_buffer.is_empty() {
-            output_ring.push(output_buffer);
-            if output_ring.len() > stderr_lines {
-                output_ring.remove(0);
-            }
-            if is_term {
-                let mut stderr_handle = std::io::stderr();
-
-                // Move cursor up to clear previous output (if any)
-                if current_lines_displayed > 0 {
-                    write!(stderr_handle, "\x1b[{}A", current_lines_displayed).ok();
-                    for _ in 0..current_lines_displayed {
-                        write!(stderr_handle, "\x1b[2K\x1b[1B").ok();
-                    }
-                    write!(stderr_handle, "\x1b[{}A", current_lines_displayed).ok();
-                }
+    /// Keep at most the last `bytes` bytes of each captured stream, so a
+    /// runaway subprocess can't exhaust memory. Unlimited by default.
+    pub fn capture_limit(self, bytes: usize) -> Self {
+        self.capture(CaptureLimit::HeadTail {
+            head: 0,
+            tail: bytes,
+        })
+    }
 
-                // Render final ring buffer state
-                for line_bytes in &output_ring {
-                    let _ = stderr_handle.write_all(line_bytes);
-                }
-                let _ = stderr_handle.flush();
+    /// Bound the memory used for each captured stream, keeping its start and
+    /// end or spilling it to a file. What was left out is reported in
+    /// [`SubprocessOutput::stdout_truncation`] and
+    /// [`SubprocessOutput::stderr_truncation`].
+    pub fn capture(mut self, limit: CaptureLimit) -> Self {
+        self.capture_limit = Some(limit);
+        self
+    }
 
-                lines_drawn_render.store(output_ring.len(), std::sync::atomic::Ordering::SeqCst);
-            }
+    /// Write the complete output to `path` as it arrives, with a timestamp
+    /// on each line, while the window only shows the last lines.
+    ///
+    /// The file is replaced at the start of each run and ends with the exit
+    /// status; see [`capture`](crate::capture) for the format. It is written
+    /// regardless of [`capture`](Self::capture) limits.
+    pub fn transcript(mut self, path: impl Into<PathBuf>) -> Self {
+        self.transcript = Some(path.into());
+        self
+    }
+
+    /// Count these nonzero exit codes as success, for tools that report
+    /// ordinary results with them, e.g. `1` for "no match" from `grep` or
+    /// "outdated dependencies found" from `cargo outdated`.
+    ///
+    /// [`SubprocessOutput::success`] is then true for them, so the run is
+    /// treated like any other successful one.
+    pub fn ok_exit_codes(mut self, codes: &[u32]) -> Self {
+        self.ok_exit_codes = codes.to_vec();
+        self
+    }
+
+    /// Print a permanent `Running` line with the command before starting it,
+    /// like cargo does with `-v`. The command is dimmed and
+    /// [shell-quoted](shell_quote), so it can be copied and pasted.
+    pub fn echo_command(mut self, echo: bool) -> Self {
+        self.echo_command = echo;
+        self
+    }
+
+    /// Only capture the output: no output window, no CI section, no
+    /// `Running` line and no terminal manipulation at all, so nothing but
+    /// the plugin's own messages reaches stderr.
+    ///
+    /// By default this follows [`should_show_progress`]: runs are quiet when
+    /// progress is off (`CARGO_TERM_PROGRESS_WHEN=never`, or stdout isn't a
+    /// terminal), except on CI, where the output is streamed into the log.
+    ///
+    /// [`should_show_progress`]: crate::tty::should_show_progress
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = Some(quiet);
+        self
+    }
+
+    /// Whether the run only captures its output, see [`quiet`](Self::quiet).
+    pub fn is_quiet(&self) -> bool {
+        self.quiet.unwrap_or_else(|| {
+            self.slot.is_none()
+                && crate::ci::provider().is_none()
+                && !crate::tty::should_show_progress()
+        })
+    }
+
+    /// Leave the output window on screen after a successful run (exit code 0
+    /// or one of the [`ok_exit_codes`](Self::ok_exit_codes)) instead of
+    /// clearing it, e.g. to keep the confirmation of `cargo publish` visible.
+    /// Failed runs are still cleared, for the plugin to report.
+    pub fn keep_output_on_success(mut self, keep: bool) -> Self {
+        self.keep_on_success = keep.then_some(KeepOutput::Window);
+        self
+    }
+
+    /// Like [`keep_output_on_success`](Self::keep_output_on_success), but
+    /// replace the window with the last `lines` lines of the output, which
+    /// may be more or fewer than the window shows.
+    pub fn keep_last_lines(mut self, lines: usize) -> Self {
+        self.keep_on_success = Some(KeepOutput::Lines(lines));
+        self
+    }
+
+    /// Run the command at a different CPU/IO priority, see
+    /// [`run_subprocess_with_priority`].
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Kill the child if it hasn't exited after `timeout`.
+    ///
+    /// The run then fails with [`SubprocessError::TimedOut`], carrying the
+    /// output captured so far. No timeout by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kill the child when `token` is cancelled, e.g. because the user
+    /// pressed a key or a sibling task failed.
+    ///
+    /// The run then fails with [`SubprocessError::Cancelled`], carrying the
+    /// output captured so far, after the output window has been cleared.
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Stop the child when the user presses Ctrl-C, instead of leaving it
+    /// running while the plugin exits.
+    ///
+    /// On Unix the interrupt is forwarded to the child's process group, which
+    /// gets the [`kill_grace`](Self::kill_grace) to exit before it is killed;
+    /// on Windows the child is killed right away. The terminal's scrolling
+    /// region is reset and the run fails with
+    /// [`SubprocessError::Interrupted`], carrying the output captured so
+    /// far.
+    ///
+    /// Once a run has used this, Ctrl-C no longer terminates the plugin
+    /// between runs; the signal handler stays installed for the process.
+    /// With [`exit::handle_signals`](crate::exit::handle_signals), Ctrl-C
+    /// during the run is left to the run, and exits the plugin again
+    /// afterwards.
+    pub fn interrupt_on_ctrl_c(mut self, interrupt: bool) -> Self {
+        self.interrupt_on_ctrl_c = interrupt;
+        self
+    }
+
+    /// How long a child stopped by a timeout, cancellation or stall gets to
+    /// exit after `SIGTERM` (or after the interrupt, for Ctrl-C) before it
+    /// is killed with `SIGKILL`; [`INTERRUPT_GRACE`] by default. Zero kills
+    /// right away, as Windows always does. The
+    /// [`termination`](SubprocessOutput::termination) of the output says
+    /// which one ended it.
+    pub fn kill_grace(mut self, grace: Duration) -> Self {
+        self.kill_grace = Some(grace);
+        self
+    }
+
+    /// The hint for a missing program, see
+    /// [`not_found_hint`](Self::not_found_hint).
+    pub(crate) fn not_found_hint_text(&self) -> Option<&str> {
+        self.not_found_hint.as_deref()
+    }
+
+    /// The grace period, see [`kill_grace`](Self::kill_grace).
+    pub(crate) fn kill_grace_or_default(&self) -> Duration {
+        self.kill_grace.unwrap_or(INTERRUPT_GRACE)
+    }
+
+    /// Show a yellow "Still running (no output for 1m 00s)" line in the
+    /// output window each time the child has printed nothing for `after`,
+    /// e.g. while a network operation hangs.
+    pub fn stall_warning(mut self, after: Duration) -> Self {
+        self.stall_warning = Some(after);
+        self
+    }
+
+    /// Kill the child once it has printed nothing for `limit`.
+    ///
+    /// The run then fails with [`SubprocessError::Stalled`], carrying the
+    /// output captured so far. Unlike [`timeout`](Self::timeout), a child
+    /// that keeps printing may run for any time.
+    pub fn stall_timeout(mut self, limit: Duration) -> Self {
+        self.stall_timeout = Some(limit);
+        self
+    }
+
+    /// Call `hook` with each complete output line (without the line ending)
+    /// as it is read, e.g. to parse progress markers or detect prompts.
+    ///
+    /// The live window keeps rendering as usual. In pipe mode the hook sees
+    /// both stdout and stderr lines.
+    pub fn on_line<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.on_line = Some(LineHook(Arc::new(Mutex::new(hook))));
+        self
+    }
+
+    /// Call `hook` with each output line after the hook set with
+    /// [`on_line`](Self::on_line), if any.
+    pub(crate) fn also_on_line<F>(self, mut hook: F) -> Self
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        match self.on_line.clone() {
+            Some(LineHook(first)) => self.on_line(move |line| {
+                (first.lock().unwrap_or_else(|err| err.into_inner()))(line);
+                hook(line);
+            }),
+            None => self.on_line(hook),
         }
+    }
 
-        (output_ring, is_term)
-    });
+    /// Write `data` (bytes or a string) to the child's stdin, then signal
+    /// end of input.
+    ///
+    /// In PTY mode the data goes through the terminal with echo turned off,
+    /// so it is subject to line editing: lines are limited to 4095 bytes and
+    /// control characters are interpreted. Use [`piped`](Self::piped) mode for
+    /// binary or large input. Without this option, stdin is the PTY in PTY
+    /// mode and closed in pipe mode.
+    pub fn stdin(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(StdinSource::Bytes(data.into().into()));
+        self
+    }
 
-    // Wait for process to complete (blocking call, so wrap in spawn_blocking)
-    let status = tokio::task::spawn_blocking(move || child.wait())
-        .await
-        .context("Failed to join process wait task")?
-        .context("Failed to wait for subprocess")?;
+    /// Copy `reader` to the child's stdin, then signal end of input; see
+    /// [`stdin`](Self::stdin).
+    ///
+    /// The reader is consumed by the first run using these options.
+    pub fn stdin_reader<R>(mut self, reader: R) -> Self
+    where
+        R: tokio::io::AsyncRead + Send + Unpin + 'static,
+    {
+        self.stdin = Some(StdinSource::Reader(Arc::new(Mutex::new(Some(Box::new(
+            reader,
+        ))))));
+        self
+    }
 
-    // Close the PTY master to signal EOF to the reader
-    // This ensures the reader sees EOF even if the process has already exited
-    // On Windows, we need to drop the master earlier to help the blocking read
-    // return
-    drop(master);
+    /// Use separate stdout and stderr pipes instead of a PTY, see
+    /// [`run_subprocess_piped`].
+    pub fn piped(mut self, piped: bool) -> Self {
+        self.piped = piped;
+        self
+    }
 
-    // On Windows, give a small delay to allow the reader to see EOF
-    #[cfg(windows)]
-    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    /// Pass the child's stdout through to the plugin's stdout, unmodified
+    /// and a whole line at a time, for commands whose output is the
+    /// plugin's product (e.g. generators). Stderr is still shown in the
+    /// window, and stdout still captured. Implies [`piped`](Self::piped).
+    pub fn passthrough_stdout(mut self, passthrough: bool) -> Self {
+        self.passthrough_stdout = passthrough;
+        self
+    }
 
-    // Wait for PTY reading to complete (with timeout to prevent hanging)
-    // If timeout occurs but process has exited, use collected output as fallback
-    // On Windows, use a very short timeout since blocking reads may never return
-    let timeout_duration = if cfg!(windows) {
-        std::time::Duration::from_millis(500)
-    } else {
-        std::time::Duration::from_secs(10)
-    };
-    let pty_output = match tokio::time::timeout(timeout_duration, pty_task).await {
-        Ok(result) => {
-            // Task completed, get the result
-            result.context("Failed to join PTY task")??
-        }
-        Err(_) => {
-            // Timeout occurred - this commonly happens on Windows where blocking
-            // reads in spawn_blocking cannot be cancelled. Since the process has
-            // already exited, we use the output we collected as it arrived through
-            // the channel. The blocking task will continue running in the background
-            // but won't affect the test outcome.
-            // Close the channel to allow render_task to complete
-            drop(tx_clone);
-            collected_output.lock().unwrap().clone()
+    /// Also keep the lines of stdout and stderr in a single timeline, in the
+    /// order they arrived and tagged with their stream, in
+    /// [`SubprocessOutput::interleaved`]. The timeline isn't subject to the
+    /// [capture limit](Self::capture). Implies [`piped`](Self::piped).
+    pub fn interleave(mut self, interleave: bool) -> Self {
+        self.interleave = interleave;
+        self
+    }
+
+    /// Apply the directory and environment options to `cmd`.
+    ///
+    /// Clearing and presets only affect the inherited environment; the
+    /// variables `cmd` sets itself, e.g. those of a
+    /// [`CommandSpec`](crate::CommandSpec), are kept.
+    pub(crate) fn apply(&self, cmd: &mut CommandBuilder) {
+        if self.env_clear {
+            let explicit = crate::command::explicit_env(cmd);
+            cmd.env_clear();
+            for (key, value) in explicit {
+                cmd.env(key, value);
+            }
         }
-    };
-    // Wait for render task with timeout to prevent hanging
-    // Use very short timeout on Windows where operations may hang
-    let render_timeout = if cfg!(windows) {
-        std::time::Duration::from_millis(500)
-    } else {
-        std::time::Duration::from_secs(5)
-    };
-    let (_final_output_ring, was_term) =
-        match tokio::time::timeout(render_timeout, render_task).await {
-            Ok(result) => result.context("Failed to join render task")?,
-            Err(_) => {
-                // Render task timed out - this can happen on Windows where
-                // blocking operations may not complete. We'll continue without
-                // the final render state.
-                (Vec::new(), is_term)
+        self.env_preset.apply(cmd);
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
             }
-        };
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.cwd(cwd);
+        }
+    }
 
-    // For now, treat all PTY output as stderr (we can separate later if needed)
-    // In PTY mode, stdout and stderr are combined
-    let stdout_bytes = Vec::new(); // PTY combines stdout/stderr, so we'll capture all as stderr
-    let stderr_bytes = pty_output;
+    fn window_size_or_default(&self) -> WindowSize {
+        self.window_size
+            .unwrap_or(WindowSize::Lines(DEFAULT_WINDOW_HEIGHT))
+    }
 
-    // Handle final cleanup
-    let exit_code = status.exit_code();
-    let final_lines_drawn = lines_drawn.load(std::sync::atomic::Ordering::SeqCst);
+    /// Start the transcript of a run of `cmd`, if one was asked for.
+    fn start_transcript(&self, cmd: &CommandBuilder) -> anyhow::Result<Option<Transcript>> {
+        self.transcript
+            .as_deref()
+            .map(|path| Transcript::create(path, &command_line(cmd)))
+            .transpose()
+    }
 
-    if was_term && final_lines_drawn > 0 {
-        // Clear the lines we drew by moving up and clearing each line
-        let mut stderr_handle = std::io::stderr();
-        write!(stderr_handle, "\x1b[{}A", final_lines_drawn).ok();
-        for _ in 0..final_lines_drawn {
-            write!(stderr_handle, "\x1b[2K\x1b[1B").ok(); // Clear line, move down
+    /// Clear the `displayed` lines of the output window on `term` at the end
+    /// of a run, or leave some output behind if the run succeeded and
+    /// `options` ask for it. `shown` is the output the window showed.
+    fn close_window(
+        &self,
+        term: &mut impl Write,
+        displayed: usize,
+        status: ExitStatus,
+        stopped: bool,
+        shown: &[u8],
+    ) {
+        let succeeded = !stopped
+            && status
+                .code()
+                .is_some_and(|code| code == 0 || self.ok_exit_codes.contains(&code));
+        match self.keep_on_success.filter(|_| succeeded) {
+            Some(KeepOutput::Window) => {}
+            Some(KeepOutput::Lines(lines)) => {
+                clear_lines_on(term, displayed);
+                print_last_lines(term, shown, lines);
+            }
+            None => clear_lines_on(term, displayed),
+        }
+    }
+
+    /// Run `print`, hiding the job window of a parallel run meanwhile.
+    fn print_above(&self, print: impl FnOnce()) {
+        match &self.slot {
+            Some(slot) => slot.suspend(print),
+            None => print(),
+        }
+    }
+}
+
+type LineCallback = dyn FnMut(&[u8]) + Send;
+
+/// Turns a stdout line into text for the window in pipe mode.
+pub(crate) type RenderLine = fn(&[u8]) -> Option<Vec<u8>>;
+
+/// Shared callback of [`RunOptions::on_line`].
+#[derive(Clone)]
+struct LineHook(Arc<Mutex<LineCallback>>);
+
+impl std::fmt::Debug for LineHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LineHook")
+    }
+}
+
+/// Splits a stream into lines for a [`LineHook`].
+struct LineSplitter {
+    hook: Option<LineHook>,
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    fn new(hook: Option<LineHook>) -> Self {
+        Self {
+            hook,
+            partial: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        if self.hook.is_none() {
+            return;
+        }
+        for &byte in chunk {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.partial);
+                self.call(&line);
+            } else {
+                self.partial.push(byte);
+            }
+        }
+    }
+
+    /// Pass on a trailing line without line ending.
+    fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.call(&line);
+        }
+    }
+
+    fn call(&self, line: &[u8]) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if let Some(LineHook(hook)) = &self.hook {
+            let mut hook = hook.lock().unwrap_or_else(|err| err.into_inner());
+            hook(line);
+        }
+    }
+}
+
+type StdinReader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
+
+/// Input for the child, see [`RunOptions::stdin`].
+#[derive(Clone)]
+enum StdinSource {
+    Bytes(Arc<[u8]>),
+    /// Taken by the first run
+    Reader(Arc<Mutex<Option<StdinReader>>>),
+}
+
+impl std::fmt::Debug for StdinSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bytes(data) => write!(f, "Bytes({} bytes)", data.len()),
+            Self::Reader(_) => f.write_str("Reader"),
+        }
+    }
+}
+
+impl StdinSource {
+    /// Write the input to `writer` in the background.
+    ///
+    /// With `eof`, the terminal's end-of-file character is sent at the end
+    /// (twice if the input doesn't end with a newline: the first one only
+    /// flushes the partial line). Otherwise dropping `writer` closes the
+    /// input. Write errors mean the child stopped reading and are ignored.
+    fn feed(self, mut writer: Box<dyn std::io::Write + Send>, eof: Option<u8>) {
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+            let mut last_byte = None;
+            match self {
+                Self::Bytes(data) => {
+                    writer.write_all(&data)?;
+                    last_byte = data.last().copied();
+                }
+                Self::Reader(reader) => {
+                    let reader = reader.lock().unwrap_or_else(|err| err.into_inner()).take();
+                    if let Some(reader) = reader {
+                        last_byte = copy_reader(&runtime, reader, &mut writer)?;
+                    }
+                }
+            }
+            if let Some(eof) = eof {
+                if last_byte.is_some_and(|byte| byte != b'\n') {
+                    writer.write_all(&[eof])?;
+                }
+                writer.write_all(&[eof])?;
+            }
+            writer.flush()
+        });
+    }
+}
+
+/// Copy an async reader to a blocking writer and return the last byte
+/// copied.
+fn copy_reader(
+    runtime: &tokio::runtime::Handle,
+    mut reader: StdinReader,
+    writer: &mut dyn std::io::Write,
+) -> std::io::Result<Option<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut last_byte = None;
+    let mut buffer = vec![0u8; 4096];
+    loop {
+        let bytes_read = runtime.block_on(reader.read(&mut buffer))?;
+        if bytes_read == 0 {
+            return Ok(last_byte);
+        }
+        writer.write_all(&buffer[..bytes_read])?;
+        last_byte = Some(buffer[bytes_read - 1]);
+    }
+}
+
+/// A writer for the PTY input with echo turned off, and the end-of-file
+/// character to send after the input.
+#[cfg(unix)]
+fn pty_stdin(
+    master: &dyn portable_pty::MasterPty,
+) -> anyhow::Result<(Box<dyn std::io::Write + Send>, Option<u8>)> {
+    use std::os::fd::FromRawFd;
+
+    let fd = master.as_raw_fd().context("PTY has no file descriptor")?;
+    // SAFETY: `termios` is plain data, filled in by `tcgetattr` on a valid
+    // descriptor owned by `master`
+    let eof = unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to get PTY attributes");
+        }
+        termios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to disable PTY echo");
+        }
+        termios.c_cc[libc::VEOF]
+    };
+    // A duplicate of the master, so closing it doesn't write anything (the
+    // writer of `take_writer` sends an extra newline when dropped)
+    // SAFETY: `fd` is valid; `dup` returns a new descriptor that we own
+    let writer = unsafe {
+        let duplicate = libc::dup(fd);
+        if duplicate < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to duplicate PTY");
+        }
+        std::fs::File::from_raw_fd(duplicate)
+    };
+    Ok((Box::new(writer), Some(eof)))
+}
+
+/// A writer for the PTY input, and the end-of-file character to send after
+/// the input.
+#[cfg(not(unix))]
+fn pty_stdin(
+    master: &dyn portable_pty::MasterPty,
+) -> anyhow::Result<(Box<dyn std::io::Write + Send>, Option<u8>)> {
+    let writer = master.take_writer().context("Failed to open PTY input")?;
+    Ok((writer, None))
+}
+
+/// Read `reader` to EOF, passing every chunk to `on_chunk` and pushing it to
+/// `capture`, with the secrets of `redactor` masked.
+///
+/// Every read counts as `activity`, also while the redaction holds back an
+/// unfinished line.
+fn read_capturing(
+    reader: &mut impl std::io::Read,
+    capture: &Mutex<Capture>,
+    mut redactor: RedactStream,
+    activity: &Activity,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> std::io::Result<()> {
+    let mut buffer = vec![0u8; 4096];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        let chunk = if bytes_read == 0 {
+            redactor.finish()
+        } else {
+            activity.touch();
+            redactor.push(&buffer[..bytes_read])
+        };
+        if !chunk.is_empty() {
+            capture
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(&chunk);
+            on_chunk(&chunk);
+        }
+        if bytes_read == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Wait for `child` to exit, killing it if the timeout elapses or the run is
+/// cancelled first.
+async fn wait_child(
+    child: Box<dyn portable_pty::Child + Send + Sync>,
+    options: &RunOptions,
+    activity: &Activity,
+) -> anyhow::Result<(
+    portable_pty::ExitStatus,
+    Option<ResourceUsage>,
+    Option<(StopReason, Termination)>,
+)> {
+    // Take Ctrl-C over from the exit signal watcher and start listening
+    // before the first await, so no interrupt from here on is missed
+    let _claim = options
+        .interrupt_on_ctrl_c
+        .then(crate::exit::claim_interrupts);
+    let mut ctrl_c = options.interrupt_on_ctrl_c.then(listen_ctrl_c).flatten();
+    let usage_tracker = UsageTracker::attach(child.as_ref());
+    let job = usage_tracker.job_killer();
+    let pid = child.process_id();
+    let mut killer = child.clone_killer();
+    let mut wait = tokio::task::spawn_blocking(move || usage_tracker.wait(child));
+
+    let timed_out = async {
+        match options.timeout {
+            Some(timeout) => {
+                tokio::time::sleep(timeout).await;
+                StopReason::TimedOut(timeout)
+            }
+            None => std::future::pending().await,
+        }
+    };
+    let cancelled = async {
+        match &options.cancel {
+            Some(token) => {
+                token.cancelled().await;
+                StopReason::Cancelled
+            }
+            None => std::future::pending().await,
+        }
+    };
+    let interrupted = async {
+        let received = match &mut ctrl_c {
+            Some(ctrl_c) => ctrl_c.recv().await.is_some(),
+            None => false,
+        };
+        if !received {
+            std::future::pending::<()>().await;
+        }
+    };
+    let reason = tokio::select! {
+        waited = &mut wait => {
+            let (status, resources) = reap(waited)?;
+            return Ok((status, resources, None));
+        }
+        reason = watch_stalls(options, activity) => reason,
+        reason = timed_out => reason,
+        reason = cancelled => reason,
+        () = interrupted => StopReason::Interrupted,
+    };
+    let tree = ProcessTree::find(pid).await;
+    let asked = match reason {
+        StopReason::Interrupted => interrupt_child(pid),
+        _ => tree.terminate(),
+    };
+    let grace = options.kill_grace_or_default();
+    let graceful = if asked && !grace.is_zero() {
+        tokio::time::timeout(grace, &mut wait).await.ok()
+    } else {
+        None
+    };
+    let (waited, termination) = match graceful {
+        Some(waited) => {
+            // Whatever the child started and left running goes too
+            tree.kill();
+            (waited, Termination::Graceful)
+        }
+        None => {
+            let _ = tokio::task::spawn_blocking(move || {
+                kill_child(pid, &job, killer.as_mut());
+                tree.kill();
+            })
+            .await;
+            (wait.await, Termination::Killed)
+        }
+    };
+    trace!("pty", "stopped child: {:?}, {:?}", reason, termination);
+    if matches!(reason, StopReason::Interrupted) {
+        let _ = crate::scrolling::reset_scrolling_region();
+    }
+    let (status, resources) = reap(waited)?;
+    Ok((status, resources, Some((reason, termination))))
+}
+
+/// Reap a child the wait task saw exit, once it is no longer signalled.
+fn reap(
+    waited: Result<std::io::Result<Exited>, tokio::task::JoinError>,
+) -> anyhow::Result<(portable_pty::ExitStatus, Option<ResourceUsage>)> {
+    waited
+        .context("Failed to join process wait task")?
+        .and_then(Exited::reap)
+        .context("Failed to wait for subprocess")
+}
+
+/// Start listening for Ctrl-C; `None` if the handler can't be installed.
+#[cfg(unix)]
+fn listen_ctrl_c() -> Option<tokio::signal::unix::Signal> {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt()).ok()
+}
+
+/// Start listening for Ctrl-C; `None` if the handler can't be installed.
+#[cfg(windows)]
+fn listen_ctrl_c() -> Option<tokio::signal::windows::CtrlC> {
+    tokio::signal::windows::ctrl_c().ok()
+}
+
+/// Warn about and stop a child that stays silent, as configured with
+/// [`RunOptions::stall_warning`] and [`RunOptions::stall_timeout`]. Never
+/// completes without a stall timeout.
+async fn watch_stalls(options: &RunOptions, activity: &Activity) -> StopReason {
+    if options.stall_warning.is_none() && options.stall_timeout.is_none() {
+        return std::future::pending().await;
+    }
+    let mut warnings = 0;
+    loop {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let silence = activity.silence();
+        if let Some(limit) = options.stall_timeout
+            && silence >= limit
+        {
+            return StopReason::Stalled(limit);
+        }
+        let Some(after) = options.stall_warning else {
+            continue;
+        };
+        // One warning per `after` of silence, starting over on output
+        let due = silence.as_nanos() / after.as_nanos().max(1);
+        if due > warnings {
+            let due = u32::try_from(due).unwrap_or(u32::MAX);
+            activity.notice(format!(
+                "Still running (no output for {})",
+                crate::human::Locale::POSIX.duration(after.saturating_mul(due))
+            ));
+        }
+        warnings = due;
+    }
+}
+
+/// When a running child last printed something, and a way to show notices
+/// in its output window.
+#[derive(Debug, Clone)]
+struct Activity {
+    last_output: Arc<Mutex<std::time::Instant>>,
+    notices: tokio::sync::mpsc::UnboundedSender<String>,
+}
+
+impl Activity {
+    /// Activity starting now, with the receiving end for the notices.
+    fn new() -> (Self, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let (notices, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let activity = Self {
+            last_output: Arc::new(Mutex::new(std::time::Instant::now())),
+            notices,
+        };
+        (activity, receiver)
+    }
+
+    /// Record output.
+    fn touch(&self) {
+        *self
+            .last_output
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = std::time::Instant::now();
+    }
+
+    /// Time since the last output.
+    fn silence(&self) -> Duration {
+        self.last_output
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .elapsed()
+    }
+
+    /// Show `text` as a warning line in the output window.
+    fn notice(&self, text: String) {
+        let _ = self.notices.send(text);
+    }
+}
+
+/// How long a child may take to exit after Ctrl-C is forwarded to it, or
+/// after `SIGTERM`, unless set with [`RunOptions::kill_grace`].
+pub const INTERRUPT_GRACE: Duration = Duration::from_secs(2);
+
+/// Forward an interrupt to the process group of a child started by the
+/// `run_subprocess*` functions. Children in a PTY session or their own
+/// process group don't receive the terminal's Ctrl-C themselves.
+fn interrupt_child(pid: Option<u32>) -> bool {
+    #[cfg(unix)]
+    if let Some(pid) = pid.and_then(|pid| i32::try_from(pid).ok()) {
+        // SAFETY: sending a signal has no memory safety preconditions
+        return unsafe { libc::kill(-pid, libc::SIGINT) } == 0;
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+    false
+}
+
+/// The process group of a child and its descendants, found before it is
+/// asked to stop: once their parents exit, descendants are reparented and
+/// can't be traced back.
+#[derive(Debug, Default)]
+pub(crate) struct ProcessTree {
+    pid: Option<u32>,
+    descendants: Vec<u32>,
+}
+
+impl ProcessTree {
+    /// Find the descendants of `pid` (none on Windows). Runs `ps` on a
+    /// blocking thread.
+    pub(crate) async fn find(pid: Option<u32>) -> Self {
+        #[cfg(unix)]
+        let descendants = match pid {
+            Some(pid) => tokio::task::spawn_blocking(move || descendants(pid))
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        #[cfg(not(unix))]
+        let descendants = Vec::new();
+        Self { pid, descendants }
+    }
+
+    /// Ask the child and everything it started to exit with `SIGTERM`;
+    /// `false` if that wasn't possible (always, on Windows).
+    pub(crate) fn terminate(&self) -> bool {
+        #[cfg(unix)]
+        if let Some(pid) = self.pid {
+            let mut pids = self.descendants.clone();
+            pids.push(pid);
+            return signal_pids(pid, &pids, libc::SIGTERM).is_ok();
+        }
+        false
+    }
+
+    /// Kill the process group and the descendants that are still running,
+    /// e.g. those ignoring `SIGTERM` after the child itself exited.
+    pub(crate) fn kill(&self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.pid {
+            let _ = signal_pids(pid, &self.descendants, libc::SIGKILL);
+        }
+    }
+}
+
+/// Forcibly terminate a child started by the `run_subprocess*` functions,
+/// with every process it started (see [`kill_tree`]).
+///
+/// On Windows the child's job object is terminated as a whole.
+fn kill_child(
+    pid: Option<u32>,
+    job: &JobKiller,
+    killer: &mut (dyn portable_pty::ChildKiller + Send + Sync),
+) {
+    if job.kill() {
+        return;
+    }
+    if let Some(pid) = pid
+        && kill_tree(pid).is_ok()
+    {
+        return;
+    }
+    let _ = killer.kill();
+}
+
+/// Forcibly terminate process `pid` and all its descendants, e.g. a cargo
+/// run with the `rustc` processes it spawned.
+///
+/// On Unix, the process group led by `pid` is killed along with every
+/// descendant found with `ps`, so processes that moved to a group of their
+/// own are included. On Windows this is `taskkill /T /F`.
+pub fn kill_tree(pid: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        signal_tree(pid, libc::SIGKILL)
+    }
+    #[cfg(not(unix))]
+    {
+        let status = std::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "taskkill failed with {}",
+                status
+            )))
+        }
+    }
+}
+
+/// Send `signal` to the process group led by `pid`, and to `pid` and all
+/// its descendants.
+#[cfg(unix)]
+fn signal_tree(pid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    // Find the descendants first: once their parents are gone, they are
+    // reparented and can't be traced back
+    let mut pids = descendants(pid);
+    pids.push(pid);
+    signal_pids(pid, &pids, signal)
+}
+
+/// Send `signal` to the process group led by `group` and to `pids`.
+#[cfg(unix)]
+fn signal_pids(group: u32, pids: &[u32], signal: libc::c_int) -> std::io::Result<()> {
+    let group = i32::try_from(group).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    // SAFETY: sending a signal has no memory safety preconditions
+    let mut sent = unsafe { libc::kill(-group, signal) } == 0;
+    for &pid in pids {
+        let Ok(pid) = i32::try_from(pid) else {
+            continue;
+        };
+        // SAFETY: as above
+        sent |= unsafe { libc::kill(pid, signal) } == 0;
+    }
+    if sent {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// The processes descended from `pid`, from a `ps` listing; none if `ps`
+/// can't be run.
+#[cfg(unix)]
+fn descendants(pid: u32) -> Vec<u32> {
+    let Ok(output) = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=", "-o", "ppid="])
+        .stderr(std::process::Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    let parents: Vec<(u32, u32)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+        })
+        .collect();
+    let mut found = Vec::new();
+    let mut queue = vec![pid];
+    while let Some(parent) = queue.pop() {
+        for &(child, _) in parents.iter().filter(|(_, ppid)| *ppid == parent) {
+            if !found.contains(&child) {
+                found.push(child);
+                queue.push(child);
+            }
+        }
+    }
+    found
+}
+
+/// The command line of `cmd`, shell-quoted for display.
+pub(crate) fn command_line(cmd: &CommandBuilder) -> String {
+    cmd.get_argv()
+        .iter()
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quote `arg` for the platform's shell if needed, so a displayed command
+/// can be pasted back: single quotes for POSIX shells, double quotes on
+/// Windows.
+pub fn shell_quote(arg: &str) -> String {
+    let is_plain =
+        |character: char| character.is_ascii_alphanumeric() || "-_.,:/@=+%".contains(character);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        return arg.to_string();
+    }
+    if cfg!(windows) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Run a subprocess with piped stdout/stderr, capturing stdout fully while
+/// rendering stderr lines live in a ring buffer.
+///
+/// # Arguments
+///
+/// * `logger` - Logger instance to manage progress bar suspension/clearing
+/// * `cmd_builder` - Closure that builds a `portable_pty::CommandBuilder`, or a
+///   [`CommandSpec`](crate::CommandSpec)
+/// * `stderr_lines` - Number of stderr lines to show in the scrolling region
+///   (default: 5)
+///
+/// # Behavior
+///
+/// - Uses PTY mode so subprocesses see a TTY (preserves ANSI colors)
+/// - The PTY merges stdout into stderr, so `stdout` of the result is empty; use
+///   [`run_subprocess_piped`] when stdout must be kept separate
+/// - On Windows the PTY is a ConPTY; on older systems without ConPTY the
+///   command runs with pipes instead, its stdout merged into stderr
+/// - Nothing is drawn when stderr isn't a terminal that understands VT
+///   sequences, see [`supports_vt`](crate::tty::supports_vt)
+/// - Sets up a scrolling region at the bottom of the terminal
+/// - Suspends/clears any active progress bar before running
+/// - Captures stdout fully
+/// - Renders stderr lines live in the scrolling region
+/// - On success: clears the scrolling region cleanly, unless
+///   [`RunOptions::keep_output_on_success`] or [`RunOptions::keep_last_lines`]
+///   ask to keep some of it
+/// - On failure: leaves/replays the final window
+///
+/// # Returns
+///
+/// Returns `SubprocessOutput` with captured stdout, stderr, and exit status.
+pub async fn run_subprocess<F>(
+    logger: &mut Logger,
+    cmd_builder: F,
+    stderr_lines: Option<usize>,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    let options = RunOptions {
+        window_size: stderr_lines.map(WindowSize::Lines),
+        ..RunOptions::default()
+    };
+    run_subprocess_with_options(logger, cmd_builder, &options).await
+}
+
+/// Run a subprocess like [`run_subprocess`], at a different CPU/IO priority.
+///
+/// Use [`Priority::Low`] or [`Priority::Idle`] for background-ish work such as
+/// doc generation so it doesn't starve the user's interactive work. If the
+/// priority cannot be applied (e.g. raising it without privileges), a warning
+/// is printed and the subprocess keeps running at normal priority.
+pub async fn run_subprocess_with_priority<F>(
+    logger: &mut Logger,
+    cmd_builder: F,
+    stderr_lines: Option<usize>,
+    priority: Priority,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    let options = RunOptions {
+        window_size: stderr_lines.map(WindowSize::Lines),
+        priority,
+        ..RunOptions::default()
+    };
+    run_subprocess_with_options(logger, cmd_builder, &options).await
+}
+
+/// Run a command line such as `"git log --oneline | head -n 5"` in the
+/// platform's shell (`sh -c` on Unix, PowerShell on Windows), configured
+/// with [`RunOptions`].
+///
+/// The script is passed as a single argument, so quotes in it reach the
+/// shell as written.
+///
+/// For another shell, run [`Shell::command`](crate::command::Shell::command)
+/// with [`run_subprocess_with_options`].
+pub async fn run_shell(
+    logger: &mut Logger,
+    script: &str,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    let cmd = crate::command::Shell::default().command(script);
+    run_subprocess_with_options(logger, || cmd, options).await
+}
+
+/// How [`run_subprocess_standalone`] (or any run with
+/// [`RunOptions::render`]) shows the child's output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// The last `lines` lines in a live window below the cursor, as
+    /// [`run_subprocess`] shows them
+    Window {
+        /// Height of the window
+        lines: usize,
+    },
+    /// The child writes to the plugin's stdout and stderr itself; nothing is
+    /// captured and the PTY, pipe, stdin, line hook and transcript options
+    /// don't apply
+    Inherit,
+    /// Nothing; the output is only captured
+    Silent,
+}
+
+/// Height of the live output window, see [`RunOptions::window_size`].
+///
+/// The relative sizes are recomputed from the terminal's size on every
+/// redraw, so the window follows when the terminal is resized mid-run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowSize {
+    /// Exactly this many lines; 0 hides the window
+    Lines(usize),
+    /// This fraction of the terminal's rows, e.g. `0.25` for a quarter
+    Fraction(f32),
+    /// A quarter of the terminal's rows, but at least the default 5 lines
+    /// and at most 20
+    Auto,
+}
+
+impl WindowSize {
+    /// Fewest lines of a [`Fraction`](Self::Fraction) window.
+    pub const MIN_LINES: usize = 3;
+    /// Most lines of a [`Fraction`](Self::Fraction) window.
+    pub const MAX_LINES: usize = 40;
+
+    /// The window height in a terminal of `terminal` size.
+    ///
+    /// A relative size always leaves two rows of the terminal for the status
+    /// line and the prompt.
+    pub fn lines(self, terminal: crate::resize::TermSize) -> usize {
+        let rows = usize::from(terminal.rows);
+        let (lines, min, max) = match self {
+            Self::Lines(lines) => return lines,
+            Self::Fraction(fraction) => (
+                (rows as f32 * fraction.clamp(0.0, 1.0)) as usize,
+                Self::MIN_LINES,
+                Self::MAX_LINES,
+            ),
+            Self::Auto => (rows / 4, DEFAULT_WINDOW_HEIGHT, 20),
+        };
+        lines.clamp(min, max).min(rows.saturating_sub(2)).max(1)
+    }
+}
+
+/// Run a subprocess without a [`Logger`], for binaries that don't use one:
+/// with a PTY and the output shown as `mode` says.
+///
+/// Everything else (directory, environment, timeout, capture limit, ...)
+/// comes from `options`; its window height and quiet setting are replaced
+/// by `mode`.
+///
+/// ```no_run
+/// use cargo_plugin_utils::RunOptions;
+/// use cargo_plugin_utils::logger::{
+///     RenderMode,
+///     run_subprocess_standalone,
+/// };
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let output = run_subprocess_standalone(
+///     || portable_pty::CommandBuilder::new("cargo"),
+///     RenderMode::Window { lines: 8 },
+///     &RunOptions::new(),
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn run_subprocess_standalone<F>(
+    cmd_builder: F,
+    mode: RenderMode,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    let options = options.clone().render(mode);
+    run_command(&Logger::new(), cmd_builder.into_command(), &options).await
+}
+
+/// Run a subprocess like [`run_subprocess`], configured with [`RunOptions`].
+///
+/// The directory and environment options are applied on top of whatever the
+/// `cmd_builder` closure sets.
+pub async fn run_subprocess_with_options<F>(
+    logger: &mut Logger,
+    cmd_builder: F,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    // Clear any existing Logger output before the window is drawn to avoid
+    // cursor position conflicts: the window moves the cursor, so Logger's
+    // Drop wouldn't be able to clear its lines correctly.
+    let term = console::Term::stderr();
+    if term.is_term() && !options.is_quiet() {
+        logger.clear_for_window(&term);
+    }
+    run_command(logger, cmd_builder.into_command(), options).await
+}
+
+/// Run a subprocess like [`run_subprocess_with_options`], for plugins
+/// without an async runtime of their own.
+///
+/// Blocks the calling thread on a private single-threaded runtime while the
+/// window renders. Fails when called from inside a tokio runtime, where the
+/// async functions should be awaited instead.
+pub fn run_subprocess_blocking<F>(
+    logger: &mut Logger,
+    cmd_builder: F,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    anyhow::ensure!(
+        tokio::runtime::Handle::try_current().is_err(),
+        "run_subprocess_blocking() can't be used inside an async runtime; await \
+         run_subprocess_with_options() instead"
+    );
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start a runtime for the subprocess")?;
+    runtime.block_on(run_subprocess_with_options(logger, cmd_builder, options))
+}
+
+/// Run a subprocess that talks to the user, e.g. git asking for credentials
+/// or `gh auth login`.
+///
+/// The child gets a PTY as large as the terminal, the user's keys are passed
+/// to it as typed (stdin in raw mode) and its output is shown as is, without
+/// the output window. The status line is hidden meanwhile. The output is
+/// still captured in [`SubprocessOutput::stderr`], within the capture limit
+/// of `options`; the window, quiet and pipe options don't apply.
+///
+/// On Windows the child inherits the console instead, and nothing is
+/// captured.
+pub async fn run_subprocess_interactive<F>(
+    logger: &mut Logger,
+    cmd_builder: F,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    let mut cmd = cmd_builder.into_command();
+    options.apply(&mut cmd);
+    if options.echo_command {
+        let command = format!("`{}`", command_line(&cmd));
+        logger.status_permanent("Running", &console::style(command).dim().to_string());
+    }
+    let started = std::time::Instant::now();
+    let mut output = logger.suspend_async(run_interactive(cmd, options)).await?;
+    output.duration = started.elapsed();
+    output.ok_exit_codes = options.ok_exit_codes.clone();
+    Ok(output)
+}
+
+/// PTY size of an interactive run: the whole terminal.
+#[cfg(unix)]
+fn full_size(size: crate::resize::TermSize) -> PtySize {
+    PtySize {
+        rows: size.rows,
+        cols: size.cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+/// Run `cmd` in a PTY connected to the user's terminal, see
+/// [`run_subprocess_interactive`].
+#[cfg(unix)]
+async fn run_interactive(
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    let pty = native_pty_system()
+        .openpty(full_size(crate::resize::current()))
+        .context("Failed to create PTY")?;
+    let child = match pty.slave.spawn_command(cmd.clone()) {
+        Ok(child) => child,
+        Err(err) => {
+            let err = err.context("Failed to spawn command in PTY");
+            return Err(spawn_error(&cmd, options.not_found_hint.as_deref(), err));
+        }
+    };
+    trace!(
+        "pty",
+        "spawned pid {} interactively",
+        child.process_id().unwrap_or_default()
+    );
+    drop(pty.slave);
+    let mut reader = pty
+        .master
+        .try_clone_reader()
+        .context("Failed to clone PTY reader")?;
+    let writer = pty
+        .master
+        .take_writer()
+        .context("Failed to open PTY input")?;
+    let raw_mode = crate::tty::RawMode::enable();
+
+    let stop_input = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let input_thread = std::thread::spawn({
+        let stop = stop_input.clone();
+        move || forward_stdin(writer, &stop)
+    });
+    let capture_limit = options.capture_limit;
+    let (activity, _notices) = Activity::new();
+    let reader_activity = activity.clone();
+    let output_task = tokio::task::spawn_blocking(move || {
+        let mut stderr = std::io::stderr();
+        let mut capture = Capture::new(capture_limit);
+        let mut buffer = vec![0u8; 4096];
+        // Shown as is, a prompt must not wait for its line to end; only the
+        // capture is masked
+        while let Ok(bytes_read @ 1..) = reader.read(&mut buffer) {
+            reader_activity.touch();
+            let _ = stderr.write_all(&buffer[..bytes_read]);
+            let _ = stderr.flush();
+            capture.push(&buffer[..bytes_read]);
+        }
+        let (captured, truncation) = capture.finish();
+        (
+            crate::redact::redact_bytes(&captured).into_owned(),
+            truncation,
+        )
+    });
+    let (stop_resizes, resizes_stopped) = tokio::sync::oneshot::channel();
+    let resize_task = tokio::spawn(forward_resizes(pty.master, full_size, resizes_stopped));
+
+    let (status, resources, stopped) = wait_child(child, options, &activity).await?;
+    trace!("pty", "child exited: {:?}, stopped: {:?}", status, stopped);
+    stop_input.store(true, Ordering::SeqCst);
+    let _ = stop_resizes.send(());
+    let _ = resize_task.await;
+    let (captured, truncation) =
+        match tokio::time::timeout(Duration::from_secs(10), output_task).await {
+            Ok(result) => result.context("Failed to join PTY task")?,
+            Err(_) => (Vec::new(), None),
+        };
+    let _ = input_thread.join();
+    drop(raw_mode);
+
+    StopReason::into_result(
+        stopped,
+        SubprocessOutput {
+            stdout: Vec::new(),
+            stderr: captured,
+            exit_code: status.exit_code(),
+            status: ExitStatus::from(&status),
+            duration: Duration::ZERO,
+            resources,
+            stdout_truncation: None,
+            stderr_truncation: truncation,
+            ok_exit_codes: Vec::new(),
+            termination: None,
+            interleaved: Vec::new(),
+        },
+    )
+}
+
+/// Copy the user's stdin to the PTY until `stop` is set or stdin ends,
+/// polling so the thread notices `stop` without waiting for another key.
+#[cfg(unix)]
+fn forward_stdin(mut writer: Box<dyn std::io::Write + Send>, stop: &std::sync::atomic::AtomicBool) {
+    let mut buffer = [0u8; 1024];
+    while !stop.load(Ordering::SeqCst) {
+        let mut poll = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: one valid pollfd, and a buffer of the given length
+        let bytes_read = unsafe {
+            if libc::poll(&mut poll, 1, 100) <= 0 {
+                continue;
+            }
+            libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len())
+        };
+        if bytes_read <= 0 {
+            return;
+        }
+        let chunk = &buffer[..bytes_read as usize];
+        if writer
+            .write_all(chunk)
+            .and_then(|()| writer.flush())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Run `cmd` on the user's console, see [`run_subprocess_interactive`].
+#[cfg(not(unix))]
+async fn run_interactive(
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    run_inherited(cmd, options).await
+}
+
+/// Run `cmd` with the plugin's stdin, stdout and stderr, capturing nothing.
+async fn run_inherited(
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    let mut command = std_command(&cmd)?;
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            let err =
+                anyhow::Error::new(err).context(format!("Failed to spawn {:?}", cmd.get_argv()[0]));
+            return Err(spawn_error(&cmd, options.not_found_hint.as_deref(), err));
+        }
+    };
+    let child: Box<dyn portable_pty::Child + Send + Sync> = Box::new(child);
+    let (activity, _notices) = Activity::new();
+    let (status, resources, stopped) = wait_child(child, options, &activity).await?;
+    StopReason::into_result(
+        stopped,
+        SubprocessOutput {
+            exit_code: status.exit_code(),
+            status: ExitStatus::from(&status),
+            resources,
+            ..SubprocessOutput::default()
+        },
+    )
+}
+
+/// Run `cmd` with `options` once the Logger's own lines are cleared.
+pub(crate) async fn run_command(
+    logger: &Logger,
+    mut cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    options.apply(&mut cmd);
+    if options.echo_command && !options.is_quiet() {
+        options.print_above(|| {
+            let command = format!("`{}`", command_line(&cmd));
+            logger.status_permanent("Running", &console::style(command).dim().to_string());
+        });
+    }
+    if !crate::crash::is_installed() {
+        return execute(logger, cmd, options).await;
+    }
+    let command = crate::crash::redact_args(
+        cmd.get_argv()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned()),
+    )
+    .iter()
+    .map(|arg| shell_quote(arg))
+    .collect::<Vec<_>>()
+    .join(" ");
+    let result = execute(logger, cmd, options).await;
+    match &result {
+        Ok(output) => crate::crash::record_subprocess(&command, output, None),
+        Err(err) => {
+            if let Some(stopped) = err.downcast_ref::<SubprocessError>() {
+                crate::crash::record_subprocess(&command, stopped.output(), Some(stopped));
+            }
+        }
+    }
+    result
+}
+
+/// Run `cmd` with a PTY or pipes, as `options` ask.
+async fn execute(
+    logger: &Logger,
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    let started = std::time::Instant::now();
+    let mut output = spawn_and_wait(logger, cmd, options).await?;
+    output.duration = started.elapsed();
+    output.ok_exit_codes = options.ok_exit_codes.clone();
+    Ok(output)
+}
+
+async fn spawn_and_wait(
+    logger: &Logger,
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    if options.inherit {
+        return run_inherited(cmd, options).await;
+    }
+    if options.piped || options.passthrough_stdout || options.interleave {
+        return run_piped(logger, cmd, options).await;
+    }
+    let pty = match open_pty(options) {
+        Ok(pty) => pty,
+        // Windows before 10 1809 has no ConPTY: run with pipes instead, with
+        // the output merged into stderr as a PTY would
+        Err(_) if cfg!(windows) => {
+            let output = run_piped(logger, cmd, options).await?;
+            return Ok(output.merged_into_stderr());
+        }
+        Err(err) => return Err(err),
+    };
+    run_pty(logger, cmd, options, pty).await
+}
+
+/// Open a PTY sized for the output window of `options`.
+fn open_pty(options: &RunOptions) -> anyhow::Result<portable_pty::PtyPair> {
+    native_pty_system()
+        .openpty(pty_size(options, crate::resize::current()))
+        .context("Failed to create PTY")
+}
+
+/// PTY size for `options` in a terminal of `terminal` size: as high as the
+/// output window, as wide as the terminal, so the child wraps its lines
+/// where the window does.
+fn pty_size(options: &RunOptions, terminal: crate::resize::TermSize) -> PtySize {
+    let rows = options
+        .window_size_or_default()
+        .lines(terminal)
+        .clamp(1, usize::from(u16::MAX));
+    PtySize {
+        rows: rows as u16,
+        cols: terminal.cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    }
+}
+
+/// Resize the PTY along with the terminal until `stop` fires, then close it.
+/// `size` gives the PTY size for a terminal size.
+async fn forward_resizes(
+    master: Box<dyn portable_pty::MasterPty + Send>,
+    size: impl Fn(crate::resize::TermSize) -> PtySize,
+    mut stop: tokio::sync::oneshot::Receiver<()>,
+) {
+    let mut sizes = crate::resize::subscribe();
+    loop {
+        let changed = tokio::select! {
+            _ = &mut stop => return,
+            changed = sizes.changed() => changed,
+        };
+        if changed.is_err() {
+            // No more size updates, just wait for the end of the run
+            let _ = stop.await;
+            return;
+        }
+        let terminal = *sizes.borrow_and_update();
+        trace!("pty", "terminal resized, {} columns", terminal.cols);
+        let _ = master.resize(size(terminal));
+    }
+}
+
+/// Run `cmd` in a PTY, see [`run_subprocess`].
+async fn run_pty(
+    logger: &Logger,
+    cmd: CommandBuilder,
+    options: &RunOptions,
+    pty: portable_pty::PtyPair,
+) -> anyhow::Result<SubprocessOutput> {
+    let window_size = options.window_size_or_default();
+    let capture_limit = options.capture_limit;
+
+    let is_term = !options.is_quiet() && crate::tty::supports_vt();
+    let ci = ci_renderer(&cmd, options, is_term);
+
+    // Track how many lines we've drawn for cleanup
+    let lines_drawn = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let lines_drawn_render = lines_drawn.clone();
+
+    let transcript = options.start_transcript(&cmd)?;
+    let mut transcript_stream = transcript
+        .as_ref()
+        .map(|transcript| transcript.stream(None));
+
+    // Set up the input before the child starts, so turning off echo doesn't
+    // override terminal settings made by the child
+    let stdin_writer = match &options.stdin {
+        Some(_) => Some(pty_stdin(pty.master.as_ref())?),
+        None => None,
+    };
+
+    // Spawn command in PTY; only the child may hold the slave side open,
+    // otherwise the reader never sees EOF after the child exits
+    let slave = pty.slave;
+    let spawn_cmd = cmd.clone();
+    let (child, unprioritized) = options
+        .priority
+        .spawn(move || slave.spawn_command(spawn_cmd));
+    let child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            let err = err.context("Failed to spawn command in PTY");
+            return Err(spawn_error(&cmd, options.not_found_hint.as_deref(), err));
+        }
+    };
+    trace!(
+        "pty",
+        "spawned pid {} in a PTY, window of {:?}",
+        child.process_id().unwrap_or_default(),
+        window_size
+    );
+    if let (Some(source), Some((writer, eof))) = (options.stdin.clone(), stdin_writer) {
+        source.feed(writer, eof);
+    }
+
+    if let Some(err) = unprioritized {
+        options.print_above(|| logger.warning("Warning", &format!("{:#}", err)));
+    }
+
+    // Get handles for stdout and stderr from PTY
+    // We need to keep a reference to the master to close it later
+    let mut reader = pty
+        .master
+        .try_clone_reader()
+        .context("Failed to clone PTY reader")?;
+
+    // Keep the master alive until we're done reading, following terminal
+    // resizes meanwhile
+    let (stop_resizes, resizes_stopped) = tokio::sync::oneshot::channel();
+    let window = options.clone();
+    let resize_task = tokio::spawn(forward_resizes(
+        pty.master,
+        move |terminal| pty_size(&window, terminal),
+        resizes_stopped,
+    ));
+
+    // Channel to coordinate rendering (send raw bytes to preserve ANSI codes)
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+    // Keep a clone of tx to close the channel if we timeout
+    let tx_clone = tx.clone();
+
+    // Collect output as it arrives, shared so it's still available if the
+    // reader task hangs
+    let collected_output = std::sync::Arc::new(std::sync::Mutex::new(Capture::new(capture_limit)));
+    let collected_output_clone = collected_output.clone();
+    let (activity, mut notices) = Activity::new();
+    let reader_activity = activity.clone();
+
+    // Task to read from PTY (combines stdout and stderr)
+    // PTY reader is blocking, so we use spawn_blocking
+    // Allow excessive nesting: inherent to async spawn + spawn_blocking + loop +
+    // match pattern
+    #[allow(clippy::excessive_nesting)]
+    let pty_task = tokio::spawn(async move {
+        tokio::task::spawn_blocking(move || {
+            let mut buffer = vec![0u8; 4096];
+            let mut redactor = RedactStream::new();
+
+            loop {
+                let (chunk, done) = match reader.read(&mut buffer) {
+                    Ok(0) => {
+                        trace!("pty", "reader reached EOF");
+                        (redactor.finish(), true)
+                    }
+                    Ok(bytes_read) => {
+                        reader_activity.touch();
+                        (redactor.push(&buffer[..bytes_read]), false)
+                    }
+                    Err(err) => {
+                        // On error, still capture what we have
+                        let mut rest = redactor.finish();
+                        rest.extend_from_slice(format!("<pty read error: {}>", err).as_bytes());
+                        (rest, true)
+                    }
+                };
+                if !chunk.is_empty() {
+                    if let Some(stream) = &mut transcript_stream {
+                        stream.push(&chunk);
+                    }
+                    if let Ok(mut collected) = collected_output_clone.lock() {
+                        collected.push(&chunk);
+                    }
+                    let _ = tx.send(chunk);
+                }
+                if done {
+                    break;
+                }
+            }
+
+            if let Some(stream) = &mut transcript_stream {
+                stream.finish();
+            }
+            // Close the channel to signal completion
+            drop(tx);
+        })
+        .await
+        .context("Failed to join blocking PTY read task")
+    });
+
+    // Render output inline (below current cursor position)
+    let slot = options.slot.clone().filter(|_| !options.is_quiet());
+    let mut splitter = LineSplitter::new(options.on_line.clone());
+    let render_task = tokio::spawn(async move {
+        let mut window = OutputWindow::new(window_size, is_term, slot, ci);
+        loop {
+            tokio::select! {
+                chunk = rx.recv() => {
+                    let Some(chunk) = chunk else { break };
+                    trace!("render", "{} bytes, {} chunks queued", chunk.len(), rx.len());
+                    splitter.push(&chunk);
+                    window.push(&chunk);
+                }
+                Some(notice) = notices.recv() => window.notice(&notice),
+            }
+            lines_drawn_render.store(window.displayed(), std::sync::atomic::Ordering::SeqCst);
+        }
+        // Handle any remaining partial line
+        splitter.finish();
+        window.finish();
+        lines_drawn_render.store(window.displayed(), std::sync::atomic::Ordering::SeqCst);
+        (window.into_lines(), is_term)
+    });
+
+    // Wait for process to complete (blocking call, so wrap in spawn_blocking)
+    let (status, resources, stopped) = wait_child(child, options, &activity).await?;
+    trace!("pty", "child exited: {:?}, stopped: {:?}", status, stopped);
+
+    // Close the PTY master to signal EOF to the reader
+    // This ensures the reader sees EOF even if the process has already exited
+    // On Windows, we need to drop the master earlier to help the blocking read
+    // return
+    let _ = stop_resizes.send(());
+    let _ = resize_task.await;
+
+    // On Windows, give a small delay to allow the reader to see EOF
+    #[cfg(windows)]
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Wait for PTY reading to complete (with timeout to prevent hanging)
+    // If timeout occurs but process has exited, use collected output as fallback
+    // On Windows, use a very short timeout since blocking reads may never return
+    let timeout_duration = reader_timeout();
+    // On timeout (common on Windows, where blocking reads in spawn_blocking
+    // cannot be cancelled) the process has already exited, so we use the
+    // output collected so far. The blocking task keeps running in the
+    // background but won't affect the outcome.
+    match tokio::time::timeout(timeout_duration, pty_task).await {
+        Ok(result) => result.context("Failed to join PTY task")??,
+        Err(_) => trace!(
+            "pty",
+            "reader still blocked after {:?}, using the output so far", timeout_duration
+        ),
+    }
+    if let Some(transcript) = &transcript {
+        transcript.finish(&ExitStatus::from(&status).describe());
+    }
+    let (pty_output, pty_truncation) = std::mem::take(
+        &mut *collected_output
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()),
+    )
+    .finish();
+    // Close the channel to allow render_task to complete
+    drop(tx_clone);
+    // Wait for render task with timeout to prevent hanging
+    // Use very short timeout on Windows where operations may hang
+    let render_timeout = if cfg!(windows) {
+        std::time::Duration::from_millis(500)
+    } else {
+        std::time::Duration::from_secs(5)
+    };
+    let (_final_output_ring, was_term) =
+        match tokio::time::timeout(render_timeout, render_task).await {
+            Ok(result) => result.context("Failed to join render task")?,
+            Err(_) => {
+                // Render task timed out - this can happen on Windows where
+                // blocking operations may not complete. We'll continue without
+                // the final render state.
+                (Vec::new(), is_term)
+            }
+        };
+
+    // For now, treat all PTY output as stderr (we can separate later if needed)
+    // In PTY mode, stdout and stderr are combined
+    let stdout_bytes = Vec::new(); // PTY combines stdout/stderr, so we'll capture all as stderr
+    let stderr_bytes = pty_output;
+
+    // Handle final cleanup
+    let exit_code = status.exit_code();
+    let final_lines_drawn = lines_drawn.load(std::sync::atomic::Ordering::SeqCst);
+
+    if was_term {
+        options.close_window(
+            &mut std::io::stderr(),
+            final_lines_drawn,
+            ExitStatus::from(&status),
+            stopped.is_some(),
+            &stderr_bytes,
+        );
+    }
+
+    StopReason::into_result(
+        stopped,
+        SubprocessOutput {
+            stdout: stdout_bytes,
+            stderr: stderr_bytes,
+            exit_code,
+            status: ExitStatus::from(&status),
+            duration: Duration::ZERO,
+            resources,
+            stdout_truncation: None,
+            stderr_truncation: pty_truncation,
+            ok_exit_codes: Vec::new(),
+            termination: None,
+            interleaved: Vec::new(),
+        },
+    )
+}
+
+/// Run a subprocess with separate stdout and stderr pipes instead of a PTY.
+///
+/// Stdout is captured in full and never shown, so machine-readable output
+/// (e.g. `cargo metadata`, `gh api`) arrives in [`SubprocessOutput::stdout`]
+/// intact, while stderr is rendered live in the same window as
+/// [`run_subprocess`] and captured in [`SubprocessOutput::stderr`]. Stdin is
+/// closed, unless input is given with [`RunOptions::stdin`].
+///
+/// Because the child doesn't see a terminal, tools may disable colors and
+/// progress output; set e.g. `CARGO_TERM_COLOR=always` on the command to keep
+/// them.
+pub async fn run_subprocess_piped<F>(
+    logger: &mut Logger,
+    cmd_builder: F,
+    stderr_lines: Option<usize>,
+) -> anyhow::Result<SubprocessOutput>
+where
+    F: IntoCommand,
+{
+    let options = RunOptions {
+        window_size: stderr_lines.map(WindowSize::Lines),
+        piped: true,
+        ..RunOptions::default()
+    };
+    run_subprocess_with_options(logger, cmd_builder, &options).await
+}
+
+/// What the stdout and stderr readers of a piped run send to the window.
+enum WindowInput {
+    /// Output to show in the window
+    Output(Vec<u8>),
+    /// Whole stdout lines to pass through, see
+    /// [`RunOptions::passthrough_stdout`]
+    Stdout(Vec<u8>),
+    /// No more output is coming
+    End,
+}
+
+/// Passes a stream on a whole line at a time.
+struct LineBuffer {
+    write: Box<LineCallback>,
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn new(write: impl FnMut(&[u8]) + Send + 'static) -> Self {
+        Self {
+            write: Box::new(write),
+            pending: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.pending.extend_from_slice(chunk);
+        if let Some(end) = self.pending.iter().rposition(|&byte| byte == b'\n') {
+            let rest = self.pending.split_off(end + 1);
+            (self.write)(&self.pending);
+            self.pending = rest;
+        }
+    }
+
+    /// Pass on a trailing line without line ending.
+    fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            (self.write)(&std::mem::take(&mut self.pending));
+        }
+    }
+}
+
+fn write_stdout(bytes: &[u8]) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(bytes);
+    let _ = stdout.flush();
+}
+
+/// How long to wait for a child's output to end after it exited. A grandchild
+/// can hold the output open indefinitely, and on Windows a blocking read may
+/// never return at all.
+fn reader_timeout() -> Duration {
+    if cfg!(windows) {
+        Duration::from_millis(500)
+    } else {
+        Duration::from_secs(10)
+    }
+}
+
+/// Where the output of one of the pipes of a piped run goes, besides into its
+/// capture.
+#[derive(Default)]
+struct PipeSinks {
+    /// Line hooks and renderers
+    splitters: Vec<LineSplitter>,
+    passthrough: Option<LineBuffer>,
+    transcript: Option<TranscriptStream>,
+    timeline: Option<InterleavedStream>,
+    window: Option<tokio::sync::mpsc::UnboundedSender<WindowInput>>,
+}
+
+impl PipeSinks {
+    fn new(
+        stream: Stream,
+        transcript: Option<&Transcript>,
+        timeline: Option<&Interleaved>,
+    ) -> Self {
+        Self {
+            transcript: transcript.map(|transcript| transcript.stream(Some(stream.tag()))),
+            timeline: timeline.map(|timeline| timeline.stream(stream)),
+            ..Self::default()
+        }
+    }
+
+    /// Add what only stdout goes to: the line hook, the passthrough and the
+    /// renderer.
+    fn stdout(
+        mut self,
+        options: &RunOptions,
+        is_term: bool,
+        tx: &tokio::sync::mpsc::UnboundedSender<WindowInput>,
+    ) -> Self {
+        self.splitters
+            .push(LineSplitter::new(options.on_line.clone()));
+        self.passthrough = options.passthrough_stdout.then(|| {
+            // Lines for a terminal go through the window, which makes room
+            // for them; anything else is written as it comes
+            let window = (is_term && std::io::IsTerminal::is_terminal(&std::io::stdout()))
+                .then(|| tx.clone());
+            LineBuffer::new(move |lines: &[u8]| match &window {
+                Some(window) => {
+                    let _ = window.send(WindowInput::Stdout(lines.to_vec()));
+                }
+                None => write_stdout(lines),
+            })
+        });
+        if let Some(render) = options.render_stdout {
+            let window = tx.clone();
+            self.splitters
+                .push(LineSplitter::new(Some(LineHook(Arc::new(Mutex::new(
+                    move |line: &[u8]| {
+                        if let Some(text) = render(line) {
+                            let _ = window.send(WindowInput::Output(text));
+                        }
+                    },
+                ))))));
+        }
+        self
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        for splitter in &mut self.splitters {
+            splitter.push(chunk);
+        }
+        if let Some(passthrough) = &mut self.passthrough {
+            passthrough.push(chunk);
+        }
+        if let Some(stream) = &mut self.transcript {
+            stream.push(chunk);
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.push(chunk);
+        }
+        if let Some(window) = &self.window {
+            let _ = window.send(WindowInput::Output(chunk.to_vec()));
+        }
+    }
+
+    fn finish(&mut self) {
+        for splitter in &mut self.splitters {
+            splitter.finish();
+        }
+        if let Some(passthrough) = &mut self.passthrough {
+            passthrough.finish();
+        }
+        if let Some(stream) = &mut self.transcript {
+            stream.finish();
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.finish();
+        }
+    }
+}
+
+/// Reads one of the pipes of a piped run on a blocking thread.
+struct PipeReader {
+    /// Shared so the output so far can be taken if the read never ends
+    captured: Arc<Mutex<Capture>>,
+    task: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+impl PipeReader {
+    fn spawn(
+        mut pipe: impl std::io::Read + Send + 'static,
+        limit: Option<CaptureLimit>,
+        activity: Activity,
+        mut sinks: PipeSinks,
+    ) -> Self {
+        let captured = Arc::new(Mutex::new(Capture::new(limit)));
+        let capture = captured.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let read = read_capturing(
+                &mut pipe,
+                &capture,
+                RedactStream::new(),
+                &activity,
+                |chunk| sinks.push(chunk),
+            );
+            sinks.finish();
+            read
+        });
+        Self { captured, task }
+    }
+
+    /// Wait until `deadline` for the end of the pipe, then take what was
+    /// captured.
+    async fn finish(
+        self,
+        deadline: tokio::time::Instant,
+        name: &str,
+    ) -> anyhow::Result<(Vec<u8>, Option<Truncation>)> {
+        match tokio::time::timeout_at(deadline, self.task).await {
+            Ok(result) => result
+                .with_context(|| format!("Failed to join {} task", name))?
+                .with_context(|| format!("Failed to read subprocess {}", name))?,
+            Err(_) => trace!(
+                "pty",
+                "{} reader still blocked, using the output so far", name
+            ),
+        }
+        let mut captured = self.captured.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(std::mem::take(&mut *captured).finish())
+    }
+}
+
+/// Run `cmd` with separate pipes, see [`run_subprocess_piped`].
+async fn run_piped(
+    logger: &Logger,
+    cmd: CommandBuilder,
+    options: &RunOptions,
+) -> anyhow::Result<SubprocessOutput> {
+    let window_size = options.window_size_or_default();
+    let capture_limit = options.capture_limit;
+    let is_term = !options.is_quiet() && crate::tty::supports_vt();
+    let ci = ci_renderer(&cmd, options, is_term);
+
+    let transcript = options.start_transcript(&cmd)?;
+    let mut command = std_command(&cmd)?;
+    let stdin = match options.stdin {
+        Some(_) => std::process::Stdio::piped(),
+        None => std::process::Stdio::null(),
+    };
+    command
+        .stdin(stdin)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    // Own process group, like a PTY session, so the whole tree can be
+    // addressed at once
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    let (child, unprioritized) = options
+        .priority
+        .spawn(|| command.spawn().map_err(anyhow::Error::new));
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            let err = err.context(format!("Failed to spawn {:?}", cmd.get_argv()[0]));
+            return Err(spawn_error(&cmd, options.not_found_hint.as_deref(), err));
+        }
+    };
+    let stdout = child.stdout.take().context("Failed to capture stdout")?;
+    let stderr = child.stderr.take().context("Failed to capture stderr")?;
+    if let (Some(source), Some(stdin)) = (options.stdin.clone(), child.stdin.take()) {
+        source.feed(Box::new(stdin), None);
+    }
+    trace!("pty", "spawned pid {} with pipes", child.id());
+    let child: Box<dyn portable_pty::Child + Send + Sync> = Box::new(child);
+    if let Some(err) = unprioritized {
+        options.print_above(|| logger.warning("Warning", &format!("{:#}", err)));
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<WindowInput>();
+    let (activity, mut notices) = Activity::new();
+    let interleaved = Interleaved::default();
+    let timeline = options.interleave.then_some(&interleaved);
+    let stdout_sinks =
+        PipeSinks::new(Stream::Stdout, transcript.as_ref(), timeline).stdout(options, is_term, &tx);
+    let stdout_task = PipeReader::spawn(stdout, capture_limit, activity.clone(), stdout_sinks);
+    let mut stderr_sinks = PipeSinks::new(Stream::Stderr, transcript.as_ref(), timeline);
+    stderr_sinks.window = Some(tx.clone());
+    let stderr_task = PipeReader::spawn(stderr, capture_limit, activity.clone(), stderr_sinks);
+
+    let mut stderr_splitter = LineSplitter::new(options.on_line.clone());
+    let slot = options.slot.clone().filter(|_| !options.is_quiet());
+    let render_task = tokio::spawn(async move {
+        let mut window = OutputWindow::new(window_size, is_term, slot, ci);
+        loop {
+            tokio::select! {
+                input = rx.recv() => match input {
+                    Some(WindowInput::Output(chunk)) => {
+                        stderr_splitter.push(&chunk);
+                        window.push(&chunk);
+                    }
+                    Some(WindowInput::Stdout(lines)) => window.print_above(&lines),
+                    Some(WindowInput::End) | None => break,
+                },
+                Some(notice) = notices.recv() => window.notice(&notice),
+            }
+        }
+        stderr_splitter.finish();
+        window.finish();
+        window.displayed()
+    });
+
+    let (status, resources, stopped) = wait_child(child, options, &activity).await?;
+    trace!("pty", "child exited: {:?}, stopped: {:?}", status, stopped);
+    // A grandchild may hold the pipes open long after the child exited
+    let deadline = tokio::time::Instant::now() + reader_timeout();
+    let (stdout, stdout_truncation) = stdout_task.finish(deadline, "stdout").await?;
+    let (stderr, stderr_truncation) = stderr_task.finish(deadline, "stderr").await?;
+    if let Some(transcript) = &transcript {
+        transcript.finish(&ExitStatus::from(&status).describe());
+    }
+    // Readers given up on still hold senders, so end the window explicitly
+    let _ = tx.send(WindowInput::End);
+    let displayed = render_task.await.context("Failed to join render task")?;
+    if is_term {
+        options.close_window(
+            &mut std::io::stderr(),
+            displayed,
+            ExitStatus::from(&status),
+            stopped.is_some(),
+            &stderr,
+        );
+    }
+
+    StopReason::into_result(
+        stopped,
+        SubprocessOutput {
+            stdout,
+            stderr,
+            exit_code: status.exit_code(),
+            status: ExitStatus::from(&status),
+            duration: Duration::ZERO,
+            resources,
+            stdout_truncation,
+            stderr_truncation,
+            ok_exit_codes: Vec::new(),
+            termination: None,
+            interleaved: interleaved.take(),
+        },
+    )
+}
+
+/// Start `cmd` in the background and return its process ID without waiting
+/// for it, e.g. for a preview server that should outlive the plugin.
+///
+/// The child gets no stdin and its output is discarded. It runs in its own
+/// process group (a new console process group on Windows), so a Ctrl-C in
+/// the plugin's terminal doesn't reach it.
+pub fn spawn_detached(cmd: CommandBuilder) -> anyhow::Result<u32> {
+    let mut command = std_command(&cmd)?;
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::{
+            CREATE_NEW_PROCESS_GROUP,
+            DETACHED_PROCESS,
+        };
+        std::os::windows::process::CommandExt::creation_flags(
+            &mut command,
+            DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP,
+        );
+    }
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            let err =
+                anyhow::Error::new(err).context(format!("Failed to spawn {:?}", cmd.get_argv()[0]));
+            return Err(spawn_error(&cmd, None, err));
+        }
+    };
+    Ok(child.id())
+}
+
+/// Translate a PTY command into a `std::process::Command`.
+pub(crate) fn std_command(cmd: &CommandBuilder) -> anyhow::Result<std::process::Command> {
+    let argv = cmd.get_argv();
+    let Some(program) = argv.first() else {
+        anyhow::bail!("Pipe mode needs an explicit program, not the default shell");
+    };
+    let mut command = std::process::Command::new(program);
+    command.args(&argv[1..]);
+    command.env_clear();
+    command.envs(cmd.iter_full_env_as_str());
+    if let Some(cwd) = cmd.get_cwd() {
+        command.current_dir(cwd);
+    }
+    Ok(command)
+}
+
+/// Live window showing the last lines of subprocess output below the cursor.
+///
+/// Incoming bytes are split into lines (keeping ANSI codes); after each batch
+/// of complete lines the window is redrawn in place, or handed to the job
+/// window of a parallel run. Nothing is drawn when stderr is not a terminal.
+struct OutputWindow {
+    size: WindowSize,
+    is_term: bool,
+    slot: Option<PanelSlot>,
+    /// Streams the lines to a log instead, when stderr isn't a terminal
+    ci: Option<CiRenderer>,
+    ring: std::collections::VecDeque<Vec<u8>>,
+    /// Bytes of the current, incomplete line
+    partial: Vec<u8>,
+    /// Number of lines currently on screen
+    displayed: usize,
+}
+
+impl OutputWindow {
+    fn new(
+        size: WindowSize,
+        is_term: bool,
+        slot: Option<PanelSlot>,
+        ci: Option<CiRenderer>,
+    ) -> Self {
+        Self {
+            size,
+            is_term,
+            slot,
+            ci,
+            ring: std::collections::VecDeque::new(),
+            partial: Vec::new(),
+            displayed: 0,
+        }
+    }
+
+    /// Add output bytes, redrawing if any line was completed.
+    fn push(&mut self, chunk: &[u8]) {
+        let mut completed = false;
+        for &byte in chunk {
+            self.partial.push(byte);
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.partial);
+                self.push_line(line);
+                completed = true;
+            }
+        }
+        if completed {
+            self.redraw();
+        }
+    }
+
+    /// Move a trailing partial line into the window and draw the final state.
+    fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.push_line(line);
+            self.redraw();
+        }
+    }
+
+    /// Show a message of the runner itself, not the child, as a yellow line.
+    fn notice(&mut self, text: &str) {
+        let line = format!("{}\n", console::style(text).yellow());
+        self.push_line(line.into_bytes());
+        self.redraw();
+    }
+
+    fn push_line(&mut self, line: Vec<u8>) {
+        let line = keep_colors_only(&line).into_owned();
+        if let Some(ci) = &self.ci {
+            ci.line(&line);
+        }
+        self.ring.push_back(line);
+        self.fit_ring();
+    }
+
+    /// Drop the oldest lines the window has no room for at the current
+    /// terminal size.
+    fn fit_ring(&mut self) {
+        let capacity = self.size.lines(crate::resize::current());
+        while self.ring.len() > capacity {
+            self.ring.pop_front();
+        }
+    }
+
+    fn redraw(&mut self) {
+        self.fit_ring();
+        if let Some(slot) = &self.slot {
+            slot.update(&self.ring);
+            return;
+        }
+        if !self.is_term || self.ring.is_empty() {
+            return;
+        }
+        let mut stderr_handle = std::io::stderr();
+        // Move cursor up to clear previous output (if any)
+        if self.displayed > 0 {
+            write!(stderr_handle, "\x1b[{}A", self.displayed).ok();
+            for _ in 0..self.displayed {
+                write!(stderr_handle, "\x1b[2K\x1b[1B").ok(); // Clear line, move down
+            }
+            // Move back up to start position
+            write!(stderr_handle, "\x1b[{}A", self.displayed).ok();
+        }
+
+        // Write all lines in the ring buffer (preserving ANSI codes), cut to
+        // the current width so wrapped lines don't break the line accounting
+        // after a resize
+        let started = std::time::Instant::now();
+        let width = usize::from(crate::resize::current().cols);
+        for line_bytes in &self.ring {
+            let _ = stderr_handle.write_all(&fit_to_width(line_bytes, width));
         }
-        // Move back up to where we started
-        write!(stderr_handle, "\x1b[{}A", final_lines_drawn).ok();
         let _ = stderr_handle.flush();
+        trace!(
+            "render",
+            "redrew {} lines over {} in {:?}",
+            self.ring.len(),
+            self.displayed,
+            started.elapsed()
+        );
+        self.displayed = self.ring.len();
+    }
+
+    /// Write `bytes` to stdout above the window, when both go to the
+    /// terminal.
+    fn print_above(&mut self, bytes: &[u8]) {
+        if self.is_term {
+            clear_window_lines(self.displayed);
+            self.displayed = 0;
+        }
+        write_stdout(bytes);
+        self.redraw();
+    }
+
+    /// Number of lines currently drawn.
+    fn displayed(&self) -> usize {
+        self.displayed
+    }
+
+    /// The lines in the window, oldest first.
+    fn into_lines(self) -> Vec<Vec<u8>> {
+        self.ring.into()
+    }
+}
+
+/// The log renderer for `cmd` when stderr isn't a terminal, the output
+/// doesn't go to a parallel job window and the run isn't quiet.
+fn ci_renderer(cmd: &CommandBuilder, options: &RunOptions, is_term: bool) -> Option<CiRenderer> {
+    (!is_term && options.slot.is_none() && !options.is_quiet())
+        .then(|| CiRenderer::new(&command_line(cmd)))
+}
+
+/// Clear `lines` lines drawn above the cursor and move back up to where they
+/// started.
+pub(crate) fn clear_window_lines(lines: usize) {
+    clear_lines_on(&mut std::io::stderr(), lines);
+}
+
+/// [`clear_window_lines`] on `term`.
+fn clear_lines_on(term: &mut impl Write, lines: usize) {
+    if lines == 0 {
+        return;
+    }
+    write!(term, "\x1b[{}A", lines).ok();
+    for _ in 0..lines {
+        write!(term, "\x1b[2K\x1b[1B").ok(); // Clear line, move down
+    }
+    // Move back up to where we started
+    write!(term, "\x1b[{}A", lines).ok();
+    let _ = term.flush();
+}
+
+/// Print the last `lines` lines of `output` on `term`, as the window would
+/// show them.
+fn print_last_lines(term: &mut impl Write, output: &[u8], lines: usize) {
+    let width = usize::from(crate::resize::current().cols);
+    for line in last_lines(output, lines) {
+        let line = keep_colors_only(line);
+        let _ = term.write_all(&fit_to_width(&line, width));
+        let _ = term.write_all(b"\x1b[0m\n");
+    }
+    let _ = term.flush();
+}
+
+/// The last `lines` lines of `output`, without line endings, each as a
+/// terminal shows it when it was rewritten with carriage returns.
+fn last_lines(output: &[u8], lines: usize) -> Vec<&[u8]> {
+    let text = output.strip_suffix(b"\n").unwrap_or(output);
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let all: Vec<&[u8]> = text.split(|&byte| byte == b'\n').collect();
+    all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            match line.iter().rposition(|&byte| byte == b'\r') {
+                Some(index) => &line[index + 1..],
+                None => line,
+            }
+        })
+        .collect()
+}
+
+/// Drop escape sequences other than colors (SGR) from an output line.
+///
+/// Cursor movement and screen clearing, e.g. the sequences ConPTY emits on
+/// Windows, would break the window's line accounting.
+fn keep_colors_only(line: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+    if !line.contains(&0x1b) {
+        return line.into();
+    }
+    let mut kept = Vec::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.iter().position(|&byte| byte == 0x1b) {
+        kept.extend_from_slice(&rest[..start]);
+        let (len, is_color) = escape_sequence_len(&rest[start..]);
+        if is_color {
+            kept.extend_from_slice(&rest[start..start + len]);
+        }
+        rest = &rest[start + len..];
+    }
+    kept.extend_from_slice(rest);
+    kept.into()
+}
+
+/// Length of the escape sequence at the start of `bytes`, and whether it
+/// sets colors. Unterminated sequences extend to the end.
+fn escape_sequence_len(bytes: &[u8]) -> (usize, bool) {
+    match bytes.get(1) {
+        // CSI: parameters up to a final byte in `@`..=`~`
+        Some(b'[') => match bytes[2..]
+            .iter()
+            .position(|byte| (0x40..=0x7e).contains(byte))
+        {
+            Some(end) => (end + 3, bytes[end + 2] == b'm'),
+            None => (bytes.len(), false),
+        },
+        // OSC (window title, hyperlinks): up to BEL or ST
+        Some(b']') => {
+            let end = (2..bytes.len()).find_map(|index| match bytes[index] {
+                0x07 => Some(index + 1),
+                0x1b if bytes.get(index + 1) == Some(&b'\\') => Some(index + 2),
+                _ => None,
+            });
+            (end.unwrap_or(bytes.len()), false)
+        }
+        Some(_) => (2, false),
+        None => (1, false),
+    }
+}
+
+/// Cut a rendered output line to `width` columns, keeping ANSI codes and the
+/// line ending. Lines that aren't valid UTF-8 are returned unchanged.
+pub(crate) fn fit_to_width(line: &[u8], width: usize) -> std::borrow::Cow<'_, [u8]> {
+    let Ok(text) = std::str::from_utf8(line) else {
+        return line.into();
+    };
+    let content = text.trim_end_matches(['\r', '\n']);
+    if console::measure_text_width(content) <= width {
+        return line.into();
+    }
+    let mut fitted = console::truncate_str(content, width, "").into_owned();
+    fitted.push_str(&text[content.len()..]);
+    fitted.into_bytes().into()
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        // Clear the progress bar
+        if let Some(pb) = self.progress_bar.take() {
+            pb.finish_and_clear();
+        }
+
+        // Clear the reserved lines (including our status + subprocess output)
+        if self.line_count > 0 {
+            use console::Term;
+            let term = Term::stderr();
+            if term.is_term() {
+                let _ = term.clear_last_lines(self.line_count);
+            }
+            self.line_count = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(windows))]
+    use portable_pty::CommandBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_logger_new() {
+        let logger = Logger::new();
+        assert!(logger.progress_bar.is_none());
+        assert_eq!(logger.line_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_logger_status() {
+        let mut logger = Logger::new();
+        logger.status("Building", "test-crate");
+        assert!(logger.progress_bar.is_some());
+        assert_eq!(logger.line_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_logger_status_coalesces_updates() {
+        let mut logger = Logger::new();
+        for index in 0..1000 {
+            logger.status("Processing", &format!("file-{}", index));
+        }
+        assert!(logger.status_active);
+        assert_eq!(logger.line_count, 1);
+        let message = logger.progress_bar.as_ref().unwrap().message();
+        assert!(message.ends_with("file-999"));
+    }
+
+    #[tokio::test]
+    async fn test_logger_step_counter() {
+        let mut logger = Logger::new();
+        logger.step(3, 7, "Building", "foo");
+        let message = logger.progress_bar.as_ref().unwrap().message();
+        assert!(console::strip_ansi_codes(&message).starts_with("[3/7] "));
+        assert!(message.ends_with("foo"));
+
+        // The counter is kept for status updates within the phase
+        logger.status("Linking", "foo");
+        let message = logger.progress_bar.as_ref().unwrap().message();
+        assert!(console::strip_ansi_codes(&message).starts_with("[3/7] "));
+
+        // ...and dropped once the phase ends
+        logger.clear_status();
+        logger.status("Checking", "bar");
+        let message = logger.progress_bar.as_ref().unwrap().message();
+        assert!(!message.contains("[3/7]"));
+        logger.finish();
+    }
+
+    #[tokio::test]
+    async fn test_logger_status_board() {
+        let mut logger = Logger::new();
+        let mut table = Table::new(["Crate", "Status"]);
+        table.add_row(["foo", "building"]);
+        logger.status_board(&table);
+        assert!(logger.board.is_some());
+
+        // Updates replace the board contents in place
+        table.add_row(["bar", "queued"]);
+        logger.status_board(&table);
+        assert_eq!(logger.board.as_ref().unwrap().lock().unwrap().len(), 2);
+
+        // A status line replaces the board
+        logger.status("Finishing", "foo");
+        assert!(logger.board.is_none());
+        logger.finish();
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_logger_status_board_keeps_lines_above() {
+        use std::io::{
+            Read,
+            Seek,
+        };
+
+        let mut logger = Logger::new();
+        logger.status("Building", "foo");
+        let mut table = Table::new(["Crate", "Status"]);
+        table.add_row(["foo", "building"]);
+        logger.status_board(&table);
+        // The board's bar clears itself; no lines above it are erased
+        let mut file = tempfile::tempfile().unwrap();
+        let term =
+            console::Term::read_write_pair(file.try_clone().unwrap(), file.try_clone().unwrap());
+        logger.clear_for_window(&term);
+        logger.status_board(&table);
+        logger.finish();
+        logger.clear_for_window(&term);
+        term.flush().unwrap();
+        let mut written = String::new();
+        file.rewind().unwrap();
+        file.read_to_string(&mut written).unwrap();
+        assert_eq!(written, "");
+        assert_eq!(logger.line_count, 0);
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_piped_separates_streams() {
+        let mut logger = Logger::new();
+        let output = run_subprocess_piped(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", "echo out; echo err >&2; echo $PIPE_TEST; exit 3"]);
+                cmd.env("PIPE_TEST", "from-env");
+                cmd
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.stdout_str().unwrap(), "out\nfrom-env\n");
+        assert_eq!(output.stderr_str().unwrap(), "err\n");
+        assert_eq!(output.exit_code(), 3);
+        assert!(!output.success());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_piped_grandchild_holds_pipes() {
+        let mut logger = Logger::new();
+        let started = std::time::Instant::now();
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", "sleep 30 & echo $!; echo done >&2"]);
+                cmd
+            },
+            &RunOptions::new().piped(true),
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(20));
+        let grandchild: i32 = output.stdout_str().unwrap().trim().parse().unwrap();
+        unsafe { libc::kill(grandchild, libc::SIGKILL) };
+        assert_eq!(output.stderr_str().unwrap(), "done\n");
+        assert!(output.success());
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_piped_nonexistent_command() {
+        let mut logger = Logger::new();
+        let result = run_subprocess_piped(
+            &mut logger,
+            || CommandBuilder::new("nonexistent-command-for-pipe-test"),
+            None,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_with_options_env_and_cwd() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut logger = Logger::new();
+        let options = RunOptions::new()
+            .cwd(dir.path())
+            .env("RUN_OPTIONS_SET", "set")
+            .env("RUN_OPTIONS_REMOVED", "still here")
+            .env_remove("RUN_OPTIONS_REMOVED")
+            .piped(true);
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.arg("-c");
+                cmd.arg("echo \"$RUN_OPTIONS_SET:${RUN_OPTIONS_REMOVED-unset}:$(pwd)\"");
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let dir = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            output.stdout_str().unwrap().trim(),
+            format!("set:unset:{}", dir.display())
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_with_options_env_clear() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new()
+            .env("KEPT", "yes")
+            .env_clear()
+            .env("PATH", "/usr/bin:/bin")
+            .piped(true);
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("/bin/sh");
+                cmd.arg("-c");
+                cmd.arg("echo \"${HOME-unset}:${KEPT-unset}\"");
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.stdout_str().unwrap().trim(), "unset:unset");
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_with_options_capture_limit() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new().capture_limit(6).piped(true);
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.arg("-c");
+                cmd.arg("seq 1 5000; echo tail");
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.stdout_str().unwrap(), "\ntail\n");
+        let truncation = output.stdout_truncation.unwrap();
+        assert_eq!(truncation.dropped_bytes, truncation.total_bytes - 6);
+        assert!(output.stderr_truncation.is_none());
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_capture_head_tail_in_pty() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new().capture(CaptureLimit::HeadTail { head: 3, tail: 6 });
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.arg("-c");
+                cmd.arg("seq 1 5000; echo tail");
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap();
+
+        // The PTY turns newlines into CRLF
+        assert_eq!(output.stderr_str().unwrap(), "1\r\ntail\r\n");
+        assert!(output.truncated());
+    }
+
+    #[test]
+    fn test_command_line_quoting() {
+        let mut cmd = CommandBuilder::new("cargo");
+        cmd.args(["build", "--features", "a b", "--config", "x=1"]);
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                command_line(&cmd),
+                "cargo build --features 'a b' --config x=1"
+            );
+            assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        }
+        #[cfg(windows)]
+        assert_eq!(
+            command_line(&cmd),
+            "cargo build --features \"a b\" --config x=1"
+        );
+        assert_eq!(shell_quote("--locked"), "--locked");
+        assert_ne!(shell_quote(""), "");
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_returns_promptly() {
+        // The reader must see EOF when the child exits instead of waiting for
+        // the read timeout
+        let mut logger = Logger::new();
+        let started = std::time::Instant::now();
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("echo");
+                cmd.arg("done");
+                cmd
+            },
+            &RunOptions::new().echo_command(true),
+        )
+        .await
+        .unwrap();
+
+        assert!(output.stderr_str().unwrap().contains("done"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_timeout() {
+        for piped in [false, true] {
+            let mut logger = Logger::new();
+            let started = std::time::Instant::now();
+            let options = RunOptions::new()
+                .timeout(Duration::from_millis(500))
+                .piped(piped);
+            let err = run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.arg("-c");
+                    cmd.arg("echo started >&2; sleep 30");
+                    cmd
+                },
+                &options,
+            )
+            .await
+            .unwrap_err();
+
+            assert!(started.elapsed() < Duration::from_secs(10));
+            let err = err.downcast_ref::<SubprocessError>().unwrap();
+            assert!(matches!(err, SubprocessError::TimedOut { .. }));
+            assert_eq!(err.to_string(), "Subprocess timed out after 500ms");
+            assert!(err.output().stderr_str().unwrap().contains("started"));
+            assert_eq!(err.output().termination, Some(Termination::Graceful));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_kill_grace() {
+        let mut logger = Logger::new();
+        let started = std::time::Instant::now();
+        let options = RunOptions::new()
+            .timeout(Duration::from_millis(200))
+            .kill_grace(Duration::from_millis(300))
+            .piped(true);
+        let err = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", "trap '' TERM; sleep 30"]);
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let err = err.downcast_ref::<SubprocessError>().unwrap();
+        assert_eq!(err.output().termination, Some(Termination::Killed));
+        assert_eq!(err.output().status().signal(), Some(9));
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_run_subprocess_graceful_kills_leftovers() {
+        // The child exits on SIGTERM, its background job ignores it
+        let dir = tempfile::TempDir::new().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "(trap '' TERM; exec sleep 30) & echo $! > {}; sleep 30",
+            pid_file.display()
+        );
+        let mut logger = Logger::new();
+        let started = std::time::Instant::now();
+        let options = RunOptions::new()
+            .timeout(Duration::from_millis(500))
+            .piped(true);
+        let err = run_subprocess_with_options(
+            &mut logger,
+            move || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", &script]);
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap_err();
+        // A surviving job would hold the output pipes open
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let err = err.downcast_ref::<SubprocessError>().unwrap();
+        assert_eq!(err.output().termination, Some(Termination::Graceful));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let alive = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                .is_ok_and(|stat| !stat.contains(") Z "))
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while alive() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!alive(), "{} survived", pid.trim());
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_run_subprocess_blocking() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new().piped(true);
+        let output = run_subprocess_blocking(
+            &mut logger,
+            crate::CommandSpec::new("echo").arg("sync"),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(output.stdout, b"sync\n");
+        let pty = run_subprocess_blocking(
+            &mut logger,
+            crate::CommandSpec::new("echo").arg("pty"),
+            &RunOptions::new(),
+        )
+        .unwrap();
+        assert!(pty.stderr_str().unwrap().contains("pty"));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let nested = runtime.block_on(async {
+            run_subprocess_blocking(&mut logger, crate::CommandSpec::new("true"), &options)
+        });
+        assert!(nested.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_shell() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new().piped(true);
+        let output = run_shell(&mut logger, "echo 'one two' | tr a-z A-Z", &options)
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"ONE TWO\n");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_kill_tree() {
+        use std::os::unix::process::ExitStatusExt;
+
+        // A grandchild in a session of its own, out of the child's group
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "setsid sleep 60 & sleep 60"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        let found = descendants(child.id());
+        assert_eq!(found.len(), 2, "{:?}", found);
+        kill_tree(child.id()).unwrap();
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+        std::thread::sleep(Duration::from_millis(100));
+        for pid in found {
+            let alive = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .is_ok_and(|stat| !stat.contains(") Z "));
+            assert!(!alive, "{} survived", pid);
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_interactive() {
+        let mut logger = Logger::new();
+        let output = run_subprocess_interactive(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", "echo hello; exit 3"]);
+                cmd
+            },
+            &RunOptions::new().ok_exit_codes(&[3]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.exit_code(), 3);
+        assert!(output.success());
+        assert!(output.stderr_str().unwrap().contains("hello"));
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_quiet() {
+        assert!(RunOptions::new().quiet(true).is_quiet());
+        assert!(!RunOptions::new().quiet(false).is_quiet());
+        for piped in [false, true] {
+            let mut logger = Logger::new();
+            let options = RunOptions::new()
+                .quiet(true)
+                .echo_command(true)
+                .piped(piped);
+            let output = run_shell(&mut logger, "echo out; echo err >&2", &options)
+                .await
+                .unwrap();
+            assert!(output.success());
+            let captured = [output.stdout_str().unwrap(), output.stderr_str().unwrap()].concat();
+            assert!(captured.contains("out") && captured.contains("err"));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_transcript() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for piped in [false, true] {
+            let path = dir.path().join(format!("piped-{}.log", piped));
+            let mut logger = Logger::new();
+            let options = RunOptions::new()
+                .window_height(1)
+                .transcript(&path)
+                .piped(piped);
+            let output = run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.arg("-c");
+                    cmd.arg("echo one; echo two >&2; echo three; exit 3");
+                    cmd
+                },
+                &options,
+            )
+            .await
+            .unwrap();
+            assert_eq!(output.exit_code, 3);
+
+            // Everything is in the file, although the window showed one line
+            let text = std::fs::read_to_string(&path).unwrap();
+            assert!(text.starts_with("$ sh -c 'echo one; echo two >&2; echo three; exit 3'\n"));
+            for line in ["one", "two", "three"] {
+                assert!(text.contains(&format!("] {}", line)), "{}", text);
+            }
+            if piped {
+                assert!(text.contains(" err] two\n"));
+            }
+            assert!(text.contains("\n# exit code 3 after "));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_stall_timeout() {
+        for piped in [false, true] {
+            let mut logger = Logger::new();
+            let options = RunOptions::new()
+                .stall_warning(Duration::from_secs(1))
+                .stall_timeout(Duration::from_secs(1))
+                .piped(piped);
+            // Regular output keeps the child alive past the limit
+            let chatty = run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.arg("-c");
+                    cmd.arg("for _ in 1 2 3 4 5; do echo tick >&2; sleep 0.3; done");
+                    cmd
+                },
+                &options,
+            )
+            .await
+            .unwrap();
+            assert!(chatty.success());
+
+            let err = run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.arg("-c");
+                    cmd.arg("echo started >&2; sleep 30");
+                    cmd
+                },
+                &options,
+            )
+            .await
+            .unwrap_err();
+            let err = err.downcast_ref::<SubprocessError>().unwrap();
+            assert!(matches!(err, SubprocessError::Stalled { .. }));
+            assert_eq!(err.to_string(), "Subprocess stalled: no output for 1.0s");
+            assert!(err.output().stderr_str().unwrap().contains("started"));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_sub_second_stall_timeout() {
+        for piped in [false, true] {
+            let mut logger = Logger::new();
+            let options = RunOptions::new()
+                .stall_warning(Duration::from_millis(200))
+                .stall_timeout(Duration::from_millis(500))
+                .piped(piped);
+            let started = std::time::Instant::now();
+            let err = run_shell(&mut logger, "echo started >&2; sleep 30", &options)
+                .await
+                .unwrap_err();
+            let err = err.downcast_ref::<SubprocessError>().unwrap();
+            assert!(matches!(err, SubprocessError::Stalled { .. }));
+            assert_eq!(err.to_string(), "Subprocess stalled: no output for 500ms");
+            assert!(started.elapsed() < Duration::from_secs(10));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_cancel() {
+        for piped in [false, true] {
+            let token = CancellationToken::new();
+            let cancel = token.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                cancel.cancel();
+            });
+
+            let mut logger = Logger::new();
+            let options = RunOptions::new().cancel_on(token).piped(piped);
+            let err = run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.arg("-c");
+                    cmd.arg("echo started >&2; sleep 30");
+                    cmd
+                },
+                &options,
+            )
+            .await
+            .unwrap_err();
+
+            let err = err.downcast_ref::<SubprocessError>().unwrap();
+            assert!(matches!(err, SubprocessError::Cancelled { .. }));
+            assert!(err.output().stderr_str().unwrap().contains("started"));
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_on_line() {
+        for piped in [false, true] {
+            let lines = Arc::new(Mutex::new(Vec::new()));
+            let seen = lines.clone();
+            let options = RunOptions::new()
+                .on_line(move |line| {
+                    seen.lock()
+                        .unwrap()
+                        .push(String::from_utf8_lossy(line).into_owned())
+                })
+                .piped(piped);
+            let mut logger = Logger::new();
+            run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.arg("-c");
+                    cmd.arg("echo one; echo two >&2; printf three");
+                    cmd
+                },
+                &options,
+            )
+            .await
+            .unwrap();
+
+            let mut lines = lines.lock().unwrap().clone();
+            // Pipe mode reads stdout and stderr concurrently
+            lines.sort();
+            assert_eq!(lines, ["one", "three", "two"], "piped: {}", piped);
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_stdin() {
+        let cat = || {
+            let mut cmd = CommandBuilder::new("sh");
+            cmd.arg("-c");
+            cmd.arg("cat; echo '<eof>'");
+            cmd
+        };
+        let mut logger = Logger::new();
+        let cases = [false, true]
+            .into_iter()
+            .flat_map(|piped| ["one\ntwo\n", "one\ntwo"].map(|data| (piped, data)));
+        for (piped, data) in cases {
+            let options = RunOptions::new()
+                .stdin(data)
+                .piped(piped)
+                .timeout(Duration::from_secs(10));
+            let output = run_subprocess_with_options(&mut logger, cat, &options)
+                .await
+                .unwrap();
+            let text = if piped {
+                output.stdout_str().unwrap()
+            } else {
+                output.stderr_str().unwrap().replace("\r\n", "\n")
+            };
+            // Not echoed, and the child sees the end of the input
+            let expected = if data.ends_with('\n') {
+                "one\ntwo\n<eof>\n"
+            } else {
+                "one\ntwo<eof>\n"
+            };
+            assert_eq!(text, expected, "piped: {}, input: {:?}", piped, data);
+        }
+
+        let reader = std::io::Cursor::new(b"from a reader\n".to_vec());
+        let options = RunOptions::new().stdin_reader(reader).piped(true);
+        let output = run_subprocess_with_options(&mut logger, cat, &options)
+            .await
+            .unwrap();
+        assert_eq!(output.stdout_str().unwrap(), "from a reader\n<eof>\n");
+    }
+
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_run_subprocess_within_timeout() {
+        let mut logger = Logger::new();
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || CommandBuilder::new("true"),
+            &RunOptions::new().timeout(Duration::from_secs(30)),
+        )
+        .await
+        .unwrap();
+        assert!(output.success());
+    }
+
+    #[tokio::test]
+    async fn test_output_window_ring() {
+        let mut window = OutputWindow::new(WindowSize::Lines(2), false, None, None);
+        window.push(b"one\ntwo\nthr");
+        window.push(b"ee\nfour");
+        assert_eq!(window.displayed(), 0);
+        window.finish();
+        assert_eq!(
+            window.into_lines(),
+            vec![b"three\n".to_vec(), b"four".to_vec()]
+        );
     }
 
-    Ok(SubprocessOutput {
-        stdout: stdout_bytes,
-        stderr: stderr_bytes,
-        exit_code,
-    })
-}
-
-impl Default for Logger {
-    fn default() -> Self {
-        Self::new()
+    #[tokio::test]
+    async fn test_fit_to_width() {
+        assert_eq!(&*fit_to_width(b"short\r\n", 80), b"short\r\n");
+        assert_eq!(&*fit_to_width(b"0123456789\r\n", 4), b"0123\r\n");
+        let colored = format!(
+            "{}\n",
+            console::style("0123456789").red().force_styling(true)
+        );
+        let fitted = fit_to_width(colored.as_bytes(), 4);
+        let fitted = String::from_utf8(fitted.into_owned()).unwrap();
+        assert_eq!(console::strip_ansi_codes(&fitted), "0123\n");
+        assert_eq!(&*fit_to_width(b"\xff\xfe long\n", 2), b"\xff\xfe long\n");
     }
-}
 
-impl Drop for Logger {
-    fn drop(&mut self) {
-        // Clear the progress bar
-        if let Some(pb) = self.progress_bar.take() {
-            pb.finish_and_clear();
-        }
+    #[test]
+    fn test_line_buffer() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut buffer = LineBuffer::new({
+            let written = written.clone();
+            move |bytes: &[u8]| written.lock().unwrap().push(bytes.to_vec())
+        });
+        buffer.push(b"one\r\ntw");
+        buffer.push(b"o\nthree\nfo");
+        buffer.push(b"ur");
+        buffer.finish();
+        assert_eq!(
+            *written.lock().unwrap(),
+            [&b"one\r\n"[..], b"two\nthree\n", b"four"]
+        );
+    }
 
-        // Clear the reserved lines (including our status + subprocess output)
-        if self.line_count > 0 {
-            use console::Term;
-            let term = Term::stderr();
-            if term.is_term() {
-                let _ = term.clear_last_lines(self.line_count);
-            }
-            self.line_count = 0;
-        }
+    #[test]
+    fn test_last_lines() {
+        let output = b"one\r\ntwo\r\n 50%\r100%\r\n";
+        assert_eq!(last_lines(output, 2), [&b"two"[..], &b"100%"[..]]);
+        assert_eq!(last_lines(output, 9).len(), 3);
+        assert!(last_lines(output, 0).is_empty());
+        assert!(last_lines(b"", 3).is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[cfg(not(windows))]
-    use portable_pty::CommandBuilder;
+    #[test]
+    fn test_close_window() {
+        let shown = b"one\ntwo\nthree\n";
+        let close = |options: RunOptions, status: u32, stopped: bool| {
+            let mut term = Vec::new();
+            options.close_window(&mut term, 3, ExitStatus::Code(status), stopped, shown);
+            String::from_utf8(term).unwrap()
+        };
+        let cleared = "\x1b[3A\x1b[2K\x1b[1B\x1b[2K\x1b[1B\x1b[2K\x1b[1B\x1b[3A";
+        assert_eq!(close(RunOptions::new(), 0, false), cleared);
 
-    use super::*;
+        // The last lines stay after a successful run only
+        let keep_lines = || RunOptions::new().keep_last_lines(2).ok_exit_codes(&[2]);
+        assert_eq!(
+            close(keep_lines(), 0, false),
+            format!("{}two\x1b[0m\nthree\x1b[0m\n", cleared)
+        );
+        assert!(close(keep_lines(), 2, false).ends_with("three\x1b[0m\n"));
+        assert_eq!(close(keep_lines(), 1, false), cleared);
+        assert_eq!(close(keep_lines(), 0, true), cleared);
 
-    #[tokio::test]
-    async fn test_logger_new() {
-        let logger = Logger::new();
-        assert!(logger.progress_bar.is_none());
-        assert_eq!(logger.line_count, 0);
+        let keep_window = || RunOptions::new().keep_output_on_success(true);
+        assert_eq!(close(keep_window(), 0, false), "");
+        assert_eq!(close(keep_window(), 1, false), cleared);
+    }
+
+    #[test]
+    fn test_keep_colors_only() {
+        assert_eq!(&*keep_colors_only(b"plain\r\n"), b"plain\r\n");
+        // ConPTY-style preamble: hide cursor, clear screen, home, title
+        let line = b"\x1b[?25l\x1b[2J\x1b[H\x1b]0;cmd.exe\x07\x1b[31mred\x1b[0m\x1b[K\r\n";
+        assert_eq!(&*keep_colors_only(line), b"\x1b[31mred\x1b[0m\r\n");
+        assert_eq!(&*keep_colors_only(b"a\x1b]8;;url\x1b\\b\x1b["), b"ab");
     }
 
     #[tokio::test]
-    async fn test_logger_status() {
+    async fn test_logger_progress_replaces_status() {
         let mut logger = Logger::new();
         logger.status("Building", "test-crate");
-        assert!(logger.progress_bar.is_some());
-        assert_eq!(logger.line_count, 1);
+        logger.progress("Downloading...");
+        assert!(!logger.status_active);
+        logger.status("Building", "test-crate");
+        assert!(logger.status_active);
     }
 
     #[tokio::test]
@@ -651,12 +4694,46 @@ mod tests {
         assert_eq!(logger.line_count, 0);
     }
 
+    #[tokio::test]
+    async fn test_logger_scope_clears_on_drop() {
+        let mut logger = Logger::new();
+        {
+            let mut scope = logger.scope("Publishing", "test-crate");
+            assert!(scope.progress_bar.is_some());
+            scope.info("Info", "inside scope");
+            scope.status("Uploading", "test-crate");
+        }
+        assert!(logger.progress_bar.is_none());
+        assert_eq!(logger.line_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_logger_scope_finish_with() {
+        let mut logger = Logger::new();
+        logger
+            .scope("Publishing", "test-crate")
+            .finish_with("Published", "test-crate");
+        assert!(logger.progress_bar.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_logger_scope_on_panic() {
+        let mut logger = Logger::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _scope = logger.scope("Publishing", "test-crate");
+            panic!("boom");
+        }));
+        assert!(result.is_err());
+        assert!(logger.progress_bar.is_none());
+    }
+
     #[tokio::test]
     async fn test_subprocess_output_success() {
         let output = SubprocessOutput {
             stdout: b"stdout content".to_vec(),
             stderr: b"stderr content".to_vec(),
             exit_code: 0,
+            ..Default::default()
         };
         assert!(output.success());
         assert_eq!(output.exit_code(), 0);
@@ -664,18 +4741,157 @@ mod tests {
         assert_eq!(output.stderr_str().unwrap(), "stderr content");
     }
 
+    #[tokio::test]
+    #[cfg(not(windows))]
+    async fn test_ok_exit_codes() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new().piped(true).ok_exit_codes(&[1]);
+        let no_match = run_shell(&mut logger, "echo hay | grep needle", &options)
+            .await
+            .unwrap();
+        assert_eq!(no_match.exit_code, 1);
+        assert!(no_match.success());
+        let broken = run_shell(&mut logger, "exit 2", &options).await.unwrap();
+        assert!(!broken.success());
+    }
+
     #[tokio::test]
     async fn test_subprocess_output_failure() {
         let output = SubprocessOutput {
             stdout: b"".to_vec(),
             stderr: b"error message".to_vec(),
             exit_code: 1,
+            ..Default::default()
         };
         assert!(!output.success());
         assert_eq!(output.exit_code(), 1);
         assert_eq!(output.stderr_str().unwrap(), "error message");
     }
 
+    #[test]
+    fn test_exit_status_describe() {
+        assert_eq!(ExitStatus::Code(101).describe(), "exit code 101");
+        assert_eq!(ExitStatus::Code(101).code(), Some(101));
+        assert!(ExitStatus::default().success());
+        let pty_status = portable_pty::ExitStatus::with_exit_code(3);
+        assert_eq!(ExitStatus::from(&pty_status), ExitStatus::Code(3));
+        #[cfg(unix)]
+        {
+            let killed = ExitStatus::Signal(libc::SIGSEGV);
+            assert!(!killed.success());
+            assert_eq!(killed.code(), None);
+            assert_eq!(killed.signal_name(), Some("SIGSEGV"));
+            assert_eq!(
+                killed.describe(),
+                format!("killed by SIGSEGV (signal {})", libc::SIGSEGV)
+            );
+            let pty_status = portable_pty::ExitStatus::with_signal("Killed");
+            assert_eq!(
+                ExitStatus::from(&pty_status),
+                ExitStatus::Signal(libc::SIGKILL)
+            );
+        }
+        assert_eq!(ExitStatus::Signal(77).describe(), "killed by signal 77");
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_reports_signal() {
+        let mut logger = Logger::new();
+        for piped in [false, true] {
+            let output = run_subprocess_with_options(
+                &mut logger,
+                || {
+                    let mut cmd = CommandBuilder::new("sh");
+                    cmd.args(["-c", "kill -KILL $$"]);
+                    cmd
+                },
+                &RunOptions::new().piped(piped),
+            )
+            .await
+            .unwrap();
+            assert!(!output.success());
+            assert_eq!(output.status(), ExitStatus::Signal(libc::SIGKILL));
+            assert_eq!(output.status().describe(), "killed by SIGKILL (signal 9)");
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_interrupted_by_ctrl_c() {
+        // Deliver SIGINT to the test process once the child is running. The
+        // run starts listening before it first yields, and this task can only
+        // run after that on the single-threaded test runtime
+        let (ready, started_line) = tokio::sync::oneshot::channel();
+        let mut ready = Some(ready);
+        tokio::spawn(async {
+            if started_line.await.is_ok() {
+                // SAFETY: sending a signal has no memory safety preconditions
+                unsafe { libc::kill(libc::getpid(), libc::SIGINT) };
+            }
+        });
+        let mut logger = Logger::new();
+        let started = std::time::Instant::now();
+        let err = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", "echo started; sleep 30"]);
+                cmd
+            },
+            &RunOptions::new()
+                .piped(true)
+                .interrupt_on_ctrl_c(true)
+                .on_line(move |line| {
+                    if line == b"started"
+                        && let Some(ready) = ready.take()
+                    {
+                        let _ = ready.send(());
+                    }
+                }),
+        )
+        .await
+        .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let err = err.downcast::<SubprocessError>().unwrap();
+        assert!(matches!(err, SubprocessError::Interrupted { .. }));
+        assert_eq!(err.output().stdout_str().unwrap(), "started\n");
+        assert_eq!(err.output().status().signal(), Some(libc::SIGINT));
+    }
+
+    #[test]
+    fn test_window_size() {
+        let rows = |rows| crate::resize::TermSize { rows, cols: 80 };
+        assert_eq!(WindowSize::Lines(7).lines(rows(4)), 7);
+        assert_eq!(WindowSize::Fraction(0.25).lines(rows(48)), 12);
+        assert_eq!(WindowSize::Fraction(0.25).lines(rows(8)), 3);
+        assert_eq!(WindowSize::Fraction(1.0).lines(rows(200)), 40);
+        assert_eq!(WindowSize::Auto.lines(rows(24)), 6);
+        assert_eq!(WindowSize::Auto.lines(rows(12)), 5);
+        assert_eq!(WindowSize::Auto.lines(rows(100)), 20);
+        // Tiny terminals keep room for the status line and prompt
+        assert_eq!(WindowSize::Auto.lines(rows(4)), 2);
+        assert_eq!(WindowSize::Auto.lines(rows(1)), 1);
+    }
+
+    #[test]
+    fn test_pty_size_follows_window_and_terminal() {
+        let pty = open_pty(&RunOptions::new().window_height(7)).unwrap();
+        let size = pty.master.get_size().unwrap();
+        assert_eq!(size.rows, 7);
+        assert_eq!(size.cols, crate::resize::current().cols);
+        let terminal = crate::resize::TermSize {
+            rows: 48,
+            cols: 120,
+        };
+        assert_eq!(
+            pty_size(&RunOptions::new().window_height(0), terminal).rows,
+            1
+        );
+        let fraction = RunOptions::new().window_size(WindowSize::Fraction(0.25));
+        assert_eq!(pty_size(&fraction, terminal).rows, 12);
+    }
+
     #[tokio::test]
     #[cfg(not(windows))]
     async fn test_run_subprocess_simple_success() {
@@ -699,6 +4915,123 @@ mod tests {
         assert!(stderr.contains("hello world") || stderr.is_empty());
     }
 
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_resources() {
+        let mut logger = Logger::new();
+        let output = run_subprocess(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args(["-c", "i=0; while [ $i -lt 200000 ]; do i=$((i + 1)); done"]);
+                cmd
+            },
+            Some(3),
+        )
+        .await
+        .unwrap();
+        let resources = output.resources().unwrap();
+        assert!(resources.max_rss_bytes > 0);
+        assert!(resources.cpu_time() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_read_capturing_redacts_secrets() {
+        let secret = regex::bytes::Regex::new("hunter2-7c1e").unwrap();
+        let redactor = RedactStream::with_patterns(vec![Arc::new(secret)]);
+        let (activity, _notices) = Activity::new();
+        std::thread::sleep(Duration::from_millis(200));
+        // A progress line without a line ending is held back by the
+        // redaction, but still counts as activity
+        let mut reader: &[u8] = b"pw hunter2-7c1e\nprogress 50%";
+        let mut chunks = Vec::new();
+        let capture = Mutex::new(Capture::new(None));
+        read_capturing(&mut reader, &capture, redactor, &activity, |chunk| {
+            chunks.push(chunk.to_vec())
+        })
+        .unwrap();
+        let (captured, truncation) = capture.into_inner().unwrap().finish();
+        assert!(activity.silence() < Duration::from_millis(200));
+        assert_eq!(captured, b"pw ***\nprogress 50%");
+        assert_eq!(truncation, None);
+        assert_eq!(chunks.concat(), captured);
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_interleaved() {
+        let mut logger = Logger::new();
+        let options = RunOptions::new().interleave(true).window_height(0);
+        let output = run_subprocess_with_options(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sh");
+                cmd.args([
+                    "-c",
+                    "echo one; sleep 0.1; echo two >&2; sleep 0.1; printf three",
+                ]);
+                cmd
+            },
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.tagged(), "[out] one\n[err] two\n[out] three\n");
+        assert_eq!(output.stdout, b"one\nthree");
+        assert_eq!(output.interleaved[1].stream, Stream::Stderr);
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_standalone() {
+        let script = || {
+            let mut cmd = CommandBuilder::new("sh");
+            cmd.args(["-c", "echo captured; exit 3"]);
+            cmd
+        };
+        let silent = run_subprocess_standalone(script, RenderMode::Silent, &RunOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(silent.exit_code(), 3);
+        assert!(silent.stderr_str().unwrap().contains("captured"));
+
+        let inherited = run_subprocess_standalone(script, RenderMode::Inherit, &RunOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(inherited.exit_code(), 3);
+        assert!(inherited.stderr.is_empty() && inherited.stdout.is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_subprocess_duration() {
+        let mut logger = Logger::new();
+        let output = run_subprocess(
+            &mut logger,
+            || {
+                let mut cmd = CommandBuilder::new("sleep");
+                cmd.arg("0.2");
+                cmd
+            },
+            Some(3),
+        )
+        .await
+        .unwrap();
+
+        assert!(output.duration() >= Duration::from_millis(200));
+        let summary = output.timing_summary();
+        assert!(summary.ends_with("s CPU"), "{}", summary);
+    }
+
+    #[test]
+    fn test_timing_summary_without_resources() {
+        let output = SubprocessOutput {
+            duration: Duration::from_millis(12_340),
+            ..SubprocessOutput::default()
+        };
+        assert_eq!(output.timing_summary(), "12.3s");
+    }
+
     #[tokio::test]
     #[cfg(not(windows))]
     async fn test_run_subprocess_simple_failure() {
@@ -842,6 +5175,22 @@ mod tests {
         assert!(output.success());
     }
 
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_run_subprocess_with_low_priority() {
+        for piped in [false, true] {
+            let mut logger = Logger::new();
+            let options = RunOptions::new().priority(Priority::Low).piped(piped);
+            // The child starts with the priority, not just gets it later
+            let output = run_shell(&mut logger, "ps -o ni= -p $$ >&2", &options)
+                .await
+                .unwrap();
+
+            assert!(output.success());
+            assert_eq!(output.stderr_str().unwrap().trim(), "10");
+        }
+    }
+
     #[tokio::test]
     #[cfg(not(windows))]
     async fn test_run_subprocess_nonexistent_command() {
@@ -862,6 +5211,7 @@ mod tests {
             stdout: "hello 世界".as_bytes().to_vec(),
             stderr: "error 错误".as_bytes().to_vec(),
             exit_code: 0,
+            ..Default::default()
         };
 
         assert_eq!(output.stdout_str().unwrap(), "hello 世界");
@@ -874,6 +5224,7 @@ mod tests {
             stdout: vec![0xFF, 0xFE, 0xFD], // Invalid UTF-8
             stderr: vec![],
             exit_code: 0,
+            ..Default::default()
         };
 
         assert!(output.stdout_str().is_err());
@@ -894,6 +5245,30 @@ mod tests {
         assert_eq!(result, 42);
     }
 
+    #[tokio::test]
+    async fn test_logger_suspend_async() {
+        let mut logger = Logger::new();
+        logger.status("Building", "test");
+        let result = logger
+            .suspend_async(async {
+                tokio::task::yield_now().await;
+                42
+            })
+            .await;
+        assert_eq!(result, 42);
+        assert!(logger.status_active);
+        let message = logger.progress_bar.as_ref().unwrap().message();
+        assert!(message.ends_with("test"));
+    }
+
+    #[tokio::test]
+    async fn test_logger_suspend_async_without_progress() {
+        let mut logger = Logger::new();
+        let result = logger.suspend_async(async { 42 }).await;
+        assert_eq!(result, 42);
+        assert!(logger.progress_bar.is_none());
+    }
+
     #[tokio::test]
     async fn test_logger_status_permanent() {
         let logger = Logger::new();
@@ -929,6 +5304,203 @@ mod tests {
         logger.print_message("test message");
     }
 
+    #[tokio::test]
+    async fn test_logger_deprecated() {
+        // Notices are reported once per process, so report them in a child
+        // running this test and check its stderr
+        if std::env::var_os("CARGO_PLUGIN_TEST_DEPRECATED").is_some() {
+            let logger = Logger::new();
+            logger.deprecated("--test-old-flag", "0.1.0", Some("--test-new-flag"));
+            // Reported once per run, later calls are no-ops
+            logger.deprecated("--test-old-flag", "0.1.0", Some("--test-new-flag"));
+            Logger::new().deprecated("--test-old-flag", "0.1.0", None);
+            return;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "logger::tests::test_logger_deprecated",
+                "--nocapture",
+            ])
+            .env("CARGO_PLUGIN_TEST_DEPRECATED", "1")
+            .env_remove("CARGO_PLUGIN_NO_DEPRECATION_WARNINGS")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stderr =
+            console::strip_ansi_codes(&String::from_utf8_lossy(&output.stderr)).into_owned();
+        assert_eq!(stderr.matches("Deprecated").count(), 1, "{}", stderr);
+        assert!(
+            stderr.contains("Deprecated --test-old-flag since 0.1.0, use --test-new-flag instead")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logger_deny_warnings() {
+        let mut logger = Logger::new();
+        logger.warning("Skipping", "foo");
+        assert!(!logger.has_errors());
+
+        logger.deny_warnings(true);
+        logger.warning("Skipping", "foo");
+        assert!(logger.has_errors());
+        logger.error("Failed", "bar");
+        assert_eq!(logger.error_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_logger_filters() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut logger = Logger::new();
+        logger.add_filter(|mut event| {
+            event.target = event.target.replace("hunter2", "***");
+            Some(event)
+        });
+        let seen_filter = seen.clone();
+        logger.add_filter(move |event| {
+            seen_filter.lock().unwrap().push(event.clone());
+            (event.action != "Secret").then_some(event)
+        });
+
+        logger.status("Pushing", "token hunter2");
+        let message = logger.progress_bar.as_ref().unwrap().message();
+        assert!(message.ends_with("token ***"));
+        assert!(!message.contains("hunter2"));
+
+        logger.status_permanent("Secret", "dropped");
+        logger.error("Failed", "hunter2");
+        logger.print_message("hunter2 printed");
+        logger.set_message_format(MessageFormat::Json);
+        logger.deprecated("--test-filtered-flag", "0.2.0", None);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                LogEvent::new(EventLevel::Status, "Pushing", "token ***"),
+                LogEvent::new(EventLevel::Success, "Secret", "dropped"),
+                LogEvent::new(EventLevel::Error, "Failed", "***"),
+                LogEvent::new(EventLevel::Message, "", "*** printed"),
+                LogEvent::new(
+                    EventLevel::Warning,
+                    "Deprecated",
+                    "--test-filtered-flag since 0.2.0"
+                ),
+            ]
+        );
+        assert_eq!(logger.error_count(), 1);
+        logger.finish();
+    }
+
+    #[tokio::test]
+    async fn test_logger_json_mode() {
+        let mut logger = Logger::new();
+        logger.status("Building", "test-crate");
+        logger.set_message_format(MessageFormat::Json);
+        assert_eq!(logger.message_format(), MessageFormat::Json);
+        // Switching to JSON drops the status line and no new ones are drawn
+        assert!(logger.progress_bar.is_none());
+        logger.status("Building", "test-crate");
+        logger.progress("Working");
+        assert!(logger.progress_bar.is_none());
+        logger.warning("Skipping", "test-crate");
+        logger
+            .emit(Message::Data {
+                kind: "test".to_string(),
+                data: serde_json::Value::Null,
+            })
+            .unwrap();
+    }
+
+    /// A terminal recording what is drawn, with cursor movements and line
+    /// clearing spelled out.
+    #[derive(Debug, Clone, Default)]
+    struct RecordingTerm(Arc<Mutex<String>>);
+
+    impl RecordingTerm {
+        fn record(&self, text: &str) -> std::io::Result<()> {
+            self.0.lock().unwrap().push_str(text);
+            Ok(())
+        }
+    }
+
+    impl indicatif::TermLike for RecordingTerm {
+        fn width(&self) -> u16 {
+            80
+        }
+
+        fn move_cursor_up(&self, lines: usize) -> std::io::Result<()> {
+            self.record(&format!("<up {}>", lines))
+        }
+
+        fn move_cursor_down(&self, lines: usize) -> std::io::Result<()> {
+            self.record(&format!("<down {}>", lines))
+        }
+
+        fn move_cursor_right(&self, columns: usize) -> std::io::Result<()> {
+            self.record(&format!("<right {}>", columns))
+        }
+
+        fn move_cursor_left(&self, columns: usize) -> std::io::Result<()> {
+            self.record(&format!("<left {}>", columns))
+        }
+
+        fn write_line(&self, line: &str) -> std::io::Result<()> {
+            self.record(&format!("{}\n", line))
+        }
+
+        fn write_str(&self, text: &str) -> std::io::Result<()> {
+            self.record(text)
+        }
+
+        fn clear_line(&self) -> std::io::Result<()> {
+            self.record("<clear>")
+        }
+
+        fn flush(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for RecordingTerm {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.record(&String::from_utf8_lossy(bytes))?;
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logger_write_stdout() {
+        let mut logger = Logger::new();
+        logger.write_stdout(b"").unwrap();
+        logger.status("Generating", "badge");
+        let mut term = RecordingTerm::default();
+        let pb = logger.progress_bar.clone().unwrap();
+        pb.set_draw_target(ProgressDrawTarget::term_like(Box::new(term.clone())));
+        // Draw on `tick()` only, not from the ticker thread
+        pb.disable_steady_tick();
+        pb.tick();
+        let payload = "{\"badge\": \"passing\"}\n";
+        logger
+            .write_below_status(&mut term, payload.as_bytes())
+            .unwrap();
+        logger.status("Generating", "report");
+        pb.tick();
+        logger.finish();
+
+        // indicatif moves up zero lines on a single-line bar
+        let drawn = console::strip_ansi_codes(&term.0.lock().unwrap()).replace("<up 0>", "");
+        let (before, after) = drawn.split_once(payload).unwrap();
+        // The status line was cleared before the payload, and redrawn below
+        // it without moving back up
+        assert!(before.contains("Generating badge"), "{:?}", drawn);
+        assert!(before.ends_with("<clear>"), "{:?}", drawn);
+        assert!(after.contains("Generating report"), "{:?}", drawn);
+        assert!(!after.contains("<up"), "{:?}", drawn);
+    }
+
     #[tokio::test]
     async fn test_logger_progress() {
         let mut logger = Logger::new();
@@ -943,4 +5515,80 @@ mod tests {
         logger.set_progress_message("Updated");
         assert!(logger.progress_bar.is_some());
     }
+
+    #[tokio::test]
+    async fn test_logger_emoji_mode() {
+        let mut logger = Logger::new();
+        assert!(!logger.emoji);
+        logger.set_emoji(true);
+        assert!(logger.emoji);
+        // Should not panic
+        logger.status_permanent("Finished", "test-crate");
+        logger.warning("Warning", "test message");
+        logger.error("Error", "test message");
+    }
+
+    #[tokio::test]
+    async fn test_line_kind_prefix() {
+        assert_eq!(LineKind::Success.prefix(true), "✅ ");
+        assert_eq!(LineKind::Error.prefix(false), "[x] ");
+        assert!(LineKind::Info.prefix(false).trim().is_empty());
+        // ASCII prefixes share a width so lines stay aligned
+        assert_eq!(
+            LineKind::Warning.prefix(false).len(),
+            LineKind::Info.prefix(false).len()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_prompt_without_terminal() {
+        use std::os::unix::process::CommandExt;
+
+        // Prompts in a child without a controlling terminal take their
+        // default instead of reading the piped stdin
+        if std::env::var_os("CARGO_PLUGIN_TEST_NO_TTY").is_some() {
+            let mut logger = Logger::new();
+            assert!(!logger.confirm("Proceed?"));
+            assert_eq!(logger.input("Name:"), "");
+            let mut rest = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut rest).unwrap();
+            assert_eq!(rest, "y\nname\n");
+            return;
+        }
+        let mut command = std::process::Command::new(std::env::current_exe().unwrap());
+        command
+            .args(["--exact", "logger::tests::test_prompt_without_terminal"])
+            .env("CARGO_PLUGIN_TEST_NO_TTY", "1")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        // SAFETY: setsid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"y\nname\n").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_affirmative() {
+        assert!(is_affirmative("y"));
+        assert!(is_affirmative("Yes "));
+        assert!(is_affirmative("YES"));
+        assert!(!is_affirmative(""));
+        assert!(!is_affirmative("n"));
+        assert!(!is_affirmative("yep"));
+    }
 }
@@ -0,0 +1,417 @@
+//! Run a command once per feature combination of a package.
+//!
+//! A [`FeatureMatrix`] expands into feature sets: the powerset of the
+//! package's features, each feature on its own, or an explicit list. Sets that
+//! enable the same features in the end (e.g. `default` and the features it
+//! lists) are run only once. [`run`] appends
+//! `--no-default-features --features ...` to a command template for every set,
+//! runs them through [`run_subprocesses_parallel`] and prints a grid of the
+//! results:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::matrix::{
+//!     self,
+//!     FeatureMatrix,
+//! };
+//! use cargo_plugin_utils::{
+//!     Logger,
+//!     common,
+//! };
+//! use portable_pty::CommandBuilder;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let package = common::find_package(None)?;
+//! let mut check = CommandBuilder::new("cargo");
+//! check.args(["check", "--all-targets"]);
+//! let mut logger = Logger::new();
+//! let report = matrix::run(
+//!     &mut logger,
+//!     &package,
+//!     &FeatureMatrix::powerset().depth(2),
+//!     &check,
+//! )
+//! .await;
+//! anyhow::ensure!(report.success(), "some feature combinations don't build");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+    HashSet,
+};
+
+use cargo_metadata::Package;
+use portable_pty::CommandBuilder;
+
+use crate::logger::Logger;
+use crate::parallel::{
+    Job,
+    run_subprocesses_parallel,
+};
+use crate::pipeline::StepStatus;
+use crate::table::Table;
+
+/// Which feature combinations to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureMatrix {
+    kind: MatrixKind,
+    depth: Option<usize>,
+    exclude: BTreeSet<String>,
+    jobs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MatrixKind {
+    Powerset,
+    EachFeature,
+    Explicit(Vec<Vec<String>>),
+}
+
+impl FeatureMatrix {
+    /// Every subset of the package's features, from no features to all.
+    pub fn powerset() -> Self {
+        Self::new(MatrixKind::Powerset)
+    }
+
+    /// No features, then each feature on its own.
+    pub fn each_feature() -> Self {
+        Self::new(MatrixKind::EachFeature)
+    }
+
+    /// Exactly the given feature sets.
+    pub fn explicit<I, S, F>(sets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        let sets = sets
+            .into_iter()
+            .map(|set| set.into_iter().map(Into::into).collect())
+            .collect();
+        Self::new(MatrixKind::Explicit(sets))
+    }
+
+    fn new(kind: MatrixKind) -> Self {
+        Self {
+            kind,
+            depth: None,
+            exclude: BTreeSet::new(),
+            jobs: 1,
+        }
+    }
+
+    /// Only combine up to `depth` features per set, to keep a powerset of
+    /// many features manageable.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Leave out sets containing any of `features`.
+    pub fn exclude<I, S>(mut self, features: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exclude.extend(features.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run up to `jobs` combinations at once (default 1).
+    ///
+    /// Cargo commands sharing a target directory wait for each other's lock;
+    /// give each job its own `CARGO_TARGET_DIR` to really run them in
+    /// parallel.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// The feature sets to run for a package with `features` (the
+    /// `[features]` table as in [`Package::features`]), without sets
+    /// equivalent to an earlier one.
+    pub fn combinations(&self, features: &BTreeMap<String, Vec<String>>) -> Vec<Vec<String>> {
+        let names: Vec<&String> = features
+            .keys()
+            .filter(|name| !self.exclude.contains(*name))
+            .collect();
+        let max_size = self.depth.unwrap_or(names.len());
+        let candidates: Vec<Vec<String>> = match &self.kind {
+            // Smaller sets first, so the simplest of equivalent sets wins
+            MatrixKind::Powerset => (0..=max_size)
+                .flat_map(|size| subsets(&names, size))
+                .collect(),
+            MatrixKind::EachFeature => std::iter::once(Vec::new())
+                .chain(names.iter().map(|name| vec![(*name).clone()]))
+                .collect(),
+            MatrixKind::Explicit(sets) => sets
+                .iter()
+                .filter(|set| !set.iter().any(|feature| self.exclude.contains(feature)))
+                .cloned()
+                .collect(),
+        };
+
+        let mut seen = HashSet::new();
+        candidates
+            .into_iter()
+            .filter(|set| seen.insert(enabled_features(features, set)))
+            .collect()
+    }
+}
+
+/// All `size`-element subsets of `names`, in lexicographic order.
+fn subsets(names: &[&String], size: usize) -> Vec<Vec<String>> {
+    let mut subsets = Vec::new();
+    let mut indices: Vec<usize> = (0..size).collect();
+    if size > names.len() {
+        return subsets;
+    }
+    loop {
+        subsets.push(indices.iter().map(|&index| names[index].clone()).collect());
+        // Advance the rightmost index that still has room
+        let Some(position) = (0..size)
+            .rev()
+            .find(|&position| indices[position] < names.len() - size + position)
+        else {
+            return subsets;
+        };
+        indices[position] += 1;
+        for next in position + 1..size {
+            indices[next] = indices[next - 1] + 1;
+        }
+    }
+}
+
+/// Everything `set` ends up enabling: the features themselves, the features
+/// they enable, and `dep:`/`dep/feature` entries.
+fn enabled_features(features: &BTreeMap<String, Vec<String>>, set: &[String]) -> BTreeSet<String> {
+    let mut enabled = BTreeSet::new();
+    let mut pending: Vec<&str> = set.iter().map(String::as_str).collect();
+    while let Some(feature) = pending.pop() {
+        if !enabled.insert(feature.to_string()) {
+            continue;
+        }
+        let implied = features.get(feature).map_or(&[][..], Vec::as_slice);
+        for entry in implied {
+            pending.push(entry);
+            // `dep/feature` also enables the optional dependency `dep`
+            if let Some((dependency, _)) = entry.split_once('/')
+                && !dependency.ends_with('?')
+            {
+                pending.push(dependency);
+            }
+        }
+    }
+    enabled
+}
+
+/// Run `template` with every feature set of `matrix` for `package` and print
+/// a grid of the results.
+///
+/// Each run gets `--manifest-path <package> --no-default-features --features
+/// <set>` appended to the template's arguments.
+pub async fn run(
+    logger: &mut Logger,
+    package: &Package,
+    matrix: &FeatureMatrix,
+    template: &CommandBuilder,
+) -> MatrixReport {
+    let sets = matrix.combinations(&package.features);
+    let jobs = sets.iter().map(|set| {
+        let mut cmd = template.clone();
+        cmd.arg("--manifest-path");
+        cmd.arg(package.manifest_path.as_std_path());
+        cmd.arg("--no-default-features");
+        if !set.is_empty() {
+            cmd.args(["--features", &set.join(",")]);
+        }
+        Job::new(label(set), cmd)
+    });
+    let outputs = run_subprocesses_parallel(logger, jobs, matrix.jobs).await;
+
+    let results = sets
+        .into_iter()
+        .zip(outputs)
+        .map(|(features, output)| {
+            let status = match output {
+                Ok(output) if output.success() => StepStatus::Passed,
+                Ok(output) => StepStatus::Failed {
+                    exit_code: output.exit_code(),
+                },
+                Err(err) => StepStatus::Error(format!("{:#}", err)),
+            };
+            MatrixResult { features, status }
+        })
+        .collect();
+    let report = MatrixReport { results };
+    report.print(logger, package);
+    report
+}
+
+fn label(set: &[String]) -> String {
+    if set.is_empty() {
+        "(no features)".to_string()
+    } else {
+        set.join(",")
+    }
+}
+
+/// Result of one feature set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixResult {
+    /// The enabled features
+    pub features: Vec<String>,
+    /// How the command ended
+    pub status: StepStatus,
+}
+
+/// Results of a [`run`], one per feature set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixReport {
+    /// The results in run order
+    pub results: Vec<MatrixResult>,
+}
+
+impl MatrixReport {
+    /// Whether the command succeeded for every feature set.
+    pub fn success(&self) -> bool {
+        !self.results.iter().any(|result| result.status.is_failure())
+    }
+
+    /// The feature sets the command failed for.
+    pub fn failures(&self) -> impl Iterator<Item = &MatrixResult> {
+        self.results
+            .iter()
+            .filter(|result| result.status.is_failure())
+    }
+
+    /// A grid with a column per feature, marking the enabled ones in each
+    /// row, and the result.
+    pub fn table(&self) -> Table {
+        let features: BTreeSet<&String> = self
+            .results
+            .iter()
+            .flat_map(|result| &result.features)
+            .collect();
+        let headers = features
+            .iter()
+            .map(|feature| feature.as_str())
+            .chain(["Result"]);
+        let mut table = Table::new(headers);
+        for result in &self.results {
+            let marks = features.iter().map(|feature| {
+                if result.features.contains(feature) {
+                    "x".to_string()
+                } else {
+                    String::new()
+                }
+            });
+            table.add_row(marks.chain([result.status.styled()]));
+        }
+        table
+    }
+
+    fn print(&self, logger: &Logger, package: &Package) {
+        if self.results.is_empty() {
+            return;
+        }
+        let width = usize::from(crate::resize::current().cols);
+        logger.print_message(&self.table().render(width));
+        let failed = self.failures().count();
+        let summary = format!(
+            "{}: {} of {} feature sets",
+            package.name,
+            self.results.len() - failed,
+            self.results.len()
+        );
+        if failed == 0 {
+            logger.status_permanent("Passed", &summary);
+        } else {
+            logger.error("Failed", &format!("{} failed", failed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features() -> BTreeMap<String, Vec<String>> {
+        [
+            ("default", vec!["std"]),
+            ("std", vec![]),
+            ("serde", vec!["dep:serde"]),
+            ("full", vec!["std", "serde"]),
+        ]
+        .into_iter()
+        .map(|(name, implied)| {
+            (
+                name.to_string(),
+                implied.into_iter().map(String::from).collect(),
+            )
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_powerset_deduplicates() {
+        let sets = FeatureMatrix::powerset().combinations(&features());
+        let labels: Vec<_> = sets.iter().map(|set| label(set)).collect();
+        // Sets with a feature another one already enables, like `full,std`,
+        // are left out
+        assert_eq!(
+            labels,
+            [
+                "(no features)",
+                "default",
+                "full",
+                "serde",
+                "std",
+                "default,full",
+                "default,serde",
+                "serde,std",
+            ]
+        );
+        let shallow = FeatureMatrix::powerset().depth(1);
+        assert_eq!(shallow.combinations(&features()).len(), 5);
+    }
+
+    #[test]
+    fn test_each_feature_and_explicit() {
+        let matrix = FeatureMatrix::each_feature().exclude(["full"]);
+        let labels: Vec<_> = matrix
+            .combinations(&features())
+            .iter()
+            .map(|set| label(set))
+            .collect();
+        assert_eq!(labels, ["(no features)", "default", "serde", "std"]);
+
+        let matrix = FeatureMatrix::explicit([vec!["full"], vec!["full", "serde"], vec!["std"]]);
+        let sets = matrix.combinations(&features());
+        assert_eq!(sets, [vec!["full".to_string()], vec!["std".to_string()]]);
+    }
+
+    #[test]
+    fn test_table() {
+        let report = MatrixReport {
+            results: vec![
+                MatrixResult {
+                    features: vec![],
+                    status: StepStatus::Passed,
+                },
+                MatrixResult {
+                    features: vec!["serde".to_string()],
+                    status: StepStatus::Failed { exit_code: 101 },
+                },
+            ],
+        };
+        assert!(!report.success());
+        let table = console::strip_ansi_codes(&report.table().render(80)).into_owned();
+        let lines: Vec<_> = table.lines().map(str::trim_end).collect();
+        assert_eq!(lines[0], "serde  Result");
+        assert_eq!(lines[2], "x      FAILED");
+    }
+}
@@ -0,0 +1,198 @@
+//! Machine-readable output (`--message-format json`).
+//!
+//! In JSON mode the [`Logger`](crate::logger::Logger) writes one JSON object
+//! per line to stdout instead of drawing status lines, similar to cargo's
+//! `--message-format json`. Every line is an [`Envelope`]: the
+//! `schema_version` plus a message identified by its `reason` field:
+//!
+//! ```json
+//! {"schema_version":1,"reason":"status","level":"warning","action":"Skipping","target":"foo"}
+//! {"schema_version":1,"reason":"data","kind":"badge","data":{"label":"msrv","message":"1.93"}}
+//! ```
+//!
+//! # Compatibility
+//!
+//! Within a schema version:
+//!
+//! - existing reasons and fields are never removed, renamed or retyped;
+//! - new reasons, new fields and new enum values (e.g. levels) may be added in
+//!   any release.
+//!
+//! Consumers should therefore ignore unknown fields, and skip unknown reasons
+//! (deserialized as [`Message::Unknown`]). Breaking changes bump
+//! [`SCHEMA_VERSION`]; use [`Envelope::is_compatible`] to check.
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// Current version of the message schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Output format for messages, selected with `--message-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Human-readable, cargo-style status lines on stderr
+    #[default]
+    Human,
+    /// One JSON [`Envelope`] per line on stdout
+    Json,
+}
+
+/// A single line of JSON output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Schema version the message was written with
+    pub schema_version: u32,
+    /// The message itself, tagged by its `reason` field
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+impl Envelope {
+    /// Wrap a message with the current [`SCHEMA_VERSION`].
+    pub fn new(message: Message) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            message,
+        }
+    }
+
+    /// Parse one line of JSON output.
+    pub fn parse(line: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(line)
+    }
+
+    /// Whether this message follows the schema version of this crate.
+    pub fn is_compatible(&self) -> bool {
+        self.schema_version == SCHEMA_VERSION
+    }
+}
+
+/// A machine-readable message, identified by its `reason` field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Message {
+    /// A permanent status line (e.g. `Finished`, a warning or an error)
+    Status {
+        /// Severity of the line
+        level: Level,
+        /// The action word, e.g. `Finished`
+        action: String,
+        /// The text after the action word
+        target: String,
+    },
+    /// Plugin-specific payload, e.g. a generated badge or changelog
+    Data {
+        /// Plugin-defined payload type, e.g. `badge`
+        kind: String,
+        /// The payload
+        data: serde_json::Value,
+    },
+    /// Use of a deprecated plugin feature (reported once per run)
+    Deprecated {
+        /// The deprecated feature, e.g. `--old-flag`
+        feature: String,
+        /// Version that deprecated it
+        since: String,
+        /// What to use instead, if anything
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alternative: Option<String>,
+    },
+    /// A reason introduced by a newer release of this crate
+    #[serde(other)]
+    Unknown,
+}
+
+/// Severity of a [`Message::Status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Level {
+    /// A completed operation (green in human output)
+    Success,
+    /// Informational (cyan in human output)
+    Info,
+    /// A warning (yellow in human output)
+    Warning,
+    /// An error (red in human output)
+    Error,
+    /// A level introduced by a newer release of this crate
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_serialization() {
+        let envelope = Envelope::new(Message::Status {
+            level: Level::Warning,
+            action: "Skipping".to_string(),
+            target: "foo".to_string(),
+        });
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"schema_version":1,"reason":"status","level":"warning","action":"Skipping","target":"foo"}"#
+        );
+    }
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let envelope = Envelope::new(Message::Data {
+            kind: "badge".to_string(),
+            data: serde_json::json!({"label": "msrv", "message": "1.93"}),
+        });
+        let line = serde_json::to_string(&envelope).unwrap();
+        let parsed = Envelope::parse(&line).unwrap();
+        assert_eq!(parsed, envelope);
+        assert!(parsed.is_compatible());
+    }
+
+    #[test]
+    fn test_envelope_deprecated() {
+        let envelope = Envelope::new(Message::Deprecated {
+            feature: "--old".to_string(),
+            since: "0.3.0".to_string(),
+            alternative: None,
+        });
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"schema_version":1,"reason":"deprecated","feature":"--old","since":"0.3.0"}"#
+        );
+    }
+
+    #[test]
+    fn test_envelope_tolerates_unknown_fields_and_reasons() {
+        let parsed = Envelope::parse(
+            r#"{"schema_version":1,"reason":"status","level":"info","action":"A","target":"b","extra":true}"#,
+        )
+        .unwrap();
+        assert!(matches!(parsed.message, Message::Status { .. }));
+
+        let parsed = Envelope::parse(r#"{"schema_version":1,"reason":"from-the-future"}"#).unwrap();
+        assert_eq!(parsed.message, Message::Unknown);
+
+        let parsed = Envelope::parse(
+            r#"{"schema_version":1,"reason":"status","level":"debug","action":"A","target":"b"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            parsed.message,
+            Message::Status {
+                level: Level::Unknown,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_envelope_incompatible_version() {
+        let parsed = Envelope::parse(r#"{"schema_version":2,"reason":"new"}"#).unwrap();
+        assert!(!parsed.is_compatible());
+    }
+}
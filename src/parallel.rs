@@ -0,0 +1,333 @@
+//! Run several subprocesses concurrently with one output window each.
+//!
+//! [`run_subprocesses_parallel`] is the concurrent counterpart of
+//! [`run_subprocess_with_options`](crate::logger::run_subprocess_with_options):
+//! every running command gets a small window at the bottom of the terminal,
+//! headed by its label, showing its last lines of output. Windows are stacked
+//! in start order and disappear when their command finishes:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::parallel::{
+//!     Job,
+//!     run_subprocesses_parallel,
+//! };
+//! use portable_pty::CommandBuilder;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let jobs = ["core", "cli"].map(|package| {
+//!     let mut cmd = CommandBuilder::new("cargo");
+//!     cmd.args(["doc", "--no-deps", "-p", package]);
+//!     Job::new(package, cmd)
+//! });
+//! for output in run_subprocesses_parallel(&mut logger, jobs, 4).await {
+//!     anyhow::ensure!(output?.success(), "cargo doc failed");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::io::Write;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::task::Poll;
+
+use portable_pty::CommandBuilder;
+
+use crate::logger::{
+    Logger,
+    RunOptions,
+    SubprocessOutput,
+    WindowSize,
+    fit_to_width,
+    run_command,
+};
+
+/// One command of [`run_subprocesses_parallel`].
+#[derive(Debug)]
+pub struct Job {
+    pub(crate) label: String,
+    pub(crate) command: CommandBuilder,
+    pub(crate) options: RunOptions,
+}
+
+impl Job {
+    /// Run `command` under the window title `label`, with default options.
+    pub fn new(label: impl Into<String>, command: CommandBuilder) -> Self {
+        Self {
+            label: label.into(),
+            command,
+            options: RunOptions::default(),
+        }
+    }
+
+    /// Run with `options`; the window height defaults to 3 lines here.
+    pub fn options(mut self, options: RunOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Default height of a job window, smaller than a single subprocess window
+/// so several fit on screen.
+const DEFAULT_JOB_WINDOW_HEIGHT: usize = 3;
+
+/// Run `jobs` concurrently, at most `max_jobs` at a time (at least one).
+///
+/// Each running job gets a labeled window showing its last output lines,
+/// like [`run_subprocess`](crate::logger::run_subprocess) does for a single
+/// command. The results are returned in the order of `jobs`; a job that
+/// fails to start, times out or is cancelled doesn't stop the others.
+pub async fn run_subprocesses_parallel<I>(
+    logger: &mut Logger,
+    jobs: I,
+    max_jobs: usize,
+) -> Vec<anyhow::Result<SubprocessOutput>>
+where
+    I: IntoIterator<Item = Job>,
+{
+    let term = console::Term::stderr();
+    let panel = Arc::new(Mutex::new(Panel::new(crate::tty::supports_vt())));
+    if term.is_term() {
+        logger.clear_for_window(&term);
+    }
+    let logger: &Logger = logger;
+    let permits = tokio::sync::Semaphore::new(max_jobs.max(1));
+    let runs = jobs.into_iter().map(|job| {
+        let panel = panel.clone();
+        let permits = &permits;
+        async move {
+            let _permit = permits.acquire().await?;
+            let slot = PanelSlot::open(panel, job.label);
+            let mut options = job.options;
+            if options.window_size.is_none() {
+                options.window_size = Some(WindowSize::Lines(DEFAULT_JOB_WINDOW_HEIGHT));
+            }
+            options.slot = Some(slot.clone());
+            let result = run_command(logger, job.command, &options).await;
+            slot.close();
+            result
+        }
+    });
+    join_all(runs.collect()).await
+}
+
+/// Poll all `futures` concurrently on the current task, returning their
+/// outputs in order.
+async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut pending = false;
+        let unfinished = futures
+            .iter_mut()
+            .zip(&mut outputs)
+            .filter(|(_, output)| output.is_none());
+        for (future, output) in unfinished {
+            match future.as_mut().poll(cx) {
+                Poll::Ready(value) => *output = Some(value),
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+/// Stacked job windows at the bottom of the terminal.
+#[derive(Debug)]
+struct Panel {
+    is_term: bool,
+    next_id: usize,
+    windows: Vec<PanelWindow>,
+    /// Number of lines currently on screen
+    displayed: usize,
+}
+
+#[derive(Debug)]
+struct PanelWindow {
+    id: usize,
+    label: String,
+    lines: Vec<Vec<u8>>,
+}
+
+impl Panel {
+    fn new(is_term: bool) -> Self {
+        Self {
+            is_term,
+            next_id: 0,
+            windows: Vec::new(),
+            displayed: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        crate::logger::clear_window_lines(self.displayed);
+        self.displayed = 0;
+    }
+
+    fn redraw(&mut self) {
+        if !self.is_term {
+            return;
+        }
+        self.clear();
+        let size = crate::resize::current();
+        let width = usize::from(size.cols);
+        // Keep the panel on screen: shrink the windows so all headers and
+        // at least their latest line fit, leaving the cursor row free
+        let windows = self.windows.len().max(1);
+        let budget = usize::from(size.rows).saturating_sub(1) / windows;
+        let max_lines = budget.saturating_sub(1).max(1);
+
+        let mut stderr = std::io::stderr().lock();
+        let mut displayed = 0;
+        for window in &self.windows {
+            let header = format!(
+                "{} {}",
+                console::style(format!("{:>12}", "Running")).cyan().bold(),
+                window.label
+            );
+            let _ = writeln!(stderr, "{}", console::truncate_str(&header, width, ""));
+            let skip = window.lines.len().saturating_sub(max_lines);
+            for line in &window.lines[skip..] {
+                let _ = stderr.write_all(&fit_to_width(line, width));
+                if !line.ends_with(b"\n") {
+                    let _ = stderr.write_all(b"\n");
+                }
+            }
+            displayed += 1 + window.lines.len() - skip;
+        }
+        let _ = stderr.flush();
+        self.displayed = displayed;
+    }
+}
+
+/// Handle of one job window in the panel.
+#[derive(Debug, Clone)]
+pub(crate) struct PanelSlot {
+    panel: Arc<Mutex<Panel>>,
+    id: usize,
+}
+
+impl PanelSlot {
+    /// Add a window titled `label` below the existing ones.
+    fn open(panel: Arc<Mutex<Panel>>, label: String) -> Self {
+        let id = {
+            let mut locked = panel.lock().unwrap_or_else(|err| err.into_inner());
+            let id = locked.next_id;
+            locked.next_id += 1;
+            locked.windows.push(PanelWindow {
+                id,
+                label,
+                lines: Vec::new(),
+            });
+            locked.redraw();
+            id
+        };
+        Self { panel, id }
+    }
+
+    /// Replace the lines shown in this window.
+    pub(crate) fn update<'a>(&self, lines: impl IntoIterator<Item = &'a Vec<u8>>) {
+        self.with_panel(|panel| {
+            if let Some(window) = panel.windows.iter_mut().find(|window| window.id == self.id) {
+                window.lines = lines.into_iter().cloned().collect();
+            }
+            panel.redraw();
+        });
+    }
+
+    /// Hide the panel while `print` writes permanent lines above it.
+    pub(crate) fn suspend<R>(&self, print: impl FnOnce() -> R) -> R {
+        self.with_panel(|panel| {
+            panel.clear();
+            let result = print();
+            panel.redraw();
+            result
+        })
+    }
+
+    /// Remove this window.
+    fn close(&self) {
+        self.with_panel(|panel| {
+            panel.windows.retain(|window| window.id != self.id);
+            panel.redraw();
+        });
+    }
+
+    fn with_panel<R>(&self, f: impl FnOnce(&mut Panel) -> R) -> R {
+        let mut panel = self.panel.lock().unwrap_or_else(|err| err.into_inner());
+        f(&mut panel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn echo(text: &str) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new("sh");
+        cmd.args(["-c", &format!("echo {}", text)]);
+        cmd
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_subprocesses_parallel_keeps_order() {
+        let mut logger = Logger::new();
+        let jobs = vec![
+            Job::new("first", echo("one")),
+            Job::new("second", echo("two")).options(RunOptions::new().piped(true)),
+            Job::new("missing", CommandBuilder::new("/nonexistent/program"))
+                .options(RunOptions::new().piped(true)),
+        ];
+        let results = run_subprocesses_parallel(&mut logger, jobs, 2).await;
+        assert_eq!(results.len(), 3);
+        let first = results[0].as_ref().unwrap();
+        assert!(first.success());
+        assert!(String::from_utf8_lossy(&first.stderr).contains("one"));
+        assert_eq!(results[1].as_ref().unwrap().stdout_str().unwrap(), "two\n");
+        assert!(results[2].is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_subprocesses_parallel_limits_jobs() {
+        let mut logger = Logger::new();
+        let dir = tempfile::TempDir::new().unwrap();
+        let marker = dir.path().join("running");
+        // Each job fails if another one is running at the same time
+        let jobs = (0..3).map(|index| {
+            let mut cmd = CommandBuilder::new("sh");
+            cmd.args([
+                "-c",
+                "test ! -e \"$1\" && touch \"$1\" && sleep 0.1 && rm \"$1\"",
+                "sh",
+            ]);
+            cmd.arg(&marker);
+            Job::new(format!("job {}", index), cmd).options(RunOptions::new().piped(true))
+        });
+        let results = run_subprocesses_parallel(&mut logger, jobs, 1).await;
+        assert!(
+            results
+                .iter()
+                .all(|result| result.as_ref().unwrap().success())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_join_all() {
+        let futures = (0..3).map(|index| async move { index * 2 }).collect();
+        assert_eq!(join_all(futures).await, [0, 2, 4]);
+    }
+}
@@ -0,0 +1,913 @@
+//! Proposed file edits that can be applied or emitted as a patch.
+//!
+//! A [`PatchSet`] collects the file edits of a plugin run (manifest edits,
+//! template renders, replacements, ...) in memory. Later edits see earlier
+//! ones through [`PatchSet::read`]. At the end the plugin either applies them
+//! or writes a unified diff for review, which [`apply_patch`] can apply
+//! later, e.g. in a review-first CI workflow. Interactive plugins can let the
+//! user pick the edits to apply with [`apply_interactively`].
+//!
+//! ```no_run
+//! use cargo_plugin_utils::patch::PatchSet;
+//!
+//! let mut patch = PatchSet::new(".");
+//! patch.edit("Cargo.toml", |manifest| {
+//!     manifest.replace("version = \"0.1.0\"", "version = \"0.2.0\"")
+//! })?;
+//! patch.write("CHANGELOG.md", "# Changelog\n")?;
+//! # let review_only = true;
+//! if review_only {
+//!     patch.write_patch("release.patch")?;
+//! } else {
+//!     patch.apply()?;
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+
+use crate::logger::Logger;
+
+/// Lines of context around each change in generated patches.
+const CONTEXT_LINES: usize = 3;
+
+/// Original and proposed contents of one file (`None`: file doesn't exist).
+#[derive(Debug, Clone)]
+struct FileEdit {
+    original: Option<String>,
+    proposed: Option<String>,
+}
+
+/// A set of proposed edits to files below a root directory.
+#[derive(Debug, Clone)]
+pub struct PatchSet {
+    root: PathBuf,
+    files: BTreeMap<PathBuf, FileEdit>,
+}
+
+impl PatchSet {
+    /// Start an empty patch set for files below `root`.
+    ///
+    /// Paths passed to the other methods are relative to `root`, and so are
+    /// the paths in generated patches.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// The root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Current contents of `path`, including proposed edits, or `None` if the
+    /// file doesn't exist (or is proposed for removal).
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Option<String>> {
+        let path = path.as_ref();
+        match self.files.get(path) {
+            Some(edit) => Ok(edit.proposed.clone()),
+            None => read_optional(&self.root.join(path)),
+        }
+    }
+
+    /// Propose new contents for `path`, creating it if it doesn't exist.
+    pub fn write(&mut self, path: impl AsRef<Path>, contents: impl Into<String>) -> Result<()> {
+        self.entry(path.as_ref())?.proposed = Some(contents.into());
+        Ok(())
+    }
+
+    /// Propose an edit of the current contents of `path`.
+    ///
+    /// Fails if the file doesn't exist.
+    pub fn edit<F>(&mut self, path: impl AsRef<Path>, edit: F) -> Result<()>
+    where
+        F: FnOnce(&str) -> String,
+    {
+        let path = path.as_ref();
+        let entry = self.entry(path)?;
+        let current = entry
+            .proposed
+            .as_deref()
+            .with_context(|| format!("Cannot edit missing file {}", path.display()))?;
+        entry.proposed = Some(edit(current));
+        Ok(())
+    }
+
+    /// Propose removing `path`.
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.entry(path.as_ref())?.proposed = None;
+        Ok(())
+    }
+
+    /// Files whose proposed contents differ from the original.
+    pub fn changed_files(&self) -> Vec<&Path> {
+        self.files
+            .iter()
+            .filter(|(_, edit)| edit.original != edit.proposed)
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+
+    /// Whether no file would change.
+    pub fn is_empty(&self) -> bool {
+        self.changed_files().is_empty()
+    }
+
+    /// Render the proposed edits as a unified diff (`a/` and `b/` prefixes,
+    /// `/dev/null` for created and removed files).
+    pub fn to_patch(&self) -> String {
+        self.files
+            .iter()
+            .filter(|(_, edit)| edit.original != edit.proposed)
+            .map(|(path, edit)| file_diff(path, edit.original.as_deref(), edit.proposed.as_deref()))
+            .collect()
+    }
+
+    /// Write the proposed edits as a unified diff to `path`.
+    pub fn write_patch(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_patch())
+            .with_context(|| format!("Failed to write patch {}", path.display()))
+    }
+
+    /// Apply the proposed edits to the files on disk.
+    ///
+    /// Returns the paths (relative to the root) that were changed.
+    pub fn apply(&self) -> Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        for (path, edit) in &self.files {
+            if edit.original == edit.proposed {
+                continue;
+            }
+            write_optional(&self.root.join(path), edit.proposed.as_deref())?;
+            changed.push(path.clone());
+        }
+        Ok(changed)
+    }
+
+    /// The edit entry for `path`, reading the original on first access.
+    fn entry(&mut self, path: &Path) -> Result<&mut FileEdit> {
+        if !self.files.contains_key(path) {
+            let original = read_optional(&self.root.join(path))?;
+            self.files.insert(
+                path.to_path_buf(),
+                FileEdit {
+                    proposed: original.clone(),
+                    original,
+                },
+            );
+        }
+        Ok(self.files.get_mut(path).expect("entry was just inserted"))
+    }
+}
+
+/// Outcome of [`apply_interactively`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplySummary {
+    /// Files that were written (possibly with user edits)
+    pub applied: Vec<PathBuf>,
+    /// Files that were left untouched
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Answer to the per-file prompt of [`apply_interactively`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+    Edit,
+    Help,
+}
+
+impl Answer {
+    fn parse(answer: &str) -> Option<Self> {
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => Some(Self::Yes),
+            "n" | "no" | "" => Some(Self::No),
+            "a" | "all" => Some(Self::All),
+            "q" | "quit" => Some(Self::Quit),
+            "e" | "edit" => Some(Self::Edit),
+            "?" | "h" | "help" => Some(Self::Help),
+            _ => None,
+        }
+    }
+}
+
+const PROMPT_HELP: &str = "y - apply this file
+n - skip this file
+a - apply this and all remaining files
+q - skip this and all remaining files
+e - edit the proposed contents before applying
+? - print help";
+
+/// Show the diff of each changed file and ask whether to apply it, like
+/// `git add -p`.
+///
+/// Per file the user can apply (`y`), skip (`n`), apply all remaining (`a`),
+/// skip all remaining (`q`) or edit the proposed contents in `$VISUAL` /
+/// `$EDITOR` first (`e`). A summary line is printed at the end.
+pub fn apply_interactively(changes: &PatchSet, logger: &mut Logger) -> Result<ApplySummary> {
+    apply_with(
+        changes,
+        logger,
+        |logger, prompt| logger.input(prompt),
+        edit_in_editor,
+    )
+}
+
+/// [`apply_interactively`] with the prompt and editor injected.
+fn apply_with<A, E>(
+    changes: &PatchSet,
+    logger: &mut Logger,
+    mut ask: A,
+    mut edit: E,
+) -> Result<ApplySummary>
+where
+    A: FnMut(&mut Logger, &str) -> String,
+    E: FnMut(&Path, &str) -> Result<String>,
+{
+    let files: Vec<_> = changes
+        .files
+        .iter()
+        .filter(|(_, file)| file.original != file.proposed)
+        .collect();
+    let total = files.len();
+    let mut summary = ApplySummary::default();
+    let mut apply_all = false;
+    let mut quit = false;
+
+    for (index, (path, file)) in files.into_iter().enumerate() {
+        if quit {
+            summary.skipped.push(path.clone());
+            continue;
+        }
+        let mut proposed = file.proposed.clone();
+        let mut show_diff = true;
+        let answer = loop {
+            if apply_all {
+                break Answer::Yes;
+            }
+            if show_diff {
+                let diff = file_diff(path, file.original.as_deref(), proposed.as_deref());
+                logger.print_message(colorize_diff(&diff).trim_end());
+                show_diff = false;
+            }
+            let prompt = format!("({}/{}) Apply this change [y,n,a,q,e,?]?", index + 1, total);
+            match Answer::parse(&ask(logger, &prompt)) {
+                Some(Answer::Help) | None => logger.print_message(PROMPT_HELP),
+                Some(Answer::Edit) => match proposed.as_deref() {
+                    Some(current) => {
+                        proposed = Some(edit(path, current)?);
+                        show_diff = true;
+                    }
+                    None => logger.warning("Warning", "Cannot edit a file that is removed"),
+                },
+                Some(answer) => break answer,
+            }
+        };
+        match answer {
+            Answer::All => apply_all = true,
+            Answer::Quit => quit = true,
+            _ => {}
+        }
+        if matches!(answer, Answer::Yes | Answer::All) && proposed != file.original {
+            write_optional(&changes.root.join(path), proposed.as_deref())?;
+            summary.applied.push(path.clone());
+        } else {
+            summary.skipped.push(path.clone());
+        }
+    }
+
+    logger.status_permanent(
+        "Applied",
+        &format!(
+            "{} of {} changed files ({} skipped)",
+            summary.applied.len(),
+            total,
+            summary.skipped.len()
+        ),
+    );
+    Ok(summary)
+}
+
+/// Color a unified diff like `git diff`.
+fn colorize_diff(diff: &str) -> String {
+    diff.split_inclusive('\n')
+        .map(|line| {
+            let style = if line.starts_with("+++") || line.starts_with("---") {
+                console::Style::new().bold()
+            } else if line.starts_with('+') {
+                console::Style::new().green()
+            } else if line.starts_with('-') {
+                console::Style::new().red()
+            } else if line.starts_with("@@") {
+                console::Style::new().cyan()
+            } else {
+                return line.to_string();
+            };
+            let content = line.trim_end_matches('\n');
+            format!("{}{}", style.apply_to(content), &line[content.len()..])
+        })
+        .collect()
+}
+
+/// Let the user edit `contents` in `$VISUAL` or `$EDITOR` (default `vi`).
+fn edit_in_editor(path: &Path, contents: &str) -> Result<String> {
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("Empty editor command")?;
+
+    // Keep the file name so the editor picks the right syntax
+    let dir = crate::tempdirs::scoped("edit")?;
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    let temp_file = dir.path().join(file_name);
+    std::fs::write(&temp_file, contents)
+        .with_context(|| format!("Failed to write {}", temp_file.display()))?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&temp_file)
+        .status()
+        .with_context(|| format!("Failed to start editor `{}`", editor))?;
+    if !status.success() {
+        anyhow::bail!("Editor `{}` exited with {}", editor, status);
+    }
+    std::fs::read_to_string(&temp_file)
+        .with_context(|| format!("Failed to read {}", temp_file.display()))
+}
+
+/// Render the change of one file as a unified diff.
+fn file_diff(path: &Path, original: Option<&str>, proposed: Option<&str>) -> String {
+    let name = path_for_patch(path);
+    let old_header = match original {
+        Some(_) => format!("a/{}", name),
+        None => "/dev/null".to_string(),
+    };
+    let new_header = match proposed {
+        Some(_) => format!("b/{}", name),
+        None => "/dev/null".to_string(),
+    };
+    let diff =
+        similar::TextDiff::from_lines(original.unwrap_or_default(), proposed.unwrap_or_default());
+    let mut unified = diff.unified_diff();
+    unified
+        .context_radius(CONTEXT_LINES)
+        .header(&old_header, &new_header);
+    unified.to_string()
+}
+
+/// Apply a unified diff (as written by [`PatchSet::write_patch`], or `git
+/// diff`) to the files below `root`.
+///
+/// All hunks are checked against the current file contents before anything
+/// is written, so a patch that doesn't apply leaves the tree untouched.
+/// Patches naming files outside `root` (absolute paths or `..`) are
+/// rejected. Returns the paths (relative to `root`) that were changed.
+pub fn apply_patch(root: impl AsRef<Path>, patch: &str) -> Result<Vec<PathBuf>> {
+    let root = root.as_ref();
+    let mut results = Vec::new();
+    for file in parse_patch(patch)? {
+        for path in file.old_path.iter().chain(&file.new_path) {
+            check_relative(path)?;
+        }
+        let path = file
+            .new_path
+            .as_ref()
+            .or(file.old_path.as_ref())
+            .context("Patch entry without a file name")?
+            .clone();
+        let original = match &file.old_path {
+            Some(old_path) => read_optional(&root.join(old_path))?
+                .with_context(|| format!("Cannot patch missing file {}", old_path.display()))?,
+            None => String::new(),
+        };
+        let patched = apply_hunks(&original, &file.hunks)
+            .with_context(|| format!("Patch does not apply to {}", path.display()))?;
+        results.push((file, path, patched));
+    }
+
+    let mut changed = Vec::new();
+    for (file, path, patched) in results {
+        if let (Some(old_path), Some(new_path)) = (&file.old_path, &file.new_path)
+            && old_path != new_path
+        {
+            write_optional(&root.join(old_path), None)?;
+        }
+        let contents = file.new_path.is_some().then_some(patched.as_str());
+        write_optional(&root.join(&path), contents)?;
+        changed.push(path);
+    }
+    Ok(changed)
+}
+
+/// Fail unless `path` is a plain relative path that stays below the root.
+fn check_relative(path: &Path) -> Result<()> {
+    if path.as_os_str().is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+    {
+        anyhow::bail!("Patch names a file outside the tree: {}", path.display());
+    }
+    Ok(())
+}
+
+/// One file of a unified diff (`None` path: `/dev/null`).
+#[derive(Debug)]
+struct FilePatch {
+    old_path: Option<PathBuf>,
+    new_path: Option<PathBuf>,
+    hunks: Vec<Hunk>,
+}
+
+/// One hunk; lines keep their line endings.
+#[derive(Debug, Default)]
+struct Hunk {
+    /// Index of the first old line
+    old_start: usize,
+    /// Context and removed lines
+    old_lines: Vec<String>,
+    /// Context and added lines
+    new_lines: Vec<String>,
+}
+
+/// Which sides of the hunk the previous line belonged to, for the
+/// `\ No newline at end of file` marker.
+#[derive(Clone, Copy)]
+enum LastLine {
+    Old,
+    New,
+    Both,
+}
+
+/// Parse a unified diff. Hunk bodies are read by the line counts in their
+/// `@@` header, so removed or added lines that look like file headers
+/// (`--- ...`, `+++ ...`) stay in the hunk.
+fn parse_patch(patch: &str) -> Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut last_line = LastLine::Both;
+    // Old and new lines the current hunk still has to read
+    let mut remaining = (0, 0);
+    // Lines keep their own endings, so CRLF files round-trip
+    let mut lines = patch.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        if line.starts_with('\\') {
+            let hunk = files
+                .last_mut()
+                .and_then(|file| file.hunks.last_mut())
+                .context("Unexpected `\\` line in patch")?;
+            let strip = |lines: &mut Vec<String>| {
+                if let Some(last) = lines.last_mut() {
+                    last.pop();
+                }
+            };
+            match last_line {
+                LastLine::Old => strip(&mut hunk.old_lines),
+                LastLine::New => strip(&mut hunk.new_lines),
+                LastLine::Both => {
+                    strip(&mut hunk.old_lines);
+                    strip(&mut hunk.new_lines);
+                }
+            }
+        } else if remaining != (0, 0) {
+            let hunk = files
+                .last_mut()
+                .and_then(|file| file.hunks.last_mut())
+                .context("Hunk line outside a hunk")?;
+            // Some tools strip the space of empty context lines
+            let (kind, text) = match line.chars().next() {
+                Some(kind @ (' ' | '-' | '+')) => (kind, &line[1..]),
+                _ if line.trim_end_matches(['\r', '\n']).is_empty() => (' ', line),
+                _ => anyhow::bail!("Hunk does not match its header: {}", line.trim_end()),
+            };
+            let mut text = text.to_string();
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            match kind {
+                ' ' if remaining.0 > 0 && remaining.1 > 0 => {
+                    hunk.old_lines.push(text.clone());
+                    hunk.new_lines.push(text);
+                    remaining = (remaining.0 - 1, remaining.1 - 1);
+                    last_line = LastLine::Both;
+                }
+                '-' if remaining.0 > 0 => {
+                    hunk.old_lines.push(text);
+                    remaining.0 -= 1;
+                    last_line = LastLine::Old;
+                }
+                '+' if remaining.1 > 0 => {
+                    hunk.new_lines.push(text);
+                    remaining.1 -= 1;
+                    last_line = LastLine::New;
+                }
+                _ => anyhow::bail!("Hunk does not match its header: {}", line.trim_end()),
+            }
+        } else if let Some(old) = line.strip_prefix("--- ") {
+            let new = lines
+                .next()
+                .and_then(|line| line.strip_prefix("+++ "))
+                .context("Expected `+++` after `---` in patch")?;
+            files.push(FilePatch {
+                old_path: parse_path(old, "a/"),
+                new_path: parse_path(new, "b/"),
+                hunks: Vec::new(),
+            });
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            let file = files
+                .last_mut()
+                .context("Hunk before file header in patch")?;
+            let (hunk, old_count, new_count) = parse_hunk_header(header)?;
+            file.hunks.push(hunk);
+            remaining = (old_count, new_count);
+        }
+        // Anything else (e.g. `diff --git` lines) is not part of a hunk
+    }
+    if remaining != (0, 0) {
+        anyhow::bail!("Patch ends inside a hunk");
+    }
+    Ok(files)
+}
+
+/// Parse `-l,s +l,s @@` into an empty hunk and its old and new line counts.
+fn parse_hunk_header(header: &str) -> Result<(Hunk, usize, usize)> {
+    let malformed = || format!("Malformed hunk header: @@ {}", header);
+    let mut ranges = header.split_whitespace();
+    let old_range = ranges
+        .next()
+        .and_then(|range| range.strip_prefix('-'))
+        .with_context(malformed)?;
+    let new_range = ranges
+        .next()
+        .and_then(|range| range.strip_prefix('+'))
+        .with_context(malformed)?;
+    let (start, old_count) = parse_range(old_range)?;
+    let (_, new_count) = parse_range(new_range)?;
+    // Empty ranges name the line *before* the insertion point
+    let old_start = if old_count == 0 {
+        start
+    } else {
+        start.saturating_sub(1)
+    };
+    let hunk = Hunk {
+        old_start,
+        ..Hunk::default()
+    };
+    Ok((hunk, old_count, new_count))
+}
+
+/// Parse a `start,count` hunk range; the count defaults to 1.
+fn parse_range(range: &str) -> Result<(usize, usize)> {
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    let start = start.parse().context("Malformed hunk start")?;
+    let count = count.parse().context("Malformed hunk length")?;
+    Ok((start, count))
+}
+
+/// Parse a `---`/`+++` file name, stripping the `a/`/`b/` prefix.
+fn parse_path(name: &str, prefix: &str) -> Option<PathBuf> {
+    // Drop an optional tab-separated timestamp
+    let name = name.split('\t').next().unwrap_or(name).trim_end();
+    if name == "/dev/null" {
+        return None;
+    }
+    Some(PathBuf::from(name.strip_prefix(prefix).unwrap_or(name)))
+}
+
+/// Apply hunks in order. A hunk whose lines moved is searched for nearby,
+/// after the previous hunk.
+fn apply_hunks(original: &str, hunks: &[Hunk]) -> Result<String> {
+    let lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(original.len());
+    let mut position = 0;
+    for hunk in hunks {
+        let matches_at = |start: usize| {
+            start + hunk.old_lines.len() <= lines.len()
+                && hunk
+                    .old_lines
+                    .iter()
+                    .zip(&lines[start..])
+                    .all(|(expected, actual)| expected == actual)
+        };
+        let expected = hunk.old_start.max(position);
+        let start = (0..=lines.len())
+            .flat_map(|distance| {
+                [
+                    expected.checked_add(distance),
+                    expected.checked_sub(distance),
+                ]
+            })
+            .flatten()
+            .filter(|&start| start >= position && start <= lines.len())
+            .find(|&start| matches_at(start))
+            .with_context(|| format!("Hunk at line {} does not match", hunk.old_start + 1))?;
+        out.extend(lines[position..start].iter().copied());
+        out.extend(hunk.new_lines.iter().map(String::as_str));
+        position = start + hunk.old_lines.len();
+    }
+    out.extend(lines[position..].iter().copied());
+    Ok(out)
+}
+
+/// Use `/` separators in patch file names on all platforms.
+pub(crate) fn path_for_patch(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Write `contents` to `path`, or remove it if `contents` is `None`.
+fn write_optional(path: &Path, contents: Option<&str>) -> Result<()> {
+    match contents {
+        Some(contents) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            std::fs::write(path, contents)
+                .with_context(|| format!("Failed to write {}", path.display()))
+        }
+        None => match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn tree(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (path, contents) in files {
+            write_optional(&dir.path().join(path), Some(contents)).unwrap();
+        }
+        dir
+    }
+
+    fn read(dir: &TempDir, path: &str) -> Option<String> {
+        read_optional(&dir.path().join(path)).unwrap()
+    }
+
+    const MANIFEST: &str = "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nserde = \"1\"\n";
+
+    #[test]
+    fn test_patch_set_accumulates_edits() {
+        let dir = tree(&[("Cargo.toml", MANIFEST)]);
+        let mut patch = PatchSet::new(dir.path());
+        patch
+            .edit("Cargo.toml", |manifest| manifest.replace("0.1.0", "0.2.0"))
+            .unwrap();
+        patch
+            .edit("Cargo.toml", |manifest| {
+                manifest.replace("\"1\"", "\"1.0.200\"")
+            })
+            .unwrap();
+        let current = patch.read("Cargo.toml").unwrap().unwrap();
+        assert!(current.contains("0.2.0") && current.contains("1.0.200"));
+        assert!(patch.edit("missing.toml", |text| text.to_string()).is_err());
+        // Nothing is written until applied
+        assert_eq!(read(&dir, "Cargo.toml").unwrap(), MANIFEST);
+        assert_eq!(patch.changed_files(), vec![Path::new("Cargo.toml")]);
+    }
+
+    #[test]
+    fn test_patch_set_to_patch() {
+        let dir = tree(&[("Cargo.toml", MANIFEST), ("old.txt", "bye\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        patch
+            .edit("Cargo.toml", |manifest| manifest.replace("0.1.0", "0.2.0"))
+            .unwrap();
+        patch.write("docs/new.md", "hello\n").unwrap();
+        patch.remove("old.txt").unwrap();
+        patch.write("unchanged.txt", "x").unwrap();
+        patch.remove("unchanged.txt").unwrap();
+
+        let text = patch.to_patch();
+        assert!(text.contains("--- a/Cargo.toml\n+++ b/Cargo.toml\n@@ -1,6 +1,6 @@\n"));
+        assert!(text.contains("-version = \"0.1.0\"\n+version = \"0.2.0\"\n"));
+        assert!(text.contains("--- /dev/null\n+++ b/docs/new.md\n@@ -0,0 +1 @@\n+hello\n"));
+        assert!(text.contains("--- a/old.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-bye\n"));
+        assert!(!text.contains("unchanged"));
+    }
+
+    #[test]
+    fn test_patch_set_apply() {
+        let dir = tree(&[("Cargo.toml", MANIFEST), ("old.txt", "bye\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        patch
+            .edit("Cargo.toml", |manifest| manifest.replace("0.1.0", "0.2.0"))
+            .unwrap();
+        patch.write("docs/new.md", "hello\n").unwrap();
+        patch.remove("old.txt").unwrap();
+        let changed = patch.apply().unwrap();
+        assert_eq!(changed.len(), 3);
+        assert!(read(&dir, "Cargo.toml").unwrap().contains("0.2.0"));
+        assert_eq!(read(&dir, "docs/new.md").unwrap(), "hello\n");
+        assert_eq!(read(&dir, "old.txt"), None);
+    }
+
+    #[test]
+    fn test_apply_with_answers() {
+        let dir = tree(&[("a.txt", "a\n"), ("b.txt", "b\n"), ("c.txt", "c\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        for (path, contents) in [("a.txt", "A\n"), ("b.txt", "B\n"), ("c.txt", "C\n")] {
+            patch.write(path, contents).unwrap();
+        }
+        patch.write("d.txt", "D\n").unwrap();
+
+        // Unknown answers print help and ask again; the edit result is shown
+        // and applied when accepted
+        let mut answers = ["maybe", "y", "n", "e", "y", "q"].into_iter();
+        let mut logger = Logger::new();
+        let summary = apply_with(
+            &patch,
+            &mut logger,
+            |_, _| answers.next().unwrap().to_string(),
+            |path, contents| {
+                assert_eq!(path, Path::new("c.txt"));
+                Ok(format!("edited {}", contents))
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            summary.applied,
+            vec![PathBuf::from("a.txt"), PathBuf::from("c.txt")]
+        );
+        assert_eq!(
+            summary.skipped,
+            vec![PathBuf::from("b.txt"), PathBuf::from("d.txt")]
+        );
+        assert_eq!(read(&dir, "a.txt").unwrap(), "A\n");
+        assert_eq!(read(&dir, "b.txt").unwrap(), "b\n");
+        assert_eq!(read(&dir, "c.txt").unwrap(), "edited C\n");
+        assert_eq!(read(&dir, "d.txt"), None);
+        assert_eq!(answers.next(), None);
+    }
+
+    #[test]
+    fn test_apply_with_all() {
+        let dir = tree(&[("a.txt", "a\n"), ("b.txt", "b\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        patch.write("a.txt", "A\n").unwrap();
+        patch.remove("b.txt").unwrap();
+
+        let mut asked = 0;
+        let mut logger = Logger::new();
+        let summary = apply_with(
+            &patch,
+            &mut logger,
+            |_, _| {
+                asked += 1;
+                "a".to_string()
+            },
+            |_, _| unreachable!(),
+        )
+        .unwrap();
+
+        assert_eq!(asked, 1);
+        assert_eq!(summary.applied.len(), 2);
+        assert_eq!(read(&dir, "b.txt"), None);
+    }
+
+    #[test]
+    fn test_colorize_diff_keeps_text() {
+        let diff = "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-old\n+new\n";
+        assert_eq!(console::strip_ansi_codes(&colorize_diff(diff)), diff);
+    }
+
+    #[test]
+    fn test_apply_patch_roundtrip() {
+        let files = [
+            ("Cargo.toml", MANIFEST),
+            ("old.txt", "bye\n"),
+            ("no-newline.txt", "a\nb"),
+        ];
+        let dir = tree(&files);
+        let mut patch = PatchSet::new(dir.path());
+        patch
+            .edit("Cargo.toml", |manifest| {
+                manifest
+                    .replace("0.1.0", "0.2.0")
+                    .replace("serde = \"1\"\n", "serde = \"1\"\ntokio = \"1\"\n")
+            })
+            .unwrap();
+        patch.write("docs/new.md", "hello\n").unwrap();
+        patch.remove("old.txt").unwrap();
+        patch
+            .edit("no-newline.txt", |_| "a\nc".to_string())
+            .unwrap();
+        let text = patch.to_patch();
+
+        // Apply the generated patch to a fresh copy of the tree
+        let target = tree(&files);
+        let mut changed = apply_patch(target.path(), &text).unwrap();
+        changed.sort();
+        assert_eq!(changed.len(), 4);
+        for path in ["Cargo.toml", "docs/new.md", "old.txt", "no-newline.txt"] {
+            assert_eq!(read(&target, path), patch.read(path).unwrap(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_apply_patch_crlf_roundtrip() {
+        let files = [("a.txt", "one\r\ntwo\r\nthree\r\n")];
+        let dir = tree(&files);
+        let mut patch = PatchSet::new(dir.path());
+        patch.write("a.txt", "one\r\nTWO\r\nthree\r\n").unwrap();
+        let text = patch.to_patch();
+
+        let target = tree(&files);
+        apply_patch(target.path(), &text).unwrap();
+        assert_eq!(read(&target, "a.txt").unwrap(), "one\r\nTWO\r\nthree\r\n");
+    }
+
+    #[test]
+    fn test_apply_patch_with_offset() {
+        let dir = tree(&[("file.txt", "one\ntwo\nthree\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        patch.write("file.txt", "one\ntwo\nTHREE\n").unwrap();
+        let text = patch.to_patch();
+
+        // Lines were added above the hunk since the patch was generated
+        let target = tree(&[("file.txt", "zero\nzero\none\ntwo\nthree\n")]);
+        apply_patch(target.path(), &text).unwrap();
+        assert_eq!(
+            read(&target, "file.txt").unwrap(),
+            "zero\nzero\none\ntwo\nTHREE\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_mismatch_leaves_tree_untouched() {
+        let dir = tree(&[("a.txt", "a\n"), ("b.txt", "b\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        patch.write("a.txt", "A\n").unwrap();
+        patch.write("b.txt", "B\n").unwrap();
+        let text = patch.to_patch();
+
+        let target = tree(&[("a.txt", "a\n"), ("b.txt", "something else\n")]);
+        assert!(apply_patch(target.path(), &text).is_err());
+        assert_eq!(read(&target, "a.txt").unwrap(), "a\n");
+    }
+
+    #[test]
+    fn test_apply_patch_header_like_lines() {
+        // A removed `-- x` line and an added `++ y` line look like file headers
+        let dir = tree(&[("notes.md", "-- x\nkeep\n")]);
+        let mut patch = PatchSet::new(dir.path());
+        patch.write("notes.md", "++ y\nkeep\n").unwrap();
+        let text = patch.to_patch();
+        assert!(text.contains("\n--- x\n+++ y\n"), "{}", text);
+
+        let target = tree(&[("notes.md", "-- x\nkeep\n")]);
+        assert_eq!(
+            apply_patch(target.path(), &text).unwrap(),
+            vec![PathBuf::from("notes.md")]
+        );
+        assert_eq!(read(&target, "notes.md").unwrap(), "++ y\nkeep\n");
+    }
+
+    #[test]
+    fn test_apply_patch_rejects_paths_outside_root() {
+        let dir = tree(&[("a.txt", "a\n")]);
+        for name in ["../escape.txt", "/tmp/escape.txt", "docs/../../escape.txt"] {
+            let text = format!("--- /dev/null\n+++ {}\n@@ -0,0 +1 @@\n+x\n", name);
+            let err = apply_patch(dir.path(), &text).unwrap_err();
+            assert!(err.to_string().contains("outside the tree"), "{}", err);
+        }
+        assert!(!dir.path().parent().unwrap().join("escape.txt").exists());
+    }
+}
@@ -0,0 +1,298 @@
+//! Run a fixed sequence of commands, like a CI script.
+//!
+//! A [`Pipeline`] runs its steps one after another through the [`Logger`],
+//! stopping at the first failing step unless
+//! [`keep_going`](Pipeline::keep_going) is set, and ends with a summary
+//! table of every step's status and duration:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::pipeline::Pipeline;
+//! use portable_pty::CommandBuilder;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let cargo = |args: &[&str]| {
+//!     let mut cmd = CommandBuilder::new("cargo");
+//!     cmd.args(args);
+//!     cmd
+//! };
+//! let mut logger = Logger::new();
+//! let report = Pipeline::new()
+//!     .step("fmt", cargo(&["fmt", "--check"]))
+//!     .step("clippy", cargo(&["clippy", "--", "-D", "warnings"]))
+//!     .step("test", cargo(&["test"]))
+//!     .run(&mut logger)
+//!     .await;
+//! anyhow::ensure!(report.success(), "CI failed");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use portable_pty::CommandBuilder;
+
+use crate::human::Locale;
+use crate::logger::{
+    Logger,
+    RunOptions,
+    SubprocessOutput,
+    run_subprocess_with_options,
+};
+use crate::parallel::Job;
+use crate::table::{
+    Align,
+    Table,
+};
+
+/// A sequence of named commands.
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    steps: Vec<Job>,
+    keep_going: bool,
+}
+
+impl Pipeline {
+    /// An empty pipeline that stops at the first failure.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a step running `command` with default options.
+    pub fn step(self, name: impl Into<String>, command: CommandBuilder) -> Self {
+        self.step_with_options(name, command, RunOptions::default())
+    }
+
+    /// Append a step running `command` with `options`.
+    pub fn step_with_options(
+        mut self,
+        name: impl Into<String>,
+        command: CommandBuilder,
+        options: RunOptions,
+    ) -> Self {
+        self.steps.push(Job::new(name, command).options(options));
+        self
+    }
+
+    /// Run the remaining steps after a failure instead of skipping them,
+    /// e.g. for `--keep-going`.
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Run the steps in order and print the summary table.
+    pub async fn run(self, logger: &mut Logger) -> PipelineReport {
+        let total = self.steps.len();
+        let mut report = PipelineReport { steps: Vec::new() };
+        for (index, job) in self.steps.into_iter().enumerate() {
+            if !self.keep_going && !report.success() {
+                report.steps.push(StepResult {
+                    name: job.label,
+                    status: StepStatus::Skipped,
+                    duration: Duration::ZERO,
+                    output: None,
+                });
+                continue;
+            }
+            logger.step(index + 1, total, "Running", &job.label);
+            let result = run_step(logger, job).await;
+            logger.clear_status();
+            result.print(logger);
+            report.steps.push(result);
+        }
+        report.print(logger);
+        report
+    }
+}
+
+async fn run_step(logger: &mut Logger, job: Job) -> StepResult {
+    let started = Instant::now();
+    let result = run_subprocess_with_options(logger, || job.command, &job.options).await;
+    let (status, output) = match result {
+        Ok(output) if output.success() => (StepStatus::Passed, Some(output)),
+        Ok(output) => (
+            StepStatus::Failed {
+                exit_code: output.exit_code(),
+            },
+            Some(output),
+        ),
+        Err(err) => (StepStatus::Error(format!("{:#}", err)), None),
+    };
+    StepResult {
+        name: job.label,
+        status,
+        duration: started.elapsed(),
+        output,
+    }
+}
+
+/// Outcome of one step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    /// The command exited successfully
+    Passed,
+    /// The command exited with a non-zero code
+    Failed {
+        /// Exit code of the command
+        exit_code: u32,
+    },
+    /// The command couldn't be run, or timed out or was cancelled
+    Error(String),
+    /// Not run because an earlier step failed
+    Skipped,
+}
+
+impl StepStatus {
+    /// Whether the step passed.
+    pub fn is_success(&self) -> bool {
+        *self == Self::Passed
+    }
+
+    /// Whether the step failed or couldn't be run.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Failed { .. } | Self::Error(_))
+    }
+
+    pub(crate) fn styled(&self) -> String {
+        match self {
+            Self::Passed => console::style("ok").green().to_string(),
+            Self::Failed { .. } | Self::Error(_) => console::style("FAILED").red().to_string(),
+            Self::Skipped => console::style("skipped").dim().to_string(),
+        }
+    }
+}
+
+/// Result of one step.
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    /// Name the step was registered with
+    pub name: String,
+    /// How the step ended
+    pub status: StepStatus,
+    /// Wall-clock time the step took
+    pub duration: Duration,
+    /// Captured output, if the command ran
+    pub output: Option<SubprocessOutput>,
+}
+
+impl StepResult {
+    fn print(&self, logger: &Logger) {
+        let duration = Locale::POSIX.duration(self.duration);
+        match &self.status {
+            StepStatus::Passed => {
+                logger.status_permanent("Finished", &format!("{} in {}", self.name, duration));
+            }
+            StepStatus::Failed { exit_code } => {
+                let status = self.output.as_ref().map_or_else(
+                    || format!("exit code {}", exit_code),
+                    |output| output.status().describe(),
+                );
+                logger.error(
+                    "Failed",
+                    &format!("{} ({}) in {}", self.name, status, duration),
+                );
+            }
+            StepStatus::Error(message) => {
+                logger.error("Failed", &format!("{}: {}", self.name, message));
+            }
+            StepStatus::Skipped => {}
+        }
+    }
+}
+
+/// Results of a [`Pipeline`] run, in step order.
+#[derive(Debug, Clone)]
+pub struct PipelineReport {
+    /// One result per registered step
+    pub steps: Vec<StepResult>,
+}
+
+impl PipelineReport {
+    /// Whether no step failed.
+    pub fn success(&self) -> bool {
+        !self.steps.iter().any(|step| step.status.is_failure())
+    }
+
+    /// The first step that failed.
+    pub fn first_failure(&self) -> Option<&StepResult> {
+        self.steps.iter().find(|step| step.status.is_failure())
+    }
+
+    /// Step, status and duration of every step.
+    pub fn table(&self) -> Table {
+        let mut table = Table::new(["Step", "Status", "Duration"]).align(2, Align::Right);
+        for step in &self.steps {
+            let duration = match step.status {
+                StepStatus::Skipped => String::new(),
+                _ => Locale::POSIX.duration(step.duration),
+            };
+            table.add_row([step.name.clone(), step.status.styled(), duration]);
+        }
+        table
+    }
+
+    fn print(&self, logger: &Logger) {
+        if self.steps.is_empty() {
+            return;
+        }
+        let width = usize::from(crate::resize::current().cols);
+        logger.print_message(&self.table().render(width));
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    fn pipeline() -> Pipeline {
+        Pipeline::new()
+            .step("first", sh("exit 0"))
+            .step("broken", sh("exit 3"))
+            .step_with_options("last", sh("echo done"), RunOptions::new().piped(true))
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_stops_at_first_failure() {
+        let mut logger = Logger::new();
+        let report = pipeline().run(&mut logger).await;
+        assert!(!report.success());
+        let statuses: Vec<_> = report.steps.iter().map(|step| &step.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                &StepStatus::Passed,
+                &StepStatus::Failed { exit_code: 3 },
+                &StepStatus::Skipped
+            ]
+        );
+        assert_eq!(report.first_failure().unwrap().name, "broken");
+        assert!(report.steps[2].output.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_keep_going() {
+        let mut logger = Logger::new();
+        let report = pipeline().keep_going(true).run(&mut logger).await;
+        assert!(!report.success());
+        assert!(report.steps[2].status.is_success());
+        let output = report.steps[2].output.as_ref().unwrap();
+        assert_eq!(output.stdout_str().unwrap(), "done\n");
+
+        let table = console::strip_ansi_codes(&report.table().render(80)).into_owned();
+        let lines: Vec<_> = table.lines().collect();
+        assert!(lines[0].starts_with("Step"));
+        assert!(lines[1].starts_with("first   ok"));
+        assert!(lines[2].starts_with("broken  FAILED"));
+    }
+}
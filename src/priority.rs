@@ -0,0 +1,242 @@
+//! CPU and IO priority control for spawned subprocesses.
+//!
+//! Background-ish plugin work (doc generation, coverage, large builds) can be
+//! started at a lower priority so it doesn't starve the user's interactive
+//! work. On Unix this maps to the nice value (and the IO scheduling class on
+//! Linux); on Windows to the process priority class.
+
+/// A freshly spawned child, see [`Priority::spawn`].
+pub(crate) trait Spawned: Send {
+    /// The child, to apply the priority to.
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    fn child(&self) -> &(dyn portable_pty::Child + Send + Sync);
+}
+
+impl Spawned for Box<dyn portable_pty::Child + Send + Sync> {
+    fn child(&self) -> &(dyn portable_pty::Child + Send + Sync) {
+        self.as_ref()
+    }
+}
+
+impl Spawned for std::process::Child {
+    fn child(&self) -> &(dyn portable_pty::Child + Send + Sync) {
+        self
+    }
+}
+
+/// Scheduling priority for a spawned subprocess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Inherit the priority of the current process
+    #[default]
+    Normal,
+    /// Lower priority: nice 10 and lowest best-effort IO priority
+    /// (`BELOW_NORMAL_PRIORITY_CLASS` on Windows)
+    Low,
+    /// Only run when the system is otherwise idle: nice 19 and the idle IO
+    /// class (`IDLE_PRIORITY_CLASS` on Windows)
+    Idle,
+    /// Higher priority: nice -5 and highest best-effort IO priority
+    /// (`ABOVE_NORMAL_PRIORITY_CLASS` on Windows). Usually requires elevated
+    /// privileges on Unix.
+    High,
+    /// Explicit nice value from -20 (highest) to 19 (lowest); mapped to the
+    /// closest priority class on Windows
+    Nice(i8),
+}
+
+impl Priority {
+    /// The Unix nice value for this priority, or `None` for
+    /// [`Priority::Normal`].
+    pub fn nice_value(self) -> Option<i32> {
+        match self {
+            Self::Normal => None,
+            Self::Low => Some(10),
+            Self::Idle => Some(19),
+            Self::High => Some(-5),
+            Self::Nice(nice) => Some(i32::from(nice).clamp(-20, 19)),
+        }
+    }
+
+    /// Spawn a child with `spawn`, at this priority.
+    ///
+    /// On Linux the nice value and IO priority are per thread and inherited
+    /// by children, so `spawn` runs on a short-lived thread that takes the
+    /// priority first, and the child starts with it. Elsewhere the priority
+    /// is [applied](Self::apply) right after the spawn. The error is why the
+    /// priority couldn't be applied; the child runs at normal priority then.
+    pub(crate) fn spawn<T, F>(self, spawn: F) -> (anyhow::Result<T>, Option<anyhow::Error>)
+    where
+        T: Spawned,
+        F: FnOnce() -> anyhow::Result<T> + Send,
+    {
+        if self == Self::Normal {
+            return (spawn(), None);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            std::thread::scope(|scope| {
+                scope
+                    .spawn(move || {
+                        let applied = self.apply_to_thread().err();
+                        (spawn(), applied)
+                    })
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let child = spawn();
+            let applied = match &child {
+                Ok(child) => self.apply(child.child()).err(),
+                Err(_) => None,
+            };
+            (child, applied)
+        }
+    }
+
+    /// Apply the priority to a freshly spawned child.
+    ///
+    /// PTY children are session leaders, so on Unix the whole process group
+    /// is adjusted, including any processes the child has already started.
+    /// Processes started later inherit the new priority.
+    #[cfg(not(target_os = "linux"))]
+    fn apply(self, child: &(dyn portable_pty::Child + Send + Sync)) -> anyhow::Result<()> {
+        if self == Self::Normal {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        {
+            let pid = child
+                .process_id()
+                .ok_or_else(|| anyhow::anyhow!("Child has no process id"))?;
+            self.apply_unix(pid)
+        }
+        #[cfg(windows)]
+        {
+            let handle = child
+                .as_raw_handle()
+                .ok_or_else(|| anyhow::anyhow!("Child has no process handle"))?;
+            self.apply_windows(handle)
+        }
+    }
+
+    /// Apply the priority to the calling thread, which its children
+    /// inherit.
+    #[cfg(target_os = "linux")]
+    fn apply_to_thread(self) -> anyhow::Result<()> {
+        const WHO_PROCESS: libc::c_int = 1;
+
+        if let Some(nice) = self.nice_value() {
+            // SAFETY: setpriority has no memory safety preconditions
+            let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+            if result != 0 {
+                anyhow::bail!(
+                    "Failed to set nice value {}: {}",
+                    nice,
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+        if let Some(ioprio) = self.io_priority() {
+            // SAFETY: ioprio_set only takes integer arguments
+            let result = unsafe { libc::syscall(libc::SYS_ioprio_set, WHO_PROCESS, 0, ioprio) };
+            if result != 0 {
+                anyhow::bail!(
+                    "Failed to set IO priority: {}",
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn apply_unix(self, pid: u32) -> anyhow::Result<()> {
+        if let Some(nice) = self.nice_value() {
+            // SAFETY: setpriority has no memory safety preconditions
+            let result = unsafe { libc::setpriority(libc::PRIO_PGRP, pid as libc::id_t, nice) };
+            if result != 0 {
+                anyhow::bail!(
+                    "Failed to set nice value {}: {}",
+                    nice,
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Linux IO scheduling class and level, encoded for `ioprio_set`.
+    #[cfg(target_os = "linux")]
+    fn io_priority(self) -> Option<libc::c_int> {
+        const CLASS_SHIFT: libc::c_int = 13;
+        const CLASS_BEST_EFFORT: libc::c_int = 2;
+        const CLASS_IDLE: libc::c_int = 3;
+
+        let (class, level) = match self {
+            Self::Normal => return None,
+            Self::Low => (CLASS_BEST_EFFORT, 7),
+            Self::Idle => (CLASS_IDLE, 0),
+            Self::High => (CLASS_BEST_EFFORT, 0),
+            // Same mapping the kernel uses for processes without an explicit
+            // IO priority: (nice + 20) / 5
+            Self::Nice(_) => (CLASS_BEST_EFFORT, (self.nice_value()? + 20) / 5),
+        };
+        Some((class << CLASS_SHIFT) | level)
+    }
+
+    #[cfg(windows)]
+    fn apply_windows(self, handle: std::os::windows::io::RawHandle) -> anyhow::Result<()> {
+        use windows_sys::Win32::System::Threading::{
+            ABOVE_NORMAL_PRIORITY_CLASS,
+            BELOW_NORMAL_PRIORITY_CLASS,
+            HIGH_PRIORITY_CLASS,
+            IDLE_PRIORITY_CLASS,
+            NORMAL_PRIORITY_CLASS,
+            SetPriorityClass,
+        };
+
+        let class = match self.nice_value() {
+            None => return Ok(()),
+            Some(nice) if nice >= 15 => IDLE_PRIORITY_CLASS,
+            Some(nice) if nice >= 5 => BELOW_NORMAL_PRIORITY_CLASS,
+            Some(nice) if nice > -5 => NORMAL_PRIORITY_CLASS,
+            Some(nice) if nice > -15 => ABOVE_NORMAL_PRIORITY_CLASS,
+            Some(_) => HIGH_PRIORITY_CLASS,
+        };
+        // SAFETY: the handle belongs to the live child process
+        if unsafe { SetPriorityClass(handle, class) } == 0 {
+            anyhow::bail!(
+                "Failed to set priority class: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_nice_values() {
+        assert_eq!(Priority::Normal.nice_value(), None);
+        assert_eq!(Priority::Low.nice_value(), Some(10));
+        assert_eq!(Priority::Idle.nice_value(), Some(19));
+        assert_eq!(Priority::High.nice_value(), Some(-5));
+        assert_eq!(Priority::Nice(42).nice_value(), Some(19));
+        assert_eq!(Priority::Nice(-100).nice_value(), Some(-20));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_priority_io_classes() {
+        assert_eq!(Priority::Normal.io_priority(), None);
+        assert_eq!(Priority::Low.io_priority(), Some((2 << 13) | 7));
+        assert_eq!(Priority::Idle.io_priority(), Some(3 << 13));
+        assert_eq!(Priority::Nice(0).io_priority(), Some((2 << 13) | 4));
+    }
+}
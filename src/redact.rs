@@ -0,0 +1,212 @@
+//! Masking of secrets in everything the library prints or captures.
+//!
+//! Secrets registered here are replaced by `***` in the lines of every
+//! [`Logger`](crate::Logger), in the live output window of the
+//! `run_subprocess*` functions, in the captured
+//! [`SubprocessOutput`](crate::SubprocessOutput), in transcripts and in
+//! crash bundles:
+//!
+//! ```
+//! use cargo_plugin_utils::redact;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! redact::add_secret("ghp_0123456789abcdef");
+//! redact::add_pattern(r"glpat-[0-9A-Za-z_-]{20}")?;
+//! assert_eq!(redact::redact("token ghp_0123456789abcdef"), "token ***");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Registrations are process-wide and can't be undone. A run picks up the
+//! patterns registered before it starts. While any are registered, child
+//! output is passed on a line (or `\r`-terminated progress update) at a
+//! time, so a secret split across reads is still caught.
+
+use std::borrow::Cow;
+use std::sync::{
+    Arc,
+    RwLock,
+};
+
+use anyhow::Context;
+use regex::bytes::Regex;
+
+/// Replacement for every match.
+const MASK: &[u8] = b"***";
+
+/// Longest partial line held back while waiting for its end.
+const MAX_PENDING: usize = 64 * 1024;
+
+static PATTERNS: RwLock<Vec<Arc<Regex>>> = RwLock::new(Vec::new());
+
+/// Mask every occurrence of `secret`. Empty strings are ignored.
+pub fn add_secret(secret: &str) {
+    if let Some(regex) = secret_regex(secret) {
+        push(regex);
+    }
+}
+
+/// Mask every match of the regular expression `pattern`.
+pub fn add_pattern(pattern: &str) -> anyhow::Result<()> {
+    push(pattern_regex(pattern)?);
+    Ok(())
+}
+
+/// The regex matching `secret` literally, `None` for an empty secret.
+fn secret_regex(secret: &str) -> Option<Regex> {
+    (!secret.is_empty())
+        .then(|| Regex::new(&regex::escape(secret)).expect("escaped literal is a valid regex"))
+}
+
+fn pattern_regex(pattern: &str) -> anyhow::Result<Regex> {
+    Regex::new(pattern).with_context(|| format!("Invalid redaction pattern `{}`", pattern))
+}
+
+/// Mask the values of environment variables whose names suggest
+/// credentials (`*TOKEN*`, `*SECRET*`, `*PASSWORD*`, `*KEY*`,
+/// `*CREDENTIAL*`), such as `GITHUB_TOKEN` or `CARGO_REGISTRY_TOKEN`.
+///
+/// Values shorter than 4 characters are skipped, they would mask too much.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+pub fn add_secrets_from_env() {
+    for (key, value) in std::env::vars() {
+        if crate::crash::is_secret_name(&key) && value.len() >= 4 {
+            add_secret(&value);
+        }
+    }
+}
+
+fn push(regex: Regex) {
+    PATTERNS
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(Arc::new(regex));
+}
+
+/// The patterns registered so far.
+fn patterns() -> Vec<Arc<Regex>> {
+    PATTERNS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+/// Whether any secret or pattern is registered.
+pub fn is_active() -> bool {
+    !PATTERNS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .is_empty()
+}
+
+/// Mask the registered secrets in `text`.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    match redact_bytes(text.as_bytes()) {
+        Cow::Borrowed(_) => Cow::Borrowed(text),
+        Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+    }
+}
+
+/// Mask the registered secrets in `bytes`.
+pub fn redact_bytes(bytes: &[u8]) -> Cow<'_, [u8]> {
+    apply(&patterns(), bytes)
+}
+
+fn apply<'a>(patterns: &[Arc<Regex>], bytes: &'a [u8]) -> Cow<'a, [u8]> {
+    let mut text = Cow::Borrowed(bytes);
+    for pattern in patterns {
+        if let Cow::Owned(replaced) = pattern.replace_all(&text, MASK) {
+            text = Cow::Owned(replaced);
+        }
+    }
+    text
+}
+
+/// Masks secrets in a stream that arrives in arbitrary chunks.
+///
+/// Holds back the last incomplete line until its end arrives, so a secret
+/// split between two chunks is still masked. Without registered patterns
+/// chunks pass through unchanged.
+pub(crate) struct RedactStream {
+    patterns: Vec<Arc<Regex>>,
+    pending: Vec<u8>,
+}
+
+impl RedactStream {
+    /// Start a stream with the patterns registered now.
+    pub(crate) fn new() -> Self {
+        Self::with_patterns(patterns())
+    }
+
+    /// Start a stream masking `patterns`.
+    pub(crate) fn with_patterns(patterns: Vec<Arc<Regex>>) -> Self {
+        Self {
+            patterns,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Take a chunk and return what can be passed on, masked.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        if self.patterns.is_empty() {
+            return chunk.to_vec();
+        }
+        self.pending.extend_from_slice(chunk);
+        let complete = match self.pending.iter().rposition(|&b| b == b'\n' || b == b'\r') {
+            Some(end) => end + 1,
+            None if self.pending.len() > MAX_PENDING => self.pending.len(),
+            None => return Vec::new(),
+        };
+        let rest = self.pending.split_off(complete);
+        let lines = std::mem::replace(&mut self.pending, rest);
+        apply(&self.patterns, &lines).into_owned()
+    }
+
+    /// Return the held-back rest of the stream, masked.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let rest = std::mem::take(&mut self.pending);
+        apply(&self.patterns, &rest).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_stream() {
+        let patterns = vec![Arc::new(secret_regex("s3cr3t").unwrap())];
+        let mut stream = RedactStream::with_patterns(patterns);
+        assert_eq!(stream.push(b"token s3"), b"");
+        assert_eq!(stream.push(b"cr3t\nnext s3c"), b"token ***\n");
+        assert_eq!(stream.push(b"r3t\rdone"), b"next ***\r");
+        assert_eq!(stream.finish(), b"done");
+    }
+
+    #[test]
+    fn test_redact_stream_without_patterns() {
+        let mut stream = RedactStream::with_patterns(Vec::new());
+        assert_eq!(stream.push(b"partial"), b"partial");
+        assert_eq!(stream.finish(), b"");
+    }
+
+    // Registrations are process-wide and permanent, so tests use local
+    // pattern lists instead
+    #[test]
+    fn test_apply() {
+        let patterns = vec![
+            Arc::new(secret_regex("tok-9f8e7d6c").unwrap()),
+            Arc::new(pattern_regex(r"pw=\w+").unwrap()),
+        ];
+        assert!(secret_regex("").is_none());
+        assert!(pattern_regex("(").is_err());
+        assert_eq!(
+            apply(&patterns, b"a tok-9f8e7d6c b pw=xyz"),
+            &b"a *** b ***"[..]
+        );
+        assert!(matches!(
+            apply(&patterns, b"nothing here"),
+            Cow::Borrowed(_)
+        ));
+    }
+}
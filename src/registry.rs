@@ -0,0 +1,733 @@
+//! crates.io and the alternative registries configured for cargo.
+//!
+//! A [`Registry`] is resolved the way cargo resolves `--registry`: from the
+//! `registries.<name>.index` setting of the `.cargo/config.toml` files above
+//! the current directory and in `$CARGO_HOME`, or from
+//! `CARGO_REGISTRIES_<NAME>_INDEX`. It can list the published versions of a
+//! crate from its sparse index and find a token for private registries:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::registry::Registry;
+//!
+//! let registry = Registry::named("internal")?;
+//! let versions = registry.versions("billing-core")?;
+//! // `--registry internal` for cargo commands
+//! let args = registry.cargo_args();
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Tokens come from `CARGO_REGISTRIES_<NAME>_TOKEN` (`CARGO_REGISTRY_TOKEN`
+//! for crates.io), then from the configured credential providers: the
+//! built-in `cargo:token` reads `credentials.toml`, and external providers
+//! are asked with cargo's credential provider protocol. The other built-in
+//! providers (`cargo:libsecret`, `cargo:wincred`, ...) live inside cargo and
+//! are skipped; cargo itself still uses them when it is run with
+//! `--registry`.
+
+use std::io::{
+    BufRead,
+    BufReader,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::{
+    ChildStdin,
+    ChildStdout,
+    Stdio,
+};
+use std::time::Duration;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use cargo_metadata::semver::Version;
+
+/// The index of crates.io.
+pub const CRATES_IO_INDEX: &str = "sparse+https://index.crates.io/";
+
+/// How crates.io dependencies are identified in `cargo metadata`, whatever
+/// protocol was used to fetch them.
+const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// How long a credential provider may take to answer, long enough to unlock
+/// a password manager.
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A package registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registry {
+    name: Option<String>,
+    index: String,
+}
+
+impl Registry {
+    /// crates.io.
+    pub fn crates_io() -> Self {
+        Self {
+            name: None,
+            index: CRATES_IO_INDEX.to_string(),
+        }
+    }
+
+    /// The registry cargo calls `name` (`crates-io` is crates.io).
+    pub fn named(name: &str) -> Result<Self> {
+        if name == "crates-io" {
+            return Ok(Self::crates_io());
+        }
+        let index = env_setting(&format!("CARGO_REGISTRIES_{}_INDEX", env_key(name)))
+            .or_else(|| {
+                CargoConfig::load()
+                    .string(&["registries", name, "index"])
+                    .map(str::to_string)
+            })
+            .with_context(|| {
+                format!(
+                    "No registry `{}`: set registries.{}.index in .cargo/config.toml",
+                    name, name
+                )
+            })?;
+        Ok(Self {
+            name: Some(name.to_string()),
+            index,
+        })
+    }
+
+    /// The registry of `registry.default` (`CARGO_REGISTRY_DEFAULT`), which
+    /// cargo publishes to without `--registry`; crates.io if unset.
+    pub fn default_registry() -> Result<Self> {
+        let name = env_setting("CARGO_REGISTRY_DEFAULT").or_else(|| {
+            CargoConfig::load()
+                .string(&["registry", "default"])
+                .map(str::to_string)
+        });
+        match name {
+            Some(name) => Self::named(&name),
+            None => Ok(Self::crates_io()),
+        }
+    }
+
+    /// The registry a dependency comes from, by its `cargo metadata` source,
+    /// or `None` for path and git dependencies. The name is looked up in the
+    /// configuration, and left out if no configured registry has this index.
+    pub fn from_source(source: &cargo_metadata::Source) -> Option<Self> {
+        let repr = source.repr.as_str();
+        if repr == CRATES_IO_SOURCE {
+            return Some(Self::crates_io());
+        }
+        let index = if let Some(url) = repr.strip_prefix("registry+") {
+            url.to_string()
+        } else if repr.starts_with("sparse+") {
+            repr.to_string()
+        } else {
+            return None;
+        };
+        let name = CargoConfig::load().registry_with_index(&index);
+        Some(Self { name, index })
+    }
+
+    /// The name cargo knows this registry by; `None` for crates.io and for
+    /// registries that are not configured.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The index URL, with a `sparse+` prefix for sparse indexes.
+    pub fn index(&self) -> &str {
+        &self.index
+    }
+
+    /// Whether this is crates.io.
+    pub fn is_crates_io(&self) -> bool {
+        self.index == CRATES_IO_INDEX
+    }
+
+    /// Whether the registry has a sparse index, which
+    /// [`versions`](Self::versions) can query.
+    pub fn is_sparse(&self) -> bool {
+        self.index.starts_with("sparse+")
+    }
+
+    /// The arguments selecting this registry in cargo commands that take
+    /// `--registry` (`publish`, `search`, `install`, `add`, `owner`, `yank`,
+    /// ...): none for crates.io.
+    pub fn cargo_args(&self) -> Vec<String> {
+        match &self.name {
+            Some(name) => vec!["--registry".to_string(), name.clone()],
+            None => Vec::new(),
+        }
+    }
+
+    /// The versions of `crate_name` that aren't yanked, read from the sparse
+    /// index with `curl`. Private registries are asked again with a
+    /// [token](Self::token) when they refuse the anonymous request.
+    pub fn versions(&self, crate_name: &str) -> Result<Vec<Version>> {
+        let base = self.index.strip_prefix("sparse+").with_context(|| {
+            format!(
+                "{} uses a git index; only sparse indexes can be queried",
+                self.display_name()
+            )
+        })?;
+        let url = format!("{}/{}", base.trim_end_matches('/'), index_path(crate_name));
+        let output = match fetch(&url, None)? {
+            Ok(output) => output,
+            Err(err) => {
+                let token = if self.is_crates_io() {
+                    None
+                } else {
+                    self.token()?
+                };
+                let retried = match token {
+                    Some(token) => fetch(&url, Some(&token))?,
+                    None => Err(err),
+                };
+                retried.map_err(|err| {
+                    anyhow::anyhow!(
+                        "Failed to look up {} in the {} index: {}",
+                        crate_name,
+                        self.display_name(),
+                        err
+                    )
+                })?
+            }
+        };
+        Ok(parse_index(&output))
+    }
+
+    /// A token for reading from this registry, if one is configured.
+    pub fn token(&self) -> Result<Option<String>> {
+        let key = match &self.name {
+            Some(name) => format!("CARGO_REGISTRIES_{}_TOKEN", env_key(name)),
+            None if self.is_crates_io() => "CARGO_REGISTRY_TOKEN".to_string(),
+            None => return Ok(None),
+        };
+        if let Some(token) = env_setting(&key) {
+            return Ok(Some(token));
+        }
+        let config = CargoConfig::load();
+        for provider in config.credential_providers(self.name.as_deref()) {
+            let token = match provider.first().map(String::as_str) {
+                Some("cargo:token") => config.stored_token(self.name.as_deref()),
+                Some(builtin) if builtin.starts_with("cargo:") => None,
+                Some(_) => self.ask_provider(&provider)?,
+                None => None,
+            };
+            if token.is_some() {
+                return Ok(token);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Ask an external credential provider for a read token, with version 1
+    /// of cargo's credential provider protocol.
+    fn ask_provider(&self, provider: &[String]) -> Result<Option<String>> {
+        self.ask_provider_within(provider, PROVIDER_TIMEOUT)
+    }
+
+    /// [`ask_provider`](Self::ask_provider), killing the provider if it
+    /// doesn't answer within `timeout`.
+    fn ask_provider_within(
+        &self,
+        provider: &[String],
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        let mut child = std::process::Command::new(&provider[0])
+            .arg("--cargo-plugin")
+            .args(&provider[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Failed to run credential provider {}", provider[0]))?;
+        let stdin = child.stdin.take().context("No provider stdin")?;
+        let stdout = child.stdout.take().context("No provider stdout")?;
+        let request = serde_json::json!({
+            "v": 1,
+            "registry": { "index-url": self.index, "name": self.name },
+            "kind": "get",
+            "operation": "read",
+            "args": &provider[1..],
+        });
+        // Talk to the provider on a thread, so a hanging one can be killed
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(exchange(stdin, stdout, &request));
+        });
+        let response = receiver.recv_timeout(timeout).unwrap_or_else(|_| {
+            let _ = child.kill();
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("Timed out after {}s", timeout.as_secs()),
+            ))
+        });
+        let _ = child.wait();
+        let failed = || format!("Credential provider {} failed", provider[0]);
+        parse_provider_response(&response.with_context(failed)?).with_context(failed)
+    }
+
+    fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("`{}` registry", name),
+            None if self.is_crates_io() => "crates.io".to_string(),
+            None => format!("registry at {}", self.index),
+        }
+    }
+}
+
+/// Send `request` to a credential provider and return its response line.
+fn exchange(
+    mut stdin: ChildStdin,
+    stdout: ChildStdout,
+    request: &serde_json::Value,
+) -> std::io::Result<String> {
+    let mut stdout = BufReader::new(stdout);
+    let mut line = String::new();
+    // The provider introduces itself with the protocol versions it speaks
+    stdout.read_line(&mut line)?;
+    writeln!(stdin, "{}", request)?;
+    line.clear();
+    stdout.read_line(&mut line)?;
+    Ok(line)
+}
+
+/// The token of a credential provider response, `None` if it has none for
+/// this registry.
+fn parse_provider_response(line: &str) -> Result<Option<String>> {
+    let response: serde_json::Value =
+        serde_json::from_str(line.trim()).context("Invalid response")?;
+    if let Some(token) = response
+        .pointer("/Ok/token")
+        .and_then(|token| token.as_str())
+    {
+        return Ok(Some(token.to_string()));
+    }
+    match response.pointer("/Err/kind").and_then(|kind| kind.as_str()) {
+        Some("not-found" | "url-not-supported") => Ok(None),
+        _ => {
+            let message = response
+                .pointer("/Err/message")
+                .and_then(|message| message.as_str())
+                .unwrap_or("unexpected response");
+            anyhow::bail!("{}", message)
+        }
+    }
+}
+
+/// The body at `url`, or curl's error message. Fails only if curl can't be
+/// run.
+///
+/// The token is passed to curl on stdin, not on its command line, where
+/// other local users could read it.
+fn fetch(url: &str, token: Option<&str>) -> Result<std::result::Result<String, String>> {
+    let mut cmd = std::process::Command::new("curl");
+    cmd.args(["-sSfL", "--max-time", "20"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if token.is_some() {
+        cmd.args(["-H", "@-"]).stdin(Stdio::piped());
+    } else {
+        cmd.stdin(Stdio::null());
+    }
+    let mut child = cmd.arg(url).spawn().context("Failed to run curl")?;
+    if let (Some(token), Some(mut stdin)) = (token, child.stdin.take()) {
+        stdin
+            .write_all(format!("Authorization: {}\n", token).as_bytes())
+            .context("Failed to pass the token to curl")?;
+    }
+    let output = child.wait_with_output().context("Failed to run curl")?;
+    Ok(if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    })
+}
+
+/// Path of a crate's file in the index, e.g. `se/rd/serde`.
+fn index_path(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+/// The versions in an index file that aren't yanked.
+fn parse_index(text: &str) -> Vec<Version> {
+    #[derive(serde::Deserialize)]
+    struct Entry {
+        vers: Version,
+        #[serde(default)]
+        yanked: bool,
+    }
+    text.lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+        .filter(|entry| !entry.yanked)
+        .map(|entry| entry.vers)
+        .collect()
+}
+
+/// A registry name as it appears in environment variables.
+fn env_key(name: &str) -> String {
+    name.to_ascii_uppercase().replace('-', "_")
+}
+
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn env_setting(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// `$CARGO_HOME`, or `~/.cargo`.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn cargo_home() -> Option<PathBuf> {
+    if let Some(home) = std::env::var_os("CARGO_HOME").filter(|home| !home.is_empty()) {
+        return Some(PathBuf::from(home));
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".cargo"))
+}
+
+/// The cargo configuration files that apply in the current directory, most
+/// specific first.
+pub(crate) struct CargoConfig {
+    files: Vec<toml_edit::DocumentMut>,
+    credentials: Option<toml_edit::DocumentMut>,
+}
+
+impl CargoConfig {
+    pub(crate) fn load() -> Self {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let home = cargo_home();
+        Self::load_from(&cwd, home.as_deref())
+    }
+
+    /// Unreadable or invalid files are skipped; cargo reports them on its
+    /// next run.
+    pub(crate) fn load_from(cwd: &Path, cargo_home: Option<&Path>) -> Self {
+        let mut dirs: Vec<PathBuf> = cwd.ancestors().map(|dir| dir.join(".cargo")).collect();
+        if let Some(home) = cargo_home
+            && !dirs.iter().any(|dir| dir == home)
+        {
+            dirs.push(home.to_path_buf());
+        }
+        let files = dirs
+            .iter()
+            .filter_map(|dir| {
+                read_toml(&dir.join("config.toml")).or_else(|| read_toml(&dir.join("config")))
+            })
+            .collect();
+        let credentials = cargo_home.and_then(|home| {
+            read_toml(&home.join("credentials.toml"))
+                .or_else(|| read_toml(&home.join("credentials")))
+        });
+        Self { files, credentials }
+    }
+
+    /// The first setting at `path`, from the most specific file.
+    fn get(&self, path: &[&str]) -> Option<&toml_edit::Item> {
+        self.files.iter().find_map(|doc| lookup(doc, path))
+    }
+
+    fn string(&self, path: &[&str]) -> Option<&str> {
+        self.get(path).and_then(|item| item.as_str())
+    }
+
+    /// A list setting such as `build.rustflags`, given as an array or a
+    /// space-separated string. Like cargo, joins the lists of all files, the
+    /// most specific last.
+    pub(crate) fn string_list(&self, path: &[&str]) -> Vec<String> {
+        let mut list = Vec::new();
+        for item in self.files.iter().rev().filter_map(|doc| lookup(doc, path)) {
+            if let Some(value) = item.as_str() {
+                list.extend(split_command(value));
+            } else if let Some(values) = item.as_array() {
+                list.extend(
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_str())
+                        .map(str::to_string),
+                );
+            }
+        }
+        list
+    }
+
+    /// The configured registry whose index is `index`.
+    fn registry_with_index(&self, index: &str) -> Option<String> {
+        let same = |url: &str| url.trim_end_matches('/') == index.trim_end_matches('/');
+        self.files.iter().find_map(|doc| {
+            doc.get("registries")?
+                .as_table_like()?
+                .iter()
+                .find(|(_, registry)| {
+                    registry
+                        .get("index")
+                        .and_then(|url| url.as_str())
+                        .is_some_and(same)
+                })
+                .map(|(name, _)| name.to_string())
+        })
+    }
+
+    /// The credential providers to try for a registry, each as a program
+    /// and its arguments, in the order cargo tries them.
+    fn credential_providers(&self, name: Option<&str>) -> Vec<Vec<String>> {
+        let own = match name {
+            Some(name) => self.get(&["registries", name, "credential-provider"]),
+            None => self.get(&["registry", "credential-provider"]),
+        };
+        if let Some(provider) = own.and_then(provider_command) {
+            return vec![provider];
+        }
+        let global: Vec<Vec<String>> = self
+            .get(&["registry", "global-credential-providers"])
+            .and_then(|item| item.as_array())
+            .map(|providers| {
+                // Later entries take precedence
+                let mut providers: Vec<Vec<String>> = providers
+                    .iter()
+                    .filter_map(|provider| provider.as_str())
+                    .map(split_command)
+                    .collect();
+                providers.reverse();
+                providers
+            })
+            .unwrap_or_default();
+        if global.is_empty() {
+            vec![vec!["cargo:token".to_string()]]
+        } else {
+            global
+        }
+    }
+
+    /// The token stored by `cargo login`, or set in the configuration.
+    fn stored_token(&self, name: Option<&str>) -> Option<String> {
+        let path: &[&str] = match name {
+            Some(name) => &["registries", name, "token"],
+            None => &["registry", "token"],
+        };
+        self.credentials
+            .as_ref()
+            .and_then(|doc| lookup(doc, path))
+            .or_else(|| self.get(path))
+            .and_then(|item| item.as_str())
+            .map(str::to_string)
+    }
+}
+
+fn read_toml(path: &Path) -> Option<toml_edit::DocumentMut> {
+    std::fs::read_to_string(path).ok()?.parse().ok()
+}
+
+fn lookup<'a>(doc: &'a toml_edit::DocumentMut, path: &[&str]) -> Option<&'a toml_edit::Item> {
+    path.iter()
+        .try_fold(doc.as_item(), |item, key| item.get(key))
+}
+
+/// A `credential-provider` setting: a command line string or an array of
+/// program and arguments.
+fn provider_command(item: &toml_edit::Item) -> Option<Vec<String>> {
+    if let Some(command) = item.as_str() {
+        return Some(split_command(command));
+    }
+    let parts: Vec<String> = item
+        .as_array()?
+        .iter()
+        .filter_map(|part| part.as_str().map(str::to_string))
+        .collect();
+    (!parts.is_empty()).then_some(parts)
+}
+
+fn split_command(command: &str) -> Vec<String> {
+    command.split_whitespace().map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a"), "1/a");
+        assert_eq!(index_path("ab"), "2/ab");
+        assert_eq!(index_path("abc"), "3/a/abc");
+        assert_eq!(index_path("Serde"), "se/rd/serde");
+        assert_eq!(
+            parse_index(
+                "{\"name\":\"x\",\"vers\":\"0.1.0\",\"yanked\":false}\n\
+                 {\"name\":\"x\",\"vers\":\"0.2.0\",\"yanked\":true}\n"
+            ),
+            vec![Version::new(0, 1, 0)]
+        );
+    }
+
+    #[test]
+    fn test_fetch_sends_token_header() {
+        use std::io::{
+            BufRead,
+            Write,
+        };
+
+        // Answer one request with the headers it carried
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/config.json", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push_str(&line);
+            }
+            write!(
+                &stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                headers.len(),
+                headers
+            )
+            .unwrap();
+        });
+        let body = fetch(&url, Some("cio-secret")).unwrap().unwrap();
+        server.join().unwrap();
+        assert!(body.contains("Authorization: cio-secret\r\n"), "{}", body);
+    }
+
+    #[test]
+    fn test_from_source() {
+        let source = |repr: &str| cargo_metadata::Source {
+            repr: repr.to_string(),
+        };
+        assert!(
+            Registry::from_source(&source(CRATES_IO_SOURCE))
+                .unwrap()
+                .is_crates_io()
+        );
+        let private =
+            Registry::from_source(&source("sparse+https://registry.example/index/")).unwrap();
+        assert_eq!(private.index(), "sparse+https://registry.example/index/");
+        assert!(!private.is_crates_io());
+        assert!(private.is_sparse());
+        let git_index =
+            Registry::from_source(&source("registry+https://git.example/index")).unwrap();
+        assert!(!git_index.is_sparse());
+        assert_eq!(
+            Registry::from_source(&source("git+https://github.com/owner/repo#abc")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = dir.path().join("project");
+        let home = dir.path().join("home");
+        std::fs::create_dir_all(project.join(".cargo")).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(
+            project.join(".cargo/config.toml"),
+            "[registries.internal]\nindex = \"sparse+https://registry.example/index/\"\n\
+             credential-provider = [\"/usr/bin/provider\", \"--vault\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            home.join("config.toml"),
+            "[registries.internal]\nindex = \"sparse+https://ignored.example/\"\n\n\
+             [registry]\ndefault = \"internal\"\n\
+             global-credential-providers = [\"cargo:token\", \"cargo:libsecret\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            home.join("credentials.toml"),
+            "[registry]\ntoken = \"crates-io-token\"\n",
+        )
+        .unwrap();
+
+        let config = CargoConfig::load_from(&project, Some(&home));
+        assert_eq!(
+            config.string(&["registries", "internal", "index"]),
+            Some("sparse+https://registry.example/index/")
+        );
+        assert_eq!(config.string(&["registry", "default"]), Some("internal"));
+        assert_eq!(
+            config.registry_with_index("sparse+https://registry.example/index"),
+            Some("internal".to_string())
+        );
+        assert_eq!(
+            config.credential_providers(Some("internal")),
+            vec![vec!["/usr/bin/provider".to_string(), "--vault".to_string()]]
+        );
+        assert_eq!(
+            config.credential_providers(None),
+            vec![
+                vec!["cargo:libsecret".to_string()],
+                vec!["cargo:token".to_string()]
+            ]
+        );
+        assert_eq!(
+            config.stored_token(None),
+            Some("crates-io-token".to_string())
+        );
+        assert_eq!(config.stored_token(Some("internal")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ask_provider() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let provider = |name: &str, script: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            vec![path.to_string_lossy().into_owned()]
+        };
+        let registry = Registry::crates_io();
+        let answering = provider(
+            "answering",
+            "echo '{\"v\":[1]}'\nread request\n\
+             echo '{\"Ok\":{\"kind\":\"get\",\"token\":\"secret\"}}'\n",
+        );
+        assert_eq!(
+            registry
+                .ask_provider_within(&answering, Duration::from_secs(10))
+                .unwrap(),
+            Some("secret".to_string())
+        );
+
+        let hanging = provider("hanging", "echo '{\"v\":[1]}'\nexec sleep 30\n");
+        let started = std::time::Instant::now();
+        let err = registry
+            .ask_provider_within(&hanging, Duration::from_millis(200))
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("Timed out"), "{:#}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_parse_provider_response() {
+        assert_eq!(
+            parse_provider_response(
+                r#"{"Ok":{"kind":"get","token":"secret","cache":"session","operation_independent":true}}"#
+            )
+            .unwrap(),
+            Some("secret".to_string())
+        );
+        assert_eq!(
+            parse_provider_response(r#"{"Err":{"kind":"not-found"}}"#).unwrap(),
+            None
+        );
+        let err =
+            parse_provider_response(r#"{"Err":{"kind":"other","message":"locked"}}"#).unwrap_err();
+        assert_eq!(err.to_string(), "locked");
+    }
+}
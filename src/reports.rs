@@ -0,0 +1,316 @@
+//! Machine-readable reports for CI systems.
+//!
+//! [`junit`] writes JUnit XML, the test report format every CI system can
+//! display, from a [`TestReport`], a [`PipelineReport`] or a hand-built
+//! [`JUnitSuite`]:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::test_runner::TestRunner;
+//! use cargo_plugin_utils::{
+//!     Logger,
+//!     reports,
+//! };
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let report = TestRunner::cargo_test().run(&mut logger).await?;
+//! reports::junit(&report, "target/junit.xml")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::pipeline::{
+    PipelineReport,
+    StepStatus,
+};
+use crate::test_runner::{
+    TestOutcome,
+    TestReport,
+};
+
+/// Write `results` as a JUnit XML file to `path`.
+pub fn junit(results: impl Into<JUnitSuite>, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, results.into().to_xml())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// A JUnit test suite.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JUnitSuite {
+    /// Suite name, e.g. the tool that ran the tests
+    pub name: String,
+    /// The test cases
+    pub cases: Vec<JUnitCase>,
+}
+
+/// One JUnit test case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JUnitCase {
+    /// Test name
+    pub name: String,
+    /// Group of the test, shown as the "class" by CI systems
+    pub classname: String,
+    /// Run time, if known
+    pub time: Option<Duration>,
+    /// How the case ended
+    pub result: JUnitResult,
+    /// Captured output, written as `system-out`
+    pub output: String,
+}
+
+/// Outcome of a [`JUnitCase`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JUnitResult {
+    /// The case passed
+    Passed,
+    /// An assertion failed; the message summarizes it
+    Failure(String),
+    /// The case couldn't run properly
+    Error(String),
+    /// The case was skipped
+    Skipped,
+}
+
+impl JUnitSuite {
+    /// An empty suite.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            cases: Vec::new(),
+        }
+    }
+
+    /// The suite as a JUnit XML document.
+    pub fn to_xml(&self) -> String {
+        let count = |predicate: fn(&JUnitResult) -> bool| {
+            self.cases
+                .iter()
+                .filter(|case| predicate(&case.result))
+                .count()
+        };
+        let counts = format!(
+            "tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\"",
+            self.cases.len(),
+            count(|result| matches!(result, JUnitResult::Failure(_))),
+            count(|result| matches!(result, JUnitResult::Error(_))),
+            count(|result| *result == JUnitResult::Skipped),
+            self.cases
+                .iter()
+                .filter_map(|case| case.time)
+                .sum::<Duration>()
+                .as_secs_f64(),
+        );
+        let name = escape(&self.name);
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(xml, "<testsuites name=\"{}\" {}>", name, counts);
+        let _ = writeln!(xml, "  <testsuite name=\"{}\" {}>", name, counts);
+        for case in &self.cases {
+            case.write_xml(&mut xml);
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+}
+
+impl JUnitCase {
+    fn write_xml(&self, xml: &mut String) {
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\"",
+            escape(&self.name),
+            escape(&self.classname)
+        );
+        if let Some(time) = self.time {
+            let _ = write!(xml, " time=\"{:.3}\"", time.as_secs_f64());
+        }
+        if self.result == JUnitResult::Passed && self.output.is_empty() {
+            xml.push_str("/>\n");
+            return;
+        }
+        xml.push_str(">\n");
+        match &self.result {
+            JUnitResult::Passed => {}
+            JUnitResult::Failure(message) => {
+                let _ = writeln!(xml, "      <failure message=\"{}\"/>", escape(message));
+            }
+            JUnitResult::Error(message) => {
+                let _ = writeln!(xml, "      <error message=\"{}\"/>", escape(message));
+            }
+            JUnitResult::Skipped => xml.push_str("      <skipped/>\n"),
+        }
+        if !self.output.is_empty() {
+            let _ = writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape(&self.output)
+            );
+        }
+        xml.push_str("    </testcase>\n");
+    }
+}
+
+impl From<&TestReport> for JUnitSuite {
+    fn from(report: &TestReport) -> Self {
+        let cases = report.tests.iter().map(|test| {
+            // `module::tests::name` becomes class `module::tests`, test `name`
+            let (classname, name) = test.name.rsplit_once("::").unwrap_or(("", &test.name));
+            JUnitCase {
+                name: name.to_string(),
+                classname: classname.to_string(),
+                time: test.duration,
+                result: match test.outcome {
+                    TestOutcome::Passed => JUnitResult::Passed,
+                    TestOutcome::Failed => JUnitResult::Failure(first_line(&test.output)),
+                    TestOutcome::Ignored => JUnitResult::Skipped,
+                },
+                output: test.output.clone(),
+            }
+        });
+        Self {
+            name: "cargo test".to_string(),
+            cases: cases.collect(),
+        }
+    }
+}
+
+impl From<&PipelineReport> for JUnitSuite {
+    fn from(report: &PipelineReport) -> Self {
+        let cases = report.steps.iter().map(|step| {
+            let result = match &step.status {
+                StepStatus::Passed => JUnitResult::Passed,
+                StepStatus::Failed { exit_code } => {
+                    JUnitResult::Failure(format!("exit code {}", exit_code))
+                }
+                StepStatus::Error(message) => JUnitResult::Error(message.clone()),
+                StepStatus::Skipped => JUnitResult::Skipped,
+            };
+            let output = step.output.as_ref().map_or_else(String::new, |output| {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+                text
+            });
+            JUnitCase {
+                name: step.name.clone(),
+                classname: "pipeline".to_string(),
+                time: (step.status != StepStatus::Skipped).then_some(step.duration),
+                result,
+                output,
+            }
+        });
+        Self {
+            name: "pipeline".to_string(),
+            cases: cases.collect(),
+        }
+    }
+}
+
+/// The first non-empty line of test output, as the failure message.
+fn first_line(output: &str) -> String {
+    output
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("test failed")
+        .to_string()
+}
+
+/// Escape text for XML attributes and content, dropping ANSI escape codes
+/// and control characters XML 1.0 doesn't allow.
+fn escape(text: &str) -> String {
+    let text = console::strip_ansi_codes(text);
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(character),
+            control if control.is_control() => {}
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_runner::TestCase;
+
+    #[test]
+    fn test_junit_from_test_report() {
+        let report = TestReport {
+            tests: vec![
+                TestCase {
+                    name: "tests::passes".to_string(),
+                    outcome: TestOutcome::Passed,
+                    duration: Some(Duration::from_millis(1500)),
+                    output: String::new(),
+                },
+                TestCase {
+                    name: "tests::fails".to_string(),
+                    outcome: TestOutcome::Failed,
+                    duration: None,
+                    output: "\nassertion `left == right` failed\n  left: <1>\x1b[31m\n".to_string(),
+                },
+                TestCase {
+                    name: "skipped".to_string(),
+                    outcome: TestOutcome::Ignored,
+                    duration: None,
+                    output: String::new(),
+                },
+            ],
+            exit_code: 101,
+            stderr: String::new(),
+        };
+        let xml = JUnitSuite::from(&report).to_xml();
+        assert!(xml.contains(
+            "<testsuite name=\"cargo test\" tests=\"3\" failures=\"1\" errors=\"0\" \
+             skipped=\"1\" time=\"1.500\">"
+        ));
+        assert!(xml.contains("<testcase name=\"passes\" classname=\"tests\" time=\"1.500\"/>"));
+        assert!(xml.contains("<failure message=\"assertion `left == right` failed\"/>"));
+        assert!(xml.contains("  left: &lt;1&gt;\n</system-out>"));
+        assert!(xml.contains("<testcase name=\"skipped\" classname=\"\">\n      <skipped/>"));
+        assert!(!xml.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_junit_from_pipeline_report() {
+        use crate::pipeline::StepResult;
+
+        let step = |name: &str, status| StepResult {
+            name: name.to_string(),
+            status,
+            duration: Duration::from_secs(2),
+            output: None,
+        };
+        let report = PipelineReport {
+            steps: vec![
+                step("build", StepStatus::Passed),
+                step("test", StepStatus::Failed { exit_code: 101 }),
+                step("docs", StepStatus::Error("timed out".to_string())),
+                step("deploy", StepStatus::Skipped),
+            ],
+        };
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("junit.xml");
+        junit(&report, &path).unwrap();
+        let xml = std::fs::read_to_string(path).unwrap();
+        assert!(
+            xml.contains("tests=\"4\" failures=\"1\" errors=\"1\" skipped=\"1\" time=\"6.000\"")
+        );
+        assert!(xml.contains("<failure message=\"exit code 101\"/>"));
+        assert!(xml.contains("<error message=\"timed out\"/>"));
+        assert!(xml.contains("<testcase name=\"deploy\" classname=\"pipeline\">"));
+    }
+}
@@ -0,0 +1,116 @@
+//! Terminal size tracking.
+//!
+//! The size of the terminal behind stderr is tracked by a background thread
+//! that is started on first use. On Unix it re-reads the size whenever
+//! `SIGWINCH` arrives (via tokio's signal handling, which chains with other
+//! handlers); on Windows it polls. Renderers call [`current`] on every redraw,
+//! or [`subscribe`] to be woken up on changes, so output is re-laid out after
+//! the user resizes the terminal mid-run.
+
+use std::sync::OnceLock;
+
+use tokio::sync::watch;
+
+/// Size of a terminal in character cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermSize {
+    /// Number of rows
+    pub rows: u16,
+    /// Number of columns
+    pub cols: u16,
+}
+
+impl Default for TermSize {
+    /// The classic 24x80, used when stderr is not a terminal.
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
+/// Read the size of the terminal behind stderr.
+fn query() -> TermSize {
+    console::Term::stderr()
+        .size_checked()
+        .map(|(rows, cols)| TermSize { rows, cols })
+        .unwrap_or_default()
+}
+
+/// The size watcher, started on first use.
+fn watcher() -> &'static watch::Sender<TermSize> {
+    static WATCHER: OnceLock<watch::Sender<TermSize>> = OnceLock::new();
+    WATCHER.get_or_init(|| {
+        let (sender, _) = watch::channel(query());
+        let thread_sender = sender.clone();
+        let _ = std::thread::Builder::new()
+            .name("terminal-resize".to_string())
+            .spawn(move || watch_resizes(thread_sender));
+        sender
+    })
+}
+
+/// Update `sender` whenever the terminal is resized. Runs forever.
+fn watch_resizes(sender: watch::Sender<TermSize>) {
+    let update = |sender: &watch::Sender<TermSize>| {
+        let size = query();
+        sender.send_if_modified(|current| std::mem::replace(current, size) != size);
+    };
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{
+            SignalKind,
+            signal,
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build();
+        if let Ok(runtime) = runtime {
+            runtime.block_on(async {
+                let Ok(mut resizes) = signal(SignalKind::window_change()) else {
+                    return;
+                };
+                while resizes.recv().await.is_some() {
+                    update(&sender);
+                }
+            });
+        }
+    }
+
+    // No signal available (Windows, or signal setup failed): poll
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(250));
+        update(&sender);
+    }
+}
+
+/// The current size of the terminal behind stderr.
+///
+/// Cheap enough to call on every redraw. Returns 24x80 when stderr is not a
+/// terminal.
+pub fn current() -> TermSize {
+    *watcher().borrow()
+}
+
+/// Subscribe to terminal size changes.
+pub fn subscribe() -> watch::Receiver<TermSize> {
+    watcher().subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_size() {
+        let size = current();
+        assert!(size.cols > 0);
+        assert!(size.rows > 0);
+    }
+
+    #[test]
+    fn test_subscribe_starts_at_current() {
+        let receiver = subscribe();
+        assert_eq!(*receiver.borrow(), current());
+    }
+}
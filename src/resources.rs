@@ -0,0 +1,366 @@
+//! Resource usage accounting for subprocesses.
+//!
+//! On Unix the child is reaped with `wait4`, which reports the peak RSS and
+//! CPU time of the child including the descendants it waited for (e.g. the
+//! `rustc` processes of a `cargo build`). It is only reaped once it is no
+//! longer signalled, so its process ID can't be reused in between. On Windows
+//! the child is placed in a job object, which accounts for the whole process
+//! tree and lets it be terminated as a whole.
+
+use std::fmt;
+#[cfg(windows)]
+use std::sync::Arc;
+use std::time::Duration;
+
+use portable_pty::{
+    Child,
+    ExitStatus,
+};
+
+/// Resources consumed by a finished subprocess (and its waited-for
+/// descendants).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceUsage {
+    /// Peak resident set size in bytes (peak committed memory on Windows)
+    pub max_rss_bytes: u64,
+    /// CPU time spent in user mode
+    pub user_time: Duration,
+    /// CPU time spent in kernel mode
+    pub system_time: Duration,
+}
+
+impl ResourceUsage {
+    /// Total CPU time (user + system).
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    /// Formats as e.g. `3.2 GB RAM, 142.0s CPU`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} RAM, {:.1}s CPU",
+            indicatif::DecimalBytes(self.max_rss_bytes),
+            self.cpu_time().as_secs_f64()
+        )
+    }
+}
+
+/// Tracks resource usage of a spawned child until it is reaped.
+pub(crate) struct UsageTracker {
+    #[cfg(windows)]
+    job: Option<Arc<windows::Job>>,
+}
+
+/// Terminates the job object of a tracked child, with everything it started.
+#[derive(Clone)]
+pub(crate) struct JobKiller {
+    #[cfg(windows)]
+    job: Option<Arc<windows::Job>>,
+}
+
+impl JobKiller {
+    /// Terminate the job; `false` if there is none (always, outside
+    /// Windows).
+    pub(crate) fn kill(&self) -> bool {
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            return job.terminate();
+        }
+        false
+    }
+}
+
+impl UsageTracker {
+    /// Start tracking a freshly spawned child.
+    ///
+    /// On Windows, descendants the child started before this call are not
+    /// accounted for.
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    pub(crate) fn attach(child: &(dyn Child + Send + Sync)) -> Self {
+        Self {
+            #[cfg(windows)]
+            job: child
+                .as_raw_handle()
+                .and_then(windows::Job::assign)
+                .map(Arc::new),
+        }
+    }
+
+    /// A handle to terminate the child's process tree while it is waited
+    /// for.
+    pub(crate) fn job_killer(&self) -> JobKiller {
+        JobKiller {
+            #[cfg(windows)]
+            job: self.job.clone(),
+        }
+    }
+
+    /// Wait for the child to exit, without reaping it yet: until it is
+    /// [reaped](Exited::reap), its process ID and process group can't be
+    /// reused, so they can still be signalled safely.
+    pub(crate) fn wait(self, mut child: Box<dyn Child + Send + Sync>) -> std::io::Result<Exited> {
+        #[cfg(unix)]
+        if let Some(pid) = child.process_id() {
+            unix::wait_exited(pid)?;
+            return Ok(Exited {
+                tracker: self,
+                pid: Some(pid),
+                status: None,
+            });
+        }
+
+        let status = child.wait()?;
+        Ok(Exited {
+            tracker: self,
+            pid: None,
+            status: Some(status),
+        })
+    }
+}
+
+/// A child that exited and is not reaped yet, see [`UsageTracker::wait`].
+pub(crate) struct Exited {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    tracker: UsageTracker,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pid: Option<u32>,
+    status: Option<ExitStatus>,
+}
+
+impl Exited {
+    /// Reap the child, returning its exit status and resource usage (if the
+    /// platform could report it).
+    pub(crate) fn reap(self) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+        #[cfg(unix)]
+        if let Some(pid) = self.pid {
+            return unix::wait4(pid);
+        }
+
+        let status = self.status.unwrap_or_else(|| ExitStatus::with_exit_code(1));
+        #[cfg(windows)]
+        let usage = self.tracker.job.as_deref().and_then(windows::Job::usage);
+        #[cfg(not(windows))]
+        let usage = None;
+        Ok((status, usage))
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+
+    fn timeval_to_duration(tv: libc::timeval) -> Duration {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    }
+
+    /// Wait for `pid` to exit with `waitid(WNOWAIT)`, which leaves it to be
+    /// reaped, retrying on `EINTR`.
+    pub(super) fn wait_exited(pid: u32) -> std::io::Result<()> {
+        loop {
+            // SAFETY: siginfo_t is plain old data, all-zero is a valid value
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            // SAFETY: the pointer refers to a live, properly aligned local
+            let result = unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    pid as libc::id_t,
+                    &mut info,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            };
+            if result >= 0 {
+                return Ok(());
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Reap `pid` with `wait4`, retrying on `EINTR`.
+    pub(super) fn wait4(pid: u32) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+        let mut status: libc::c_int = 0;
+        // SAFETY: rusage is plain old data, all-zero is a valid value
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        loop {
+            // SAFETY: both pointers refer to live, properly aligned locals
+            let result = unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut rusage) };
+            if result >= 0 {
+                break;
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        let exit_status = if libc::WIFSIGNALED(status) {
+            let signal = libc::WTERMSIG(status);
+            ExitStatus::with_signal(&format!("Signal {}", signal))
+        } else {
+            ExitStatus::with_exit_code(libc::WEXITSTATUS(status) as u32)
+        };
+
+        // ru_maxrss is in kilobytes on Linux and in bytes on macOS
+        let max_rss = rusage.ru_maxrss.max(0) as u64;
+        let max_rss_bytes = if cfg!(target_os = "macos") {
+            max_rss
+        } else {
+            max_rss * 1024
+        };
+        let usage = ResourceUsage {
+            max_rss_bytes,
+            user_time: timeval_to_duration(rusage.ru_utime),
+            system_time: timeval_to_duration(rusage.ru_stime),
+        };
+        Ok((exit_status, Some(usage)))
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::os::windows::io::RawHandle;
+
+    use windows_sys::Win32::Foundation::{
+        CloseHandle,
+        HANDLE,
+    };
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject,
+        CreateJobObjectW,
+        JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectBasicAccountingInformation,
+        JobObjectExtendedLimitInformation,
+        QueryInformationJobObject,
+        TerminateJobObject,
+    };
+
+    use super::*;
+
+    /// Owned job object handle.
+    pub(super) struct Job(HANDLE);
+
+    // SAFETY: job object handles can be used from any thread
+    unsafe impl Send for Job {}
+    // SAFETY: the job object functions used are thread-safe
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        /// Create a job object and put the process in it.
+        pub(super) fn assign(process: RawHandle) -> Option<Self> {
+            // SAFETY: null attributes and name create an anonymous job
+            let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+            if handle.is_null() {
+                return None;
+            }
+            let job = Self(handle);
+            // SAFETY: both handles are valid for the duration of the call
+            if unsafe { AssignProcessToJobObject(job.0, process as HANDLE) } == 0 {
+                return None;
+            }
+            Some(job)
+        }
+
+        /// Terminate every process in the job.
+        pub(super) fn terminate(&self) -> bool {
+            // SAFETY: the handle is valid while `self` lives
+            unsafe { TerminateJobObject(self.0, 1) != 0 }
+        }
+
+        /// Query accumulated CPU time and peak memory of the job.
+        pub(super) fn usage(&self) -> Option<ResourceUsage> {
+            // SAFETY: the structs are plain old data and sized correctly for
+            // their information classes
+            unsafe {
+                let mut accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION = std::mem::zeroed();
+                if QueryInformationJobObject(
+                    self.0,
+                    JobObjectBasicAccountingInformation,
+                    &mut accounting as *mut _ as *mut _,
+                    std::mem::size_of_val(&accounting) as u32,
+                    std::ptr::null_mut(),
+                ) == 0
+                {
+                    return None;
+                }
+                let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                if QueryInformationJobObject(
+                    self.0,
+                    JobObjectExtendedLimitInformation,
+                    &mut limits as *mut _ as *mut _,
+                    std::mem::size_of_val(&limits) as u32,
+                    std::ptr::null_mut(),
+                ) == 0
+                {
+                    return None;
+                }
+                // Times are reported in 100ns units
+                Some(ResourceUsage {
+                    max_rss_bytes: limits.PeakJobMemoryUsed as u64,
+                    user_time: Duration::from_nanos(accounting.TotalUserTime as u64 * 100),
+                    system_time: Duration::from_nanos(accounting.TotalKernelTime as u64 * 100),
+                })
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: we own the handle
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_usage_cpu_time() {
+        let usage = ResourceUsage {
+            max_rss_bytes: 0,
+            user_time: Duration::from_millis(1500),
+            system_time: Duration::from_millis(500),
+        };
+        assert_eq!(usage.cpu_time(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_resource_usage_display() {
+        let usage = ResourceUsage {
+            max_rss_bytes: 3_200_000_000,
+            user_time: Duration::from_secs(140),
+            system_time: Duration::from_secs(2),
+        };
+        assert_eq!(usage.to_string(), "3.20 GB RAM, 142.0s CPU");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_leaves_child_to_reap() {
+        let child = std::process::Command::new("sh")
+            .args(["-c", "exit 3"])
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        let child: Box<dyn Child + Send + Sync> = Box::new(child);
+        let tracker = UsageTracker::attach(child.as_ref());
+        let exited = tracker.wait(child).unwrap();
+        // Exited but not reaped, so the process ID is still taken
+        // SAFETY: signal 0 only checks that the process exists
+        assert_eq!(unsafe { libc::kill(pid, 0) }, 0);
+        let (status, usage) = exited.reap().unwrap();
+        assert_eq!(status.exit_code(), 3);
+        assert!(usage.is_some());
+        // SAFETY: as above
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+    }
+}
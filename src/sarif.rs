@@ -0,0 +1,310 @@
+//! SARIF 2.1.0 export of [findings](crate::findings).
+//!
+//! [SARIF] is the format GitHub code scanning (and most other code analysis
+//! dashboards) accepts. A checker plugin describes itself and its rules with
+//! a [`Tool`] and writes its findings with [`write_sarif`]; the result can be
+//! uploaded with `github/codeql-action/upload-sarif`:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::findings::{
+//!     Findings,
+//!     Severity,
+//! };
+//! use cargo_plugin_utils::sarif::{
+//!     Rule,
+//!     Tool,
+//!     write_sarif,
+//! };
+//!
+//! let findings = Findings::new();
+//! let tool = Tool::new("cargo-lint-manifest", env!("CARGO_PKG_VERSION")).rule(
+//!     Rule::new("wildcard-version", "Dependency versions should be pinned")
+//!         .default_severity(Severity::Warning),
+//! );
+//! write_sarif(&findings, &tool, "results.sarif")?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Each result carries the finding's [fingerprint](Findings::fingerprints) as
+//! a partial fingerprint, so results are tracked across runs even when lines
+//! move.
+//!
+//! [SARIF]: https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde_json::{
+    Value,
+    json,
+};
+
+use crate::findings::{
+    Finding,
+    Findings,
+    Severity,
+};
+use crate::patch::path_for_patch;
+
+/// The SARIF version written by this module.
+pub const SARIF_VERSION: &str = "2.1.0";
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Key of the finding fingerprint in `partialFingerprints`.
+const FINGERPRINT_KEY: &str = "findingHash/v1";
+
+/// The checker that produced the findings.
+#[derive(Debug, Clone)]
+pub struct Tool {
+    name: String,
+    version: String,
+    information_uri: Option<String>,
+    rules: Vec<Rule>,
+}
+
+impl Tool {
+    /// Describe the tool by name and version.
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+            information_uri: None,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Link to the tool's homepage or documentation.
+    pub fn information_uri(mut self, uri: impl Into<String>) -> Self {
+        self.information_uri = Some(uri.into());
+        self
+    }
+
+    /// Describe a rule (finding code) of the tool.
+    ///
+    /// Codes without a rule are still exported, with a bare rule entry.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+}
+
+/// Metadata of one rule, i.e. a finding code.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    id: String,
+    description: String,
+    help_uri: Option<String>,
+    default_severity: Severity,
+}
+
+impl Rule {
+    /// A rule with the finding code `id` and a one-line description.
+    pub fn new(id: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            description: description.into(),
+            help_uri: None,
+            default_severity: Severity::Warning,
+        }
+    }
+
+    /// Link to the documentation of the rule.
+    pub fn help_uri(mut self, uri: impl Into<String>) -> Self {
+        self.help_uri = Some(uri.into());
+        self
+    }
+
+    /// Severity the rule reports by default (default: warning).
+    pub fn default_severity(mut self, severity: Severity) -> Self {
+        self.default_severity = severity;
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        let mut rule = json!({
+            "id": self.id,
+            "shortDescription": { "text": self.description },
+            "defaultConfiguration": { "level": self.default_severity.as_str() },
+        });
+        if let Some(help_uri) = &self.help_uri {
+            rule["helpUri"] = json!(help_uri);
+        }
+        rule
+    }
+}
+
+/// Convert findings to a SARIF log with a single run.
+pub fn to_sarif(findings: &Findings, tool: &Tool) -> Value {
+    // Declared rules first, then bare entries for undeclared codes
+    let mut rules = tool.rules.clone();
+    for finding in findings {
+        if !rules.iter().any(|rule| rule.id == finding.code) {
+            rules.push(Rule::new(&finding.code, &finding.code).default_severity(finding.severity));
+        }
+    }
+    let results: Vec<Value> = findings
+        .iter()
+        .zip(findings.fingerprints())
+        .map(|(finding, fingerprint)| {
+            let rule_index = rules.iter().position(|rule| rule.id == finding.code);
+            result(finding, rule_index, &fingerprint)
+        })
+        .collect();
+
+    let mut driver = json!({
+        "name": tool.name,
+        "version": tool.version,
+        "rules": rules.iter().map(Rule::to_json).collect::<Vec<_>>(),
+    });
+    if let Some(uri) = &tool.information_uri {
+        driver["informationUri"] = json!(uri);
+    }
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": SARIF_VERSION,
+        "runs": [{
+            "tool": { "driver": driver },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+/// Write findings as a SARIF file to `path`.
+pub fn write_sarif(findings: &Findings, tool: &Tool, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let sarif = serde_json::to_string_pretty(&to_sarif(findings, tool))?;
+    std::fs::write(path, sarif).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The SARIF result for one finding.
+fn result(finding: &Finding, rule_index: Option<usize>, fingerprint: &str) -> Value {
+    let mut result = json!({
+        "ruleId": finding.code,
+        "level": finding.severity.as_str(),
+        "message": { "text": finding.message },
+        "partialFingerprints": { FINGERPRINT_KEY: fingerprint },
+    });
+    if let Some(index) = rule_index {
+        result["ruleIndex"] = json!(index);
+    }
+    if let Some(location) = &finding.location {
+        let mut physical = json!({ "artifactLocation": artifact(&location.file) });
+        if let Some(line) = location.line {
+            physical["region"] = json!({ "startLine": line });
+            if let Some(column) = location.column {
+                physical["region"]["startColumn"] = json!(column);
+            }
+        }
+        result["locations"] = json!([{ "physicalLocation": physical }]);
+    }
+    if let Some(fix) = &finding.fix {
+        let changes: Vec<Value> = fix
+            .replacements
+            .iter()
+            .map(|replacement| {
+                json!({
+                    "artifactLocation": artifact(&replacement.file),
+                    "replacements": [{
+                        "deletedRegion": {
+                            "byteOffset": replacement.range.start,
+                            "byteLength": replacement.range.len(),
+                        },
+                        "insertedContent": { "text": replacement.text },
+                    }],
+                })
+            })
+            .collect();
+        result["fixes"] = json!([{
+            "description": { "text": fix.message },
+            "artifactChanges": changes,
+        }]);
+    }
+    result
+}
+
+/// A file relative to the checkout root.
+fn artifact(file: &Path) -> Value {
+    json!({ "uri": path_for_patch(file), "uriBaseId": "%SRCROOT%" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::findings::Fix;
+
+    fn findings() -> Findings {
+        [
+            Finding::new(Severity::Warning, "wildcard-version", "unpinned")
+                .at("crates/core/Cargo.toml", 4, 9)
+                .with_fix(Fix::machine_applicable("pin it").replace(
+                    "crates/core/Cargo.toml",
+                    40..43,
+                    "\"1\"",
+                )),
+            Finding::new(Severity::Error, "license", "missing license").in_file("Cargo.toml"),
+            Finding::new(Severity::Note, "info", "no location"),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_to_sarif_structure() {
+        let tool = Tool::new("cargo-check-things", "1.2.3")
+            .information_uri("https://example.com")
+            .rule(
+                Rule::new("license", "Packages need a license")
+                    .default_severity(Severity::Error)
+                    .help_uri("https://example.com/license"),
+            );
+        let sarif = to_sarif(&findings(), &tool);
+
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        let driver = &run["tool"]["driver"];
+        assert_eq!(driver["name"], "cargo-check-things");
+        assert_eq!(driver["informationUri"], "https://example.com");
+        let rule_ids: Vec<_> = driver["rules"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rule| rule["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(rule_ids, ["license", "wildcard-version", "info"]);
+        assert_eq!(driver["rules"][0]["defaultConfiguration"]["level"], "error");
+        assert_eq!(driver["rules"][0]["helpUri"], "https://example.com/license");
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["ruleIndex"], 1);
+        assert_eq!(results[0]["level"], "warning");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(
+            location["artifactLocation"]["uri"],
+            "crates/core/Cargo.toml"
+        );
+        assert_eq!(location["region"]["startLine"], 4);
+        assert_eq!(location["region"]["startColumn"], 9);
+        assert_eq!(
+            results[0]["partialFingerprints"][FINGERPRINT_KEY],
+            findings().iter().next().unwrap().fingerprint()
+        );
+        let replacement = &results[0]["fixes"][0]["artifactChanges"][0]["replacements"][0];
+        assert_eq!(replacement["deletedRegion"]["byteOffset"], 40);
+        assert_eq!(replacement["deletedRegion"]["byteLength"], 3);
+
+        assert!(results[1]["locations"][0]["physicalLocation"]["region"].is_null());
+        assert!(results[2]["locations"].is_null());
+    }
+
+    #[test]
+    fn test_write_sarif() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("results.sarif");
+        write_sarif(&findings(), &Tool::new("tool", "0.1.0"), &path).unwrap();
+        let written: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written["runs"][0]["results"].as_array().unwrap().len(), 3);
+    }
+}
@@ -5,6 +5,8 @@ use std::io::Write;
 use anyhow::Context;
 use console::Term;
 
+use crate::debug::trace;
+
 /// Get terminal size (rows, cols).
 pub fn get_terminal_size() -> anyhow::Result<(u16, u16)> {
     let term = Term::stdout();
@@ -14,14 +16,15 @@ pub fn get_terminal_size() -> anyhow::Result<(u16, u16)> {
 /// Set scrolling region using DECSTBM (Set Top and Bottom Margins).
 ///
 /// Sets the scrolling region to lines `top` through `bottom` (1-indexed).
-/// All scrolling operations will be confined to this region.
+/// All scrolling operations will be confined to this region. Does nothing
+/// when stderr doesn't support VT sequences.
 pub fn set_scrolling_region(top: u16, bottom: u16) -> anyhow::Result<()> {
     // DECSTBM: ESC [ top ; bottom r
     // top and bottom are 1-indexed
-    let mut stderr = std::io::stderr();
-    write!(stderr, "\x1b[{};{}r", top, bottom).context("Failed to set scrolling region")?;
-    stderr.flush().context("Failed to flush stdout")?;
-    Ok(())
+    write_sequence(
+        &format!("\x1b[{};{}r", top, bottom),
+        "Failed to set scrolling region",
+    )
 }
 
 /// Reset scrolling region (restore full terminal scrolling).
@@ -29,10 +32,7 @@ pub fn set_scrolling_region(top: u16, bottom: u16) -> anyhow::Result<()> {
 /// Resets the scrolling region to the entire terminal.
 pub fn reset_scrolling_region() -> anyhow::Result<()> {
     // Reset scrolling region: ESC [ r (no parameters means full terminal)
-    let mut stderr = std::io::stderr();
-    write!(stderr, "\x1b[r").context("Failed to reset scrolling region")?;
-    stderr.flush().context("Failed to flush stdout")?;
-    Ok(())
+    write_sequence("\x1b[r", "Failed to reset scrolling region")
 }
 
 /// Clear the scrolling region.
@@ -44,23 +44,44 @@ pub fn clear_scrolling_region() -> anyhow::Result<()> {
     // But we want to clear the region, so we need to:
     // 1. Move to top of region
     // 2. Clear lines in region
-    let mut stderr = std::io::stderr();
     // For now, just clear from cursor to end of screen
     // The actual region clearing will be handled by the caller
     // who knows the exact region bounds
-    write!(stderr, "\x1b[J").context("Failed to clear scrolling region")?;
-    stderr.flush().context("Failed to flush stdout")?;
-    Ok(())
+    write_sequence("\x1b[J", "Failed to clear scrolling region")
 }
 
 /// Move cursor to a specific line (1-indexed).
 pub fn move_cursor_to_line(line: u16) -> anyhow::Result<()> {
     // CUP (Cursor Position): ESC [ row ; col H
     // line is 1-indexed
+    write_sequence(
+        &format!("\x1b[{};1H", line),
+        "Failed to move cursor to line",
+    )
+}
+
+/// Write a VT sequence to stderr.
+///
+/// Nothing is written when stderr isn't a terminal understanding VT
+/// sequences (see [`supports_vt`](crate::tty::supports_vt)), e.g. a legacy
+/// Windows console or a log file.
+fn write_sequence(sequence: &str, context: &'static str) -> anyhow::Result<()> {
+    trace!(
+        "scrolling",
+        "{:?}{}",
+        sequence,
+        if crate::tty::supports_vt() {
+            ""
+        } else {
+            " (skipped, no VT)"
+        }
+    );
+    if !crate::tty::supports_vt() {
+        return Ok(());
+    }
     let mut stderr = std::io::stderr();
-    write!(stderr, "\x1b[{};1H", line).context("Failed to move cursor to line")?;
-    stderr.flush().context("Failed to flush stdout")?;
-    Ok(())
+    stderr.write_all(sequence.as_bytes()).context(context)?;
+    stderr.flush().context("Failed to flush stderr")
 }
 
 #[cfg(test)]
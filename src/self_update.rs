@@ -0,0 +1,586 @@
+//! Check for and install newer versions of the running plugin.
+//!
+//! An [`Updater`] looks up the latest release on crates.io or an alternative
+//! registry (through `cargo search`) or on GitHub, compares it with the
+//! running version and installs it with `cargo install`. [`Updater::notify`] is
+//! meant to be called on every run: it looks for a new release at most once per
+//! [interval](Updater::check_interval), remembering the answer in the user's
+//! cache directory, and prints a one-line notice if there is one:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::self_update::Updater;
+//!
+//! # async fn example(update: bool) -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let updater = Updater::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+//! if update {
+//!     updater.update(&mut logger, false).await?;
+//! } else {
+//!     updater.notify(&logger);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The notice is skipped on CI, when stderr isn't a terminal and when
+//! `CARGO_PLUGIN_NO_UPDATE_CHECK` is set.
+
+use std::io::{
+    IsTerminal,
+    Read,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::Stdio;
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use cargo_metadata::semver::Version;
+use portable_pty::CommandBuilder;
+
+use crate::logger::{
+    Logger,
+    command_line,
+    run_subprocess,
+};
+
+/// Default time between two release lookups of [`Updater::notify`].
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time a release lookup may take before it is given up.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where releases of a plugin are published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReleaseSource {
+    /// The crates.io registry, queried with `cargo search`
+    CratesIo,
+    /// An alternative registry configured for cargo, queried with
+    /// `cargo search --registry`
+    Registry {
+        /// Registry name, as in `.cargo/config.toml`
+        name: String,
+    },
+    /// Releases of a GitHub repository (`owner/repo`), tagged with the
+    /// version and an optional `v` prefix
+    GitHub {
+        /// Repository as `owner/repo`
+        repo: String,
+    },
+}
+
+/// A released version of the plugin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    /// The version
+    pub version: Version,
+    /// Tag of a GitHub release, as published (`1.2.0`, `v1.2.0`, ...)
+    pub tag: Option<String>,
+}
+
+/// Version check and update of one installed plugin.
+#[derive(Debug, Clone)]
+pub struct Updater {
+    name: String,
+    current: Version,
+    source: ReleaseSource,
+    check_interval: Duration,
+    state_dir: Option<PathBuf>,
+}
+
+/// Last lookup, stored in the state directory.
+#[derive(Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+struct State {
+    /// Seconds since the Unix epoch
+    checked_at: u64,
+    /// The latest version, or `None` if the lookup failed
+    #[serde(default)]
+    latest: Option<String>,
+    #[serde(default)]
+    tag: Option<String>,
+}
+
+impl Updater {
+    /// Updater for the crate `name` at `current_version`, usually
+    /// `env!("CARGO_PKG_NAME")` and `env!("CARGO_PKG_VERSION")`, released on
+    /// crates.io.
+    pub fn new(name: impl Into<String>, current_version: &str) -> anyhow::Result<Self> {
+        let current = Version::parse(current_version)
+            .with_context(|| format!("Invalid version `{}`", current_version))?;
+        Ok(Self {
+            name: name.into(),
+            current,
+            source: ReleaseSource::CratesIo,
+            check_interval: CHECK_INTERVAL,
+            state_dir: None,
+        })
+    }
+
+    /// Look for releases on GitHub in `repo` (`owner/repo`) instead of
+    /// crates.io.
+    pub fn github(mut self, repo: impl Into<String>) -> Self {
+        self.source = ReleaseSource::GitHub { repo: repo.into() };
+        self
+    }
+
+    /// Look for releases in the alternative registry `name` instead of
+    /// crates.io.
+    pub fn registry(mut self, name: impl Into<String>) -> Self {
+        self.source = ReleaseSource::Registry { name: name.into() };
+        self
+    }
+
+    /// Minimum time between two lookups of [`notify`](Self::notify)
+    /// (default [`CHECK_INTERVAL`]).
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    /// Remember lookups in `dir` instead of the user's cache directory.
+    pub fn state_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.state_dir = Some(dir.into());
+        self
+    }
+
+    /// The running version.
+    pub fn current_version(&self) -> &Version {
+        &self.current
+    }
+
+    /// Look up the latest released version, always asking the source.
+    pub fn latest_version(&self) -> anyhow::Result<Version> {
+        self.latest_release().map(|release| release.version)
+    }
+
+    /// Look up the latest release, always asking the source. Gives up after
+    /// 10 seconds.
+    pub fn latest_release(&self) -> anyhow::Result<Release> {
+        match &self.source {
+            ReleaseSource::CratesIo | ReleaseSource::Registry { .. } => {
+                let mut cmd = std::process::Command::new(crate::cargo::cargo_program());
+                cmd.args(["search", "--limit", "1", "--color", "never"])
+                    .args(self.registry_args())
+                    .arg(&self.name);
+                let output = output_with_timeout(&mut cmd, LOOKUP_TIMEOUT)
+                    .context("Failed to run `cargo search`")?;
+                anyhow::ensure!(
+                    output.status.success(),
+                    "cargo search failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                let version = parse_search(&String::from_utf8_lossy(&output.stdout), &self.name)?;
+                Ok(Release { version, tag: None })
+            }
+            ReleaseSource::GitHub { repo } => {
+                let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+                let output = std::process::Command::new("curl")
+                    .args(["-sSfL", "--max-time", &LOOKUP_TIMEOUT.as_secs().to_string()])
+                    .args(["-H", "Accept: application/vnd.github+json", &url])
+                    .output()
+                    .context("Failed to run curl")?;
+                anyhow::ensure!(
+                    output.status.success(),
+                    "Failed to fetch {}: {}",
+                    url,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                parse_release(&output.stdout)
+            }
+        }
+    }
+
+    /// The latest release if it is newer than the running version, asking
+    /// the source at most once per [interval](Self::check_interval).
+    ///
+    /// A failed lookup is remembered too: it is reported once, and the next
+    /// lookup waits for the interval like after a successful one.
+    pub fn check(&self) -> anyhow::Result<Option<Release>> {
+        self.check_with(|| self.latest_release())
+    }
+
+    /// [`check`](Self::check) with the lookup injected.
+    fn check_with(
+        &self,
+        lookup: impl FnOnce() -> anyhow::Result<Release>,
+    ) -> anyhow::Result<Option<Release>> {
+        let path = self.state_path()?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let latest = match read_state(&path) {
+            Some(state) if now.saturating_sub(state.checked_at) < self.check_interval.as_secs() => {
+                let Some(latest) = state.latest else {
+                    return Ok(None);
+                };
+                Release {
+                    version: Version::parse(&latest)?,
+                    tag: state.tag,
+                }
+            }
+            _ => {
+                let latest = lookup();
+                let state = State {
+                    checked_at: now,
+                    latest: latest
+                        .as_ref()
+                        .ok()
+                        .map(|release| release.version.to_string()),
+                    tag: latest.as_ref().ok().and_then(|release| release.tag.clone()),
+                };
+                write_state(&path, &state)?;
+                latest?
+            }
+        };
+        Ok((latest.version > self.current).then_some(latest))
+    }
+
+    /// Print a notice if a newer version is available, see
+    /// [`check`](Self::check). Lookup errors are ignored.
+    #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+    pub fn notify(&self, logger: &Logger) {
+        let disabled = std::env::var_os("CARGO_PLUGIN_NO_UPDATE_CHECK").is_some()
+            || std::env::var_os("CI").is_some()
+            || !std::io::stderr().is_terminal();
+        if disabled {
+            return;
+        }
+        if let Ok(Some(latest)) = self.check() {
+            logger.info(
+                "Update",
+                &format!(
+                    "{} {} is available (you have {}), install it with `{}`",
+                    self.name,
+                    latest.version,
+                    self.current,
+                    command_line(&self.install_command(&latest))
+                ),
+            );
+        }
+    }
+
+    /// Install the latest version with `cargo install` if it is newer,
+    /// asking first unless `assume_yes`. Returns whether it was installed.
+    pub async fn update(&self, logger: &mut Logger, assume_yes: bool) -> anyhow::Result<bool> {
+        let latest = self.latest_release()?;
+        if latest.version <= self.current {
+            logger.status_permanent(
+                "Fresh",
+                &format!("{} {} is the latest version", self.name, self.current),
+            );
+            return Ok(false);
+        }
+        let question = format!(
+            "Update {} from {} to {}?",
+            self.name, self.current, latest.version
+        );
+        if !assume_yes && !logger.confirm(&question) {
+            return Ok(false);
+        }
+        let output = run_subprocess(logger, || self.install_command(&latest), None).await?;
+        anyhow::ensure!(
+            output.success(),
+            "cargo install failed with {}",
+            output.status()
+        );
+        // The next `notify` shouldn't announce the version just installed
+        if let Ok(path) = self.state_path() {
+            let _ = std::fs::remove_file(path);
+        }
+        logger.status_permanent("Updated", &format!("{} to {}", self.name, latest.version));
+        Ok(true)
+    }
+
+    /// The `cargo install` command for `release`. GitHub releases are
+    /// installed from their tag, `v<version>` if it isn't known.
+    pub fn install_command(&self, release: &Release) -> CommandBuilder {
+        let version = &release.version;
+        let mut cmd = CommandBuilder::new(crate::cargo::cargo_program());
+        cmd.arg("install");
+        match &self.source {
+            ReleaseSource::CratesIo | ReleaseSource::Registry { .. } => {
+                cmd.args([&self.name, "--version", &version.to_string()]);
+                cmd.args(self.registry_args());
+            }
+            ReleaseSource::GitHub { repo } => {
+                cmd.args(["--git", &format!("https://github.com/{}", repo)]);
+                let tag = match &release.tag {
+                    Some(tag) => tag.clone(),
+                    None => format!("v{}", version),
+                };
+                cmd.args(["--tag", &tag, &self.name]);
+            }
+        }
+        cmd.arg("--locked");
+        cmd
+    }
+
+    /// `--registry name` for releases in an alternative registry.
+    fn registry_args(&self) -> Vec<String> {
+        match &self.source {
+            ReleaseSource::Registry { name } => vec!["--registry".to_string(), name.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    fn state_path(&self) -> anyhow::Result<PathBuf> {
+        let dir = match &self.state_dir {
+            Some(dir) => dir.clone(),
+            None => crate::tempdirs::user_cache_dir()
+                .context("No cache directory")?
+                .join("cargo-plugin-utils")
+                .join("self-update"),
+        };
+        Ok(dir.join(format!("{}.json", self.name)))
+    }
+}
+
+fn read_state(path: &Path) -> Option<State> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+fn write_state(path: &Path, state: &State) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, serde_json::to_vec(state)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Run `cmd` like [`Command::output`](std::process::Command::output),
+/// killing it if it doesn't finish within `timeout`.
+fn output_with_timeout(
+    cmd: &mut std::process::Command,
+    timeout: Duration,
+) -> anyhow::Result<std::process::Output> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read both pipes while waiting, so a chatty child can't block on them
+    let read = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buffer);
+            }
+            buffer
+        })
+    };
+    let stdout = read(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = read(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("Timed out after {}s", timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    Ok(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// The version of `name` in `cargo search` output, a line like
+/// `name = "1.2.3"    # description`.
+fn parse_search(output: &str, name: &str) -> anyhow::Result<Version> {
+    output
+        .lines()
+        .find_map(|line| {
+            let (found, rest) = line.split_once(" = \"")?;
+            let (version, _) = rest.split_once('"')?;
+            (found == name).then(|| Version::parse(version).ok())?
+        })
+        .with_context(|| format!("{} not found in the registry", name))
+}
+
+/// A GitHub release from its JSON, with the version from the tag name.
+fn parse_release(json: &[u8]) -> anyhow::Result<Release> {
+    #[derive(serde::Deserialize)]
+    struct GitHubRelease {
+        tag_name: String,
+    }
+    let release: GitHubRelease = serde_json::from_slice(json).context("Invalid GitHub release")?;
+    let tag = release.tag_name.trim_start_matches('v');
+    let version =
+        Version::parse(tag).with_context(|| format!("Release tag `{}` isn't a version", tag))?;
+    Ok(Release {
+        version,
+        tag: Some(release.tag_name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search_and_release() {
+        let output = "cargo-foo = \"0.3.1\"    # Does foo\ncargo-foo-bar = \"1.0.0\"\n... and 2 \
+                      crates more";
+        assert_eq!(
+            parse_search(output, "cargo-foo").unwrap(),
+            Version::new(0, 3, 1)
+        );
+        assert!(parse_search(output, "cargo-baz").is_err());
+
+        let json = br#"{"tag_name": "v1.2.0", "name": "Release 1.2.0"}"#;
+        assert_eq!(
+            parse_release(json).unwrap(),
+            Release {
+                version: Version::new(1, 2, 0),
+                tag: Some("v1.2.0".to_string()),
+            }
+        );
+        assert!(parse_release(br#"{"tag_name": "nightly"}"#).is_err());
+    }
+
+    fn no_lookup() -> anyhow::Result<Release> {
+        panic!("looked up the latest release")
+    }
+
+    #[test]
+    fn test_check_uses_recent_state() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let updater = Updater::new("cargo-foo", "0.3.0")
+            .unwrap()
+            .state_dir(dir.path());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let state = State {
+            checked_at: now,
+            latest: Some("0.4.0".to_string()),
+            tag: None,
+        };
+        write_state(&updater.state_path().unwrap(), &state).unwrap();
+        // Served from the state file, without running `cargo search`
+        assert_eq!(
+            updater
+                .check_with(no_lookup)
+                .unwrap()
+                .map(|release| release.version),
+            Some(Version::new(0, 4, 0))
+        );
+
+        let current = Updater::new("cargo-foo", "0.4.0")
+            .unwrap()
+            .state_dir(dir.path());
+        assert_eq!(current.check_with(no_lookup).unwrap(), None);
+
+        // A recent failed lookup isn't retried
+        let failed = State {
+            checked_at: now,
+            latest: None,
+            tag: None,
+        };
+        write_state(&updater.state_path().unwrap(), &failed).unwrap();
+        assert_eq!(updater.check_with(no_lookup).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_records_failed_lookup() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let updater = Updater::new("cargo-foo", "0.3.0")
+            .unwrap()
+            .state_dir(dir.path());
+        let err = updater
+            .check_with(|| Err(anyhow::anyhow!("offline")))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "offline");
+        let state = read_state(&updater.state_path().unwrap()).unwrap();
+        assert!(state.checked_at > 0);
+        assert_eq!(state.latest, None);
+        // Not asked again within the interval
+        assert_eq!(updater.check_with(no_lookup).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_with_timeout() {
+        let output =
+            output_with_timeout(std::process::Command::new("echo").arg("hi"), LOOKUP_TIMEOUT)
+                .unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+        let start = Instant::now();
+        let err = output_with_timeout(
+            std::process::Command::new("sleep").arg("10"),
+            Duration::from_millis(200),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Timed out"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_install_command() {
+        // The arguments, after checking the program
+        let argv = |cmd: CommandBuilder| -> Vec<String> {
+            assert_eq!(cmd.get_argv()[0], crate::cargo::cargo_program());
+            cmd.get_argv()[1..]
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+        let release = Release {
+            version: Version::new(1, 0, 0),
+            tag: None,
+        };
+        let updater = Updater::new("cargo-foo", "0.1.0").unwrap();
+        assert_eq!(
+            argv(updater.install_command(&release)),
+            ["install", "cargo-foo", "--version", "1.0.0", "--locked"]
+        );
+        let updater = updater.github("acme/cargo-foo");
+        assert_eq!(
+            argv(updater.install_command(&release)),
+            [
+                "install",
+                "--git",
+                "https://github.com/acme/cargo-foo",
+                "--tag",
+                "v1.0.0",
+                "cargo-foo",
+                "--locked"
+            ]
+        );
+        let tagged = Release {
+            tag: Some("1.0.0".to_string()),
+            ..release.clone()
+        };
+        assert!(argv(updater.install_command(&tagged)).contains(&"1.0.0".to_string()));
+        assert!(!argv(updater.install_command(&tagged)).contains(&"v1.0.0".to_string()));
+        let updater = updater.registry("internal");
+        assert_eq!(
+            argv(updater.install_command(&release)),
+            [
+                "install",
+                "cargo-foo",
+                "--version",
+                "1.0.0",
+                "--registry",
+                "internal",
+                "--locked"
+            ]
+        );
+    }
+}
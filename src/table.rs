@@ -0,0 +1,236 @@
+//! Width-aware plain-text tables.
+//!
+//! Column widths are computed at render time from the available width: when
+//! the natural widths don't fit, the widest columns are shrunk and their
+//! cells truncated with `…`. Rendering is cheap, so live output (e.g.
+//! [`Logger::status_board`](crate::logger::Logger::status_board)) re-renders
+//! on every redraw and follows terminal resizes.
+//!
+//! ```
+//! use cargo_plugin_utils::table::{
+//!     Align,
+//!     Table,
+//! };
+//!
+//! let mut table = Table::new(["Crate", "Version"]).align(1, Align::Right);
+//! table.add_row(["serde", "1.0.228"]);
+//! table.add_row(["tokio", "1.49.0"]);
+//! assert_eq!(
+//!     console::strip_ansi_codes(&table.render(80)),
+//!     "Crate  Version\nserde  1.0.228\ntokio   1.49.0"
+//! );
+//! ```
+
+use console::{
+    Alignment,
+    measure_text_width,
+    pad_str,
+    style,
+    truncate_str,
+};
+
+/// Columns are never shrunk below this width.
+const MIN_COLUMN_WIDTH: usize = 4;
+
+/// Space between columns.
+const COLUMN_GAP: &str = "  ";
+
+/// Alignment of a table column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    /// Pad on the right
+    #[default]
+    Left,
+    /// Pad on the left (numbers, sizes, durations)
+    Right,
+}
+
+/// A table with a bold header row.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    align: Vec<Align>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Create a table with the given column headers.
+    pub fn new<I, S>(headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let headers: Vec<String> = headers.into_iter().map(Into::into).collect();
+        Self {
+            align: vec![Align::Left; headers.len()],
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Set the alignment of column `column` (0-based).
+    pub fn align(mut self, column: usize, align: Align) -> Self {
+        if let Some(slot) = self.align.get_mut(column) {
+            *slot = align;
+        }
+        self
+    }
+
+    /// Append a row. Missing cells are left empty, extra cells are ignored.
+    pub fn add_row<I, S>(&mut self, cells: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut row: Vec<String> = cells
+            .into_iter()
+            .take(self.headers.len())
+            .map(Into::into)
+            .collect();
+        row.resize(self.headers.len(), String::new());
+        self.rows.push(row);
+    }
+
+    /// Remove all rows, keeping headers and alignment.
+    pub fn clear_rows(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Number of rows (excluding the header).
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Whether the table has no rows.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Column widths that fit into `width` terminal columns.
+    fn column_widths(&self, width: usize) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .headers
+            .iter()
+            .map(|header| measure_text_width(header))
+            .collect();
+        for row in &self.rows {
+            for (column, cell) in row.iter().enumerate() {
+                widths[column] = widths[column].max(measure_text_width(cell));
+            }
+        }
+
+        let gaps = COLUMN_GAP.len() * self.headers.len().saturating_sub(1);
+        let available = width.saturating_sub(gaps);
+        while widths.iter().sum::<usize>() > available {
+            let Some((widest, &current)) =
+                widths.iter().enumerate().max_by_key(|(_, width)| **width)
+            else {
+                break;
+            };
+            if current <= MIN_COLUMN_WIDTH {
+                break;
+            }
+            widths[widest] = current - 1;
+        }
+        widths
+    }
+
+    /// Render the table to fit into `width` terminal columns.
+    ///
+    /// Lines are separated by `\n`, without a trailing newline. The last
+    /// column is not padded, so lines don't end in whitespace.
+    pub fn render(&self, width: usize) -> String {
+        let widths = self.column_widths(width);
+        let render_row = |cells: &[String], header: bool| {
+            let last = cells.len().saturating_sub(1);
+            let mut line = String::new();
+            for (column, cell) in cells.iter().enumerate() {
+                let align = match self.align[column] {
+                    Align::Left => Alignment::Left,
+                    Align::Right => Alignment::Right,
+                };
+                let cell = truncate_str(cell, widths[column], "…");
+                let padded = if column == last && align == Alignment::Left {
+                    cell.into_owned()
+                } else {
+                    pad_str(&cell, widths[column], align, None).into_owned()
+                };
+                if column > 0 {
+                    line.push_str(COLUMN_GAP);
+                }
+                if header {
+                    line.push_str(&style(padded).bold().to_string());
+                } else {
+                    line.push_str(&padded);
+                }
+            }
+            line
+        };
+
+        let mut lines = vec![render_row(&self.headers, true)];
+        lines.extend(self.rows.iter().map(|row| render_row(row, false)));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(table: &Table, width: usize) -> String {
+        console::strip_ansi_codes(&table.render(width)).into_owned()
+    }
+
+    #[test]
+    fn test_table_natural_widths() {
+        let mut table = Table::new(["Name", "Size"]).align(1, Align::Right);
+        table.add_row(["a", "1 MB"]);
+        table.add_row(["longer-name", "12 MB"]);
+        assert_eq!(
+            plain(&table, 80),
+            "Name          Size\na             1 MB\nlonger-name  12 MB"
+        );
+    }
+
+    #[test]
+    fn test_table_shrinks_widest_column() {
+        let mut table = Table::new(["Crate", "Status"]);
+        table.add_row(["a-very-long-crate-name", "ok"]);
+        let rendered = plain(&table, 16);
+        for line in rendered.lines() {
+            assert!(measure_text_width(line) <= 16, "{:?}", line);
+        }
+        assert!(rendered.contains("a-very-…"));
+        assert!(rendered.ends_with("ok"));
+    }
+
+    #[test]
+    fn test_table_relayout_on_width_change() {
+        let mut table = Table::new(["Crate", "Status"]);
+        table.add_row(["a-very-long-crate-name", "compiling"]);
+        let narrow = plain(&table, 20);
+        let wide = plain(&table, 80);
+        assert_ne!(narrow, wide);
+        assert!(wide.contains("a-very-long-crate-name"));
+    }
+
+    #[test]
+    fn test_table_minimum_column_width() {
+        let mut table = Table::new(["Crate", "Status"]);
+        table.add_row(["serde", "compiling"]);
+        // Too narrow to fit: columns stop shrinking at the minimum width
+        let rendered = plain(&table, 3);
+        assert!(rendered.lines().all(|line| measure_text_width(line) >= 4));
+    }
+
+    #[test]
+    fn test_table_row_padding() {
+        let mut table = Table::new(["A", "B", "C"]);
+        table.add_row(["1"]);
+        table.add_row(["1", "2", "3", "4"]);
+        assert_eq!(table.len(), 2);
+        assert_eq!(plain(&table, 80), "A  B  C\n1     \n1  2  3");
+        table.clear_rows();
+        assert!(table.is_empty());
+    }
+}
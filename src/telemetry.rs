@@ -0,0 +1,366 @@
+//! Opt-in usage recording.
+//!
+//! Nothing is recorded until the user agrees, either at the first-run prompt
+//! of [`Telemetry::ensure_consent`] or with `--telemetry on`. Each recorded
+//! [`Event`] holds only the subcommand name, its duration and whether it
+//! succeeded, never arguments, paths or output. Events are appended to a file
+//! in the user's cache directory and can be [summarized](Telemetry::summary)
+//! locally; they only leave the machine if the plugin author configured an
+//! [endpoint](Telemetry::endpoint) and [`upload`](Telemetry::upload) is
+//! called.
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::telemetry::{
+//!     Telemetry,
+//!     TelemetrySetting,
+//! };
+//!
+//! # fn build() -> anyhow::Result<()> { Ok(()) }
+//! # fn example(flag: Option<TelemetrySetting>) -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let telemetry = Telemetry::new("cargo-foo", env!("CARGO_PKG_VERSION"));
+//! telemetry.ensure_consent(&mut logger, flag)?;
+//!
+//! let started = std::time::Instant::now();
+//! let result = build();
+//! telemetry.record("build", started.elapsed(), result.is_ok());
+//! # result
+//! # }
+//! ```
+//!
+//! `CARGO_PLUGIN_TELEMETRY=off` and `DO_NOT_TRACK=1` turn recording off
+//! regardless of the stored choice.
+
+use std::collections::BTreeMap;
+use std::io::{
+    IsTerminal,
+    Write,
+};
+use std::path::PathBuf;
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::logger::Logger;
+
+/// Value of a `--telemetry` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TelemetrySetting {
+    /// Record usage
+    On,
+    /// Don't record usage
+    Off,
+}
+
+/// One run of a subcommand.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Subcommand name, e.g. `build`
+    pub command: String,
+    /// Plugin version
+    pub version: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Run time in milliseconds
+    pub duration_ms: u64,
+    /// Whether the subcommand succeeded
+    pub success: bool,
+}
+
+/// Aggregated events of one subcommand.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CommandStats {
+    /// Number of runs
+    pub runs: u64,
+    /// Number of failed runs
+    pub failures: u64,
+    /// Sum of the run times in milliseconds
+    pub total_ms: u64,
+}
+
+/// Usage recording of one plugin.
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+    plugin: String,
+    version: String,
+    endpoint: Option<String>,
+    dir: Option<PathBuf>,
+}
+
+impl Telemetry {
+    /// Telemetry of the plugin `plugin` at `version`, stored under
+    /// `<cache-dir>/cargo-plugin-utils/telemetry/<plugin>`.
+    pub fn new(plugin: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            plugin: plugin.into(),
+            version: version.into(),
+            endpoint: None,
+            dir: None,
+        }
+    }
+
+    /// URL that [`upload`](Self::upload) posts the summary to.
+    pub fn endpoint(mut self, url: impl Into<String>) -> Self {
+        self.endpoint = Some(url.into());
+        self
+    }
+
+    /// Store the consent and events in `dir`.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// The stored choice: `None` until the user has been asked.
+    pub fn consent(&self) -> Option<bool> {
+        let path = self.state_dir().ok()?.join("consent");
+        match std::fs::read_to_string(path).ok()?.trim() {
+            "on" => Some(true),
+            "off" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Store the user's choice. Turning telemetry off also removes the events
+    /// recorded so far.
+    pub fn set_consent(&self, enabled: bool) -> anyhow::Result<()> {
+        let dir = self.state_dir()?;
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join("consent");
+        std::fs::write(&path, if enabled { "on\n" } else { "off\n" })
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        if !enabled {
+            self.clear()?;
+        }
+        Ok(())
+    }
+
+    /// Apply a `--telemetry` flag, or ask on the first interactive run.
+    ///
+    /// A flag is stored as the new choice. Without a flag and without a
+    /// stored choice the user is asked once; when nobody can answer (stdin
+    /// isn't a terminal) nothing is stored and telemetry stays off.
+    pub fn ensure_consent(
+        &self,
+        logger: &mut Logger,
+        flag: Option<TelemetrySetting>,
+    ) -> anyhow::Result<()> {
+        if let Some(setting) = flag {
+            return self.set_consent(setting == TelemetrySetting::On);
+        }
+        if self.consent().is_some() || disabled_by_env() || !std::io::stdin().is_terminal() {
+            return Ok(());
+        }
+        logger.print_message(&format!(
+            "{} can record which subcommands you use, how long they take and whether they \
+             succeed, to help improve it. No arguments, paths or output are recorded. Change \
+             this later with `--telemetry on|off`.",
+            self.plugin
+        ));
+        let enabled = logger.confirm("Enable usage statistics?");
+        self.set_consent(enabled)
+    }
+
+    /// Whether events are recorded: the user agreed and the environment
+    /// doesn't turn it off.
+    pub fn enabled(&self) -> bool {
+        !disabled_by_env() && self.consent() == Some(true)
+    }
+
+    /// Record a run of `command` if [enabled](Self::enabled). Errors are
+    /// ignored, telemetry never fails the plugin.
+    pub fn record(&self, command: &str, duration: Duration, success: bool) {
+        if !self.enabled() {
+            return;
+        }
+        let event = Event {
+            command: command.to_string(),
+            version: self.version.clone(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            success,
+        };
+        let _ = self.append(&event);
+    }
+
+    /// The recorded events, oldest first.
+    pub fn events(&self) -> anyhow::Result<Vec<Event>> {
+        let path = self.events_path()?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        // Skip lines torn by a crash rather than losing everything
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// The recorded events per subcommand.
+    pub fn summary(&self) -> anyhow::Result<BTreeMap<String, CommandStats>> {
+        let mut summary: BTreeMap<String, CommandStats> = BTreeMap::new();
+        for event in self.events()? {
+            let stats = summary.entry(event.command).or_default();
+            stats.runs += 1;
+            stats.failures += u64::from(!event.success);
+            stats.total_ms = stats.total_ms.saturating_add(event.duration_ms);
+        }
+        Ok(summary)
+    }
+
+    /// Post the [summary](Self::summary) as JSON to the endpoint with `curl`
+    /// and remove the uploaded events. Does nothing without an endpoint,
+    /// consent or events; returns whether anything was sent.
+    pub fn upload(&self) -> anyhow::Result<bool> {
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(false);
+        };
+        let summary = self.summary()?;
+        if !self.enabled() || summary.is_empty() {
+            return Ok(false);
+        }
+        let body = serde_json::json!({
+            "plugin": self.plugin,
+            "version": self.version,
+            "os": std::env::consts::OS,
+            "commands": summary,
+        });
+        let output = std::process::Command::new("curl")
+            .args(["-sSf", "--max-time", "10", "-X", "POST"])
+            .args(["-H", "Content-Type: application/json", "--data-binary"])
+            .arg(body.to_string())
+            .arg(endpoint)
+            .output()
+            .context("Failed to run curl")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "Failed to upload usage statistics: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        self.clear()?;
+        Ok(true)
+    }
+
+    /// Remove the recorded events.
+    pub fn clear(&self) -> anyhow::Result<()> {
+        let path = self.events_path()?;
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&self, event: &Event) -> anyhow::Result<()> {
+        let path = self.events_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+        Ok(())
+    }
+
+    fn state_dir(&self) -> anyhow::Result<PathBuf> {
+        match &self.dir {
+            Some(dir) => Ok(dir.clone()),
+            None => Ok(crate::tempdirs::user_cache_dir()
+                .context("No cache directory")?
+                .join("cargo-plugin-utils")
+                .join("telemetry")
+                .join(&self.plugin)),
+        }
+    }
+
+    fn events_path(&self) -> anyhow::Result<PathBuf> {
+        Ok(self.state_dir()?.join("events.jsonl"))
+    }
+}
+
+/// Whether the environment turns telemetry off.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+fn disabled_by_env() -> bool {
+    let var = |key: &str| std::env::var(key).unwrap_or_default().to_ascii_lowercase();
+    matches!(
+        var("CARGO_PLUGIN_TELEMETRY").as_str(),
+        "off" | "0" | "false"
+    ) || matches!(var("DO_NOT_TRACK").as_str(), "1" | "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nothing_recorded_without_consent() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let telemetry = Telemetry::new("cargo-foo", "0.1.0").dir(dir.path());
+        assert_eq!(telemetry.consent(), None);
+        telemetry.record("build", Duration::from_secs(1), true);
+        assert!(!dir.path().join("events.jsonl").exists());
+
+        telemetry.set_consent(false).unwrap();
+        telemetry.record("build", Duration::from_secs(1), true);
+        assert_eq!(telemetry.consent(), Some(false));
+        assert!(telemetry.events().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_record_and_summarize() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let telemetry = Telemetry::new("cargo-foo", "0.1.0").dir(dir.path());
+        let mut logger = Logger::new();
+        telemetry
+            .ensure_consent(&mut logger, Some(TelemetrySetting::On))
+            .unwrap();
+        if disabled_by_env() {
+            return;
+        }
+        telemetry.record("build", Duration::from_millis(1500), true);
+        telemetry.record("build", Duration::from_millis(500), false);
+        telemetry.record("check", Duration::from_millis(200), true);
+
+        let events = telemetry.events().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].version, "0.1.0");
+        let summary = telemetry.summary().unwrap();
+        assert_eq!(
+            summary["build"],
+            CommandStats {
+                runs: 2,
+                failures: 1,
+                total_ms: 2000
+            }
+        );
+        assert_eq!(summary["check"].runs, 1);
+        // Without an endpoint nothing is sent
+        assert!(!telemetry.upload().unwrap());
+
+        // Opting out removes what was recorded
+        telemetry
+            .ensure_consent(&mut logger, Some(TelemetrySetting::Off))
+            .unwrap();
+        assert!(telemetry.events().unwrap().is_empty());
+    }
+}
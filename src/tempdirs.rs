@@ -0,0 +1,260 @@
+//! Scoped temporary directories for plugin work.
+//!
+//! Temporary directories are created under `<target-dir>/tmp` by default, so
+//! they live next to the build artifacts (and are removed by `cargo clean`)
+//! instead of in the system temp directory. They are removed when dropped,
+//! unless keeping was requested (e.g. via `--keep-temp`), in which case the
+//! retained path is printed so the user can inspect it.
+//!
+//! ```no_run
+//! let dir = cargo_plugin_utils::tempdirs::scoped("cargo-foo")?;
+//! std::fs::write(dir.path().join("notes.txt"), "scratch")?;
+//! // removed here
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::{
+    AtomicU32,
+    Ordering,
+};
+
+use anyhow::Context;
+
+use crate::logger::Logger;
+
+/// Create a temporary directory under `<target-dir>/tmp` that is removed on
+/// drop.
+pub fn scoped(prefix: &str) -> anyhow::Result<ScopedTempDir> {
+    TempDirBuilder::new(prefix).create()
+}
+
+/// Default root for temporary directories: `<target-dir>/tmp`.
+///
+/// The target directory is taken from `CARGO_TARGET_DIR` or `cargo metadata`,
+/// falling back to the system temp directory outside of a cargo project.
+pub fn default_root() -> PathBuf {
+    match target_dir() {
+        Some(target_dir) => target_dir.join("tmp"),
+        None => std::env::temp_dir(),
+    }
+}
+
+/// The target directory from `CARGO_TARGET_DIR` or `cargo metadata`, if in a
+/// cargo project.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+pub(crate) fn target_dir() -> Option<PathBuf> {
+    if let Some(target_dir) = std::env::var_os("CARGO_TARGET_DIR") {
+        return Some(PathBuf::from(target_dir));
+    }
+    crate::common::get_metadata(None)
+        .ok()
+        .map(|metadata| metadata.target_directory.into_std_path_buf())
+}
+
+/// The user's cache directory: `$XDG_CACHE_HOME` or `~/.cache`,
+/// `~/Library/Caches` on macOS and `%LOCALAPPDATA%` on Windows.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+pub(crate) fn user_cache_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    }
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    if cfg!(target_os = "macos") {
+        Some(home.join("Library").join("Caches"))
+    } else {
+        Some(home.join(".cache"))
+    }
+}
+
+/// Builder for [`ScopedTempDir`] with a configurable root and keep behavior.
+#[derive(Debug, Clone)]
+pub struct TempDirBuilder {
+    prefix: String,
+    root: Option<PathBuf>,
+    keep: bool,
+}
+
+impl TempDirBuilder {
+    /// Start building a temporary directory whose name starts with `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            root: None,
+            keep: false,
+        }
+    }
+
+    /// Create the directory under `root` instead of `<target-dir>/tmp`.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Keep the directory instead of removing it on drop (`--keep-temp`).
+    pub fn keep(mut self, keep: bool) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Create the directory.
+    pub fn create(self) -> anyhow::Result<ScopedTempDir> {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+        let root = self.root.unwrap_or_else(default_root);
+        std::fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create {}", root.display()))?;
+
+        loop {
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.subsec_nanos())
+                .unwrap_or_default();
+            let name = format!(
+                "{}-{}-{}-{:08x}",
+                self.prefix,
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                nanos
+            );
+            let path = root.join(name);
+            match std::fs::create_dir(&path) {
+                Ok(()) => {
+                    return Ok(ScopedTempDir {
+                        path,
+                        keep: self.keep,
+                    });
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Failed to create {}", path.display()));
+                }
+            }
+        }
+    }
+}
+
+/// A temporary directory that is removed when dropped (unless kept).
+#[derive(Debug)]
+pub struct ScopedTempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl ScopedTempDir {
+    /// Path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the directory will be kept on drop.
+    pub fn is_kept(&self) -> bool {
+        self.keep
+    }
+
+    /// Keep the directory and return its path, without printing a notice.
+    pub fn into_path(mut self) -> PathBuf {
+        self.keep = false;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl AsRef<Path> for ScopedTempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScopedTempDir {
+    fn drop(&mut self) {
+        if self.path.as_os_str().is_empty() {
+            // Ownership was transferred by into_path()
+            return;
+        }
+        if self.keep {
+            Logger::new().info("Keeping", &self.path.display().to_string());
+        } else {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_scoped_temp_dir_removed_on_drop() {
+        let root = TempDir::new().unwrap();
+        let dir = TempDirBuilder::new("cargo-test")
+            .root(root.path())
+            .create()
+            .unwrap();
+        let path = dir.path().to_path_buf();
+        assert!(path.is_dir());
+        assert!(path.starts_with(root.path()));
+        assert!(
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("cargo-test-")
+        );
+        std::fs::write(path.join("file.txt"), "content").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_scoped_temp_dir_keep() {
+        let root = TempDir::new().unwrap();
+        let dir = TempDirBuilder::new("cargo-test")
+            .root(root.path())
+            .keep(true)
+            .create()
+            .unwrap();
+        assert!(dir.is_kept());
+        let path = dir.path().to_path_buf();
+        drop(dir);
+        assert!(path.is_dir());
+    }
+
+    #[test]
+    fn test_scoped_temp_dir_into_path() {
+        let root = TempDir::new().unwrap();
+        let dir = TempDirBuilder::new("cargo-test")
+            .root(root.path())
+            .create()
+            .unwrap();
+        let path = dir.into_path();
+        assert!(path.is_dir());
+    }
+
+    #[test]
+    fn test_scoped_temp_dirs_are_unique() {
+        let root = TempDir::new().unwrap();
+        let first = TempDirBuilder::new("same")
+            .root(root.path())
+            .create()
+            .unwrap();
+        let second = TempDirBuilder::new("same")
+            .root(root.path())
+            .create()
+            .unwrap();
+        assert_ne!(first.path(), second.path());
+    }
+
+    #[test]
+    fn test_default_root_under_target() {
+        let root = default_root();
+        assert!(root.ends_with("tmp"));
+    }
+}
@@ -0,0 +1,461 @@
+//! Run `cargo test` or `cargo nextest` and collect a typed [`TestReport`].
+//!
+//! Both tools are asked for libtest's JSON event stream, which is parsed as
+//! it arrives: the status line counts passed, failed and ignored tests live,
+//! and the finished report lists every test with its duration and, for
+//! failures, its captured output:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::test_runner::TestRunner;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut logger = Logger::new();
+//! let report = TestRunner::cargo_test()
+//!     .args(["--workspace"])
+//!     .run(&mut logger)
+//!     .await?;
+//! for test in report.failures() {
+//!     eprintln!("---- {} ----\n{}", test.name, test.output);
+//! }
+//! for test in report.slowest(5) {
+//!     eprintln!("{:?} {}", test.duration, test.name);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! libtest's JSON output is unstable: nextest is run with
+//! `NEXTEST_EXPERIMENTAL_LIBTEST_JSON=1`, and `cargo test` needs a nightly
+//! toolchain unless [`TestRunner::bootstrap`] is turned on.
+
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::Duration;
+
+use portable_pty::CommandBuilder;
+use serde::Deserialize;
+
+use crate::logger::{
+    Logger,
+    RunOptions,
+    format_status,
+    run_command,
+};
+
+/// The tool running the tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestTool {
+    /// `cargo test`
+    Cargo,
+    /// `cargo nextest run`
+    Nextest,
+}
+
+/// Builder for a test run.
+#[derive(Debug, Clone)]
+pub struct TestRunner {
+    tool: TestTool,
+    args: Vec<String>,
+    options: RunOptions,
+    bootstrap: bool,
+}
+
+impl TestRunner {
+    /// Run `cargo test`.
+    pub fn cargo_test() -> Self {
+        Self::new(TestTool::Cargo)
+    }
+
+    /// Run `cargo nextest run`.
+    pub fn nextest() -> Self {
+        Self::new(TestTool::Nextest)
+    }
+
+    /// Run the tests with `tool`.
+    pub fn new(tool: TestTool) -> Self {
+        Self {
+            tool,
+            args: Vec::new(),
+            options: RunOptions::default(),
+            bootstrap: false,
+        }
+    }
+
+    /// Add an argument, e.g. `--workspace` or a test filter. Arguments after
+    /// `--` go to the test binaries, as on the command line.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add several arguments, see [`arg`](Self::arg).
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run with `options` (directory, environment, timeout, ...). The output
+    /// is always piped and not shown in a window.
+    pub fn options(mut self, options: RunOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set `RUSTC_BOOTSTRAP=1` so `cargo test` gets libtest's JSON output on
+    /// a stable toolchain.
+    ///
+    /// Off by default: cargo passes the variable on to rustc and build
+    /// scripts as well, so the whole build runs as if on nightly, and crates
+    /// probing for nightly features turn them on.
+    pub fn bootstrap(mut self, bootstrap: bool) -> Self {
+        self.bootstrap = bootstrap;
+        self
+    }
+
+    /// The command that runs the tests.
+    pub fn command(&self) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new("cargo");
+        let (tool_args, binary_args) = match self.args.iter().position(|arg| arg == "--") {
+            Some(index) => (&self.args[..index], &self.args[index + 1..]),
+            None => (&self.args[..], &[][..]),
+        };
+        match self.tool {
+            TestTool::Cargo => {
+                cmd.arg("test");
+                cmd.args(tool_args);
+                cmd.args(["--", "-Z", "unstable-options", "--format", "json"]);
+                cmd.arg("--report-time");
+                if self.bootstrap {
+                    cmd.env("RUSTC_BOOTSTRAP", "1");
+                }
+            }
+            TestTool::Nextest => {
+                cmd.args(["nextest", "run", "--message-format", "libtest-json"]);
+                cmd.args(tool_args);
+                if !binary_args.is_empty() {
+                    cmd.arg("--");
+                }
+                cmd.env("NEXTEST_EXPERIMENTAL_LIBTEST_JSON", "1");
+            }
+        }
+        cmd.args(binary_args);
+        cmd
+    }
+
+    /// Run the tests, showing the running counts on the status line.
+    ///
+    /// Failing tests don't make this an error; check
+    /// [`TestReport::success`]. It fails when the tool can't be started, or
+    /// times out or is cancelled.
+    pub async fn run(self, logger: &mut Logger) -> anyhow::Result<TestReport> {
+        let parser = Arc::new(Mutex::new(EventParser::default()));
+        logger.status("Testing", "");
+        let status_bar = logger.status_bar();
+        let hook_parser = parser.clone();
+        let options = self
+            .options
+            .clone()
+            .piped(true)
+            .window_height(0)
+            .on_line(move |line| {
+                let mut parser = hook_parser.lock().unwrap_or_else(|err| err.into_inner());
+                if parser.push_line(line)
+                    && let Some(status_bar) = &status_bar
+                {
+                    status_bar.set_message(format_status("Testing", &parser.report.counts()));
+                }
+            });
+        let result = run_command(logger, self.command(), &options).await;
+        logger.clear_status();
+        let output = result?;
+
+        let mut parser = parser.lock().unwrap_or_else(|err| err.into_inner());
+        let mut report = std::mem::take(&mut parser.report);
+        report.exit_code = output.exit_code();
+        report.stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        if report.success() {
+            logger.status_permanent("Tested", &report.counts());
+        } else if report.tests.is_empty() && report.stderr.contains("nightly") {
+            logger.error(
+                "Failed",
+                "libtest JSON output needs nightly, or TestRunner::bootstrap",
+            );
+        } else if report.tests.is_empty() {
+            logger.error("Failed", "tests didn't run, see the report's stderr");
+        } else {
+            logger.error("Failed", &report.counts());
+        }
+        Ok(report)
+    }
+}
+
+/// How a test ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestOutcome {
+    /// The test passed
+    Passed,
+    /// The test failed or panicked
+    Failed,
+    /// The test was skipped with `#[ignore]`
+    Ignored,
+}
+
+/// Result of one test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    /// Full test name, e.g. `logger::tests::test_status`
+    pub name: String,
+    /// How the test ended
+    pub outcome: TestOutcome,
+    /// Run time, if the tool reported it
+    pub duration: Option<Duration>,
+    /// Captured output (failures only, unless run with `--nocapture`)
+    pub output: String,
+}
+
+/// Results of a test run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestReport {
+    /// All finished tests, in completion order
+    pub tests: Vec<TestCase>,
+    /// Exit code of the test tool
+    pub exit_code: u32,
+    /// Everything the tool printed to stderr, e.g. compiler errors
+    pub stderr: String,
+}
+
+impl TestReport {
+    /// Whether the run exited successfully without failed tests.
+    pub fn success(&self) -> bool {
+        self.exit_code == 0 && self.failed() == 0
+    }
+
+    /// Number of passed tests.
+    pub fn passed(&self) -> usize {
+        self.count(TestOutcome::Passed)
+    }
+
+    /// Number of failed tests.
+    pub fn failed(&self) -> usize {
+        self.count(TestOutcome::Failed)
+    }
+
+    /// Number of ignored tests.
+    pub fn ignored(&self) -> usize {
+        self.count(TestOutcome::Ignored)
+    }
+
+    /// The failed tests with their output.
+    pub fn failures(&self) -> impl Iterator<Item = &TestCase> {
+        self.tests
+            .iter()
+            .filter(|test| test.outcome == TestOutcome::Failed)
+    }
+
+    /// The `limit` slowest tests, slowest first.
+    pub fn slowest(&self, limit: usize) -> Vec<&TestCase> {
+        let mut timed: Vec<_> = self
+            .tests
+            .iter()
+            .filter(|test| test.duration.is_some())
+            .collect();
+        timed.sort_by_key(|test| std::cmp::Reverse(test.duration));
+        timed.truncate(limit);
+        timed
+    }
+
+    /// "3 passed, 1 failed, 2 ignored".
+    pub fn counts(&self) -> String {
+        format!(
+            "{} passed, {} failed, {} ignored",
+            self.passed(),
+            self.failed(),
+            self.ignored()
+        )
+    }
+
+    fn count(&self, outcome: TestOutcome) -> usize {
+        self.tests
+            .iter()
+            .filter(|test| test.outcome == outcome)
+            .count()
+    }
+}
+
+/// One line of libtest's JSON output; only the fields used here.
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    exec_time: Option<f64>,
+    #[serde(default)]
+    stdout: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Builds a [`TestReport`] from libtest JSON lines, ignoring anything else.
+#[derive(Debug, Default)]
+struct EventParser {
+    report: TestReport,
+}
+
+impl EventParser {
+    /// Parse one output line; returns whether a test finished.
+    fn push_line(&mut self, line: &[u8]) -> bool {
+        if !line.starts_with(b"{") {
+            return false;
+        }
+        let Ok(event) = serde_json::from_slice::<Event>(line) else {
+            return false;
+        };
+        if event.kind != "test" {
+            return false;
+        }
+        let outcome = match event.event.as_str() {
+            "ok" => TestOutcome::Passed,
+            "failed" => TestOutcome::Failed,
+            "ignored" => TestOutcome::Ignored,
+            _ => return false,
+        };
+        let mut output = event.stdout.unwrap_or_default();
+        if let Some(message) = event.message {
+            if !output.is_empty() && !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str(&message);
+        }
+        self.report.tests.push(TestCase {
+            name: event.name.unwrap_or_default(),
+            outcome,
+            duration: event
+                .exec_time
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+            output,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = r#"{ "type": "suite", "event": "started", "test_count": 4 }
+{ "type": "test", "event": "started", "name": "tests::fast" }
+{ "type": "test", "name": "tests::fast", "event": "ok", "exec_time": 0.001 }
+{ "type": "test", "name": "tests::slow", "event": "ok", "exec_time": 2.5 }
+{ "type": "test", "name": "tests::broken", "event": "failed", "exec_time": 0.2, "stdout": "thread 'tests::broken' panicked\n" }
+{ "type": "test", "name": "tests::later", "event": "ignored", "message": "needs network" }
+   Compiling foo v0.1.0
+{ "type": "suite", "event": "failed", "passed": 2, "failed": 1, "ignored": 1, "measured": 0, "filtered_out": 0, "exec_time": 2.7 }
+"#;
+
+    fn report() -> TestReport {
+        let mut parser = EventParser::default();
+        let finished = EVENTS
+            .lines()
+            .filter(|line| parser.push_line(line.as_bytes()))
+            .count();
+        assert_eq!(finished, 4);
+        parser.report
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let report = report();
+        assert_eq!(report.counts(), "2 passed, 1 failed, 1 ignored");
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].name, "tests::broken");
+        assert!(failures[0].output.contains("panicked"));
+        assert_eq!(report.tests[3].output, "needs network");
+        assert_eq!(report.tests[3].duration, None);
+    }
+
+    #[test]
+    fn test_slowest() {
+        let report = report();
+        let slowest: Vec<_> = report
+            .slowest(2)
+            .iter()
+            .map(|test| test.name.as_str())
+            .collect();
+        assert_eq!(slowest, ["tests::slow", "tests::broken"]);
+    }
+
+    #[test]
+    fn test_command() {
+        let argv = |runner: TestRunner| {
+            runner
+                .command()
+                .get_argv()
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(
+            argv(TestRunner::cargo_test().args(["-p", "core", "--", "--test-threads", "1"])),
+            "cargo test -p core -- -Z unstable-options --format json --report-time \
+             --test-threads 1"
+        );
+        assert_eq!(
+            argv(TestRunner::nextest().args(["--workspace", "--", "filter"])),
+            "cargo nextest run --message-format libtest-json --workspace -- filter"
+        );
+        assert_eq!(
+            TestRunner::cargo_test()
+                .command()
+                .get_env("RUSTC_BOOTSTRAP"),
+            None
+        );
+        assert_eq!(
+            TestRunner::cargo_test()
+                .bootstrap(true)
+                .command()
+                .get_env("RUSTC_BOOTSTRAP"),
+            Some("1".as_ref())
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_parses_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("cargo");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\ncat <<'EOF'\n{}EOF\nexit 101\n", EVENTS),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        #[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![dir.path().to_path_buf()];
+        paths.extend(std::env::split_paths(&path));
+        let options = RunOptions::new().env("PATH", std::env::join_paths(paths).unwrap());
+
+        let mut logger = Logger::new();
+        let report = TestRunner::cargo_test()
+            .options(options)
+            .run(&mut logger)
+            .await
+            .unwrap();
+        assert!(!report.success());
+        assert_eq!(report.exit_code, 101);
+        assert_eq!(report.tests.len(), 4);
+    }
+}
@@ -0,0 +1,499 @@
+//! Assertions for end-to-end tests of cargo plugin binaries.
+//!
+//! [`CommandAssert`] runs a binary either attached to a pseudo-terminal
+//! (so the plugin sees a TTY and renders its status lines and colors) or with
+//! plain pipes (the way CI and shell redirection see it). The captured output
+//! is normalized (ANSI escapes stripped, carriage-return overwrites resolved)
+//! so tests can match on what a user would actually see on screen.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use cargo_plugin_utils::testing::CommandAssert;
+//!
+//! CommandAssert::cargo_bin("cargo-foo")
+//!     .unwrap()
+//!     .args(["foo", "publish", "--dry-run"])
+//!     .tty(true)
+//!     .run()
+//!     .unwrap()
+//!     .assert_success()
+//!     .assert_status_line("Publishing", "foo v1.2.3");
+//! ```
+
+use std::ffi::{
+    OsStr,
+    OsString,
+};
+use std::io::Read;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::Context;
+use portable_pty::{
+    CommandBuilder,
+    PtySize,
+    native_pty_system,
+};
+
+/// Builder for running a plugin binary and asserting on its output.
+#[derive(Debug, Clone)]
+pub struct CommandAssert {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    env_remove: Vec<OsString>,
+    cwd: Option<PathBuf>,
+    tty: bool,
+    columns: u16,
+}
+
+impl CommandAssert {
+    /// Create an assertion builder for an arbitrary program.
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env: Vec::new(),
+            env_remove: Vec::new(),
+            cwd: None,
+            tty: false,
+            columns: 120,
+        }
+    }
+
+    /// Create an assertion builder for a binary of the crate under test.
+    ///
+    /// Uses the `CARGO_BIN_EXE_<name>` variable that cargo sets for
+    /// integration tests, falling back to the `target/<profile>` directory
+    /// next to the running test executable.
+    #[allow(clippy::disallowed_methods)] // Test helper needs direct env access
+    pub fn cargo_bin(name: &str) -> anyhow::Result<Self> {
+        if let Some(path) = std::env::var_os(format!("CARGO_BIN_EXE_{}", name)) {
+            return Ok(Self::new(path));
+        }
+
+        let current_exe = std::env::current_exe().context("Failed to get current executable")?;
+        // Test executables live in target/<profile>/deps
+        let profile_dir = current_exe
+            .parent()
+            .and_then(|dir| {
+                if dir.ends_with("deps") {
+                    dir.parent()
+                } else {
+                    Some(dir)
+                }
+            })
+            .context("Failed to determine target directory")?;
+        let binary = profile_dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+        if !binary.is_file() {
+            anyhow::bail!(
+                "Binary {} not found (build it first with `cargo build --bin {}`)",
+                binary.display(),
+                name
+            );
+        }
+        Ok(Self::new(binary))
+    }
+
+    /// Add an argument.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add multiple arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Set an environment variable for the child.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.env
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Remove an environment variable from the child's environment.
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.env_remove.push(key.as_ref().to_owned());
+        self
+    }
+
+    /// Set the working directory of the child.
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Run attached to a simulated TTY (`true`) or with plain pipes
+    /// (`false`, the default).
+    ///
+    /// In TTY mode stdout and stderr are merged, exactly as a user sees them
+    /// in their terminal; the combined output is available via
+    /// [`AssertOutput::output`].
+    pub fn tty(mut self, tty: bool) -> Self {
+        self.tty = tty;
+        self
+    }
+
+    /// Set the width of the simulated terminal (default: 120 columns).
+    pub fn columns(mut self, columns: u16) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Run the command to completion and capture its output.
+    pub fn run(self) -> anyhow::Result<AssertOutput> {
+        if self.tty {
+            self.run_tty()
+        } else {
+            self.run_pipe()
+        }
+    }
+
+    fn run_pipe(self) -> anyhow::Result<AssertOutput> {
+        let mut cmd = std::process::Command::new(&self.program);
+        cmd.args(&self.args);
+        for key in &self.env_remove {
+            cmd.env_remove(key);
+        }
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.stdin(std::process::Stdio::null());
+
+        let output = cmd
+            .output()
+            .with_context(|| format!("Failed to run {}", self.program.to_string_lossy()))?;
+
+        let mut combined = output.stdout.clone();
+        combined.extend_from_slice(&output.stderr);
+
+        Ok(AssertOutput {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            combined,
+            exit_code: output.status.code(),
+        })
+    }
+
+    fn run_tty(self) -> anyhow::Result<AssertOutput> {
+        let mut cmd = CommandBuilder::new(&self.program);
+        cmd.args(&self.args);
+        for key in &self.env_remove {
+            cmd.env_remove(key);
+        }
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        match &self.cwd {
+            Some(cwd) => cmd.cwd(cwd),
+            None => {
+                let cwd = std::env::current_dir().context("Failed to get current directory")?;
+                cmd.cwd(cwd);
+            }
+        }
+
+        let pty = native_pty_system()
+            .openpty(PtySize {
+                rows: 24,
+                cols: self.columns,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .context("Failed to create PTY")?;
+        let mut child = pty
+            .slave
+            .spawn_command(cmd)
+            .with_context(|| format!("Failed to spawn {}", self.program.to_string_lossy()))?;
+        // Only the child may hold the slave open, otherwise reads never see EOF
+        drop(pty.slave);
+
+        let mut reader = pty
+            .master
+            .try_clone_reader()
+            .context("Failed to clone PTY reader")?;
+        let reader_thread = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            // Linux reports EIO once the child side is closed; treat as EOF
+            let _ = reader.read_to_end(&mut output);
+            output
+        });
+
+        let status = child.wait().context("Failed to wait for command")?;
+        drop(pty.master);
+        let combined = reader_thread
+            .join()
+            .map_err(|_| anyhow::anyhow!("PTY reader thread panicked"))?;
+
+        Ok(AssertOutput {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            combined,
+            exit_code: status.exit_code().try_into().ok(),
+        })
+    }
+}
+
+/// Captured output of a [`CommandAssert`] run, with assertion helpers.
+///
+/// Assertion methods panic with the normalized output on mismatch, and return
+/// `&Self` so they can be chained.
+#[derive(Debug, Clone)]
+pub struct AssertOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    combined: Vec<u8>,
+    exit_code: Option<i32>,
+}
+
+impl AssertOutput {
+    /// Normalized stdout (empty in TTY mode, where streams are merged).
+    pub fn stdout(&self) -> String {
+        normalize_output(&self.stdout)
+    }
+
+    /// Normalized stderr (empty in TTY mode, where streams are merged).
+    pub fn stderr(&self) -> String {
+        normalize_output(&self.stderr)
+    }
+
+    /// Normalized combined output (stdout followed by stderr in pipe mode,
+    /// the interleaved terminal contents in TTY mode).
+    pub fn output(&self) -> String {
+        normalize_output(&self.combined)
+    }
+
+    /// Raw combined output, including ANSI escape sequences.
+    pub fn raw_output(&self) -> &[u8] {
+        &self.combined
+    }
+
+    /// Exit code of the command (`None` if it was killed by a signal).
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Assert that the command exited successfully.
+    #[track_caller]
+    pub fn assert_success(&self) -> &Self {
+        assert!(
+            self.exit_code == Some(0),
+            "expected success, got exit code {:?}\n--- output ---\n{}",
+            self.exit_code,
+            self.output()
+        );
+        self
+    }
+
+    /// Assert that the command failed.
+    #[track_caller]
+    pub fn assert_failure(&self) -> &Self {
+        assert!(
+            self.exit_code != Some(0),
+            "expected failure, but command succeeded\n--- output ---\n{}",
+            self.output()
+        );
+        self
+    }
+
+    /// Assert that the command exited with a specific code.
+    #[track_caller]
+    pub fn assert_code(&self, code: i32) -> &Self {
+        assert!(
+            self.exit_code == Some(code),
+            "expected exit code {}, got {:?}\n--- output ---\n{}",
+            code,
+            self.exit_code,
+            self.output()
+        );
+        self
+    }
+
+    /// Assert that a cargo-style status line (`   Publishing foo v1.2.3`) was
+    /// printed, regardless of justification and colors.
+    #[track_caller]
+    pub fn assert_status_line(&self, action: &str, target: &str) -> &Self {
+        let output = self.output();
+        assert!(
+            output
+                .lines()
+                .any(|line| is_status_line(line, action, target)),
+            "expected status line `{} {}`\n--- output ---\n{}",
+            action,
+            target,
+            output
+        );
+        self
+    }
+
+    /// Assert that no cargo-style status line with the given action was
+    /// printed.
+    #[track_caller]
+    pub fn assert_no_status(&self, action: &str) -> &Self {
+        let output = self.output();
+        assert!(
+            !output
+                .lines()
+                .any(|line| line.trim_start().split(' ').next() == Some(action)),
+            "unexpected status line `{}`\n--- output ---\n{}",
+            action,
+            output
+        );
+        self
+    }
+
+    /// Assert that the normalized combined output contains `needle`.
+    #[track_caller]
+    pub fn assert_output_contains(&self, needle: &str) -> &Self {
+        let output = self.output();
+        assert!(
+            output.contains(needle),
+            "expected output to contain {:?}\n--- output ---\n{}",
+            needle,
+            output
+        );
+        self
+    }
+
+    /// Assert that the normalized stdout contains `needle`.
+    #[track_caller]
+    pub fn assert_stdout_contains(&self, needle: &str) -> &Self {
+        let stdout = self.stdout();
+        assert!(
+            stdout.contains(needle),
+            "expected stdout to contain {:?}\n--- stdout ---\n{}",
+            needle,
+            stdout
+        );
+        self
+    }
+
+    /// Assert that the normalized stderr contains `needle`.
+    #[track_caller]
+    pub fn assert_stderr_contains(&self, needle: &str) -> &Self {
+        let stderr = self.stderr();
+        assert!(
+            stderr.contains(needle),
+            "expected stderr to contain {:?}\n--- stderr ---\n{}",
+            needle,
+            stderr
+        );
+        self
+    }
+}
+
+/// Normalize terminal output for comparison in tests.
+///
+/// Strips ANSI escape sequences, converts CRLF to LF, and resolves carriage
+/// return overwrites (keeping only the text drawn last on each line), which is
+/// how progress bars and spinners update in place.
+pub fn normalize_output(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let stripped = console::strip_ansi_codes(&text);
+    stripped
+        .replace("\r\n", "\n")
+        .split('\n')
+        .map(|line| {
+            line.rsplit('\r')
+                .find(|part| !part.is_empty())
+                .unwrap_or("")
+        })
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn is_status_line(line: &str, action: &str, target: &str) -> bool {
+    match line.trim_start().split_once(' ') {
+        Some((line_action, line_target)) => line_action == action && line_target.trim() == target,
+        None => false,
+    }
+}
+
+/// Write `contents` to `path` under `root`, creating the directories in
+/// between, to lay out a fixture workspace.
+#[cfg(test)]
+pub(crate) fn write_file(root: &Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_output_strips_ansi() {
+        let output = normalize_output(b"\x1b[1m\x1b[32m   Compiling\x1b[0m foo v0.1.0\r\n");
+        assert_eq!(output, "   Compiling foo v0.1.0\n");
+    }
+
+    #[test]
+    fn test_normalize_output_resolves_carriage_returns() {
+        let output = normalize_output(b"Building 1/3\rBuilding 2/3\rDone\n");
+        assert_eq!(output, "Done\n");
+    }
+
+    #[test]
+    fn test_is_status_line() {
+        assert!(is_status_line(
+            "  Publishing foo v1.2.3",
+            "Publishing",
+            "foo v1.2.3"
+        ));
+        assert!(!is_status_line(
+            "  Publishing foo v1.2.4",
+            "Publishing",
+            "foo v1.2.3"
+        ));
+        assert!(!is_status_line("Publishing", "Publishing", "foo"));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_command_assert_pipe() {
+        let output = CommandAssert::new("sh")
+            .args([
+                "-c",
+                "echo data; printf '\\033[32m%12s\\033[0m foo v1.2.3\\n' Publishing >&2",
+            ])
+            .run()
+            .unwrap();
+        output
+            .assert_success()
+            .assert_stdout_contains("data")
+            .assert_stderr_contains("foo v1.2.3")
+            .assert_status_line("Publishing", "foo v1.2.3")
+            .assert_no_status("Compiling");
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_command_assert_tty() {
+        let output = CommandAssert::new("sh")
+            .args(["-c", "test -t 2 && echo is-a-tty; exit 3"])
+            .tty(true)
+            .run()
+            .unwrap();
+        output.assert_code(3).assert_output_contains("is-a-tty");
+    }
+
+    #[test]
+    fn test_command_assert_cargo_bin_missing() {
+        assert!(CommandAssert::cargo_bin("nonexistent-binary-xyz-123").is_err());
+    }
+}
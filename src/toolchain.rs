@@ -0,0 +1,376 @@
+//! Cargo toolchain detection and capability checks.
+//!
+//! Plugins often need to branch on what the installed cargo can do (e.g.
+//! whether `cargo publish --workspace` exists). Instead of parsing version
+//! strings inline, check a [`Feature`] against the detected cargo:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::toolchain::{
+//!     Feature,
+//!     supports,
+//! };
+//!
+//! if supports(Feature::PublishWorkspace) {
+//!     // one `cargo publish --workspace` call
+//! } else {
+//!     // publish packages one by one
+//! }
+//! ```
+//!
+//! The minimum supported Rust version of a package or workspace is checked
+//! the same way:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::toolchain::{
+//!     toolchain_meets_msrv,
+//!     workspace_msrv,
+//! };
+//!
+//! let metadata = cargo_plugin_utils::get_metadata(None)?;
+//! if let Some(msrv) = workspace_msrv(&metadata)
+//!     && !toolchain_meets_msrv(&msrv)?
+//! {
+//!     anyhow::bail!("The workspace needs Rust {} or newer", msrv);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use cargo_metadata::semver::Version;
+
+/// The minimum supported Rust version of `package`, its `rust-version`.
+pub fn get_rust_version(package: &cargo_metadata::Package) -> Option<Version> {
+    package.rust_version.clone()
+}
+
+/// The minimum supported Rust version of the workspace of `metadata`: the
+/// highest `rust-version` of its members, `None` if none declares one.
+pub fn workspace_msrv(metadata: &cargo_metadata::Metadata) -> Option<Version> {
+    metadata
+        .workspace_packages()
+        .into_iter()
+        .filter_map(get_rust_version)
+        .max()
+}
+
+/// Release channel of a cargo binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Stable release
+    Stable,
+    /// Beta release
+    Beta,
+    /// Nightly build
+    Nightly,
+    /// Locally built cargo (`-dev` suffix)
+    Dev,
+}
+
+/// Version information of the cargo binary in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CargoVersion {
+    /// Version number without the pre-release channel suffix
+    pub version: Version,
+    /// Release channel
+    pub channel: Channel,
+}
+
+impl CargoVersion {
+    /// Check whether this cargo supports `feature`.
+    ///
+    /// Features that are only available unstably are reported as supported on
+    /// nightly (and dev) builds; callers still have to pass the matching `-Z`
+    /// flag.
+    pub fn supports(&self, feature: Feature) -> bool {
+        let unstable_allowed = matches!(self.channel, Channel::Nightly | Channel::Dev);
+        match feature.stable_since() {
+            Some(since) => self.version >= since || (unstable_allowed && feature.nightly_only()),
+            None => unstable_allowed && feature.nightly_only(),
+        }
+    }
+
+    /// Whether this toolchain is at least `msrv`, e.g. a package's
+    /// `rust-version`.
+    ///
+    /// Nightly and beta builds count as the release they lead up to, as in
+    /// cargo's own check: `1.80.0-nightly` meets an MSRV of `1.80`.
+    pub fn meets_msrv(&self, msrv: &Version) -> bool {
+        self.version >= Version::new(msrv.major, msrv.minor, msrv.patch)
+    }
+}
+
+/// Cargo capabilities plugins commonly branch on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// `cargo add` subcommand (1.62)
+    CargoAdd,
+    /// Workspace inheritance of `package` fields and dependencies (1.64)
+    WorkspaceInheritance,
+    /// `cargo remove` subcommand (1.66)
+    CargoRemove,
+    /// Sparse protocol is the default for crates.io (1.70)
+    SparseRegistryDefault,
+    /// `[lints]` table in manifests (1.74)
+    LintsTable,
+    /// `cargo info` subcommand (1.82)
+    CargoInfo,
+    /// MSRV-aware dependency resolver, `resolver = "3"` (1.84)
+    MsrvAwareResolver,
+    /// `cargo publish --workspace` and multi-package publishing (1.90)
+    PublishWorkspace,
+    /// `--artifact-dir` (formerly `--out-dir`) for copying final artifacts
+    /// (unstable, nightly only)
+    ArtifactDir,
+}
+
+impl Feature {
+    /// Cargo version in which the feature became available on stable, if it
+    /// has been stabilized.
+    pub fn stable_since(self) -> Option<Version> {
+        let minor = match self {
+            Self::CargoAdd => 62,
+            Self::WorkspaceInheritance => 64,
+            Self::CargoRemove => 66,
+            Self::SparseRegistryDefault => 70,
+            Self::LintsTable => 74,
+            Self::CargoInfo => 82,
+            Self::MsrvAwareResolver => 84,
+            Self::PublishWorkspace => 90,
+            Self::ArtifactDir => return None,
+        };
+        Some(Version::new(1, minor, 0))
+    }
+
+    /// Whether the feature can be used on nightly (with `-Z` flags) before
+    /// being stabilized.
+    pub fn nightly_only(self) -> bool {
+        matches!(self, Self::ArtifactDir)
+    }
+}
+
+/// Parse the output of `cargo --version`, e.g.
+/// `cargo 1.93.0 (083ac5135 2025-12-15)` or
+/// `cargo 1.95.0-nightly (f2d3ce0bd 2026-03-21)`.
+pub fn parse_cargo_version(output: &str) -> anyhow::Result<CargoVersion> {
+    let version_str = output
+        .split_whitespace()
+        .nth(1)
+        .with_context(|| format!("Unexpected `cargo --version` output: {}", output.trim()))?;
+    let mut version = Version::parse(version_str)
+        .with_context(|| format!("Failed to parse cargo version `{}`", version_str))?;
+
+    let channel = match version.pre.as_str() {
+        "" => Channel::Stable,
+        pre if pre.starts_with("beta") => Channel::Beta,
+        "nightly" => Channel::Nightly,
+        _ => Channel::Dev,
+    };
+    version.pre = cargo_metadata::semver::Prerelease::EMPTY;
+
+    Ok(CargoVersion { version, channel })
+}
+
+/// Detect the version of the cargo binary in use.
+///
+/// Uses the `CARGO` environment variable (set by cargo when running
+/// subcommands), falling back to `cargo` on `PATH`. The result is cached for
+/// the lifetime of the process.
+pub fn cargo_version() -> anyhow::Result<CargoVersion> {
+    static DETECTED: OnceLock<Result<CargoVersion, String>> = OnceLock::new();
+
+    DETECTED
+        .get_or_init(|| {
+            let output = std::process::Command::new(crate::cargo::cargo_program())
+                .arg("--version")
+                .output()
+                .map_err(|err| format!("Failed to run `cargo --version`: {}", err))?;
+            if !output.status.success() {
+                return Err(format!("`cargo --version` failed with {}", output.status));
+            }
+            parse_cargo_version(&String::from_utf8_lossy(&output.stdout))
+                .map_err(|err| format!("{:#}", err))
+        })
+        .clone()
+        .map_err(anyhow::Error::msg)
+}
+
+/// Check whether the toolchain in use (the one of the detected cargo) is at
+/// least `msrv`, see [`CargoVersion::meets_msrv`].
+pub fn toolchain_meets_msrv(msrv: &Version) -> anyhow::Result<bool> {
+    Ok(cargo_version()?.meets_msrv(msrv))
+}
+
+/// Check whether the cargo in use supports `feature`.
+///
+/// Returns `false` if the cargo version cannot be detected.
+pub fn supports(feature: Feature) -> bool {
+    cargo_version()
+        .map(|version| version.supports(feature))
+        .unwrap_or(false)
+}
+
+/// Target triple of the host, e.g. `x86_64-unknown-linux-gnu`.
+///
+/// Asks `rustc -vV`, using the `RUSTC` environment variable if set. The
+/// result is cached for the lifetime of the process.
+#[allow(clippy::disallowed_methods)] // CLI tool needs direct env access
+pub fn host_target() -> anyhow::Result<String> {
+    static DETECTED: OnceLock<Result<String, String>> = OnceLock::new();
+
+    DETECTED
+        .get_or_init(|| {
+            let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+            let output = std::process::Command::new(rustc)
+                .arg("-vV")
+                .output()
+                .map_err(|err| format!("Failed to run `rustc -vV`: {}", err))?;
+            parse_host(&String::from_utf8_lossy(&output.stdout))
+                .ok_or_else(|| "`rustc -vV` didn't report a host".to_string())
+        })
+        .clone()
+        .map_err(anyhow::Error::msg)
+}
+
+/// Targets whose standard library is installed, from
+/// `rustup target list --installed`. Without rustup, just the host.
+pub fn installed_targets() -> anyhow::Result<Vec<String>> {
+    let output = std::process::Command::new("rustup")
+        .args(["target", "list", "--installed"])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()),
+        _ => Ok(vec![host_target()?]),
+    }
+}
+
+/// The `host:` line of `rustc -vV`.
+fn parse_host(version_info: &str) -> Option<String> {
+    version_info
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| host.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::write_file;
+
+    #[test]
+    fn test_parse_cargo_version_stable() {
+        let version = parse_cargo_version("cargo 1.93.0 (083ac5135 2025-12-15)").unwrap();
+        assert_eq!(version.version, Version::new(1, 93, 0));
+        assert_eq!(version.channel, Channel::Stable);
+    }
+
+    #[test]
+    fn test_parse_cargo_version_nightly_and_beta() {
+        let nightly = parse_cargo_version("cargo 1.95.0-nightly (f2d3ce0bd 2026-03-21)").unwrap();
+        assert_eq!(nightly.version, Version::new(1, 95, 0));
+        assert_eq!(nightly.channel, Channel::Nightly);
+
+        let beta = parse_cargo_version("cargo 1.94.0-beta.3 (abc 2026-02-01)\n").unwrap();
+        assert_eq!(beta.channel, Channel::Beta);
+    }
+
+    #[test]
+    fn test_meets_msrv() {
+        let nightly = parse_cargo_version("cargo 1.80.0-nightly (abc 2024-06-01)").unwrap();
+        assert!(nightly.meets_msrv(&Version::new(1, 80, 0)));
+        assert!(nightly.meets_msrv(&Version::new(1, 74, 1)));
+        assert!(!nightly.meets_msrv(&Version::new(1, 80, 1)));
+    }
+
+    #[test]
+    fn test_workspace_msrv() {
+        let dir = tempfile::TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"old\", \"new\", \"any\"]\nresolver = \"2\"\n",
+        );
+        for (name, rust_version) in [
+            ("old", "rust-version = \"1.70\"\n"),
+            ("new", "rust-version = \"1.74.1\"\n"),
+            ("any", ""),
+        ] {
+            write_file(
+                dir.path(),
+                &format!("{}/Cargo.toml", name),
+                &format!(
+                    "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n{}",
+                    name, rust_version
+                ),
+            );
+            write_file(dir.path(), &format!("{}/src/lib.rs", name), "");
+        }
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(dir.path().join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .unwrap();
+        let package = |name: &str| {
+            metadata
+                .packages
+                .iter()
+                .find(|package| package.name.as_str() == name)
+                .unwrap()
+        };
+        assert_eq!(
+            get_rust_version(package("old")),
+            Some(Version::new(1, 70, 0))
+        );
+        assert_eq!(get_rust_version(package("any")), None);
+        assert_eq!(workspace_msrv(&metadata), Some(Version::new(1, 74, 1)));
+    }
+
+    #[test]
+    fn test_parse_cargo_version_invalid() {
+        assert!(parse_cargo_version("").is_err());
+        assert!(parse_cargo_version("cargo not-a-version").is_err());
+    }
+
+    #[test]
+    fn test_supports_capability_table() {
+        let old = parse_cargo_version("cargo 1.69.0 (6e9a83356 2023-04-12)").unwrap();
+        assert!(old.supports(Feature::WorkspaceInheritance));
+        assert!(!old.supports(Feature::SparseRegistryDefault));
+        assert!(!old.supports(Feature::PublishWorkspace));
+
+        let new = parse_cargo_version("cargo 1.90.0 (840b83a10 2025-07-30)").unwrap();
+        assert!(new.supports(Feature::SparseRegistryDefault));
+        assert!(new.supports(Feature::PublishWorkspace));
+        assert!(!new.supports(Feature::ArtifactDir));
+
+        let nightly = parse_cargo_version("cargo 1.80.0-nightly (abc 2024-05-01)").unwrap();
+        assert!(nightly.supports(Feature::ArtifactDir));
+        assert!(!nightly.supports(Feature::PublishWorkspace));
+    }
+
+    #[test]
+    fn test_cargo_version_detected() {
+        // Tests run under cargo, so detection should succeed
+        let version = cargo_version().unwrap();
+        assert!(version.version >= Version::new(1, 0, 0));
+        assert!(supports(Feature::CargoAdd));
+    }
+
+    #[test]
+    fn test_parse_host() {
+        let info = "rustc 1.93.0 (254b59607 2026-01-19)\nbinary: rustc\nhost: \
+                    x86_64-unknown-linux-gnu\nrelease: 1.93.0\n";
+        assert_eq!(
+            parse_host(info).as_deref(),
+            Some("x86_64-unknown-linux-gnu")
+        );
+        assert_eq!(parse_host("rustc 1.0"), None);
+    }
+}
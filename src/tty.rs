@@ -1,6 +1,8 @@
 //! TTY detection utilities for respecting cargo's progress settings.
 
 use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::OnceLock;
 
 /// Check if progress should be shown based on cargo's term.progress.when
 /// setting (respects CARGO_TERM_PROGRESS_WHEN environment variable).
@@ -43,6 +45,130 @@ pub fn should_show_progress() -> bool {
     }
 }
 
+/// Whether stderr is a terminal that understands VT escape sequences
+/// (cursor movement, line clearing, scrolling regions).
+///
+/// On Windows this turns on VT processing for the console, which conhost
+/// supports since Windows 10; legacy consoles that refuse it get plain
+/// output instead of garbage escape sequences. The answer is computed once.
+pub fn supports_vt() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| std::io::stderr().is_terminal() && enable_vt())
+}
+
+#[cfg(not(windows))]
+fn enable_vt() -> bool {
+    true
+}
+
+#[cfg(windows)]
+fn enable_vt() -> bool {
+    use windows_sys::Win32::System::Console::{
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        GetConsoleMode,
+        GetStdHandle,
+        STD_ERROR_HANDLE,
+        SetConsoleMode,
+    };
+
+    // SAFETY: plain console API calls on the process's own stderr handle
+    unsafe {
+        let handle = GetStdHandle(STD_ERROR_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            // Not a console, e.g. a mintty pipe that still renders VT codes
+            return true;
+        }
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+/// Stdin in raw mode (no line editing, echo or signal keys) until dropped,
+/// so every key reaches a child's PTY as typed. Does nothing when stdin
+/// isn't a terminal.
+#[cfg(unix)]
+pub(crate) struct RawMode {
+    saved: Option<libc::termios>,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    pub(crate) fn enable() -> Self {
+        // SAFETY: `termios` is plain data, filled in by `tcgetattr` on the
+        // process's own stdin
+        let saved = unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Self { saved: None };
+            }
+            let saved = termios;
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Self { saved: None };
+            }
+            saved
+        };
+        Self { saved: Some(saved) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            // SAFETY: restores attributes read from the same descriptor
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
+
+/// `text` as a clickable link to `url` (an OSC 8 hyperlink) when stderr is a
+/// terminal, plain `text` otherwise.
+///
+/// Terminals without hyperlink support ignore the escape sequence and show
+/// just the text.
+pub fn hyperlink(url: &str, text: &str) -> String {
+    if supports_vt() {
+        osc8(url, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// The path of a local file as a [`hyperlink`] to its `file://` URL.
+pub fn file_link(path: &Path) -> String {
+    hyperlink(&file_url(path), &path.display().to_string())
+}
+
+/// `file://` URL of a local path; relative paths are taken from the current
+/// directory.
+pub fn file_url(path: &Path) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let path = absolute.to_string_lossy().replace('\\', "/");
+    let mut url = String::from("file://");
+    if !path.starts_with('/') {
+        // Windows drive paths: file:///C:/...
+        url.push('/');
+    }
+    for character in path.chars() {
+        match character {
+            ' ' => url.push_str("%20"),
+            '#' => url.push_str("%23"),
+            '?' => url.push_str("%3F"),
+            '%' => url.push_str("%25"),
+            other => url.push(other),
+        }
+    }
+    url
+}
+
+fn osc8(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
 #[cfg(test)]
 mod tests {
     use std::env;
@@ -121,4 +247,23 @@ mod tests {
             let _ = should_show_progress();
         });
     }
+
+    #[test]
+    fn test_hyperlinks() {
+        assert_eq!(
+            osc8("https://docs.rs", "docs"),
+            "\x1b]8;;https://docs.rs\x1b\\docs\x1b]8;;\x1b\\"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            file_url(Path::new("/tmp/my docs/index.html")),
+            "file:///tmp/my%20docs/index.html"
+        );
+        #[cfg(windows)]
+        assert_eq!(
+            file_url(Path::new(r"C:\target\doc\index.html")),
+            "file:///C:/target/doc/index.html"
+        );
+        assert!(file_url(Path::new("relative.html")).ends_with("/relative.html"));
+    }
 }
@@ -0,0 +1,997 @@
+//! Versions of workspace packages.
+//!
+//! [`sync_workspace_version`] moves a workspace released in lockstep to a new
+//! version in one pass: `workspace.package.version`, the member versions
+//! pinned to the same version, and the version requirements of dependencies
+//! between members. The edits are returned as a [`PatchSet`], so plugins can
+//! preview them before applying:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::common::get_metadata;
+//! use cargo_plugin_utils::versioning::sync_workspace_version;
+//!
+//! let metadata = get_metadata(None)?;
+//! let patch = sync_workspace_version(&metadata, &"0.3.0".parse()?)?;
+//! print!("{}", patch.to_patch());
+//! patch.apply()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Manifests are edited with `toml_edit`, keeping comments and formatting.
+//! Members with `version.workspace = true` follow the workspace version by
+//! themselves and are left alone.
+//!
+//! For update-style plugins, [`available_updates`] looks up the registry
+//! dependencies of the workspace in their sparse indexes and classifies each
+//! newer version as a [patch, minor or major](UpdateKind) update relative to
+//! the requirement; [`apply_updates`] rewrites the requirements of the
+//! selected ones:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::common::get_metadata;
+//! use cargo_plugin_utils::patch::PatchSet;
+//! use cargo_plugin_utils::versioning::{
+//!     UpdateKind,
+//!     apply_updates,
+//!     available_updates,
+//! };
+//!
+//! let metadata = get_metadata(None)?;
+//! let updates = available_updates(&metadata)?;
+//! let compatible: Vec<_> = updates
+//!     .into_iter()
+//!     .filter(|update| update.kind != UpdateKind::Major)
+//!     .collect();
+//! let mut patch = PatchSet::new(metadata.workspace_root.as_std_path());
+//! apply_updates(&mut patch, &compatible)?;
+//! patch.apply()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`check_workspace_versions`] reports requirements between members that
+//! don't match their versions and members that drifted from
+//! `workspace.package.version`, e.g. for a pre-release check.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::path::PathBuf;
+
+use anyhow::{
+    Context,
+    Result,
+};
+use cargo_metadata::Metadata;
+use cargo_metadata::semver::{
+    Op,
+    Version,
+    VersionReq,
+};
+
+use crate::patch::PatchSet;
+use crate::registry::Registry;
+
+/// Propose moving the workspace of `metadata` to `new_version`.
+///
+/// Member versions are updated if they equal the old
+/// `workspace.package.version`, or all of them if the workspace doesn't
+/// define one. Requirements on those members in `[dependencies]` (of any
+/// kind and target) and `[workspace.dependencies]` are set to the new
+/// version, keeping a `=`, `^` or `~` operator; members with a version of
+/// their own keep their requirements.
+pub fn sync_workspace_version(metadata: &Metadata, new_version: &Version) -> Result<PatchSet> {
+    let root = metadata.workspace_root.as_std_path();
+    let members = metadata.workspace_packages();
+    let mut manifests = vec![PathBuf::from("Cargo.toml")];
+    for package in &members {
+        let manifest = package
+            .manifest_path
+            .as_std_path()
+            .strip_prefix(root)
+            .with_context(|| format!("{} is outside the workspace", package.manifest_path))?
+            .to_path_buf();
+        if !manifests.contains(&manifest) {
+            manifests.push(manifest);
+        }
+    }
+
+    let mut patch = PatchSet::new(root);
+    let root_manifest = patch
+        .read("Cargo.toml")?
+        .context("No Cargo.toml in the workspace root")?;
+    let old_version = workspace_version(&parse_manifest(&root_manifest, "Cargo.toml".as_ref())?);
+    // Inherited versions are resolved in the metadata, so they equal it too
+    let moved = members.iter().filter(|package| {
+        old_version
+            .as_deref()
+            .is_none_or(|old| package.version.to_string() == old)
+    });
+    let editor = ManifestEditor {
+        members: moved.map(|package| package.name.to_string()).collect(),
+        old_version,
+        new_version: new_version.to_string(),
+    };
+    for manifest in manifests {
+        edit_manifest(&mut patch, &manifest, |document| editor.edit(document))?;
+    }
+    Ok(patch)
+}
+
+/// A version inconsistency in a workspace, as found by
+/// [`check_workspace_versions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionMismatch {
+    /// A member's requirement on another member doesn't match that member's
+    /// version.
+    Requirement {
+        /// The depending member
+        package: String,
+        /// Its manifest, relative to the workspace root
+        manifest: PathBuf,
+        /// Key of the dependency in the manifest (its rename, if any)
+        dependency: String,
+        /// The requirement
+        requirement: VersionReq,
+        /// The version of the member depended on
+        version: Version,
+    },
+    /// A member that doesn't inherit `workspace.package.version` has a
+    /// different version.
+    Diverged {
+        /// The member
+        package: String,
+        /// Its manifest, relative to the workspace root
+        manifest: PathBuf,
+        /// Its version
+        version: Version,
+        /// `workspace.package.version`
+        workspace_version: Version,
+    },
+}
+
+impl std::fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Requirement {
+                package,
+                manifest,
+                dependency,
+                requirement,
+                version,
+            } => write!(
+                f,
+                "{} ({}) requires {} {}, but it is at {}",
+                package,
+                manifest.display(),
+                dependency,
+                requirement,
+                version
+            ),
+            Self::Diverged {
+                package,
+                manifest,
+                version,
+                workspace_version,
+            } => write!(
+                f,
+                "{} ({}) is at {}, the workspace at {}",
+                package,
+                manifest.display(),
+                version,
+                workspace_version
+            ),
+        }
+    }
+}
+
+/// Check that the versions in the workspace of `metadata` agree: the
+/// requirements of path dependencies between members match the versions of
+/// the members, and members that don't inherit `workspace.package.version`
+/// (if the workspace has one) are at that version too.
+///
+/// Path dependencies without a version requirement are fine with any
+/// version. The same mismatch in several dependency kinds is reported once.
+///
+/// Cargo refuses to resolve a workspace whose requirements on members don't
+/// match, so pass metadata loaded with
+/// [`no_deps`](cargo_metadata::MetadataCommand::no_deps) to get a report
+/// instead of a `cargo metadata` error.
+pub fn check_workspace_versions(metadata: &Metadata) -> Result<Vec<VersionMismatch>> {
+    let root = metadata.workspace_root.as_std_path();
+    let members = metadata.workspace_packages();
+    let relative = |package: &cargo_metadata::Package| {
+        package
+            .manifest_path
+            .as_std_path()
+            .strip_prefix(root)
+            .unwrap_or(package.manifest_path.as_std_path())
+            .to_path_buf()
+    };
+
+    let mut mismatches = Vec::new();
+    for package in &members {
+        for dependency in &package.dependencies {
+            let Some(path) = &dependency.path else {
+                continue;
+            };
+            let Some(member) = members.iter().find(|member| {
+                member.name.as_str() == dependency.name
+                    && member.manifest_path.parent() == Some(path.as_path())
+            }) else {
+                continue;
+            };
+            if dependency.req == VersionReq::STAR || dependency.req.matches(&member.version) {
+                continue;
+            }
+            let mismatch = VersionMismatch::Requirement {
+                package: package.name.to_string(),
+                manifest: relative(package),
+                dependency: dependency
+                    .rename
+                    .clone()
+                    .unwrap_or_else(|| dependency.name.clone()),
+                requirement: dependency.req.clone(),
+                version: member.version.clone(),
+            };
+            if !mismatches.contains(&mismatch) {
+                mismatches.push(mismatch);
+            }
+        }
+    }
+
+    let root_manifest = read_toml(&root.join("Cargo.toml"))?;
+    let Some(workspace_version) = root_manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("package"))
+        .and_then(|package| package.get("version"))
+        .and_then(|version| version.as_str())
+    else {
+        return Ok(mismatches);
+    };
+    let workspace_version: Version = workspace_version
+        .parse()
+        .with_context(|| format!("Invalid workspace.package.version `{}`", workspace_version))?;
+    for package in &members {
+        if package.version == workspace_version {
+            continue;
+        }
+        let manifest = read_toml(package.manifest_path.as_std_path())?;
+        let inherits = manifest
+            .get("package")
+            .and_then(|package| package.get("version"))
+            .and_then(|version| version.get("workspace"))
+            .and_then(|workspace| workspace.as_bool())
+            == Some(true);
+        if !inherits {
+            mismatches.push(VersionMismatch::Diverged {
+                package: package.name.to_string(),
+                manifest: relative(package),
+                version: package.version.clone(),
+                workspace_version: workspace_version.clone(),
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+fn read_toml(path: &std::path::Path) -> Result<toml_edit::DocumentMut> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    text.parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// How far a newer version is from a requirement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UpdateKind {
+    /// Same major and minor version, e.g. `1.2.3` for `1.2.0`
+    Patch,
+    /// Compatible with the requirement, e.g. `1.4.0` for `1.2`
+    Minor,
+    /// Incompatible, e.g. `2.0.0` for `1.2`, or `0.3.0` for `0.2`
+    Major,
+}
+
+/// A newer version of a dependency of a workspace member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyUpdate {
+    /// Manifest declaring the dependency, relative to the workspace root
+    pub manifest: PathBuf,
+    /// Key of the dependency in the manifest (its rename, if any)
+    pub key: String,
+    /// Crate name
+    pub name: String,
+    /// Current requirement
+    pub requirement: VersionReq,
+    /// Newest version the requirement already allows, if any
+    pub latest_compatible: Option<Version>,
+    /// Newest released version
+    pub latest: Version,
+    /// How far `latest` is from the requirement
+    pub kind: UpdateKind,
+}
+
+/// Classify `version` against `requirement`: `None` unless it is newer than
+/// the version the requirement is based on.
+///
+/// In cargo's terms a change of the first non-zero component is major, so
+/// `0.3.0` is a major update of `0.2`.
+pub fn classify_update(requirement: &VersionReq, version: &Version) -> Option<UpdateKind> {
+    let comparator = requirement.comparators.first()?;
+    let base = Version::new(
+        comparator.major,
+        comparator.minor.unwrap_or(0),
+        comparator.patch.unwrap_or(0),
+    );
+    if *version <= base {
+        return None;
+    }
+    let compatible = VersionReq {
+        comparators: vec![cargo_metadata::semver::Comparator {
+            op: Op::Caret,
+            ..comparator.clone()
+        }],
+    };
+    Some(if !compatible.matches(version) {
+        UpdateKind::Major
+    } else if (version.major, version.minor) == (base.major, base.minor) {
+        UpdateKind::Patch
+    } else {
+        UpdateKind::Minor
+    })
+}
+
+/// The versions of `name` on crates.io that aren't yanked, read from the
+/// sparse index with `curl` (see [`Registry::versions`]).
+pub fn index_versions(name: &str) -> Result<Vec<Version>> {
+    Registry::crates_io().versions(name)
+}
+
+/// The newer versions of the registry dependencies of all workspace members,
+/// looked up with [`Registry::versions`] in the registry each comes from
+/// (once per crate).
+///
+/// Pre-releases are only offered for requirements on a pre-release.
+/// Dependencies from registries with a git index are skipped, as their
+/// versions can't be queried.
+pub fn available_updates(metadata: &Metadata) -> Result<Vec<DependencyUpdate>> {
+    updates_with(metadata, |registry, name| registry.versions(name))
+}
+
+fn updates_with(
+    metadata: &Metadata,
+    mut lookup: impl FnMut(&Registry, &str) -> Result<Vec<Version>>,
+) -> Result<Vec<DependencyUpdate>> {
+    let root = metadata.workspace_root.as_std_path();
+    let mut registries: BTreeMap<String, Option<Registry>> = BTreeMap::new();
+    let mut versions: BTreeMap<(String, String), Vec<Version>> = BTreeMap::new();
+    let mut updates = Vec::new();
+    for package in metadata.workspace_packages() {
+        let manifest = package
+            .manifest_path
+            .as_std_path()
+            .strip_prefix(root)
+            .unwrap_or(package.manifest_path.as_std_path())
+            .to_path_buf();
+        for dependency in &package.dependencies {
+            let Some(source) = &dependency.source else {
+                continue;
+            };
+            if !registries.contains_key(&source.repr) {
+                let registry = Registry::from_source(source).filter(Registry::is_sparse);
+                registries.insert(source.repr.clone(), registry);
+            }
+            let Some(registry) = &registries[&source.repr] else {
+                continue;
+            };
+            let key = (source.repr.clone(), dependency.name.clone());
+            if !versions.contains_key(&key) {
+                let found = lookup(registry, &dependency.name)?;
+                versions.insert(key.clone(), found);
+            }
+            let allow_pre = dependency
+                .req
+                .comparators
+                .iter()
+                .any(|comparator| !comparator.pre.is_empty());
+            let candidates: Vec<&Version> = versions[&key]
+                .iter()
+                .filter(|version| allow_pre || version.pre.is_empty())
+                .collect();
+            let Some(latest) = candidates.iter().copied().max() else {
+                continue;
+            };
+            let Some(kind) = classify_update(&dependency.req, latest) else {
+                continue;
+            };
+            let update = DependencyUpdate {
+                manifest: manifest.clone(),
+                key: dependency
+                    .rename
+                    .clone()
+                    .unwrap_or_else(|| dependency.name.clone()),
+                name: dependency.name.clone(),
+                requirement: dependency.req.clone(),
+                latest_compatible: candidates
+                    .iter()
+                    .copied()
+                    .filter(|version| dependency.req.matches(version))
+                    .max()
+                    .cloned(),
+                latest: latest.clone(),
+                kind,
+            };
+            // The same dependency of several kinds is one update
+            if !updates.contains(&update) {
+                updates.push(update);
+            }
+        }
+    }
+    Ok(updates)
+}
+
+/// Propose requiring the latest version of each update in `patch` (rooted at
+/// the workspace root).
+///
+/// The requirement is rewritten wherever the dependency is declared with
+/// its current requirement: in the member's manifest, and, if the member
+/// inherits the dependency (`workspace = true`), in
+/// `[workspace.dependencies]` of the root manifest. Other tables of the root
+/// manifest are only edited for updates of the root package itself.
+pub fn apply_updates(patch: &mut PatchSet, updates: &[DependencyUpdate]) -> Result<()> {
+    for update in updates {
+        let inherited = edit_manifest(patch, &update.manifest, |document| {
+            bump_requirement(dependency_tables(document), update)
+        })?;
+        if inherited {
+            edit_manifest(patch, "Cargo.toml".as_ref(), |document| {
+                let dependencies = document
+                    .get_mut("workspace")
+                    .and_then(|workspace| workspace.get_mut("dependencies"))
+                    .and_then(|item| item.as_table_like_mut());
+                bump_requirement(dependencies.into_iter().collect(), update)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Edit the manifest at `path` in `patch`, if it exists.
+fn edit_manifest<T: Default>(
+    patch: &mut PatchSet,
+    path: &std::path::Path,
+    edit: impl FnOnce(&mut toml_edit::DocumentMut) -> T,
+) -> Result<T> {
+    let Some(text) = patch.read(path)? else {
+        return Ok(T::default());
+    };
+    let mut document = parse_manifest(&text, path)?;
+    let result = edit(&mut document);
+    let edited = document.to_string();
+    if edited != text {
+        patch.write(path, edited)?;
+    }
+    Ok(result)
+}
+
+/// Set the requirement of the dependency `update.key` in `tables` to the
+/// latest version where it currently is `update.requirement`. Returns
+/// whether one of the declarations inherits it from the workspace instead.
+fn bump_requirement(tables: Vec<&mut dyn toml_edit::TableLike>, update: &DependencyUpdate) -> bool {
+    let new_version = update.latest.to_string();
+    let mut inherited = false;
+    for table in tables {
+        let Some(dependency) = table.get_mut(&update.key) else {
+            continue;
+        };
+        let version = match dependency.as_table_like_mut() {
+            Some(dependency) => {
+                inherited |= dependency
+                    .get("workspace")
+                    .and_then(|workspace| workspace.as_bool())
+                    == Some(true);
+                dependency.get_mut("version")
+            }
+            None => Some(dependency),
+        };
+        let Some(version) = version else {
+            continue;
+        };
+        let Some(old) = version.as_str() else {
+            continue;
+        };
+        if VersionReq::parse(old).ok().as_ref() == Some(&update.requirement) {
+            let requirement = requirement_string(old, &new_version);
+            set_string(version, &requirement);
+        }
+    }
+    inherited
+}
+
+/// Edits the versions of a manifest for [`sync_workspace_version`].
+struct ManifestEditor {
+    members: BTreeSet<String>,
+    old_version: Option<String>,
+    new_version: String,
+}
+
+impl ManifestEditor {
+    fn edit(&self, document: &mut toml_edit::DocumentMut) {
+        if let Some(version) = document
+            .get_mut("package")
+            .and_then(|package| package.get_mut("version"))
+            && self
+                .old_version
+                .as_deref()
+                .is_none_or(|old| version.as_str() == Some(old))
+        {
+            set_string(version, &self.new_version);
+        }
+        if let Some(workspace) = document.get_mut("workspace") {
+            if let Some(version) = workspace
+                .get_mut("package")
+                .and_then(|package| package.get_mut("version"))
+            {
+                set_string(version, &self.new_version);
+            }
+            if let Some(dependencies) = workspace
+                .get_mut("dependencies")
+                .and_then(|item| item.as_table_like_mut())
+            {
+                self.edit_dependencies(dependencies);
+            }
+        }
+        for dependencies in dependency_tables(document) {
+            self.edit_dependencies(dependencies);
+        }
+    }
+
+    /// Set the requirements on members in a dependency table.
+    fn edit_dependencies(&self, dependencies: &mut dyn toml_edit::TableLike) {
+        for (key, dependency) in dependencies.iter_mut() {
+            let Some(dependency) = dependency.as_table_like_mut() else {
+                continue;
+            };
+            let name = dependency
+                .get("package")
+                .and_then(|package| package.as_str())
+                .unwrap_or(key.get())
+                .to_string();
+            if !self.members.contains(&name) {
+                continue;
+            }
+            if let Some(version) = dependency.get_mut("version")
+                && let Some(old) = version.as_str()
+            {
+                let requirement = requirement_string(old, &self.new_version);
+                set_string(version, &requirement);
+            }
+        }
+    }
+}
+
+/// The dependency tables of a package manifest, of all kinds and targets
+/// (not `[workspace.dependencies]`).
+fn dependency_tables(document: &mut toml_edit::DocumentMut) -> Vec<&mut dyn toml_edit::TableLike> {
+    const KINDS: [&str; 5] = [
+        "dependencies",
+        "dev-dependencies",
+        "build-dependencies",
+        "dev_dependencies",
+        "build_dependencies",
+    ];
+    let is_kind = |key: &toml_edit::KeyMut<'_>| KINDS.contains(&key.get());
+    let mut tables = Vec::new();
+    for (key, item) in document.as_table_mut().iter_mut() {
+        if is_kind(&key) {
+            tables.extend(item.as_table_like_mut());
+        } else if key.get() == "target"
+            && let Some(targets) = item.as_table_like_mut()
+        {
+            let kinds = targets
+                .iter_mut()
+                .filter_map(|(_, target)| target.as_table_like_mut())
+                .flat_map(|target| target.iter_mut())
+                .filter(|(key, _)| is_kind(key))
+                .filter_map(|(_, item)| item.as_table_like_mut());
+            tables.extend(kinds);
+        }
+    }
+    tables
+}
+
+/// Replace the string in `item`, keeping its comments and whitespace.
+fn set_string(item: &mut toml_edit::Item, new: &str) {
+    if let Some(value) = item.as_value_mut()
+        && value.is_str()
+    {
+        let decor = value.decor().clone();
+        *value = new.into();
+        *value.decor_mut() = decor;
+    }
+}
+
+/// The `workspace.package.version` of a root manifest.
+fn workspace_version(document: &toml_edit::DocumentMut) -> Option<String> {
+    document
+        .get("workspace")?
+        .get("package")?
+        .get("version")?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn parse_manifest(text: &str, path: &std::path::Path) -> Result<toml_edit::DocumentMut> {
+    text.parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// `new_version` as a requirement in the style of `old`: a leading `=`, `^`
+/// or `~` is kept; ranges are replaced.
+fn requirement_string(old: &str, new_version: &str) -> String {
+    let operator: String = old
+        .chars()
+        .take_while(|character| matches!(character, '=' | '^' | '~' | ' '))
+        .collect();
+    format!("{}{}", operator, new_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testing::write_file;
+
+    fn editor(old_version: Option<&str>) -> ManifestEditor {
+        ManifestEditor {
+            members: ["core".to_string(), "macros".to_string()].into(),
+            old_version: old_version.map(str::to_string),
+            new_version: "0.3.0".to_string(),
+        }
+    }
+
+    impl ManifestEditor {
+        fn edit_text(&self, text: &str) -> String {
+            let mut document = text.parse().unwrap();
+            self.edit(&mut document);
+            document.to_string()
+        }
+    }
+
+    #[test]
+    fn test_edit_manifest() {
+        let manifest = "\
+[package]
+name = \"app\"
+version = \"0.2.0\" # released in lockstep
+rust-version = \"1.80\"
+
+[dependencies]
+core = { path = \"../core\", version = \"=0.2.0\" }
+derive = { package = \"macros\", version = \"0.2\", path = \"../macros\" }
+serde = { version = \"1.0\" }
+
+[target.'cfg(unix)'.dev-dependencies.core]
+path = \"../core\"
+version = \"^0.2.0\"
+";
+        assert_eq!(
+            editor(Some("0.2.0")).edit_text(manifest),
+            "\
+[package]
+name = \"app\"
+version = \"0.3.0\" # released in lockstep
+rust-version = \"1.80\"
+
+[dependencies]
+core = { path = \"../core\", version = \"=0.3.0\" }
+derive = { package = \"macros\", version = \"0.3.0\", path = \"../macros\" }
+serde = { version = \"1.0\" }
+
+[target.'cfg(unix)'.dev-dependencies.core]
+path = \"../core\"
+version = \"^0.3.0\"
+"
+        );
+        // Tables are found by structure, not line by line
+        let dotted = "\
+package.name = \"app\"
+package.version = \"0.2.0\"
+dependencies.renamed = { package = \"core\", path = \"../core\", version = \"~0.2\" }
+
+[dev-dependencies.other]
+package = \"macros\"
+version = \"0.2\"
+";
+        assert_eq!(
+            editor(Some("0.2.0")).edit_text(dotted),
+            "\
+package.name = \"app\"
+package.version = \"0.3.0\"
+dependencies.renamed = { package = \"core\", path = \"../core\", version = \"~0.3.0\" }
+
+[dev-dependencies.other]
+package = \"macros\"
+version = \"0.3.0\"
+"
+        );
+        // A member on its own version stays there
+        let independent = "[package]\nname = \"tool\"\nversion = \"1.4.0\"\n";
+        assert_eq!(editor(Some("0.2.0")).edit_text(independent), independent);
+        assert_eq!(
+            editor(None).edit_text(independent),
+            "[package]\nname = \"tool\"\nversion = \"0.3.0\"\n"
+        );
+    }
+
+    #[test]
+    fn test_classify_update() {
+        let req = |text: &str| VersionReq::parse(text).unwrap();
+        let version = |text: &str| Version::parse(text).unwrap();
+        assert_eq!(
+            classify_update(&req("1.2"), &version("1.2.5")),
+            Some(UpdateKind::Patch)
+        );
+        assert_eq!(
+            classify_update(&req("1.2"), &version("1.4.0")),
+            Some(UpdateKind::Minor)
+        );
+        assert_eq!(
+            classify_update(&req("=1.2.0"), &version("2.0.0")),
+            Some(UpdateKind::Major)
+        );
+        assert_eq!(
+            classify_update(&req("0.2"), &version("0.3.0")),
+            Some(UpdateKind::Major)
+        );
+        assert_eq!(
+            classify_update(&req("0.2.1"), &version("0.2.4")),
+            Some(UpdateKind::Patch)
+        );
+        assert_eq!(classify_update(&req("1.2"), &version("1.2.0")), None);
+    }
+
+    #[test]
+    fn test_apply_updates() {
+        let dir = TempDir::new().unwrap();
+        let root = "\
+[workspace]
+members = [\"app\"]
+
+[workspace.dependencies]
+serde = \"1.0\"
+
+[package]
+name = \"root\"
+version = \"0.1.0\"
+
+[dependencies]
+serde = \"1.0\"
+";
+        write_file(dir.path(), "Cargo.toml", root);
+        write_file(
+            dir.path(),
+            "app/Cargo.toml",
+            "\
+[dependencies]
+serde.workspace = true
+json = { package = \"serde_json\", version = \"=1.0.100\" }
+anyhow = \"1.0\"
+
+[target.'cfg(unix)'.dependencies.json]
+package = \"serde_json\"
+version = \"^1.0.100\"
+",
+        );
+        let update = |key: &str, name: &str, requirement: &str, latest: Version| DependencyUpdate {
+            manifest: PathBuf::from("app/Cargo.toml"),
+            key: key.to_string(),
+            name: name.to_string(),
+            requirement: VersionReq::parse(requirement).unwrap(),
+            latest_compatible: None,
+            kind: UpdateKind::Patch,
+            latest,
+        };
+        let mut patch = PatchSet::new(dir.path());
+        apply_updates(
+            &mut patch,
+            &[
+                update("serde", "serde", "1.0", Version::new(1, 0, 228)),
+                update("json", "serde_json", "=1.0.100", Version::new(1, 0, 145)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            patch.read("app/Cargo.toml").unwrap().unwrap(),
+            "\
+[dependencies]
+serde.workspace = true
+json = { package = \"serde_json\", version = \"=1.0.145\" }
+anyhow = \"1.0\"
+
+[target.'cfg(unix)'.dependencies.json]
+package = \"serde_json\"
+version = \"^1.0.100\"
+"
+        );
+        // Only the inherited declaration; the root package's own serde is a
+        // separate dependency
+        assert_eq!(
+            patch.read("Cargo.toml").unwrap().unwrap(),
+            root.replacen("serde = \"1.0\"", "serde = \"1.0.228\"", 1)
+        );
+    }
+
+    #[test]
+    fn test_sync_workspace_version() {
+        let dir = TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"core\"]\nresolver = \"2\"\n\n\
+             [workspace.package]\nversion = \"0.2.0\"\nedition = \"2021\"\n\n\
+             [workspace.dependencies]\ncore = { path = \"core\", version = \"0.2.0\" }\n",
+        );
+        write_file(
+            dir.path(),
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion.workspace = true\nedition.workspace = true\n\n\
+             [dependencies]\ncore.workspace = true\n",
+        );
+        write_file(dir.path(), "app/src/main.rs", "fn main() {}\n");
+        write_file(
+            dir.path(),
+            "core/Cargo.toml",
+            "[package]\nname = \"core\"\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+        );
+        write_file(dir.path(), "core/src/lib.rs", "");
+
+        let metadata = crate::common::get_metadata(Some(&dir.path().join("Cargo.toml"))).unwrap();
+        let patch = sync_workspace_version(&metadata, &Version::new(0, 3, 0)).unwrap();
+        let changed: Vec<_> = patch
+            .changed_files()
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(
+            changed,
+            vec![
+                PathBuf::from("Cargo.toml"),
+                PathBuf::from("core/Cargo.toml")
+            ]
+        );
+        let root = patch.read("Cargo.toml").unwrap().unwrap();
+        assert!(root.contains("[workspace.package]\nversion = \"0.3.0\"\n"));
+        assert!(root.contains("core = { path = \"core\", version = \"0.3.0\" }"));
+        let core = patch.read("core/Cargo.toml").unwrap().unwrap();
+        assert!(core.contains("version = \"0.3.0\""));
+    }
+
+    #[test]
+    fn test_sync_workspace_version_keeps_independent_members() {
+        let dir = TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"tool\"]\nresolver = \"2\"\n\n\
+             [workspace.package]\nversion = \"0.2.0\"\nedition = \"2021\"\n",
+        );
+        write_file(
+            dir.path(),
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion.workspace = true\nedition.workspace = true\n\n\
+             [dependencies]\ntool = { path = \"../tool\", version = \"1.4\" }\n",
+        );
+        write_file(dir.path(), "app/src/main.rs", "fn main() {}\n");
+        write_file(
+            dir.path(),
+            "tool/Cargo.toml",
+            "[package]\nname = \"tool\"\nversion = \"1.4.0\"\nedition = \"2021\"\n",
+        );
+        write_file(dir.path(), "tool/src/lib.rs", "");
+
+        let metadata = crate::common::get_metadata(Some(&dir.path().join("Cargo.toml"))).unwrap();
+        let patch = sync_workspace_version(&metadata, &Version::new(0, 3, 0)).unwrap();
+        assert_eq!(patch.changed_files(), [std::path::Path::new("Cargo.toml")]);
+        assert!(
+            patch
+                .read("app/Cargo.toml")
+                .unwrap()
+                .unwrap()
+                .contains("version = \"1.4\"")
+        );
+    }
+
+    #[test]
+    fn test_check_workspace_versions() {
+        let dir = TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"app\", \"core\", \"tool\"]\nresolver = \"2\"\n\n\
+             [workspace.package]\nversion = \"1.0.0\"\nedition = \"2021\"\n",
+        );
+        write_file(
+            dir.path(),
+            "core/Cargo.toml",
+            "[package]\nname = \"core\"\nversion.workspace = true\nedition.workspace = true\n",
+        );
+        write_file(
+            dir.path(),
+            "app/Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.9.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\ncore = { path = \"../core\", version = \"0.9\" }\n\n\
+             [dev-dependencies]\ncore = { path = \"../core\", version = \"0.9\" }\n",
+        );
+        write_file(
+            dir.path(),
+            "tool/Cargo.toml",
+            "[package]\nname = \"tool\"\nversion = { workspace = true }\nedition = \"2021\"\n\n\
+             [dependencies]\nbase = { path = \"../core\", package = \"core\", version = \"1.0\" }\n\
+             app = { path = \"../app\" }\n",
+        );
+        for member in ["app", "core", "tool"] {
+            write_file(dir.path(), &format!("{}/src/lib.rs", member), "");
+        }
+
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(dir.path().join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .unwrap();
+        let mismatches = check_workspace_versions(&metadata).unwrap();
+        let messages: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                format!(
+                    "app ({}) requires core ^0.9, but it is at 1.0.0",
+                    std::path::Path::new("app/Cargo.toml").display()
+                ),
+                format!(
+                    "app ({}) is at 0.9.0, the workspace at 1.0.0",
+                    std::path::Path::new("app/Cargo.toml").display()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_updates() {
+        let dir = TempDir::new().unwrap();
+        write_file(
+            dir.path(),
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\nanyhow = \"1.0\"\n",
+        );
+        write_file(dir.path(), "src/lib.rs", "");
+        // Without resolving, so no registry access is needed
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .manifest_path(dir.path().join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .unwrap();
+        let updates = updates_with(&metadata, |registry, name| {
+            assert!(registry.is_crates_io());
+            assert_eq!(name, "anyhow");
+            Ok(vec![
+                Version::new(1, 0, 0),
+                Version::new(1, 0, 999),
+                Version::parse("2.0.0-rc.1").unwrap(),
+            ])
+        })
+        .unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].kind, UpdateKind::Patch);
+        assert_eq!(updates[0].latest, Version::new(1, 0, 999));
+        assert_eq!(updates[0].manifest, PathBuf::from("Cargo.toml"));
+
+        let mut patch = PatchSet::new(dir.path());
+        apply_updates(&mut patch, &updates).unwrap();
+        let manifest = patch.read("Cargo.toml").unwrap().unwrap();
+        assert!(manifest.contains("anyhow = \"1.0.999\""));
+    }
+}
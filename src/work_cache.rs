@@ -0,0 +1,333 @@
+//! Incremental work cache keyed by content hashes.
+//!
+//! Plugins that do expensive per-package work (generating docs, running
+//! checks, building badges) can skip packages whose inputs didn't change
+//! since the last run. The inputs (files, tool versions, configuration) are
+//! hashed into a [`CacheKey`]; the task result is stored per package under
+//! `<target-dir>/plugin-cache/<name>` and reused while the key matches,
+//! printing a `Fresh` line like cargo does:
+//!
+//! ```no_run
+//! use cargo_plugin_utils::Logger;
+//! use cargo_plugin_utils::work_cache::{
+//!     CacheKey,
+//!     WorkCache,
+//! };
+//!
+//! # fn count_lines(package: &cargo_metadata::Package) -> anyhow::Result<usize> { Ok(0) }
+//! let logger = Logger::new();
+//! let cache = WorkCache::open("cargo-loc");
+//! for package in cargo_plugin_utils::get_workspace_packages(None)? {
+//!     let key = CacheKey::new()
+//!         .value("tool", env!("CARGO_PKG_VERSION"))
+//!         .file(package.manifest_path.as_std_path())?;
+//!     let lines: usize = cache.run(&logger, &package, &key, || count_lines(&package))?;
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::{
+    Digest,
+    Sha256,
+};
+
+use crate::hash;
+use crate::logger::Logger;
+
+/// Hash of the inputs of a task.
+///
+/// Each input is hashed together with its name and length, so different
+/// splits of the same bytes produce different keys.
+#[derive(Debug, Clone, Default)]
+pub struct CacheKey {
+    hasher: Sha256,
+}
+
+impl CacheKey {
+    /// A key without inputs.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a named value, e.g. a tool version or a configuration option.
+    pub fn value(mut self, name: &str, value: impl AsRef<[u8]>) -> Self {
+        self.update(b"value", name.as_bytes());
+        self.update(b"", value.as_ref());
+        self
+    }
+
+    /// Add the path and contents of a file.
+    pub fn file(mut self, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let digest = hash::digest_file(path)?;
+        self.add_file(path, &digest);
+        Ok(self)
+    }
+
+    /// Add several files, in the given order. The files are hashed in
+    /// parallel.
+    pub fn files<I, P>(mut self, paths: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        for (path, digest) in hash::digest_files(paths)? {
+            self.add_file(&path, &digest);
+        }
+        Ok(self)
+    }
+
+    /// Add the path and [digest](hash::digest_dir) of a directory tree,
+    /// skipping files ignored by `.gitignore`.
+    pub fn dir(mut self, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let digest = hash::digest_dir(path)?;
+        self.update(b"dir", path.as_os_str().as_encoded_bytes());
+        self.update(b"", digest.as_bytes());
+        Ok(self)
+    }
+
+    /// The key as a lowercase hex string.
+    pub fn digest(&self) -> String {
+        let bytes: [u8; 32] = self.hasher.clone().finalize().into();
+        hash::Digest::from(bytes).to_string()
+    }
+
+    fn add_file(&mut self, path: &Path, digest: &hash::Digest) {
+        self.update(b"file", path.as_os_str().as_encoded_bytes());
+        self.update(b"", digest.as_bytes());
+    }
+
+    fn update(&mut self, tag: &[u8], bytes: &[u8]) {
+        self.hasher.update(tag);
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+    }
+}
+
+/// A stored task result.
+#[derive(serde::Deserialize, Serialize)]
+struct Entry<T> {
+    key: String,
+    result: T,
+}
+
+/// Per-package task results of one plugin.
+#[derive(Debug, Clone)]
+pub struct WorkCache {
+    dir: PathBuf,
+}
+
+impl WorkCache {
+    /// The cache named `name` (usually the plugin name) under
+    /// `<target-dir>/plugin-cache`.
+    ///
+    /// Like cargo's own fingerprints, it is removed by `cargo clean`. Outside
+    /// of a cargo project the system temp directory is used.
+    pub fn open(name: &str) -> Self {
+        let root = crate::tempdirs::target_dir().unwrap_or_else(std::env::temp_dir);
+        Self::in_dir(root.join("plugin-cache").join(name))
+    }
+
+    /// A cache stored in `dir`.
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The cache directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The stored result for `package`, if it was computed with `key`.
+    ///
+    /// Unreadable or outdated entries count as missing.
+    pub fn get<T: DeserializeOwned>(&self, package: &str, key: &CacheKey) -> Option<T> {
+        let contents = std::fs::read(self.entry_path(package)).ok()?;
+        let entry: Entry<T> = serde_json::from_slice(&contents).ok()?;
+        (entry.key == key.digest()).then_some(entry.result)
+    }
+
+    /// Store the result for `package`, replacing any previous result.
+    pub fn put<T: Serialize>(
+        &self,
+        package: &str,
+        key: &CacheKey,
+        result: &T,
+    ) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let entry = Entry {
+            key: key.digest(),
+            result,
+        };
+        let path = self.entry_path(package);
+        // Write to a temporary file first so an interrupted run can't leave a
+        // truncated entry behind
+        let temp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        std::fs::write(&temp_path, serde_json::to_vec(&entry)?)
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        std::fs::rename(&temp_path, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Forget the result for `package`.
+    pub fn invalidate(&self, package: &str) -> anyhow::Result<()> {
+        let path = self.entry_path(package);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Return the stored result for `package` if its inputs are unchanged,
+    /// printing `Fresh <name> v<version>`; otherwise run `task` and store its
+    /// result.
+    pub fn run<T, F>(
+        &self,
+        logger: &Logger,
+        package: &cargo_metadata::Package,
+        key: &CacheKey,
+        task: F,
+    ) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> anyhow::Result<T>,
+    {
+        if let Some(result) = self.fresh(logger, package, key) {
+            return Ok(result);
+        }
+        let result = task()?;
+        self.put(&package.name, key, &result)?;
+        Ok(result)
+    }
+
+    /// Like [`run`](Self::run), for async tasks such as subprocesses.
+    pub async fn run_async<T, F, Fut>(
+        &self,
+        logger: &Logger,
+        package: &cargo_metadata::Package,
+        key: &CacheKey,
+        task: F,
+    ) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        if let Some(result) = self.fresh(logger, package, key) {
+            return Ok(result);
+        }
+        let result = task().await?;
+        self.put(&package.name, key, &result)?;
+        Ok(result)
+    }
+
+    fn fresh<T: DeserializeOwned>(
+        &self,
+        logger: &Logger,
+        package: &cargo_metadata::Package,
+        key: &CacheKey,
+    ) -> Option<T> {
+        let result = self.get(&package.name, key)?;
+        logger.status_permanent("Fresh", &format!("{} v{}", package.name, package.version));
+        Some(result)
+    }
+
+    fn entry_path(&self, package: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", package))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_key() {
+        let base = CacheKey::new().value("tool", "1.0").digest();
+        assert_eq!(base.len(), 64);
+        assert_eq!(CacheKey::new().value("tool", "1.0").digest(), base);
+        assert_ne!(CacheKey::new().value("tool", "1.1").digest(), base);
+        // Same bytes, split differently
+        assert_ne!(
+            CacheKey::new().value("ab", "c").digest(),
+            CacheKey::new().value("a", "bc").digest()
+        );
+    }
+
+    #[test]
+    fn test_cache_key_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("input.txt");
+        std::fs::write(&file, "one").unwrap();
+        let before = CacheKey::new().files([&file]).unwrap().digest();
+        std::fs::write(&file, "two").unwrap();
+        assert_ne!(CacheKey::new().file(&file).unwrap().digest(), before);
+        assert!(CacheKey::new().file(dir.path().join("missing")).is_err());
+
+        let tree = CacheKey::new().dir(dir.path()).unwrap().digest();
+        std::fs::write(dir.path().join("other.txt"), "new").unwrap();
+        assert_ne!(CacheKey::new().dir(dir.path()).unwrap().digest(), tree);
+    }
+
+    #[test]
+    fn test_get_put_invalidate() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = WorkCache::in_dir(dir.path().join("cache"));
+        let key = CacheKey::new().value("input", "1");
+        assert_eq!(cache.get::<u32>("foo", &key), None);
+
+        cache.put("foo", &key, &42u32).unwrap();
+        assert_eq!(cache.get::<u32>("foo", &key), Some(42));
+        // A different key or result type is a miss
+        assert_eq!(
+            cache.get::<u32>("foo", &key.clone().value("input", "2")),
+            None
+        );
+        assert_eq!(cache.get::<String>("foo", &key), None);
+
+        cache.invalidate("foo").unwrap();
+        cache.invalidate("foo").unwrap();
+        assert_eq!(cache.get::<u32>("foo", &key), None);
+    }
+
+    #[test]
+    fn test_run_skips_fresh_packages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = WorkCache::in_dir(dir.path());
+        let metadata = crate::common::get_metadata(None).unwrap();
+        let package = &metadata.packages[0];
+        let key = CacheKey::new().value("input", "1");
+        let logger = Logger::new();
+
+        let mut runs = 0;
+        for _ in 0..2 {
+            let result: String = cache
+                .run(&logger, package, &key, || {
+                    runs += 1;
+                    Ok("done".to_string())
+                })
+                .unwrap();
+            assert_eq!(result, "done");
+        }
+        assert_eq!(runs, 1);
+
+        // Failed tasks aren't cached
+        let failed = cache.run::<String, _>(&logger, package, &CacheKey::new(), || {
+            anyhow::bail!("failed")
+        });
+        assert!(failed.is_err());
+        assert_eq!(cache.get::<String>(&package.name, &CacheKey::new()), None);
+    }
+}